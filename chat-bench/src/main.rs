@@ -0,0 +1,246 @@
+use std::time::{Duration, Instant};
+
+use anyhow::{bail, Context, Result};
+use chat_client::{ChatClient, ClientOptions, Event, Events};
+use clap::Parser;
+use tokio::time::MissedTickBehavior;
+
+mod stats;
+
+use stats::{ClientStats, Report};
+
+/// Body prefix of bench messages, followed by the send time
+const BODY_PREFIX: &str = "bench ";
+
+/// How long a client may take to connect, log in and join its room
+const SETUP_TIMEOUT: Duration = Duration::from_secs(30);
+
+/// Load generator: N clients spread over M rooms chat at a fixed rate
+/// while end-to-end delivery latency is measured
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// Server websocket url
+    #[arg(short, long, default_value = "ws://127.0.0.1:3000/")]
+    url: String,
+
+    /// Bearer token for servers with AUTH_TOKEN set
+    #[arg(long)]
+    token: Option<String>,
+
+    /// Concurrent clients
+    #[arg(short, long, default_value_t = 50)]
+    clients: usize,
+
+    /// Rooms the clients are spread across
+    #[arg(short, long, default_value_t = 5)]
+    rooms: usize,
+
+    /// Messages per second sent by each client
+    #[arg(long, default_value_t = 1.0)]
+    rate: f64,
+
+    /// Seconds of sending
+    #[arg(short, long, default_value_t = 10)]
+    duration: u64,
+
+    /// Seconds to wait for messages in flight once sending stops
+    #[arg(long, default_value_t = 2)]
+    drain: u64,
+
+    /// Print the report as JSON
+    #[arg(long)]
+    json: bool,
+}
+
+/// A client that is logged in and sitting in its room
+struct BenchClient {
+    client: ChatClient,
+    events: Events,
+    room: usize,
+}
+
+#[tokio::main]
+async fn main() -> Result<()> {
+    let args = Args::parse();
+    if args.clients == 0 || args.rooms == 0 || args.rate.is_nan() || args.rate <= 0.0 {
+        bail!("--clients, --rooms and --rate must be greater than zero");
+    }
+
+    let setup_start = Instant::now();
+    let setups: Vec<_> = (0..args.clients)
+        .map(|index| {
+            tokio::spawn(setup(
+                args.url.clone(),
+                args.token.clone(),
+                index,
+                args.rooms,
+            ))
+        })
+        .collect();
+    let mut clients = Vec::new();
+    let mut connect_errors = 0;
+    for setup in setups {
+        match setup.await.context("setup task failed")? {
+            Ok(client) => clients.push(client),
+            Err(err) => {
+                if connect_errors == 0 {
+                    eprintln!("client setup failed: {:#}", err);
+                }
+                connect_errors += 1;
+            }
+        }
+    }
+    let setup_time = setup_start.elapsed();
+    if clients.is_empty() {
+        bail!("no client could connect to {}", args.url);
+    }
+
+    let mut room_sizes = vec![0; args.rooms];
+    for client in &clients {
+        room_sizes[client.room] += 1;
+    }
+
+    let epoch = Instant::now();
+    let count = clients.len();
+    let runs: Vec<_> = clients
+        .into_iter()
+        .enumerate()
+        .map(|(index, client)| {
+            // spread the sends of different clients over one period
+            let period = Duration::from_secs_f64(1.0 / args.rate);
+            let offset = period.mul_f64(index as f64 / count as f64);
+            let room = client.room;
+            let run = tokio::spawn(run(
+                client,
+                epoch,
+                offset,
+                period,
+                Duration::from_secs(args.duration),
+                Duration::from_secs(args.drain),
+            ));
+            (room, run)
+        })
+        .collect();
+
+    let mut results = Vec::new();
+    for (room, run) in runs {
+        let stats = run.await.context("client task failed")?;
+        results.push((stats, room_sizes[room]));
+    }
+
+    let report = Report::new(
+        &args.url,
+        args.clients,
+        args.rooms,
+        args.rate,
+        Duration::from_secs(args.duration),
+        connect_errors,
+        setup_time,
+        results,
+    );
+    if args.json {
+        println!("{}", serde_json::to_string_pretty(&report)?);
+    } else {
+        report.print();
+    }
+    Ok(())
+}
+
+/// Connect, log in as `bench-<index>` and join the client's room
+async fn setup(
+    url: String,
+    token: Option<String>,
+    index: usize,
+    rooms: usize,
+) -> Result<BenchClient> {
+    let room = index % rooms;
+    let room_name = format!("bench-{}", room);
+
+    let mut options = ClientOptions::new(&url);
+    options.token = token;
+    // a lost connection is a result, not something to hide
+    options.reconnect = None;
+
+    tokio::time::timeout(SETUP_TIMEOUT, async {
+        let (client, mut events) = ChatClient::connect(options).await?;
+        client.login(&format!("bench-{}", index)).await?;
+        client.join(&room_name).await?;
+        while let Some(event) = events.next_event().await {
+            match event {
+                Event::Roster(roster) if roster.room == room_name => {
+                    return Ok(BenchClient {
+                        client,
+                        events,
+                        room,
+                    });
+                }
+                Event::Error(error) => bail!("server refused: {}", error.message),
+                _ => {}
+            }
+        }
+        bail!("connection closed during setup")
+    })
+    .await
+    .context("setup timed out")?
+}
+
+/// Send at a fixed period until `duration` is over, keep receiving for `drain`
+async fn run(
+    bench: BenchClient,
+    epoch: Instant,
+    offset: Duration,
+    period: Duration,
+    duration: Duration,
+    drain: Duration,
+) -> ClientStats {
+    let BenchClient {
+        client, mut events, ..
+    } = bench;
+    let room = format!("bench-{}", bench.room);
+    let mut stats = ClientStats::default();
+
+    let start = tokio::time::Instant::from_std(epoch);
+    let mut ticks = tokio::time::interval_at(start + offset, period);
+    ticks.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let send_end = start + duration;
+    let end = tokio::time::sleep_until(send_end + drain);
+    tokio::pin!(end);
+
+    loop {
+        tokio::select! {
+            tick = ticks.tick(), if !stats.disconnected => {
+                if tick >= send_end {
+                    continue;
+                }
+                let body = format!("{}{}", BODY_PREFIX, epoch.elapsed().as_micros());
+                match client.send_chat(&room, &body).await {
+                    Ok(_) => stats.sent += 1,
+                    Err(_) => stats.send_errors += 1,
+                }
+            }
+            event = events.next_event() => match event {
+                Some(Event::Chat(chat)) => {
+                    let sent = chat
+                        .body
+                        .strip_prefix(BODY_PREFIX)
+                        .and_then(|micros| micros.parse::<u128>().ok());
+                    if let Some(sent) = sent {
+                        let latency = epoch.elapsed().as_micros().saturating_sub(sent);
+                        stats.record(latency as u64);
+                    }
+                }
+                Some(Event::Error(_)) => stats.server_errors += 1,
+                Some(Event::Disconnected { .. }) | None => {
+                    stats.disconnected = true;
+                    break;
+                }
+                Some(_) => {}
+            },
+            _ = &mut end => break,
+        }
+    }
+
+    client.close().await;
+    stats
+}
@@ -0,0 +1,187 @@
+use std::time::Duration;
+
+use serde::Serialize;
+
+/// What one client saw during the run
+#[derive(Default, Debug)]
+pub struct ClientStats {
+    pub sent: u64,
+    pub send_errors: u64,
+    pub server_errors: u64,
+    /// The connection ended before the run did
+    pub disconnected: bool,
+    /// Delivery latency of every bench message received, in microseconds
+    latencies: Vec<u64>,
+}
+
+impl ClientStats {
+    pub fn record(&mut self, latency_us: u64) {
+        self.latencies.push(latency_us);
+    }
+}
+
+/// Latency percentiles in milliseconds
+#[derive(Serialize, Debug)]
+pub struct Latency {
+    pub mean: f64,
+    pub p50: f64,
+    pub p90: f64,
+    pub p99: f64,
+    pub p999: f64,
+    pub max: f64,
+}
+
+impl Latency {
+    /// `None` when nothing was delivered
+    fn from_samples(samples: &mut [u64]) -> Option<Latency> {
+        if samples.is_empty() {
+            return None;
+        }
+        samples.sort_unstable();
+        let ms = |us: u64| us as f64 / 1000.0;
+        let percentile = |p: f64| {
+            let rank = (p * samples.len() as f64).ceil() as usize;
+            ms(samples[rank.clamp(1, samples.len()) - 1])
+        };
+        let total: u64 = samples.iter().sum();
+        Some(Latency {
+            mean: ms(total / samples.len() as u64),
+            p50: percentile(0.50),
+            p90: percentile(0.90),
+            p99: percentile(0.99),
+            p999: percentile(0.999),
+            max: ms(samples[samples.len() - 1]),
+        })
+    }
+}
+
+#[derive(Serialize, Debug)]
+pub struct Report {
+    pub url: String,
+    pub clients: usize,
+    pub rooms: usize,
+    /// Messages per second per client
+    pub rate: f64,
+    pub duration_secs: f64,
+    pub connected: usize,
+    pub connect_errors: usize,
+    /// Time to connect, log in and join every client
+    pub setup_secs: f64,
+    pub sent: u64,
+    pub send_errors: u64,
+    /// Deliveries expected: every message reaches every member of its room
+    pub expected: u64,
+    pub delivered: u64,
+    pub missing: u64,
+    pub server_errors: u64,
+    pub disconnects: usize,
+    /// Messages sent per second
+    pub send_throughput: f64,
+    /// Messages delivered per second, the fan-out load of the server
+    pub delivery_throughput: f64,
+    pub latency_ms: Option<Latency>,
+}
+
+impl Report {
+    /// `results` pairs the stats of each client with the size of its room
+    #[allow(clippy::too_many_arguments)]
+    pub fn new(
+        url: &str,
+        clients: usize,
+        rooms: usize,
+        rate: f64,
+        duration: Duration,
+        connect_errors: usize,
+        setup: Duration,
+        results: Vec<(ClientStats, usize)>,
+    ) -> Report {
+        let mut samples = Vec::new();
+        let mut report = Report {
+            url: url.to_owned(),
+            clients,
+            rooms,
+            rate,
+            duration_secs: duration.as_secs_f64(),
+            connected: results.len(),
+            connect_errors,
+            setup_secs: setup.as_secs_f64(),
+            sent: 0,
+            send_errors: 0,
+            expected: 0,
+            delivered: 0,
+            missing: 0,
+            server_errors: 0,
+            disconnects: 0,
+            send_throughput: 0.0,
+            delivery_throughput: 0.0,
+            latency_ms: None,
+        };
+
+        for (stats, room_size) in results {
+            report.sent += stats.sent;
+            report.send_errors += stats.send_errors;
+            report.expected += stats.sent * room_size as u64;
+            report.delivered += stats.latencies.len() as u64;
+            report.server_errors += stats.server_errors;
+            report.disconnects += stats.disconnected as usize;
+            samples.extend(stats.latencies);
+        }
+        report.missing = report.expected.saturating_sub(report.delivered);
+        if report.duration_secs > 0.0 {
+            report.send_throughput = report.sent as f64 / report.duration_secs;
+            report.delivery_throughput = report.delivered as f64 / report.duration_secs;
+        }
+        report.latency_ms = Latency::from_samples(&mut samples);
+        report
+    }
+
+    pub fn print(&self) {
+        println!(
+            "{} clients in {} rooms, {} msg/s each for {}s against {}",
+            self.clients, self.rooms, self.rate, self.duration_secs, self.url
+        );
+        println!(
+            "setup       {} connected, {} failed, {:.2}s",
+            self.connected, self.connect_errors, self.setup_secs
+        );
+        println!(
+            "sent        {} ({} errors), {:.1} msg/s",
+            self.sent, self.send_errors, self.send_throughput
+        );
+        println!(
+            "delivered   {} of {} ({} missing), {:.1} msg/s",
+            self.delivered, self.expected, self.missing, self.delivery_throughput
+        );
+        match &self.latency_ms {
+            Some(l) => println!(
+                "latency ms  mean {:.2}  p50 {:.2}  p90 {:.2}  p99 {:.2}  p99.9 {:.2}  max {:.2}",
+                l.mean, l.p50, l.p90, l.p99, l.p999, l.max
+            ),
+            None => println!("latency ms  no deliveries"),
+        }
+        println!(
+            "errors      {} server errors, {} disconnects",
+            self.server_errors, self.disconnects
+        );
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn percentiles_use_nearest_rank() {
+        let mut samples: Vec<u64> = (1..=1000).rev().map(|ms| ms * 1000).collect();
+        let latency = Latency::from_samples(&mut samples).unwrap();
+        assert_eq!(latency.p50, 500.0);
+        assert_eq!(latency.p90, 900.0);
+        assert_eq!(latency.p99, 990.0);
+        assert_eq!(latency.p999, 999.0);
+        assert_eq!(latency.max, 1000.0);
+
+        assert!(Latency::from_samples(&mut []).is_none());
+        let one = Latency::from_samples(&mut [1500]).unwrap();
+        assert_eq!((one.p50, one.p999, one.max), (1.5, 1.5, 1.5));
+    }
+}
@@ -0,0 +1,361 @@
+use std::{
+    collections::hash_map::RandomState,
+    hash::{BuildHasher, Hasher},
+    pin::Pin,
+    sync::{
+        atomic::{AtomicBool, AtomicU64, Ordering},
+        Arc,
+    },
+    task::{Context, Poll},
+};
+
+use futures_util::Stream;
+use tokio::sync::mpsc;
+
+use proto::{ChatPacket, ChatPacketType};
+
+use crate::{
+    connection::{self, Command},
+    ClientOptions, Error, Event,
+};
+
+/// Handle for sending to the server, cheap to clone
+#[derive(Clone)]
+pub struct ChatClient {
+    commands: mpsc::Sender<Command>,
+    connected: Arc<AtomicBool>,
+    /// Random per connection, so nonces don't repeat across clients
+    nonce_prefix: u64,
+    sent_chats: Arc<AtomicU64>,
+}
+
+/// Decoded events of a connection, ends once the connection is gone for good
+pub struct Events {
+    rx: mpsc::Receiver<Event>,
+}
+
+impl ChatClient {
+    /// Open a connection. Handshake errors such as a refused token are returned
+    /// here, later connection losses are reported as `Event::Disconnected`.
+    pub async fn connect(options: ClientOptions) -> Result<(ChatClient, Events), Error> {
+        let stream = connection::open(&options).await?;
+
+        let (commands, commands_rx) = mpsc::channel(options.queue_size);
+        let (events_tx, rx) = mpsc::channel(options.queue_size);
+        let connected = Arc::new(AtomicBool::new(true));
+
+        tokio::spawn(connection::run(
+            options,
+            stream,
+            commands_rx,
+            events_tx,
+            connected.clone(),
+        ));
+
+        Ok((
+            ChatClient {
+                commands,
+                connected,
+                nonce_prefix: RandomState::new().build_hasher().finish(),
+                sent_chats: Arc::default(),
+            },
+            Events { rx },
+        ))
+    }
+
+    pub fn is_connected(&self) -> bool {
+        self.connected.load(Ordering::Relaxed)
+    }
+
+    /// Queue a raw packet, packets sent while reconnecting go out once the
+    /// connection is back
+    pub async fn send_packet(&self, packet: ChatPacket) -> Result<(), Error> {
+        self.commands
+            .send(Command::Packet(packet))
+            .await
+            .map_err(|_| Error::NotConnected)
+    }
+
+    /// Set or change the name, kept across reconnects
+    pub async fn login(&self, name: &str) -> Result<(), Error> {
+        let packet = ChatPacket::new(ChatPacketType::Login, name.to_owned());
+        self.send_packet(packet).await
+    }
+
+    /// Join a room, rejoined after reconnects
+    pub async fn join(&self, room: &str) -> Result<(), Error> {
+        let packet = ChatPacket::new(ChatPacketType::Join, room.to_owned());
+        self.send_packet(packet).await
+    }
+
+    /// Join a password protected room, rejoined after reconnects
+    pub async fn join_with_password(&self, room: &str, password: &str) -> Result<(), Error> {
+        let join = proto::JoinRoom {
+            room: room.to_owned(),
+            password: Some(password.to_owned()),
+            ttl_secs: None,
+        };
+        let packet = ChatPacket::new(ChatPacketType::Join, join.to_message());
+        self.send_packet(packet).await
+    }
+
+    /// Join a room that closes `ttl_secs` after this join creates it, a plain
+    /// join when it is there already
+    pub async fn create_room(&self, room: &str, ttl_secs: u64) -> Result<(), Error> {
+        let join = proto::JoinRoom {
+            room: room.to_owned(),
+            password: None,
+            ttl_secs: Some(ttl_secs),
+        };
+        let packet = ChatPacket::new(ChatPacketType::Join, join.to_message());
+        self.send_packet(packet).await
+    }
+
+    /// Ask for an invite code to a room, answered with `Event::Invite`.
+    /// 0 is no limit for either
+    pub async fn invite(&self, room: &str, max_uses: u32, expires_secs: u64) -> Result<(), Error> {
+        let invite = proto::Invite {
+            room: room.to_owned(),
+            max_uses,
+            expires_secs,
+        };
+        self.send_packet(ChatPacket::with_payload(ChatPacketType::Invite, &invite))
+            .await
+    }
+
+    /// Join the room of an invite code, rejoined after reconnects
+    pub async fn accept_invite(&self, code: &str) -> Result<(), Error> {
+        let accept = proto::AcceptInvite {
+            code: code.to_owned(),
+            room: String::new(),
+        };
+        let packet = ChatPacket::with_payload(ChatPacketType::AcceptInvite, &accept);
+        self.send_packet(packet).await
+    }
+
+    /// Ask for the rooms we may see, answered with `Event::Rooms`
+    pub async fn list_rooms(&self) -> Result<(), Error> {
+        let packet = ChatPacket::new(ChatPacketType::ListRooms, String::new());
+        self.send_packet(packet).await
+    }
+
+    pub async fn leave(&self, room: &str) -> Result<(), Error> {
+        let packet = ChatPacket::new(ChatPacketType::Leave, room.to_owned());
+        self.send_packet(packet).await
+    }
+
+    /// Ask for the member list, answered with `Event::Roster`
+    pub async fn request_roster(&self, room: &str) -> Result<(), Error> {
+        let packet = ChatPacket::new(ChatPacketType::Roster, room.to_owned());
+        self.send_packet(packet).await
+    }
+
+    /// Send a chat, returns its nonce. The server answers with `Event::Ack`
+    /// or an `Event::Error` carrying the nonce, unanswered chats are sent
+    /// again after a reconnect.
+    pub async fn send_chat(&self, room: &str, body: &str) -> Result<String, Error> {
+        self.chat(room, body, None).await
+    }
+
+    /// Send a chat answering the message `reply_to` of the same room, like
+    /// `send_chat`
+    pub async fn reply(&self, room: &str, reply_to: u64, body: &str) -> Result<String, Error> {
+        self.chat(room, body, Some(reply_to)).await
+    }
+
+    async fn chat(&self, room: &str, body: &str, reply_to: Option<u64>) -> Result<String, Error> {
+        let count = self.sent_chats.fetch_add(1, Ordering::Relaxed);
+        let nonce = format!("{:016x}-{}", self.nonce_prefix, count);
+        let chat = proto::ChatSend {
+            room: room.to_owned(),
+            body: body.to_owned(),
+            nonce: Some(nonce.clone()),
+            reply_to,
+        };
+        let packet = ChatPacket::with_payload(ChatPacketType::Chat, &chat);
+        self.send_packet(packet).await?;
+        Ok(nonce)
+    }
+
+    /// Change the body of an own message, or any as a room operator
+    pub async fn edit(&self, message_id: u64, new_body: &str) -> Result<(), Error> {
+        let edit = proto::Edit {
+            message_id,
+            new_body: new_body.to_owned(),
+        };
+        let packet = ChatPacket::with_payload(ChatPacketType::Edit, &edit);
+        self.send_packet(packet).await
+    }
+
+    /// Delete an own message, or any as a room operator
+    pub async fn delete(&self, message_id: u64) -> Result<(), Error> {
+        let delete = proto::Delete { message_id };
+        let packet = ChatPacket::with_payload(ChatPacketType::Delete, &delete);
+        self.send_packet(packet).await
+    }
+
+    /// React to a message of a joined room, e.g. with `👍`
+    pub async fn react(&self, message_id: u64, emoji: &str) -> Result<(), Error> {
+        self.send_reaction(ChatPacketType::React, message_id, emoji)
+            .await
+    }
+
+    /// Take back an own reaction
+    pub async fn unreact(&self, message_id: u64, emoji: &str) -> Result<(), Error> {
+        self.send_reaction(ChatPacketType::Unreact, message_id, emoji)
+            .await
+    }
+
+    /// Tell the room we are composing a chat, `Active` has to be sent again
+    /// every few seconds for as long as it lasts
+    pub async fn typing(&self, room: &str, state: proto::TypingState) -> Result<(), Error> {
+        let typing = proto::Typing {
+            room: room.to_owned(),
+            state,
+        };
+        self.send_packet(ChatPacket::with_payload(ChatPacketType::Typing, &typing))
+            .await
+    }
+
+    /// Tell the other sessions of our name the messages of a room were read
+    /// up to `message_id`
+    pub async fn mark_read(&self, room: &str, message_id: u64) -> Result<(), Error> {
+        let read = proto::MarkRead {
+            room: room.to_owned(),
+            message_id,
+        };
+        self.send_packet(ChatPacket::with_payload(ChatPacketType::MarkRead, &read))
+            .await
+    }
+
+    /// Show the members of our rooms as online, away, busy or invisible
+    pub async fn set_status(&self, status: &proto::Status) -> Result<(), Error> {
+        self.send_packet(ChatPacket::with_payload(ChatPacketType::Status, status))
+            .await
+    }
+
+    /// Change the fields of our profile that are set, empty ones are cleared
+    pub async fn set_profile(&self, profile: &proto::Profile) -> Result<(), Error> {
+        self.send_packet(ChatPacket::with_payload(ChatPacketType::Profile, profile))
+            .await
+    }
+
+    /// Ask for the status and profile of a name, answered with `Event::WhoIs`
+    pub async fn whois(&self, name: &str) -> Result<(), Error> {
+        let whois = proto::WhoIs {
+            name: name.to_owned(),
+        };
+        self.send_packet(ChatPacket::with_payload(ChatPacketType::WhoIs, &whois))
+            .await
+    }
+
+    /// Change the topic and description of a room we operate, `None` keeps
+    /// a text and an empty one clears it
+    pub async fn set_topic(
+        &self,
+        room: &str,
+        topic: Option<&str>,
+        description: Option<&str>,
+    ) -> Result<(), Error> {
+        let set = proto::SetTopic {
+            room: room.to_owned(),
+            topic: topic.map(str::to_owned),
+            description: description.map(str::to_owned),
+        };
+        self.send_packet(ChatPacket::with_payload(ChatPacketType::SetTopic, &set))
+            .await
+    }
+
+    /// Ask for the details of a joined room, answered with `Event::RoomInfo`
+    pub async fn room_info(&self, room: &str) -> Result<(), Error> {
+        self.send_room_query(room, None, None).await
+    }
+
+    /// Replace the settings of a room we operate
+    pub async fn change_room(
+        &self,
+        room: &str,
+        settings: &proto::RoomSettings,
+    ) -> Result<(), Error> {
+        self.send_room_query(room, Some(settings.clone()), None)
+            .await
+    }
+
+    /// Protect a room we operate with a password
+    pub async fn set_room_password(
+        &self,
+        room: &str,
+        settings: &proto::RoomSettings,
+        password: &str,
+    ) -> Result<(), Error> {
+        let settings = proto::RoomSettings {
+            visibility: proto::Visibility::Password,
+            ..settings.clone()
+        };
+        self.send_room_query(room, Some(settings), Some(password.to_owned()))
+            .await
+    }
+
+    async fn send_room_query(
+        &self,
+        room: &str,
+        settings: Option<proto::RoomSettings>,
+        password: Option<String>,
+    ) -> Result<(), Error> {
+        let query = proto::RoomQuery {
+            room: room.to_owned(),
+            settings,
+            password,
+        };
+        self.send_packet(ChatPacket::with_payload(ChatPacketType::RoomInfo, &query))
+            .await
+    }
+
+    async fn send_reaction(
+        &self,
+        packet_type: ChatPacketType,
+        message_id: u64,
+        emoji: &str,
+    ) -> Result<(), Error> {
+        let react = proto::React {
+            message_id,
+            emoji: emoji.to_owned(),
+        };
+        self.send_packet(ChatPacket::with_payload(packet_type, &react))
+            .await
+    }
+
+    pub async fn send_dm(&self, to: &str, body: &str) -> Result<(), Error> {
+        let direct = proto::DirectSend {
+            to: to.to_owned(),
+            body: body.to_owned(),
+        };
+        let packet = ChatPacket::with_payload(ChatPacketType::Direct, &direct);
+        self.send_packet(packet).await
+    }
+
+    /// Close the connection without reconnecting, the event stream ends after
+    /// the queued packets are flushed
+    pub async fn close(&self) {
+        let _ = self.commands.send(Command::Close).await;
+    }
+}
+
+impl Events {
+    pub async fn next_event(&mut self) -> Option<Event> {
+        self.rx.recv().await
+    }
+
+    /// Next event if one is ready, for callers polling from a UI loop
+    pub fn try_next_event(&mut self) -> Option<Event> {
+        self.rx.try_recv().ok()
+    }
+}
+
+impl Stream for Events {
+    type Item = Event;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Event>> {
+        self.rx.poll_recv(cx)
+    }
+}
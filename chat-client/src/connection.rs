@@ -0,0 +1,437 @@
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::{Duration, Instant},
+};
+
+use futures_util::{SinkExt, StreamExt};
+use tokio::{net::TcpStream, sync::mpsc, time::MissedTickBehavior};
+use tokio_tungstenite::{
+    connect_async_tls_with_config,
+    tungstenite::{
+        self,
+        client::IntoClientRequest,
+        http::{header::AUTHORIZATION, HeaderValue, StatusCode},
+        Message,
+    },
+    MaybeTlsStream, WebSocketStream,
+};
+
+use proto::{ChatPacket, ChatPacketType};
+
+use crate::{sequence::Sequencer, tls, ClientOptions, Error, Event};
+
+type WsStream = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+/// How long a close waits for the server to confirm
+const CLOSE_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// Chats waiting for their ack, and packets held while reconnecting, kept
+/// at most. Well below the acks the server remembers for recognizing retries.
+const MAX_PENDING: usize = 1024;
+
+pub(crate) enum Command {
+    Packet(ChatPacket),
+    Close,
+}
+
+/// Why a connection ended
+enum End {
+    /// Closed on request, or every `ChatClient` was dropped
+    Closed,
+    /// The event receiver was dropped, nobody is listening
+    Abandoned,
+    /// The connection broke and may be retried
+    Lost(String),
+}
+
+/// Name, rooms and unacknowledged chats sent again after a reconnect
+#[derive(Default)]
+struct Session {
+    name: Option<String>,
+    rooms: Vec<proto::JoinRoom>,
+    /// Chats by nonce in the order they were sent
+    pending: Vec<(String, ChatPacket)>,
+    /// Packets sent while reconnecting that the replay doesn't cover
+    held: Vec<ChatPacket>,
+    sequence: Sequencer,
+}
+
+impl Session {
+    /// Remember what a reconnect replays, returns the error for the
+    /// application when the packet can't be sent
+    fn track(&mut self, packet: &ChatPacket) -> Option<Event> {
+        match packet.packet_type {
+            ChatPacketType::Login => self.name = Some(packet.packet_message.clone()),
+            ChatPacketType::Join => {
+                self.joining(proto::JoinRoom::parse(&packet.packet_message));
+            }
+            ChatPacketType::Leave => {
+                self.sequence.left(&packet.packet_message);
+                self.rooms.retain(|r| r.room != packet.packet_message);
+            }
+            ChatPacketType::Chat => {
+                if let Ok(proto::ChatSend {
+                    nonce: Some(nonce), ..
+                }) = packet.payload()
+                {
+                    if self.pending.len() >= MAX_PENDING {
+                        return Some(refused(
+                            "too many chats waiting for the server",
+                            Some(nonce),
+                        ));
+                    }
+                    self.pending.push((nonce, packet.clone()));
+                }
+            }
+            _ => {}
+        }
+        None
+    }
+
+    /// Keep a packet sent while reconnecting for the next connection
+    fn hold(&mut self, packet: ChatPacket) -> Option<Event> {
+        let replayed = matches!(
+            packet.packet_type,
+            ChatPacketType::Login
+                | ChatPacketType::Join
+                | ChatPacketType::Leave
+                | ChatPacketType::Chat
+        );
+        let refusal = self.track(&packet);
+        if replayed || refusal.is_some() {
+            return refusal;
+        }
+        if self.held.len() >= MAX_PENDING {
+            return Some(refused("too many packets waiting for a connection", None));
+        }
+        self.held.push(packet);
+        None
+    }
+
+    /// Remember a join, the latest password of a room is kept
+    fn joining(&mut self, join: proto::JoinRoom) {
+        self.sequence.joining(&join.room);
+        match self.rooms.iter_mut().find(|r| r.room == join.room) {
+            Some(known) => *known = join,
+            None => self.rooms.push(join),
+        }
+    }
+
+    /// Add the events of a server packet to `out` in room order, returns a
+    /// packet to send back when a gap has to be filled
+    fn receive(&mut self, packet: ChatPacket, out: &mut Vec<Event>) -> Option<ChatPacket> {
+        if packet.packet_type == ChatPacketType::Resync {
+            match packet.payload() {
+                Ok(resync) => self.sequence.resynced(resync, out),
+                Err(err) => log::warn!("invalid resync from server: {}", err),
+            }
+            return None;
+        }
+        let Some(event) = Event::from_packet(packet) else {
+            log::warn!("undecodable packet from server");
+            return None;
+        };
+        self.answered(&event);
+        match &event {
+            Event::InviteAccepted(accepted) => self.joining(proto::JoinRoom {
+                room: accepted.room.clone(),
+                password: None,
+                ttl_secs: None,
+            }),
+            Event::RoomClosed(closed) => {
+                self.sequence.left(&closed.room);
+                self.rooms.retain(|r| r.room != closed.room);
+            }
+            // no use trying again after a reconnect
+            Event::Error(proto::ErrorInfo {
+                detail: Some(proto::ErrorDetail::JoinRefused { room, .. }),
+                ..
+            }) => {
+                self.sequence.left(room);
+                self.rooms.retain(|r| r.room != *room);
+            }
+            _ => {}
+        }
+        self.sequence.receive(event, out)
+    }
+
+    /// Forget chats the server answered
+    fn answered(&mut self, event: &Event) {
+        let nonce = match event {
+            Event::Ack(ack) => &ack.nonce,
+            Event::Error(proto::ErrorInfo {
+                nonce: Some(nonce), ..
+            }) => nonce,
+            _ => return,
+        };
+        self.pending.retain(|(pending, _)| pending != nonce);
+    }
+
+    fn replay(&mut self) -> Vec<ChatPacket> {
+        self.sequence.connected();
+        let login = self
+            .name
+            .iter()
+            .map(|name| ChatPacket::new(ChatPacketType::Login, name.clone()));
+        let joins = self
+            .rooms
+            .iter()
+            .map(|join| ChatPacket::new(ChatPacketType::Join, join.to_message()));
+        // the server acks chats it already delivered instead of sending them twice
+        let chats = self.pending.iter().map(|(_, packet)| packet.clone());
+        let held = self.held.drain(..);
+        login.chain(joins).chain(chats).chain(held).collect()
+    }
+}
+
+/// Error for a packet that was never sent
+fn refused(message: &str, nonce: Option<String>) -> Event {
+    Event::Error(proto::ErrorInfo {
+        message: message.to_owned(),
+        nonce,
+        detail: None,
+    })
+}
+
+/// Websocket handshake with the auth and tls settings of `options`
+pub(crate) async fn open(options: &ClientOptions) -> Result<WsStream, Error> {
+    let url = url::Url::parse(&options.url)?;
+
+    let mut request = url
+        .into_client_request()
+        .map_err(|err| Error::Connect(Box::new(err)))?;
+    let client_id = HeaderValue::from_str(&options.client_id).map_err(|_| Error::ClientId)?;
+    request
+        .headers_mut()
+        .insert(proto::CLIENT_ID_HEADER, client_id);
+    if let Some(token) = &options.token {
+        let bearer =
+            HeaderValue::from_str(&format!("Bearer {}", token)).map_err(|_| Error::Token)?;
+        request.headers_mut().insert(AUTHORIZATION, bearer);
+    }
+    let connector = options.tls.as_ref().map(tls::connector).transpose()?;
+
+    match connect_async_tls_with_config(request, None, false, connector).await {
+        Ok((stream, _)) => Ok(stream),
+        Err(tungstenite::Error::Http(response))
+            if response.status() == StatusCode::UNAUTHORIZED =>
+        {
+            Err(Error::Unauthorized)
+        }
+        Err(err) => Err(Error::Connect(Box::new(err))),
+    }
+}
+
+/// Background task owning the socket: forwards commands, decodes events,
+/// sends heartbeats and reconnects until closed or out of attempts
+pub(crate) async fn run(
+    options: ClientOptions,
+    stream: WsStream,
+    mut commands: mpsc::Receiver<Command>,
+    events: mpsc::Sender<Event>,
+    connected: Arc<AtomicBool>,
+) {
+    let mut session = Session::default();
+    let mut stream = Some(stream);
+
+    loop {
+        let ws = match stream.take() {
+            Some(ws) => ws,
+            None => match reconnect(&options, &mut session, &mut commands, &events).await {
+                Some(ws) => ws,
+                None => break,
+            },
+        };
+
+        connected.store(true, Ordering::Relaxed);
+        if events.send(Event::Connected).await.is_err() {
+            break;
+        }
+
+        let end = drive(ws, &options, &mut session, &mut commands, &events).await;
+        connected.store(false, Ordering::Relaxed);
+
+        match end {
+            End::Closed => {
+                let _ = events
+                    .send(Event::Disconnected {
+                        reason: "closed".to_owned(),
+                        reconnecting: false,
+                    })
+                    .await;
+                break;
+            }
+            End::Abandoned => break,
+            End::Lost(reason) => {
+                log::debug!("connection lost: {}", reason);
+                let reconnecting = options.reconnect.is_some();
+                let lost = Event::Disconnected {
+                    reason,
+                    reconnecting,
+                };
+                if events.send(lost).await.is_err() || !reconnecting {
+                    break;
+                }
+            }
+        }
+    }
+
+    connected.store(false, Ordering::Relaxed);
+}
+
+/// Retry with exponential backoff, `None` when giving up or closed meanwhile
+async fn reconnect(
+    options: &ClientOptions,
+    session: &mut Session,
+    commands: &mut mpsc::Receiver<Command>,
+    events: &mpsc::Sender<Event>,
+) -> Option<WsStream> {
+    let policy = options.reconnect.as_ref()?;
+    let mut delay = policy.initial_delay;
+    let mut attempts = 0;
+
+    loop {
+        let sleep = tokio::time::sleep(delay);
+        tokio::pin!(sleep);
+        loop {
+            tokio::select! {
+                _ = &mut sleep => break,
+                command = commands.recv() => match command {
+                    Some(Command::Packet(packet)) => {
+                        if let Some(refusal) = session.hold(packet) {
+                            if events.send(refusal).await.is_err() {
+                                return None;
+                            }
+                        }
+                    }
+                    Some(Command::Close) | None => {
+                        let _ = events
+                            .send(Event::Disconnected {
+                                reason: "closed".to_owned(),
+                                reconnecting: false,
+                            })
+                            .await;
+                        return None;
+                    }
+                },
+            }
+        }
+        if events.is_closed() {
+            return None;
+        }
+
+        match open(options).await {
+            Ok(ws) => return Some(ws),
+            Err(err) => {
+                attempts += 1;
+                log::debug!("reconnect attempt {} failed: {}", attempts, err);
+                // a refused token won't get better by retrying
+                let give_up = matches!(err, Error::Unauthorized)
+                    || policy.max_attempts.is_some_and(|max| attempts >= max);
+                if give_up {
+                    let _ = events
+                        .send(Event::Disconnected {
+                            reason: err.to_string(),
+                            reconnecting: false,
+                        })
+                        .await;
+                    return None;
+                }
+            }
+        }
+        delay = (delay * 2).min(policy.max_delay);
+    }
+}
+
+async fn drive(
+    ws: WsStream,
+    options: &ClientOptions,
+    session: &mut Session,
+    commands: &mut mpsc::Receiver<Command>,
+    events: &mpsc::Sender<Event>,
+) -> End {
+    let (mut write, mut read) = ws.split();
+
+    for packet in session.replay() {
+        if let Err(err) = write.send(Message::Binary(packet.serialize())).await {
+            return End::Lost(err.to_string());
+        }
+    }
+
+    let mut heartbeat = tokio::time::interval(options.heartbeat_interval);
+    heartbeat.set_missed_tick_behavior(MissedTickBehavior::Delay);
+    let mut last_seen = Instant::now();
+
+    loop {
+        tokio::select! {
+            command = commands.recv() => match command {
+                Some(Command::Packet(packet)) => {
+                    if let Some(refusal) = session.track(&packet) {
+                        if events.send(refusal).await.is_err() {
+                            return End::Abandoned;
+                        }
+                        continue;
+                    }
+                    if let Err(err) = write.send(Message::Binary(packet.serialize())).await {
+                        return End::Lost(err.to_string());
+                    }
+                }
+                Some(Command::Close) | None => {
+                    let _ = write.send(Message::Close(None)).await;
+                    // wait for the server to answer the close
+                    let _ = tokio::time::timeout(CLOSE_TIMEOUT, async {
+                        while let Some(Ok(_)) = read.next().await {}
+                    })
+                    .await;
+                    return End::Closed;
+                }
+            },
+            frame = read.next() => {
+                last_seen = Instant::now();
+                match frame {
+                    Some(Ok(Message::Binary(bytes))) => {
+                        let Some(packet) = decode(&bytes) else {
+                            continue;
+                        };
+                        let mut ready = Vec::new();
+                        if let Some(reply) = session.receive(packet, &mut ready) {
+                            if let Err(err) = write.send(Message::Binary(reply.serialize())).await {
+                                return End::Lost(err.to_string());
+                            }
+                        }
+                        for event in ready {
+                            if events.send(event).await.is_err() {
+                                return End::Abandoned;
+                            }
+                        }
+                    }
+                    Some(Ok(Message::Close(_))) => return End::Lost("closed by server".to_owned()),
+                    Some(Ok(_)) => {}
+                    Some(Err(err)) => return End::Lost(err.to_string()),
+                    None => return End::Lost("connection closed".to_owned()),
+                }
+            },
+            _ = heartbeat.tick() => {
+                if last_seen.elapsed() > options.heartbeat_timeout {
+                    return End::Lost("heartbeat timed out".to_owned());
+                }
+                if let Err(err) = write.send(Message::Ping(Vec::new())).await {
+                    return End::Lost(err.to_string());
+                }
+            },
+        }
+    }
+}
+
+fn decode(bytes: &[u8]) -> Option<ChatPacket> {
+    match ChatPacket::deserialize(bytes) {
+        Ok(packet) => Some(packet),
+        Err(err) => {
+            log::warn!("malformed packet from server: {}", err);
+            None
+        }
+    }
+}
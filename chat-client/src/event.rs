@@ -0,0 +1,93 @@
+use serde::Serialize;
+
+use proto::{ChatPacket, ChatPacketType};
+
+/// Something that happened on the connection, decoded from the server packets
+#[derive(Serialize, Clone, Debug, PartialEq)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum Event {
+    /// The connection is up, also sent after every reconnect
+    Connected,
+    /// The connection is down, `reconnecting` tells whether it will be retried
+    Disconnected {
+        reason: String,
+        reconnecting: bool,
+    },
+    /// Plain text notice such as a name change
+    Notice {
+        message: String,
+    },
+    /// Message of the day, sent once connected
+    Motd {
+        message: String,
+    },
+    Chat(proto::ChatMessage),
+    /// The server accepted a chat sent by this client
+    Ack(proto::Ack),
+    Edited(proto::Edited),
+    Deleted(proto::Deleted),
+    Reacted(proto::Reacted),
+    /// A reaction was taken back
+    Unreacted(proto::Reacted),
+    /// A member started or stopped composing a chat
+    Typing(proto::TypingStatus),
+    /// How far another session of the same name read a room, or the stored
+    /// position after a login or join
+    Read(proto::MarkRead),
+    Direct(proto::DirectMessage),
+    /// Answer to a `whois`
+    WhoIs(proto::UserInfo),
+    /// Details of a joined room, after joining, asking or a change
+    RoomInfo(proto::RoomInfo),
+    /// Invite code issued on request
+    Invite(proto::InviteCode),
+    /// An invite was accepted, the room's roster follows
+    InviteAccepted(proto::AcceptInvite),
+    /// Answer to `list_rooms`
+    Rooms(proto::RoomList),
+    /// A room created with a TTL closed, we are no longer in it
+    RoomClosed(proto::RoomClosed),
+    /// Chats that outlived the retention of their room, gone from the history
+    Expired(proto::Expired),
+    Roster(proto::Roster),
+    Presence(proto::Presence),
+    Error(proto::ErrorInfo),
+    /// The server dropped messages because this client read too slowly
+    Missed(proto::Missed),
+}
+
+impl Event {
+    /// Decode a server packet, `None` for packets clients don't handle
+    pub fn from_packet(packet: ChatPacket) -> Option<Event> {
+        let event = match packet.packet_type {
+            ChatPacketType::Login | ChatPacketType::Close => Event::Notice {
+                message: packet.packet_message,
+            },
+            ChatPacketType::Motd => Event::Motd {
+                message: packet.packet_message,
+            },
+            ChatPacketType::Chat => Event::Chat(packet.payload().ok()?),
+            ChatPacketType::Ack => Event::Ack(packet.payload().ok()?),
+            ChatPacketType::Edit => Event::Edited(packet.payload().ok()?),
+            ChatPacketType::Delete => Event::Deleted(packet.payload().ok()?),
+            ChatPacketType::React => Event::Reacted(packet.payload().ok()?),
+            ChatPacketType::Unreact => Event::Unreacted(packet.payload().ok()?),
+            ChatPacketType::Typing => Event::Typing(packet.payload().ok()?),
+            ChatPacketType::MarkRead => Event::Read(packet.payload().ok()?),
+            ChatPacketType::Direct => Event::Direct(packet.payload().ok()?),
+            ChatPacketType::WhoIs => Event::WhoIs(packet.payload().ok()?),
+            ChatPacketType::RoomInfo => Event::RoomInfo(packet.payload().ok()?),
+            ChatPacketType::Invite => Event::Invite(packet.payload().ok()?),
+            ChatPacketType::AcceptInvite => Event::InviteAccepted(packet.payload().ok()?),
+            ChatPacketType::ListRooms => Event::Rooms(packet.payload().ok()?),
+            ChatPacketType::RoomClosed => Event::RoomClosed(packet.payload().ok()?),
+            ChatPacketType::Expired => Event::Expired(packet.payload().ok()?),
+            ChatPacketType::Roster => Event::Roster(packet.payload().ok()?),
+            ChatPacketType::Presence => Event::Presence(packet.payload().ok()?),
+            ChatPacketType::Error => Event::Error(packet.payload().ok()?),
+            ChatPacketType::Missed => Event::Missed(packet.payload().ok()?),
+            _ => return None,
+        };
+        Some(event)
+    }
+}
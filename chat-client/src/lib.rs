@@ -0,0 +1,112 @@
+//! Async client for the rust-chat websocket server.
+//!
+//! ```no_run
+//! # async fn demo() -> Result<(), chat_client::Error> {
+//! use chat_client::{ChatClient, ClientOptions, Event};
+//!
+//! let (client, mut events) = ChatClient::connect(ClientOptions::new("ws://localhost:3000")).await?;
+//! client.login("bot").await?;
+//! client.join("ops").await?;
+//! client.send_chat("ops", "deploy finished").await?;
+//!
+//! while let Some(event) = events.next_event().await {
+//!     if let Event::Chat(chat) = event {
+//!         println!("{}: {}", chat.from, chat.body);
+//!     }
+//! }
+//! # Ok(())
+//! # }
+//! ```
+
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::time::Duration;
+
+mod client;
+mod connection;
+mod event;
+mod sequence;
+mod tls;
+
+pub use client::{ChatClient, Events};
+pub use event::Event;
+pub use tls::TlsOptions;
+
+#[derive(thiserror::Error, Debug)]
+pub enum Error {
+    #[error("invalid server url: {0}")]
+    Url(#[from] url::ParseError),
+    #[error("invalid auth token")]
+    Token,
+    #[error("invalid client id")]
+    ClientId,
+    #[error("tls: {0}")]
+    Tls(String),
+    #[error("server refused the auth token")]
+    Unauthorized,
+    #[error("{0}")]
+    Connect(Box<tokio_tungstenite::tungstenite::Error>),
+    #[error("not connected")]
+    NotConnected,
+}
+
+/// When and how often a lost connection is retried
+#[derive(Clone, Debug)]
+pub struct Reconnect {
+    /// Delay before the first retry, doubled after each failed attempt
+    pub initial_delay: Duration,
+    pub max_delay: Duration,
+    /// Give up after this many failed attempts in a row, `None` retries forever
+    pub max_attempts: Option<u32>,
+}
+
+impl Default for Reconnect {
+    fn default() -> Reconnect {
+        Reconnect {
+            initial_delay: Duration::from_millis(500),
+            max_delay: Duration::from_secs(30),
+            max_attempts: None,
+        }
+    }
+}
+
+#[derive(Clone, Debug)]
+pub struct ClientOptions {
+    pub url: String,
+    /// Sent as `Authorization: Bearer <token>` with the handshake
+    pub token: Option<String>,
+    /// Sent with every handshake, so after a reconnect the server hands the
+    /// name of the lost connection back. Random unless set, clients connected
+    /// at the same time need different ids.
+    pub client_id: String,
+    pub tls: Option<TlsOptions>,
+    /// `None` ends the event stream when the connection is lost
+    pub reconnect: Option<Reconnect>,
+    /// How often pings are sent
+    pub heartbeat_interval: Duration,
+    /// How long the server may stay silent before the connection counts as lost
+    pub heartbeat_timeout: Duration,
+    /// Capacity of the command and event queues
+    pub queue_size: usize,
+}
+
+impl ClientOptions {
+    pub fn new(url: &str) -> ClientOptions {
+        ClientOptions {
+            url: url.to_owned(),
+            token: None,
+            client_id: new_client_id(),
+            tls: None,
+            reconnect: Some(Reconnect::default()),
+            heartbeat_interval: Duration::from_secs(2),
+            heartbeat_timeout: Duration::from_secs(10),
+            queue_size: 256,
+        }
+    }
+}
+
+/// 128 random bits in hex
+fn new_client_id() -> String {
+    let half = || RandomState::new().build_hasher().finish();
+    format!("{:016x}{:016x}", half(), half())
+}
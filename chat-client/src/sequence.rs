@@ -0,0 +1,341 @@
+//! Per-room sequence numbers: broadcasts are passed on in order, gaps are
+//! filled through `Resync` and the duplicates of overlapping replays dropped
+
+use std::collections::{BTreeMap, HashMap, HashSet};
+
+use proto::{ChatPacket, ChatPacketType};
+
+use crate::Event;
+
+/// Broadcasts held back behind a gap before giving up on filling it
+const MAX_HELD: usize = 1024;
+
+#[derive(Default)]
+struct RoomSequence {
+    /// Last broadcast passed on
+    last: u64,
+    /// Broadcasts after a gap by sequence number
+    held: BTreeMap<u64, Event>,
+    /// A `Resync` was sent and not answered yet
+    resyncing: bool,
+    /// The gap of that `Resync` was given up on, its answer is ignored
+    outdated: bool,
+    /// Last broadcast of the replay on its way, the gaps wait for it
+    replaying: Option<u64>,
+}
+
+impl RoomSequence {
+    fn starting_at(last: u64) -> RoomSequence {
+        RoomSequence {
+            last,
+            ..RoomSequence::default()
+        }
+    }
+
+    /// Pass on the held broadcasts that follow `last`
+    fn release(&mut self, out: &mut Vec<Event>) {
+        while let Some(entry) = self.held.first_entry() {
+            let seq = *entry.key();
+            if seq > self.last + 1 {
+                break;
+            }
+            let event = entry.remove();
+            if seq == self.last + 1 {
+                self.last = seq;
+                out.push(event);
+            }
+        }
+    }
+
+    /// A `Resync` for the gap left, unless one is already on its way
+    fn fill_gap(&mut self, room: &str) -> Option<ChatPacket> {
+        if self.held.is_empty() || self.resyncing || self.replaying.is_some() {
+            return None;
+        }
+        self.resyncing = true;
+        Some(resync(room, self.last + 1))
+    }
+}
+
+#[derive(Default)]
+pub(crate) struct Sequencer {
+    rooms: HashMap<String, RoomSequence>,
+    /// Rooms whose roster was seen since the connection was made or the room
+    /// joined, later ones are refreshes
+    settled: HashSet<String>,
+}
+
+impl Sequencer {
+    /// The rosters of the (re)joined rooms tell where they stand, requests
+    /// sent on the lost connection won't be answered
+    pub fn connected(&mut self) {
+        self.settled.clear();
+        for room in self.rooms.values_mut() {
+            room.resyncing = false;
+            room.outdated = false;
+            room.replaying = None;
+        }
+    }
+
+    /// A join was sent, the roster answering it tells where the room stands
+    pub fn joining(&mut self, room: &str) {
+        self.settled.remove(&room_name(room));
+    }
+
+    pub fn left(&mut self, room: &str) {
+        let room = room_name(room);
+        self.settled.remove(&room);
+        self.rooms.remove(&room);
+    }
+
+    /// Add the events ready for the application to `out`, returns a `Resync`
+    /// to send when the event showed a gap
+    pub fn receive(&mut self, event: Event, out: &mut Vec<Event>) -> Option<ChatPacket> {
+        let (room, seq) = match &event {
+            Event::Chat(chat) => (chat.room.clone(), chat.seq),
+            Event::Presence(presence) => (presence.room.clone(), presence.seq),
+            Event::Edited(edited) => (edited.room.clone(), edited.seq),
+            Event::Deleted(deleted) => (deleted.room.clone(), deleted.seq),
+            Event::Reacted(reacted) | Event::Unreacted(reacted) => {
+                (reacted.room.clone(), reacted.seq)
+            }
+            Event::RoomInfo(info) => (info.room.clone(), info.seq),
+            Event::Expired(expired) => (expired.room.clone(), expired.seq),
+            Event::Roster(roster) => {
+                let (room, seq) = (roster.room.clone(), roster.seq);
+                out.push(event);
+                return self.roster(room, seq);
+            }
+            _ => {
+                out.push(event);
+                return None;
+            }
+        };
+        // the server doesn't number its broadcasts
+        if seq == 0 {
+            out.push(event);
+            return None;
+        }
+
+        let Some(state) = self.rooms.get_mut(&room) else {
+            self.rooms.insert(room, RoomSequence::starting_at(seq));
+            out.push(event);
+            return None;
+        };
+        if state.replaying.is_some_and(|until| seq >= until) {
+            // the rest of the replay was dropped on the way, if any
+            state.replaying = None;
+        }
+        if seq <= state.last {
+            // the end of an overlapping replay still leaves the gap after it
+            return state.fill_gap(&room);
+        }
+        if seq == state.last + 1 {
+            state.last = seq;
+            out.push(event);
+            state.release(out);
+            return state.fill_gap(&room);
+        }
+
+        state.held.insert(seq, event);
+        if state.held.len() > MAX_HELD {
+            let first = state.held.keys().next().copied().unwrap_or(seq);
+            skip_to(state, first, out);
+            state.outdated = state.resyncing;
+            state.release(out);
+            return None;
+        }
+        state.fill_gap(&room)
+    }
+
+    /// Answer to a `Resync`, the replayed broadcasts follow it
+    pub fn resynced(&mut self, resync: proto::Resync, out: &mut Vec<Event>) {
+        let Some(state) = self.rooms.get_mut(&resync.room) else {
+            return;
+        };
+        state.resyncing = false;
+        if std::mem::take(&mut state.outdated) {
+            return;
+        }
+        if resync.from_seq <= state.last {
+            // the room started over on the server
+            *state = RoomSequence::starting_at(resync.from_seq.saturating_sub(1));
+        } else if resync.from_seq > state.last + 1 {
+            skip_to(state, resync.from_seq, out);
+        }
+        // a replay cut short is continued once it arrived
+        state.replaying = resync.until_seq.filter(|until| *until > state.last);
+        state.release(out);
+    }
+
+    fn roster(&mut self, room: String, seq: u64) -> Option<ChatPacket> {
+        // broadcasts may still be on their way when a refresh is answered
+        if !self.settled.insert(room.clone()) || seq == 0 {
+            return None;
+        }
+        let Some(state) = self.rooms.get_mut(&room) else {
+            self.rooms.insert(room, RoomSequence::starting_at(seq));
+            return None;
+        };
+        if seq < state.last {
+            *state = RoomSequence::starting_at(seq);
+        } else if seq > state.last && !state.resyncing {
+            // rejoined after a reconnect, fetch what happened meanwhile
+            state.resyncing = true;
+            return Some(resync(&room, state.last + 1));
+        }
+        None
+    }
+}
+
+/// Give up on the broadcasts before `seq`, the application is told how many
+fn skip_to(state: &mut RoomSequence, seq: u64, out: &mut Vec<Event>) {
+    let count = seq - state.last - 1;
+    out.push(Event::Missed(proto::Missed { count }));
+    state.last = seq - 1;
+}
+
+fn resync(room: &str, from_seq: u64) -> ChatPacket {
+    let resync = proto::Resync {
+        room: room.to_owned(),
+        from_seq,
+        until_seq: None,
+    };
+    ChatPacket::with_payload(ChatPacketType::Resync, &resync)
+}
+
+/// Room name the way the server normalizes it
+fn room_name(room: &str) -> String {
+    room.trim().trim_start_matches('#').to_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn chat(seq: u64) -> Event {
+        Event::Chat(proto::ChatMessage {
+            room: "main".to_owned(),
+            from: "alice".to_owned(),
+            body: format!("#{}", seq),
+            time: String::new(),
+            id: seq,
+            nonce: None,
+            seq,
+            edited: false,
+            deleted: false,
+            reply_to: None,
+            reactions: Vec::new(),
+            mentions: Vec::new(),
+        })
+    }
+
+    /// Sequence numbers of the chats passed on, 0 for missed ones
+    fn passed(out: &[Event]) -> Vec<u64> {
+        out.iter()
+            .map(|event| match event {
+                Event::Chat(chat) => chat.seq,
+                Event::Missed(_) => 0,
+                other => panic!("unexpected event {:?}", other),
+            })
+            .collect()
+    }
+
+    fn resync_from(packet: Option<ChatPacket>) -> u64 {
+        let packet = packet.expect("a resync");
+        assert_eq!(packet.packet_type, ChatPacketType::Resync);
+        packet.payload::<proto::Resync>().unwrap().from_seq
+    }
+
+    fn answer(from_seq: u64, until_seq: u64) -> proto::Resync {
+        proto::Resync {
+            room: "main".to_owned(),
+            from_seq,
+            until_seq: Some(until_seq),
+        }
+    }
+
+    #[test]
+    fn reorders_and_drops_duplicates() {
+        let mut sequencer = Sequencer::default();
+        let mut out = Vec::new();
+        assert!(sequencer.receive(chat(1), &mut out).is_none());
+        assert_eq!(resync_from(sequencer.receive(chat(3), &mut out)), 2);
+        assert!(sequencer.receive(chat(2), &mut out).is_none());
+        assert!(sequencer.receive(chat(2), &mut out).is_none());
+        assert!(sequencer.receive(chat(3), &mut out).is_none());
+        assert!(sequencer.receive(chat(4), &mut out).is_none());
+        assert_eq!(passed(&out), [1, 2, 3, 4]);
+    }
+
+    #[test]
+    fn fills_a_gap_from_the_replay() {
+        let mut sequencer = Sequencer::default();
+        let mut out = Vec::new();
+        sequencer.receive(chat(1), &mut out);
+        assert_eq!(resync_from(sequencer.receive(chat(4), &mut out)), 2);
+        // no second request while the first is on its way
+        assert!(sequencer.receive(chat(5), &mut out).is_none());
+
+        sequencer.resynced(answer(2, 5), &mut out);
+        for seq in 2..=5 {
+            assert!(sequencer.receive(chat(seq), &mut out).is_none());
+        }
+        assert_eq!(passed(&out), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn asks_again_after_a_short_replay() {
+        let mut sequencer = Sequencer::default();
+        let mut out = Vec::new();
+        sequencer.receive(chat(1), &mut out);
+        sequencer.receive(chat(5), &mut out);
+
+        // the server had room for one broadcast only
+        sequencer.resynced(answer(2, 2), &mut out);
+        assert_eq!(resync_from(sequencer.receive(chat(2), &mut out)), 3);
+        sequencer.resynced(answer(3, 4), &mut out);
+        sequencer.receive(chat(3), &mut out);
+        sequencer.receive(chat(4), &mut out);
+        assert_eq!(passed(&out), [1, 2, 3, 4, 5]);
+    }
+
+    #[test]
+    fn skips_what_the_history_lost() {
+        let mut sequencer = Sequencer::default();
+        let mut out = Vec::new();
+        sequencer.receive(chat(1), &mut out);
+        sequencer.receive(chat(4), &mut out);
+
+        // 2 is gone, the replay starts at 3
+        sequencer.resynced(answer(3, 4), &mut out);
+        sequencer.receive(chat(3), &mut out);
+        assert_eq!(passed(&out), [1, 0, 3, 4]);
+        assert_eq!(out[1], Event::Missed(proto::Missed { count: 1 }));
+    }
+
+    #[test]
+    fn gives_up_on_a_gap_holding_too_much() {
+        let mut sequencer = Sequencer::default();
+        let mut out = Vec::new();
+        sequencer.receive(chat(1), &mut out);
+        let last = 3 + MAX_HELD as u64;
+        for seq in 3..last {
+            sequencer.receive(chat(seq), &mut out);
+        }
+        assert_eq!(passed(&out), [1]);
+
+        assert!(sequencer.receive(chat(last), &mut out).is_none());
+        assert_eq!(out[1], Event::Missed(proto::Missed { count: 1 }));
+        assert_eq!(passed(&out[2..]), (3..=last).collect::<Vec<_>>());
+
+        // the resync still on its way changes nothing
+        sequencer.resynced(answer(2, 2), &mut out);
+        assert!(sequencer.receive(chat(2), &mut out).is_none());
+        assert_eq!(out.len(), 3 + MAX_HELD);
+        assert_eq!(
+            resync_from(sequencer.receive(chat(last + 2), &mut out)),
+            last + 1
+        );
+    }
+}
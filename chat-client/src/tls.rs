@@ -0,0 +1,65 @@
+use std::{path::PathBuf, sync::Arc, time::SystemTime};
+
+use serde::Deserialize;
+use tokio_tungstenite::Connector;
+
+use crate::Error;
+
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct TlsOptions {
+    /// Extra PEM root certificates, for servers behind a private CA
+    pub ca_file: Option<PathBuf>,
+    /// Accept any server certificate, only for testing
+    pub insecure: bool,
+}
+
+/// rustls connector with the native roots plus the configured extras
+pub(crate) fn connector(tls: &TlsOptions) -> Result<Connector, Error> {
+    let mut roots = rustls::RootCertStore::empty();
+    for cert in rustls_native_certs::load_native_certs().unwrap_or_default() {
+        // platform stores may contain certificates rustls can't parse
+        let _ = roots.add(&rustls::Certificate(cert.0));
+    }
+    if let Some(path) = &tls.ca_file {
+        let tls_error = |err: &dyn std::fmt::Display| {
+            Error::Tls(format!("ca file {}: {}", path.display(), err))
+        };
+        let file = std::fs::File::open(path).map_err(|err| tls_error(&err))?;
+        let certs = rustls_pemfile::certs(&mut std::io::BufReader::new(file))
+            .map_err(|err| tls_error(&err))?;
+        for cert in certs {
+            roots
+                .add(&rustls::Certificate(cert))
+                .map_err(|err| tls_error(&err))?;
+        }
+    }
+
+    let mut config = rustls::ClientConfig::builder()
+        .with_safe_defaults()
+        .with_root_certificates(roots)
+        .with_no_client_auth();
+    if tls.insecure {
+        config
+            .dangerous()
+            .set_certificate_verifier(Arc::new(AcceptAnyCert));
+    }
+    Ok(Connector::Rustls(Arc::new(config)))
+}
+
+/// Certificate verifier for `insecure = true`
+struct AcceptAnyCert;
+
+impl rustls::client::ServerCertVerifier for AcceptAnyCert {
+    fn verify_server_cert(
+        &self,
+        _end_entity: &rustls::Certificate,
+        _intermediates: &[rustls::Certificate],
+        _server_name: &rustls::ServerName,
+        _scts: &mut dyn Iterator<Item = &[u8]>,
+        _ocsp_response: &[u8],
+        _now: SystemTime,
+    ) -> Result<rustls::client::ServerCertVerified, rustls::Error> {
+        Ok(rustls::client::ServerCertVerified::assertion())
+    }
+}
@@ -1,13 +1,84 @@
-use std::{io, time::Duration};
+use std::{
+    collections::HashMap,
+    io::{self, Write},
+    time::{Duration, Instant},
+};
 
-use super::client::WsClient;
-use crossterm::event::{self, poll, Event, KeyCode, KeyEventKind};
+use super::config::{ConfigFile, Profile};
+use super::emoji;
+use super::room::{
+    describe_invite, describe_room, describe_rooms, describe_user, Room, STATUS_ROOM,
+};
+use super::scrollback::{Scrollback, WHEEL_STEP};
+use chat_client::{ChatClient, ClientOptions, Event as ChatEvent, Events};
+use crossterm::event::{
+    self, poll, Event, KeyCode, KeyEvent, KeyEventKind, KeyModifiers, MouseEventKind,
+};
 use ratatui::{prelude::*, widgets::*};
-use tokio_tungstenite::tungstenite::Message;
+use unicode_width::UnicodeWidthStr;
+
+/// How often the member list of the active room is refreshed
+const ROSTER_REFRESH: Duration = Duration::from_secs(30);
+
+/// How often the room is told again that we are still typing
+const TYPING_DEBOUNCE: Duration = Duration::from_secs(3);
+
+/// Mentions kept for the `/mentions` view
+const MENTIONS_KEPT: usize = 1000;
 
 pub enum InputMode {
     Normal,
     Editing,
+    /// Picking a chat in the scrollback to answer, react to or open its thread
+    Selecting,
+}
+
+/// State of the server connection, shown in the tip row
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ConnectionStatus {
+    Offline,
+    Connected,
+    Reconnecting,
+}
+
+/// Chat sent from this client that the server has not echoed back yet
+struct Outgoing {
+    room: String,
+    /// Index of its line in the scrollback of `room`
+    line: usize,
+    from: String,
+    body: String,
+    reply_to: Option<u64>,
+    /// The server accepted it, only the echo is outstanding
+    acked: bool,
+}
+
+impl Outgoing {
+    fn sending(&self) -> String {
+        format!("{}: {} (sending…)", self.from, self.body)
+    }
+
+    /// The chat as the server stored it
+    fn message(&self, ack: &proto::Ack) -> proto::ChatMessage {
+        proto::ChatMessage {
+            room: self.room.clone(),
+            from: self.from.clone(),
+            body: self.body.clone(),
+            time: ack.time.clone(),
+            id: ack.message_id,
+            nonce: Some(ack.nonce.clone()),
+            seq: 0,
+            edited: false,
+            deleted: false,
+            reply_to: self.reply_to,
+            reactions: Vec::new(),
+            mentions: proto::parse_mentions(&self.body),
+        }
+    }
+
+    fn failed(&self, reason: &str) -> String {
+        format!("{}: {} (not sent: {})", self.from, self.body, reason)
+    }
 }
 
 /// App holds the state of the application
@@ -18,21 +89,60 @@ pub struct App {
     cursor_position: usize,
     /// Current input mode
     input_mode: InputMode,
-    /// History of recorded messages
-    messages: Vec<String>,
-    message_index: usize,
-    client: Option<WsClient>,
+    /// Status buffer followed by the joined rooms, in tab order
+    rooms: Vec<Room>,
+    /// Index of the visible tab
+    active: usize,
+    /// Name sent with the last login
+    nickname: Option<String>,
+    /// Last time the member list of the active room was requested
+    roster_requested: Instant,
+    client: Option<ChatClient>,
+    /// Events of `client`
+    events: Option<Events>,
+    connection: ConnectionStatus,
+    /// Server of the current or last connection
+    server_url: Option<String>,
+    /// Theme, key bindings and the other server profiles
+    config: ConfigFile,
+    /// Settings of the server we connect to
+    profile: Profile,
+    /// Connect to `profile.url` when the app starts
+    auto_connect: bool,
+    /// Own chats by nonce
+    outgoing: HashMap<String, Outgoing>,
+    /// Room last told that we are typing, and when
+    typing_sent: Option<(String, Instant)>,
+    /// Chats of every room that mention us, oldest first
+    mentions: Vec<String>,
+    /// The `/mentions` view replaces the scrollback
+    show_mentions: bool,
+    /// Notifications for the terminal, written after the next draw
+    alerts: Vec<String>,
 }
 
-impl Default for App {
-    fn default() -> App {
+impl App {
+    pub fn new(config: ConfigFile, profile: Profile, auto_connect: bool) -> App {
         App {
             input: String::new(),
             input_mode: InputMode::Normal,
-            messages: Vec::new(),
-            message_index: 0,
+            rooms: vec![Room::new(STATUS_ROOM)],
+            active: 0,
+            nickname: None,
+            roster_requested: Instant::now(),
             cursor_position: 0,
             client: None,
+            events: None,
+            connection: ConnectionStatus::Offline,
+            server_url: None,
+            config,
+            profile,
+            auto_connect,
+            outgoing: HashMap::new(),
+            typing_sent: None,
+            mentions: Vec::new(),
+            show_mentions: false,
+            alerts: Vec::new(),
         }
     }
 }
@@ -49,7 +159,13 @@ impl App {
     }
 
     fn enter_char(&mut self, new_char: char) {
-        self.input.insert(self.cursor_position, new_char);
+        // the cursor counts chars, `insert` wants a byte index
+        let index = self
+            .input
+            .char_indices()
+            .nth(self.cursor_position)
+            .map_or(self.input.len(), |(index, _)| index);
+        self.input.insert(index, new_char);
 
         self.move_cursor_right();
     }
@@ -77,55 +193,665 @@ impl App {
     }
 
     fn clamp_cursor(&self, new_cursor_pos: usize) -> usize {
-        new_cursor_pos.clamp(0, self.input.len())
+        new_cursor_pos.clamp(0, self.input.chars().count())
     }
 
     fn reset_cursor(&mut self) {
         self.cursor_position = 0;
     }
 
-    async fn recv_messages(&mut self) {
+    fn active_room(&mut self) -> &mut Room {
+        &mut self.rooms[self.active]
+    }
+
+    /// Tab of the room, opened in the background if it is not there yet
+    fn room(&mut self, name: &str) -> &mut Room {
+        let index = match self.rooms.iter().position(|r| r.name == name) {
+            Some(index) => index,
+            None => {
+                self.rooms.push(Room::new(name));
+                self.rooms.len() - 1
+            }
+        };
+        &mut self.rooms[index]
+    }
+
+    /// Show a local notice in the active tab
+    fn notice(&mut self, message: String) {
+        self.active_room().scrollback.push(message);
+    }
+
+    fn switch_room(&mut self, index: usize) {
+        if index >= self.rooms.len() || index == self.active {
+            return;
+        }
+        if let InputMode::Selecting = self.input_mode {
+            self.input_mode = InputMode::Normal;
+        }
+        let room = self.active_room();
+        room.select(None);
+        room.scrollback.set_divider(None);
+        self.show_mentions = false;
+        self.active = index;
+        self.active_room().mark_read();
+        // refresh the member list on the next tick
+        self.roster_requested = Instant::now() - ROSTER_REFRESH;
+    }
+
+    fn next_room(&mut self) {
+        self.switch_room((self.active + 1) % self.rooms.len());
+    }
+
+    fn prev_room(&mut self) {
+        self.switch_room((self.active + self.rooms.len() - 1) % self.rooms.len());
+    }
+
+    /// The client if connected, otherwise tell the user
+    fn connected_client(&mut self) -> Option<ChatClient> {
         if self.client.is_none() {
+            self.notice("Not connected".into());
+        }
+        self.client.clone()
+    }
+
+    /// Chat the input answers: the picked one or the first of the open thread
+    fn reply_target(&self) -> Option<u64> {
+        let room = &self.rooms[self.active];
+        room.reply_to.or(room.thread)
+    }
+
+    /// Pick the latest chat of the active room, the scrollback replaces an
+    /// open thread
+    fn start_selecting(&mut self) {
+        self.show_mentions = false;
+        let room = self.active_room();
+        room.thread = None;
+        if room.chats.is_empty() {
+            self.notice("No messages to reply to here".into());
             return;
         }
+        room.select_last();
+        self.input_mode = InputMode::Selecting;
+    }
 
-        let mut client = self.client.take().unwrap();
-        let mut msg = client.recv();
+    /// Keys while picking a chat
+    fn handle_select_key(&mut self, key: &KeyEvent) {
+        let room = &mut self.rooms[self.active];
+        match key.code {
+            KeyCode::Up | KeyCode::Char('k') => room.select_step(true),
+            KeyCode::Down | KeyCode::Char('j') => room.select_step(false),
+            KeyCode::Enter | KeyCode::Char('r') => {
+                room.reply_to = room.selected;
+                room.select(None);
+                self.input_mode = InputMode::Editing;
+            }
+            KeyCode::Char(':') => {
+                room.react_to = room.selected;
+                room.select(None);
+                self.input = ":".to_owned();
+                self.cursor_position = 1;
+                self.input_mode = InputMode::Editing;
+            }
+            KeyCode::Char('t') => {
+                room.thread = room.selected.map(|id| room.thread_root(id));
+                room.select(None);
+                self.input_mode = InputMode::Normal;
+            }
+            KeyCode::Esc => {
+                room.select(None);
+                self.input_mode = InputMode::Normal;
+            }
+            _ => {
+                self.handle_scroll_key(key);
+            }
+        }
+    }
 
-        while msg.is_ok() {
-            let message = msg.unwrap();
-            match message {
-                Message::Binary(bytes) => {
-                    let packet = proto::ChatPacket::deserialize(bytes);
+    /// Toggle our reaction to a chat of the active room, typed as `:shortcode:`
+    /// or as the emoji
+    async fn react(&mut self, message_id: u64, input: &str) {
+        let Some(emoji) = emoji::reaction(input) else {
+            self.notice(format!("Unknown reaction: {}", input.trim()));
+            return;
+        };
+        let nickname = self.nickname.clone().unwrap_or_default();
+        let reacted = self.rooms[self.active]
+            .chat(message_id)
+            .is_some_and(|chat| chat.has_reacted(&emoji, &nickname));
+        if let Some(client) = self.connected_client() {
+            let result = if reacted {
+                client.unreact(message_id, &emoji).await
+            } else {
+                client.react(message_id, &emoji).await
+            };
+            self.check_sent(result);
+        }
+    }
 
-                    match packet.packet_type {
-                        proto::ChatPacketType::Login => {
-                            self.messages.push(packet.packet_message);
-                        }
-                        proto::ChatPacketType::Close => {
-                            self.messages
-                                .push(format!("{} has left the chat", packet.packet_message));
-                        }
-                        proto::ChatPacketType::Chat => {
-                            self.messages.push(format!("{}", packet.packet_message));
-                        }
-                        _ => {}
+    async fn set_status(&mut self, status: proto::Status) {
+        if let Some(client) = self.connected_client() {
+            let result = client.set_status(&status).await;
+            self.check_sent(result);
+        }
+    }
+
+    /// Change a setting of the active room with
+    /// `/set max-members|slow-mode|read-only|announcement|retention|visibility|password <value>`
+    async fn change_room(&mut self, setting: &str) {
+        let room = &self.rooms[self.active];
+        let Some(mut settings) = room.info.as_ref().map(|i| i.settings.clone()) else {
+            self.notice("No room details here".into());
+            return;
+        };
+        let name = room.name.clone();
+        if let Some(password) = setting.strip_prefix("password ").map(str::trim) {
+            if let Some(client) = self.connected_client() {
+                let result = client.set_room_password(&name, &settings, password).await;
+                self.check_sent(result);
+            }
+            return;
+        }
+        let changed = match setting.split_once(' ').map(|(k, v)| (k, v.trim())) {
+            Some(("max-members", value)) => value.parse().map(|n| settings.max_members = n).is_ok(),
+            Some(("slow-mode", value)) => {
+                value.parse().map(|s| settings.slow_mode_secs = s).is_ok()
+            }
+            Some(("read-only", value @ ("on" | "off"))) => {
+                settings.read_only = value == "on";
+                true
+            }
+            Some(("announcement", value @ ("on" | "off"))) => {
+                settings.announcement = value == "on";
+                true
+            }
+            // minutes, 0 keeps chats
+            Some(("retention", value)) => value
+                .parse::<u64>()
+                .map(|m| settings.retention_secs = m * 60)
+                .is_ok(),
+            Some(("visibility", value)) => {
+                let visibility = match value {
+                    "public" => Some(proto::Visibility::Public),
+                    "unlisted" => Some(proto::Visibility::Unlisted),
+                    "invite-only" => Some(proto::Visibility::InviteOnly),
+                    _ => None,
+                };
+                visibility.map(|v| settings.visibility = v).is_some()
+            }
+            _ => false,
+        };
+        if !changed {
+            self.notice(
+                "Usage: /set max-members <n>|slow-mode <secs>|read-only on|off|announcement on|off|retention <minutes>|visibility public|unlisted|invite-only|password <password>"
+                    .into(),
+            );
+            return;
+        }
+        if let Some(client) = self.connected_client() {
+            let result = client.change_room(&name, &settings).await;
+            self.check_sent(result);
+        }
+    }
+
+    /// Complete the shortcode of a reaction being typed
+    fn complete_reaction(&mut self) {
+        if let Some((code, _)) = emoji::completions(&self.input).first() {
+            self.input = format!(":{}:", code);
+            self.cursor_position = self.input.chars().count();
+        }
+    }
+
+    /// Report a failed send in the active tab
+    fn check_sent(&mut self, result: Result<(), chat_client::Error>) {
+        if let Err(err) = result {
+            self.notice(format!("Send failed: {}", err));
+        }
+    }
+
+    /// Show an own chat in its room, pending until the server answers
+    fn track_chat(
+        &mut self,
+        room: String,
+        body: String,
+        reply_to: Option<u64>,
+        sent: Result<String, chat_client::Error>,
+    ) {
+        let mut outgoing = Outgoing {
+            from: self.nickname.clone().unwrap_or_else(|| "me".to_owned()),
+            line: 0,
+            room,
+            body,
+            reply_to,
+            acked: false,
+        };
+        let text = match &sent {
+            Ok(_) => outgoing.sending(),
+            Err(err) => outgoing.failed(&err.to_string()),
+        };
+        outgoing.line = self.room(&outgoing.room).scrollback.push(text);
+        if let Ok(nonce) = sent {
+            self.outgoing.insert(nonce, outgoing);
+        }
+    }
+
+    /// Rewrite the line of an own chat
+    fn update_outgoing(&mut self, outgoing: &Outgoing, text: String) {
+        if let Some(room) = self.rooms.iter_mut().find(|r| r.name == outgoing.room) {
+            room.scrollback.replace(outgoing.line, text);
+        }
+    }
+
+    /// Show an own chat the server accepted, it can be edited from now on
+    fn outgoing_sent(&mut self, outgoing: &Outgoing, chat: proto::ChatMessage) {
+        if let Some(room) = self.rooms.iter_mut().find(|r| r.name == outgoing.room) {
+            room.scrollback.replace(outgoing.line, room.line(&chat));
+            room.record_chat(outgoing.line, chat);
+        }
+    }
+
+    /// Apply an edit or deletion to the room showing the message
+    fn amend_chat(
+        &mut self,
+        room: &str,
+        message_id: u64,
+        change: impl FnOnce(&mut proto::ChatMessage),
+    ) {
+        if let Some(room) = self.rooms.iter_mut().find(|r| r.name == room) {
+            room.amend_chat(message_id, change);
+        }
+    }
+
+    /// Chats that were not accepted won't be retried once the client gave up
+    fn fail_outgoing(&mut self, reason: &str) {
+        for (_, outgoing) in std::mem::take(&mut self.outgoing) {
+            if !outgoing.acked {
+                self.update_outgoing(&outgoing, outgoing.failed(reason));
+            }
+        }
+    }
+
+    /// Tell the active room whether we are composing a chat, at most every
+    /// `TYPING_DEBOUNCE` while it lasts
+    async fn update_typing(&mut self) {
+        let Some(client) = self.client.clone() else {
+            self.typing_sent = None;
+            return;
+        };
+        let room = &self.rooms[self.active];
+        let typing = matches!(self.input_mode, InputMode::Editing)
+            && !self.input.is_empty()
+            && !self.input.starts_with('/')
+            && room.react_to.is_none()
+            && !(room.is_status() || room.is_direct());
+        let room = room.name.clone();
+
+        if let Some((sent_to, at)) = &self.typing_sent {
+            if typing && *sent_to == room && at.elapsed() < TYPING_DEBOUNCE {
+                return;
+            }
+            if !typing || *sent_to != room {
+                let result = client.typing(sent_to, proto::TypingState::Stopped).await;
+                self.typing_sent = None;
+                self.check_sent(result);
+            }
+        }
+        if typing {
+            let result = client.typing(&room, proto::TypingState::Active).await;
+            self.typing_sent = Some((room, Instant::now()));
+            self.check_sent(result);
+        }
+    }
+
+    /// Tell the other sessions of our name how far the active room was read,
+    /// once its newest chat is on screen
+    async fn sync_read(&mut self) {
+        let Some(client) = self.client.clone() else {
+            return;
+        };
+        let room = &self.rooms[self.active];
+        if self.nickname.is_none()
+            || room.catching_up
+            || !room.scrollback.is_following()
+            || room.is_status()
+            || room.is_direct()
+        {
+            return;
+        }
+        let Some(last) = room.last_chat().map(|chat| chat.id) else {
+            return;
+        };
+        if room.read_up_to == Some(last) {
+            return;
+        }
+        let room = self.active_room();
+        room.read_up_to = Some(last);
+        let name = room.name.clone();
+        let result = client.mark_read(&name, last).await;
+        self.check_sent(result);
+    }
+
+    /// The user is back in control after a reconnect
+    fn stop_catching_up(&mut self) {
+        for room in self.rooms.iter_mut() {
+            room.catching_up = false;
+        }
+    }
+
+    async fn refresh_roster(&mut self) {
+        if self.roster_requested.elapsed() < ROSTER_REFRESH {
+            return;
+        }
+        self.roster_requested = Instant::now();
+
+        let room = &self.rooms[self.active];
+        if room.is_status() || room.is_direct() {
+            return;
+        }
+        let Some(client) = self.client.clone() else {
+            return;
+        };
+        let result = client.request_roster(&room.name).await;
+        self.check_sent(result);
+    }
+
+    fn handle_event(&mut self, event: ChatEvent) {
+        match event {
+            ChatEvent::Connected => {
+                if self.connection == ConnectionStatus::Reconnecting {
+                    for room in self.rooms.iter_mut() {
+                        room.catching_up = true;
+                    }
+                }
+                self.connection = ConnectionStatus::Connected;
+                self.notice("Connection established".into());
+            }
+            ChatEvent::Disconnected {
+                reason,
+                reconnecting,
+            } => {
+                for room in self.rooms.iter_mut() {
+                    room.members.clear();
+                    room.typing.clear();
+                }
+                if reconnecting {
+                    self.connection = ConnectionStatus::Reconnecting;
+                    self.notice(format!("Connection lost ({}), reconnecting", reason));
+                } else {
+                    self.connection_closed();
+                }
+            }
+            ChatEvent::Notice { message } => {
+                // name changes concern every room we share with the member
+                let only_status = self.rooms.len() == 1;
+                for room in self.rooms.iter_mut() {
+                    if !(room.is_status() || room.is_direct()) || only_status {
+                        room.scrollback.push(message.clone());
+                    }
+                }
+            }
+            ChatEvent::Chat(chat) => {
+                // our own chat coming back
+                if let Some(outgoing) = chat.nonce.as_ref().and_then(|n| self.outgoing.remove(n)) {
+                    self.outgoing_sent(&outgoing, chat);
+                    return;
+                }
+                let mentioned = self.nickname.as_ref().is_some_and(|nickname| {
+                    chat.from != *nickname
+                        && chat
+                            .mentions
+                            .iter()
+                            .any(|m| m.eq_ignore_ascii_case(nickname))
+                });
+                self.room(&chat.room).typing.remove(&chat.from);
+                let text = self.room(&chat.room).line(&chat);
+                let line = self.push_message(&chat.room, text, mentioned);
+                if mentioned {
+                    self.mentioned(&chat, line);
+                }
+                let room = self.room(&chat.room);
+                let message_id = chat.id;
+                room.record_chat(line, chat);
+                // read elsewhere before it was replayed here
+                if room.read_up_to == Some(message_id) {
+                    room.read_until(message_id);
+                }
+            }
+            ChatEvent::Read(read) => {
+                let is_active = self.rooms[self.active].name == read.room;
+                let Some(room) = self.rooms.iter_mut().find(|r| r.name == read.room) else {
+                    return;
+                };
+                // the room on screen keeps its divider until it is left
+                if is_active && !room.catching_up {
+                    room.read_up_to = Some(read.message_id);
+                    return;
+                }
+                room.read_until(read.message_id);
+                if let Some(divider) = room.scrollback.divider().filter(|_| is_active) {
+                    room.scrollback.reveal(divider);
+                }
+            }
+            ChatEvent::Edited(edited) => {
+                self.amend_chat(&edited.room, edited.message_id, |chat| {
+                    chat.body = edited.new_body;
+                    chat.edited = true;
+                });
+            }
+            ChatEvent::Deleted(deleted) => {
+                self.amend_chat(&deleted.room, deleted.message_id, |chat| {
+                    chat.body.clear();
+                    chat.deleted = true;
+                });
+            }
+            ChatEvent::Reacted(reacted) => {
+                self.amend_chat(&reacted.room, reacted.message_id, |chat| {
+                    chat.add_reaction(&reacted.emoji, &reacted.by);
+                });
+            }
+            ChatEvent::Unreacted(reacted) => {
+                self.amend_chat(&reacted.room, reacted.message_id, |chat| {
+                    chat.remove_reaction(&reacted.emoji, &reacted.by);
+                });
+            }
+            ChatEvent::Typing(status) => {
+                let room = status.room.clone();
+                self.room(&room).set_typing(status);
+            }
+            ChatEvent::Ack(ack) => {
+                if let Some(mut outgoing) = self.outgoing.remove(&ack.nonce) {
+                    self.room(&outgoing.room).slow_down(ack.wait_secs);
+                    self.outgoing_sent(&outgoing, outgoing.message(&ack));
+                    outgoing.acked = true;
+                    self.outgoing.insert(ack.nonce, outgoing);
+                }
+            }
+            ChatEvent::Direct(direct) => {
+                // the tab is named after the other end of the conversation
+                let peer = if self.nickname.as_deref() == Some(direct.from.as_str()) {
+                    &direct.to
+                } else {
+                    &direct.from
+                };
+                let tab = format!("@{}", peer);
+                let line = format!("[{}] {}: {}", direct.time, direct.from, direct.body);
+                let mentioned = peer == &direct.from;
+                self.push_message(&tab, line, mentioned);
+                if mentioned {
+                    self.alert(&format!("{} (private)", direct.from), &direct.body);
+                }
+            }
+            ChatEvent::WhoIs(info) => self.notice(describe_user(&info)),
+            ChatEvent::Invite(invite) => self.notice(describe_invite(&invite)),
+            ChatEvent::Rooms(list) => self.notice(describe_rooms(&list)),
+            // the roster opens the tab
+            ChatEvent::InviteAccepted(_) => {}
+            ChatEvent::RoomClosed(closed) => {
+                if let Some(index) = self.rooms.iter().position(|r| r.name == closed.room) {
+                    self.close_tab(index);
+                }
+                self.notice(format!("#{} closed, its time ran out", closed.room));
+            }
+            ChatEvent::Expired(expired) => {
+                if let Some(room) = self.rooms.iter_mut().find(|r| r.name == expired.room) {
+                    for message_id in expired.message_ids {
+                        room.expire_chat(message_id);
+                    }
+                }
+            }
+            ChatEvent::RoomInfo(info) => {
+                if let Some(room) = self.rooms.iter_mut().find(|r| r.name == info.room) {
+                    room.set_info(info);
+                }
+            }
+            ChatEvent::Motd { message } => {
+                let status = self.room(STATUS_ROOM);
+                for line in message.lines() {
+                    status.scrollback.push(line.to_owned());
+                }
+            }
+            ChatEvent::Roster(roster) => {
+                let is_new = !self.rooms.iter().any(|r| r.name == roster.room);
+                self.room(&roster.room).set_members(roster.members);
+                if is_new {
+                    // only our own joins open tabs
+                    self.switch_room(self.rooms.len() - 1);
+                }
+            }
+            ChatEvent::Presence(presence) => {
+                let room = self.room(&presence.room);
+                match presence.kind {
+                    proto::PresenceKind::Joined => {
+                        room.scrollback
+                            .push(format!("{} joined #{}", presence.member.name, room.name));
+                        room.upsert_member(presence.member);
+                    }
+                    proto::PresenceKind::Left => {
+                        room.scrollback
+                            .push(format!("{} left #{}", presence.member.name, room.name));
+                        room.typing.remove(&presence.member.name);
+                        room.remove_member(presence.member.id);
+                    }
+                    proto::PresenceKind::Renamed { .. } => {
+                        room.upsert_member(presence.member);
+                    }
+                    proto::PresenceKind::Updated => {
+                        room.update_member(presence.member);
                     }
                 }
-                Message::Close(_) => {
-                    self.client = None;
-                    self.messages.push("Connection closed".into());
-                    break;
+            }
+            ChatEvent::Error(error) => {
+                if let Some(proto::ErrorDetail::SlowMode { room, wait_secs }) = &error.detail {
+                    self.room(room).slow_down(*wait_secs);
                 }
-                Message::Ping(_) => {
-                    client.send(Message::Pong(vec![])).await.unwrap();
+                match error.nonce.and_then(|n| self.outgoing.remove(&n)) {
+                    Some(outgoing) => {
+                        self.update_outgoing(&outgoing, outgoing.failed(&error.message))
+                    }
+                    None => match error.detail {
+                        Some(proto::ErrorDetail::JoinRefused {
+                            room,
+                            reason: proto::JoinRefusal::PasswordRequired,
+                        }) => self.notice(format!(
+                            "#{} needs a password: /join {} <password>",
+                            room, room
+                        )),
+                        _ => self.notice(format!("Error: {}", error.message)),
+                    },
                 }
-                _ => {}
             }
-            msg = client.recv();
+            ChatEvent::Missed(missed) => {
+                self.notice(format!(
+                    "Missed {} message{} while catching up",
+                    missed.count,
+                    if missed.count == 1 { "" } else { "s" }
+                ));
+                // presences may be among them
+                self.roster_requested = Instant::now() - ROSTER_REFRESH;
+            }
+        }
+    }
+
+    /// Drop the tab at `index` with the chats still going out from it
+    fn close_tab(&mut self, index: usize) {
+        let room = self.rooms.remove(index);
+        self.outgoing
+            .retain(|_, outgoing| outgoing.room != room.name);
+        if self.active >= index {
+            self.active = self.active.saturating_sub(1);
+        }
+    }
+
+    /// Add a message to a tab and count it when the tab is in the background,
+    /// returns its line. The first unread one gets the divider, which stays
+    /// in view while catching up on the active tab.
+    fn push_message(&mut self, tab: &str, line: String, mentioned: bool) -> usize {
+        let is_active = self.rooms[self.active].name == tab;
+        let room = self.room(tab);
+        let index = if is_active && room.catching_up {
+            room.scrollback.push_below_divider(line)
+        } else {
+            room.scrollback.push(line)
+        };
+        if !is_active {
+            room.unread += 1;
+            if mentioned {
+                room.mentions += 1;
+            }
+        }
+        if (!is_active || room.catching_up) && room.scrollback.divider().is_none() {
+            room.scrollback.set_divider(Some(index));
+        }
+        index
+    }
+
+    /// Highlight a chat that mentions us, collect it for `/mentions` and
+    /// notify the user
+    fn mentioned(&mut self, chat: &proto::ChatMessage, line: usize) {
+        let style = Style::default().fg(self.config.theme.mention);
+        self.room(&chat.room).scrollback.set_style(line, style);
+        self.mentions.push(format!(
+            "[{}] #{} {}: {}",
+            chat.time, chat.room, chat.from, chat.body
+        ));
+        if self.mentions.len() > MENTIONS_KEPT {
+            self.mentions.remove(0);
+        }
+        self.alert(&format!("{} in #{}", chat.from, chat.room), &chat.body);
+    }
+
+    /// Queue the notification configured for the terminal
+    fn alert(&mut self, title: &str, body: &str) {
+        if let Some(sequence) = self.config.notify.sequence(title, body) {
+            self.alerts.push(sequence);
+        }
+    }
+
+    fn write_alerts(&mut self) -> io::Result<()> {
+        if self.alerts.is_empty() {
+            return Ok(());
+        }
+        let mut stdout = io::stdout();
+        for alert in self.alerts.drain(..) {
+            stdout.write_all(alert.as_bytes())?;
         }
+        stdout.flush()
+    }
 
-        self.client = Some(client);
+    fn connection_closed(&mut self) {
+        self.fail_outgoing("connection closed");
+        self.client = None;
+        self.events = None;
+        self.connection = ConnectionStatus::Offline;
+        for room in self.rooms.iter_mut() {
+            room.members.clear();
+        }
+        self.notice("Connection closed".into());
+    }
+
+    fn recv_messages(&mut self) {
+        while let Some(event) = self.events.as_mut().and_then(|e| e.try_next_event()) {
+            self.handle_event(event);
+        }
     }
 
     async fn submit_message(&mut self) {
@@ -135,43 +861,232 @@ impl App {
 
         let mut message = self.input.clone();
 
-        if message.starts_with("connect ") {
-            let url = message.split_off(8);
-            if self.client.is_some() {
-                let mut client = self.client.take().unwrap();
-                client.disconnect().await.unwrap();
-                self.client = None;
+        if let Some(message_id) = self.active_room().react_to.take() {
+            self.react(message_id, &message).await;
+        } else if message.starts_with("connect ") {
+            let target = message.split_off(8);
+            let url = if self.config.profiles.contains_key(&target) {
+                match self.config.profile(Some(&target)) {
+                    Ok(profile) => {
+                        self.profile = profile;
+                        self.profile.url.clone()
+                    }
+                    Err(err) => {
+                        self.notice(format!("{:#}", err));
+                        None
+                    }
+                }
+            } else {
+                Some(target)
+            };
+            match url {
+                Some(url) => self.connect(url).await,
+                None => self.notice(format!("Profile has no url: {}", message)),
             }
-            let client = WsClient::new(&url).await.unwrap();
-            self.client = Some(client);
-            self.messages.push("Connection established".into());
         } else if message.starts_with("exit") {
-            if self.client.is_some() {
-                let mut client = self.client.take().unwrap();
-                client.disconnect().await.unwrap();
-                self.client = None;
-                self.messages.push("Connection closed".into());
+            if let Some(client) = self.client.take() {
+                client.close().await;
+                self.connection_closed();
             }
         } else if message.starts_with("login ") {
             let name = message.split_off(6);
-            let message = proto::ChatPacket::new(proto::ChatPacketType::Login, name);
-            let bytes = Message::Binary(message.serialize());
-            let send_result = self.client.as_ref().unwrap().send(bytes).await;
-            if send_result.is_err() {
-                self.messages.push("Not connected".into());
-                self.client = None;
-            } else {
-                // self.messages.push(self.input.clone());
+            if let Some(client) = self.connected_client() {
+                self.nickname = Some(name.clone());
+                let result = client.login(&name).await;
+                self.check_sent(result);
             }
-        } else {
-            let message = proto::ChatPacket::new(proto::ChatPacketType::Chat, message);
-            let bytes = Message::Binary(message.serialize());
-            let send_result = self.client.as_ref().unwrap().send(bytes).await;
-            if send_result.is_err() {
-                self.messages.push("Not connected".into());
-                self.client = None;
-            } else {
-                // self.messages.push(self.input.clone());
+        } else if message.starts_with("/join ") {
+            let target = message.split_off(6);
+            if let Some(client) = self.connected_client() {
+                let result = match target.trim().split_once(' ') {
+                    Some((room, password)) => {
+                        client.join_with_password(room, password.trim()).await
+                    }
+                    None => client.join(target.trim()).await,
+                };
+                self.check_sent(result);
+            }
+        } else if let Some(rest) = message.strip_prefix("/create ") {
+            // a room that closes after the given minutes
+            match rest
+                .split_once(' ')
+                .map(|(room, minutes)| (room, minutes.trim().parse::<u64>()))
+            {
+                Some((room, Ok(minutes))) if minutes > 0 => {
+                    if let Some(client) = self.connected_client() {
+                        let result = client.create_room(room, minutes * 60).await;
+                        self.check_sent(result);
+                    }
+                }
+                _ => self.notice("Usage: /create <room> <minutes>".into()),
+            }
+        } else if message == "/invite" || message.starts_with("/invite ") {
+            // optional use limit and minutes until the code expires
+            let limits: Result<Vec<u64>, _> =
+                message.split_whitespace().skip(1).map(str::parse).collect();
+            match limits {
+                Ok(limits) if limits.len() <= 2 => {
+                    let max_uses = limits.first().copied().unwrap_or(0) as u32;
+                    let expires_secs = limits.get(1).copied().unwrap_or(0) * 60;
+                    let room = self.rooms[self.active].name.clone();
+                    if let Some(client) = self.connected_client() {
+                        let result = client.invite(&room, max_uses, expires_secs).await;
+                        self.check_sent(result);
+                    }
+                }
+                _ => self.notice("Usage: /invite [max uses] [minutes]".into()),
+            }
+        } else if let Some(code) = message.strip_prefix("/accept ") {
+            if let Some(client) = self.connected_client() {
+                let result = client.accept_invite(code.trim()).await;
+                self.check_sent(result);
+            }
+        } else if message == "/rooms" {
+            if let Some(client) = self.connected_client() {
+                let result = client.list_rooms().await;
+                self.check_sent(result);
+            }
+        } else if message.starts_with("/msg ") {
+            let rest = message.split_off(5);
+            match rest.split_once(' ') {
+                Some((to, body)) if !body.trim().is_empty() => {
+                    if let Some(client) = self.connected_client() {
+                        let result = client.send_dm(to, body).await;
+                        self.check_sent(result);
+                    }
+                }
+                _ => self.notice("Usage: /msg <name> <message>".into()),
+            }
+        } else if message.starts_with("/edit ") || message == "/delete" {
+            // both act on our last message in the active room
+            let nickname = self.nickname.clone().unwrap_or_default();
+            let last = self.rooms[self.active]
+                .last_chat_of(&nickname)
+                .map(|c| c.id);
+            match (last, message.strip_prefix("/edit ")) {
+                (None, _) => self.notice("No message of yours to change here".into()),
+                (Some(id), Some(body)) if !body.trim().is_empty() => {
+                    if let Some(client) = self.connected_client() {
+                        let result = client.edit(id, body).await;
+                        self.check_sent(result);
+                    }
+                }
+                (Some(_), Some(_)) => self.notice("Usage: /edit <message>".into()),
+                (Some(id), None) => {
+                    if let Some(client) = self.connected_client() {
+                        let result = client.delete(id).await;
+                        self.check_sent(result);
+                    }
+                }
+            }
+        } else if message == "/away" || message.starts_with("/away ") {
+            let message = message.get(6..).unwrap_or_default().trim().to_owned();
+            self.set_status(proto::Status::Away { message }).await;
+        } else if message == "/back" {
+            self.set_status(proto::Status::Online).await;
+        } else if message == "/busy" {
+            self.set_status(proto::Status::Busy).await;
+        } else if message == "/invisible" {
+            self.set_status(proto::Status::Invisible).await;
+        } else if let Some(rest) = message.strip_prefix("/profile ") {
+            // without a value the field is cleared
+            let (field, value) = rest.split_once(' ').unwrap_or((rest, ""));
+            let mut profile = proto::Profile::default();
+            let target = match field {
+                "name" => Some(&mut profile.display_name),
+                "pronouns" => Some(&mut profile.pronouns),
+                "timezone" => Some(&mut profile.timezone),
+                "bio" => Some(&mut profile.bio),
+                _ => None,
+            };
+            match target {
+                Some(target) => {
+                    *target = Some(value.trim().to_owned());
+                    if let Some(client) = self.connected_client() {
+                        let result = client.set_profile(&profile).await;
+                        self.check_sent(result);
+                    }
+                }
+                None => self.notice("Usage: /profile name|pronouns|timezone|bio [value]".into()),
+            }
+        } else if let Some(name) = message.strip_prefix("/whois ") {
+            if let Some(client) = self.connected_client() {
+                let result = client.whois(name.trim()).await;
+                self.check_sent(result);
+            }
+        } else if message == "/topic" || message == "/info" {
+            let info = self.rooms[self.active].info.as_ref().map(describe_room);
+            match info {
+                Some(info) => self.notice(info),
+                None => self.notice("No room details here".into()),
+            }
+        } else if let Some(topic) = message.strip_prefix("/topic ") {
+            let room = self.rooms[self.active].name.clone();
+            if let Some(client) = self.connected_client() {
+                let result = client.set_topic(&room, Some(topic.trim()), None).await;
+                self.check_sent(result);
+            }
+        } else if let Some(description) = message.strip_prefix("/description ") {
+            let room = self.rooms[self.active].name.clone();
+            if let Some(client) = self.connected_client() {
+                let result = client
+                    .set_topic(&room, None, Some(description.trim()))
+                    .await;
+                self.check_sent(result);
+            }
+        } else if let Some(rest) = message.strip_prefix("/set ") {
+            self.change_room(rest).await;
+        } else if message == "/mentions" {
+            self.show_mentions = true;
+            self.input_mode = InputMode::Normal;
+        } else if let Some(reaction) = message.strip_prefix("/react ") {
+            // the latest chat of the room, others are picked in the scrollback
+            match self.rooms[self.active].last_chat().map(|c| c.id) {
+                Some(id) => self.react(id, reaction).await,
+                None => self.notice("No message to react to here".into()),
+            }
+        } else if message == "/leave" || message.starts_with("/leave ") {
+            let room = match message.get(7..) {
+                Some(room) if !room.trim().is_empty() => room.trim().trim_start_matches('#'),
+                _ => self.rooms[self.active].name.as_str(),
+            }
+            .to_owned();
+
+            match self.rooms.iter().position(|r| r.name == room) {
+                Some(index) if !self.rooms[index].is_status() => {
+                    if !self.rooms[index].is_direct() {
+                        if let Some(client) = self.connected_client() {
+                            let result = client.leave(&room).await;
+                            self.check_sent(result);
+                        }
+                    }
+                    self.close_tab(index);
+                }
+                _ => self.notice(format!("Not in room: {}", room)),
+            }
+        } else if self.rooms[self.active].is_status() {
+            self.notice("Join a room to chat: /join <room>".into());
+        } else if self.rooms[self.active].slow_mode_wait().is_some()
+            && !self.rooms[self.active].is_direct()
+        {
+            // slow mode holds the line back until the countdown runs out
+            return;
+        } else if let Some(client) = self.connected_client() {
+            let room = self.rooms[self.active].name.clone();
+            match room.strip_prefix('@') {
+                Some(to) => {
+                    let result = client.send_dm(to, &message).await;
+                    self.check_sent(result);
+                }
+                None => {
+                    let reply_to = self.reply_target();
+                    self.active_room().reply_to = None;
+                    let result = match reply_to {
+                        Some(parent) => client.reply(&room, parent, &message).await,
+                        None => client.send_chat(&room, &message).await,
+                    };
+                    self.track_chat(room, message, reply_to, result);
+                }
             }
         }
 
@@ -179,95 +1094,304 @@ impl App {
         self.reset_cursor();
     }
 
-    pub async fn run_app<B: Backend>(mut self: Self, terminal: &mut Terminal<B>) -> io::Result<()> {
-        loop {
-            terminal.draw(|f| self.ui(f))?;
+    /// Open a connection with the auth and tls settings of the current profile,
+    /// then log in and join the profile's rooms
+    async fn connect(&mut self, url: String) {
+        if let Some(client) = self.client.take() {
+            client.close().await;
+        }
+        self.events = None;
+
+        let mut options = ClientOptions::new(&url);
+        options.token = self.profile.token.clone();
+        options.tls = self.profile.tls.clone();
+        let (client, events) = match ChatClient::connect(options).await {
+            Ok(connection) => connection,
+            Err(err) => {
+                self.connection = ConnectionStatus::Offline;
+                self.notice(format!("Connection to {} failed: {}", url, err));
+                return;
+            }
+        };
+        self.client = Some(client.clone());
+        self.events = Some(events);
+        self.server_url = Some(url);
+
+        if let Some(name) = self.profile.nickname.clone() {
+            self.nickname = Some(name.clone());
+            let result = client.login(&name).await;
+            self.check_sent(result);
+        }
+        for room in self.profile.rooms.clone() {
+            let result = client.join(&room).await;
+            self.check_sent(result);
+        }
+    }
+
+    /// Keys that work in both input modes, returns whether the key was used
+    fn handle_global_key(&mut self, key: &KeyEvent) -> bool {
+        let keys = &self.config.keys;
+        if keys.next_room.matches(key) {
+            self.next_room();
+        } else if keys.prev_room.matches(key) {
+            self.prev_room();
+        } else {
+            match key.code {
+                KeyCode::Char(c @ '1'..='9') if key.modifiers.contains(KeyModifiers::ALT) => {
+                    self.switch_room(c as usize - '1' as usize);
+                }
+                _ => return false,
+            }
+        }
+        true
+    }
 
-            self.recv_messages().await;
-
-            if poll(Duration::from_millis(100))? {
-                if let Event::Key(key) = event::read()? {
-                    match self.input_mode {
-                        InputMode::Normal => match key.code {
-                            KeyCode::Char('e') => {
-                                self.input_mode = InputMode::Editing;
-                            }
-                            KeyCode::Char('q') => {
-                                return Ok(());
-                            }
-                            _ => {}
-                        },
-                        InputMode::Editing if key.kind == KeyEventKind::Press => match key.code {
-                            KeyCode::Enter => self.submit_message().await,
-                            KeyCode::Char(to_insert) => {
-                                self.enter_char(to_insert);
-                            }
-                            KeyCode::Backspace => {
-                                self.delete_char();
-                            }
-                            KeyCode::Left => {
-                                self.move_cursor_left();
-                            }
-                            KeyCode::Right => {
-                                self.move_cursor_right();
-                            }
-                            KeyCode::Esc => {
-                                self.input_mode = InputMode::Normal;
-                            }
-                            KeyCode::Up => {
-                                if self.message_index > 0 {
-                                    self.message_index -= 1;
-                                }
-                            }
-                            KeyCode::Down => {
-                                if self.message_index < self.messages.len() - 1 {
-                                    self.message_index += 1;
-                                }
-                            }
-                            _ => {}
-                        },
-                        _ => {}
+    /// Scroll keys shared by both input modes, returns whether the key was used
+    fn handle_scroll_key(&mut self, key: &KeyEvent) -> bool {
+        let keys = &self.config.keys;
+        let scrollback = &mut self.rooms[self.active].scrollback;
+        if keys.page_up.matches(key) {
+            scrollback.page_up();
+        } else if keys.page_down.matches(key) {
+            scrollback.page_down();
+        } else if keys.jump_to_bottom.matches(key) {
+            scrollback.scroll_to_bottom();
+        } else {
+            match key.code {
+                KeyCode::Up => scrollback.scroll_up(1),
+                KeyCode::Down => scrollback.scroll_down(1),
+                _ => return false,
+            }
+        }
+        true
+    }
+
+    /// Handle a key press, returns whether the app should quit
+    async fn handle_key(&mut self, key: KeyEvent) -> bool {
+        if self.handle_global_key(&key) {
+            return false;
+        }
+
+        match self.input_mode {
+            InputMode::Normal => {
+                if self.config.keys.quit.matches(&key) {
+                    return true;
+                }
+                if self.config.keys.edit.matches(&key) {
+                    self.input_mode = InputMode::Editing;
+                    return false;
+                }
+                if self.config.keys.select.matches(&key) {
+                    self.start_selecting();
+                    return false;
+                }
+                if self.handle_scroll_key(&key) {
+                    return false;
+                }
+                let scrollback = &mut self.rooms[self.active].scrollback;
+                match key.code {
+                    KeyCode::Char('k') => scrollback.scroll_up(1),
+                    KeyCode::Char('j') => scrollback.scroll_down(1),
+                    KeyCode::Home | KeyCode::Char('g') => scrollback.scroll_to_top(),
+                    KeyCode::Char('G') => scrollback.scroll_to_bottom(),
+                    KeyCode::Esc if self.show_mentions => self.show_mentions = false,
+                    KeyCode::Esc => self.rooms[self.active].thread = None,
+                    _ => {}
+                }
+            }
+            InputMode::Selecting if key.kind == KeyEventKind::Press => {
+                self.handle_select_key(&key);
+            }
+            InputMode::Editing if key.kind == KeyEventKind::Press => match key.code {
+                KeyCode::Enter => {
+                    self.submit_message().await;
+                    self.active_room().scrollback.scroll_to_bottom();
+                }
+                KeyCode::Char(to_insert)
+                    if !key
+                        .modifiers
+                        .intersects(KeyModifiers::CONTROL | KeyModifiers::ALT) =>
+                {
+                    self.enter_char(to_insert);
+                }
+                KeyCode::Backspace => {
+                    self.delete_char();
+                }
+                KeyCode::Left => {
+                    self.move_cursor_left();
+                }
+                KeyCode::Right => {
+                    self.move_cursor_right();
+                }
+                KeyCode::Tab if self.rooms[self.active].react_to.is_some() => {
+                    self.complete_reaction();
+                }
+                KeyCode::Esc => {
+                    self.input_mode = InputMode::Normal;
+                    let room = self.active_room();
+                    room.reply_to = None;
+                    if room.react_to.take().is_some() {
+                        self.input.clear();
+                        self.reset_cursor();
                     }
                 }
+                _ => {
+                    self.handle_scroll_key(&key);
+                }
+            },
+            _ => {}
+        }
+        false
+    }
+
+    /// Handle a terminal event, returns whether the app should quit
+    async fn handle_input(&mut self, event: Event) -> bool {
+        match event {
+            Event::Key(key) => {
+                self.stop_catching_up();
+                return self.handle_key(key).await;
+            }
+            Event::Mouse(mouse) => {
+                let scrollback = &mut self.rooms[self.active].scrollback;
+                match mouse.kind {
+                    MouseEventKind::ScrollUp => scrollback.scroll_up(WHEEL_STEP),
+                    MouseEventKind::ScrollDown => scrollback.scroll_down(WHEEL_STEP),
+                    _ => return false,
+                }
+                self.stop_catching_up();
             }
+            _ => {}
         }
+        false
     }
 
-    pub fn ui(self: &Self, f: &mut Frame) {
+    pub async fn run_app<B: Backend>(mut self, terminal: &mut Terminal<B>) -> io::Result<()> {
+        if self.auto_connect {
+            if let Some(url) = self.profile.url.clone() {
+                self.connect(url).await;
+            }
+        }
+
+        loop {
+            terminal.draw(|f| self.ui(f))?;
+            self.write_alerts()?;
+
+            self.recv_messages();
+            self.refresh_roster().await;
+            self.update_typing().await;
+            self.sync_read().await;
+
+            if poll(Duration::from_millis(100))? && self.handle_input(event::read()?).await {
+                return Ok(());
+            }
+        }
+    }
+
+    pub fn ui(&mut self, f: &mut Frame) {
         let chunks = Layout::default()
             .direction(Direction::Vertical)
             .constraints([
-                Constraint::Min(1),    // Messages
+                Constraint::Length(1), // Rooms
+                Constraint::Min(1),    // Messages and members
                 Constraint::Length(1), // Tip
                 Constraint::Length(3), // Input
             ])
             .split(f.size());
 
-        // Messages
-        let messages: Vec<ListItem> = self.messages[self.message_index..]
+        // Rooms
+        let titles: Vec<Line> = self
+            .rooms
             .iter()
             .enumerate()
-            .map(|(_i, m)| {
-                let content = Line::from(Span::raw(m));
-                ListItem::new(content)
-            })
+            .map(|(i, room)| room.tab_title(i, &self.config.theme))
             .collect();
-        let messages =
-            List::new(messages).block(Block::default().borders(Borders::ALL).title("Messages"));
-        f.render_widget(messages, chunks[0]);
+        let tabs = Tabs::new(titles)
+            .select(self.active)
+            .highlight_style(Style::default().fg(self.config.theme.highlight).reversed());
+        f.render_widget(tabs, chunks[0]);
+
+        // Messages and members
+        let room = &mut self.rooms[self.active];
+        let body = if room.is_status() || room.is_direct() {
+            vec![chunks[1]]
+        } else {
+            Layout::default()
+                .direction(Direction::Horizontal)
+                .constraints([Constraint::Min(1), Constraint::Length(24)])
+                .split(chunks[1])
+                .to_vec()
+        };
+        match room.thread {
+            _ if self.show_mentions => {
+                let mut mentions = Scrollback::default();
+                for line in &self.mentions {
+                    mentions.push(line.clone());
+                }
+                let block = Block::default().borders(Borders::ALL).title("Mentions");
+                mentions.render(f, body[0], block);
+            }
+            Some(root) => {
+                let mut thread = Scrollback::default();
+                for line in room.thread_lines(root) {
+                    thread.push(line);
+                }
+                let block = Block::default()
+                    .borders(Borders::ALL)
+                    .title(format!("Thread #{}", room.name));
+                thread.render(f, body[0], block);
+            }
+            None => {
+                let block = Block::default().borders(Borders::ALL).title(room.title());
+                room.scrollback.render(f, body[0], block);
+            }
+        }
+        if let Some(area) = body.get(1) {
+            room.render_members(f, *area, &self.config.theme);
+        }
 
         // Tip
+        let in_thread = self.rooms[self.active].thread.is_some();
+        let reacting = self.rooms[self.active].react_to.is_some();
         let (msg, style) = match self.input_mode {
+            InputMode::Normal if self.show_mentions => (
+                vec![
+                    "Press ".into(),
+                    "Esc".bold(),
+                    " to close the mentions.".into(),
+                ],
+                Style::default(),
+            ),
+            InputMode::Normal if in_thread => (
+                vec![
+                    "Press ".into(),
+                    "Esc".bold(),
+                    " to close the thread, ".into(),
+                    "e".bold(),
+                    " to answer in it.".into(),
+                ],
+                Style::default(),
+            ),
             InputMode::Normal => (
                 vec![
                     "Press ".into(),
                     "q".bold(),
                     " to exit, ".into(),
                     "e".bold(),
-                    " to start editing.".bold(),
+                    " to start editing, ".into(),
+                    "PgUp/PgDn".bold(),
+                    " to scroll, ".into(),
+                    "Ctrl+N/P".bold(),
+                    " to switch rooms.".into(),
                 ],
                 Style::default().add_modifier(Modifier::RAPID_BLINK),
             ),
+            InputMode::Editing if reacting => {
+                let mut picker = vec!["Tab".bold(), " to complete:".into()];
+                for (code, emoji) in emoji::completions(&self.input) {
+                    picker.push(format!("  {} :{}:", emoji, code).into());
+                }
+                (picker, Style::default())
+            }
             InputMode::Editing => (
                 vec![
                     "Press ".into(),
@@ -278,36 +1402,101 @@ impl App {
                 ],
                 Style::default(),
             ),
+            InputMode::Selecting => (
+                vec![
+                    "Press ".into(),
+                    "Up/Down".bold(),
+                    " to pick a message, ".into(),
+                    "Enter".bold(),
+                    " to reply, ".into(),
+                    ":".bold(),
+                    " to react, ".into(),
+                    "t".bold(),
+                    " to open its thread, ".into(),
+                    "Esc".bold(),
+                    " to cancel".into(),
+                ],
+                Style::default(),
+            ),
         };
         let mut text = Text::from(Line::from(msg));
         text.patch_style(style);
+        let typing = match self.rooms[self.active].typing_notice() {
+            Some(notice) => Line::from(format!(" {} ", notice).italic()),
+            None => Line::default(),
+        };
+        let status = self.connection_line();
+        let tip = Layout::default()
+            .direction(Direction::Horizontal)
+            .constraints([
+                Constraint::Min(0),
+                Constraint::Length(typing.width() as u16),
+                Constraint::Length(status.width() as u16),
+            ])
+            .split(chunks[2]);
         let help_message = Paragraph::new(text);
-        f.render_widget(help_message, chunks[1]);
+        f.render_widget(help_message, tip[0]);
+        f.render_widget(Paragraph::new(typing), tip[1]);
+        f.render_widget(Paragraph::new(status), tip[2]);
 
         // Input
+        let room = &self.rooms[self.active];
+        let slow_mode_wait = room.slow_mode_wait();
+        let title = match (room.react_to, self.reply_target()) {
+            _ if slow_mode_wait.is_some() => None,
+            (Some(id), _) => room.chat(id).map(|chat| format!("React to {}", chat.from)),
+            (None, Some(id)) => room.chat(id).map(|chat| format!("Reply to {}", chat.from)),
+            (None, None) => None,
+        };
+        let title = match slow_mode_wait {
+            Some(wait) => format!("Slow mode, wait {}s", wait),
+            None => title.unwrap_or_else(|| "Input".to_owned()),
+        };
         let input = Paragraph::new(self.input.as_str())
             .style(match self.input_mode {
-                InputMode::Normal => Style::default(),
-                InputMode::Editing => Style::default().fg(Color::Yellow),
+                _ if slow_mode_wait.is_some() => Style::default().dim(),
+                InputMode::Normal | InputMode::Selecting => Style::default(),
+                InputMode::Editing => Style::default().fg(self.config.theme.input),
             })
-            .block(Block::default().borders(Borders::ALL).title("Input"));
-        f.render_widget(input, chunks[2]);
+            .block(Block::default().borders(Borders::ALL).title(title));
+        f.render_widget(input, chunks[3]);
         match self.input_mode {
-            InputMode::Normal =>
+            InputMode::Normal | InputMode::Selecting =>
                 // Hide the cursor. `Frame` does this by default, so we don't need to do anything here
                 {}
 
             InputMode::Editing => {
                 // Make the cursor visible and ask ratatui to put it at the specified coordinates after
                 // rendering
+                let before_cursor: String = self.input.chars().take(self.cursor_position).collect();
                 f.set_cursor(
                     // Draw the cursor at the current position in the input field.
-                    // This position is can be controlled via the left and right arrow key
-                    chunks[2].x + self.cursor_position as u16 + 1,
+                    // This position is can be controlled via the left and right arrow key,
+                    // wide characters take two columns
+                    chunks[3].x + before_cursor.width() as u16 + 1,
                     // Move one line down, from the border to the input line
-                    chunks[2].y + 1,
+                    chunks[3].y + 1,
                 )
             }
         }
     }
+
+    /// Right side of the tip row, e.g. `● ws://localhost:3000`
+    fn connection_line(&self) -> Line<'static> {
+        let url = self.server_url.clone().unwrap_or_default();
+        match self.connection {
+            ConnectionStatus::Offline => Line::from(" ○ offline ".dim()),
+            ConnectionStatus::Connected => Line::from(vec![
+                " ● ".fg(self.config.theme.online),
+                Span::raw(format!("{} ", url)),
+            ]),
+            ConnectionStatus::Reconnecting => Line::from(vec![
+                " ◌ ".yellow(),
+                Span::raw(format!("reconnecting to {} ", url)),
+            ]),
+        }
+    }
 }
+
+#[cfg(test)]
+mod tests;
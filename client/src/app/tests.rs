@@ -0,0 +1,920 @@
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::{backend::TestBackend, buffer::Buffer};
+
+use super::*;
+use crate::scrollback::MAX_MESSAGES;
+
+const TIME: &str = "2024-01-01 12:00:00";
+
+fn app() -> App {
+    App::new(ConfigFile::default(), Profile::default(), false)
+}
+
+/// Draw the app and return the screen as text, one string per row
+fn screen(app: &mut App, width: u16, height: u16) -> Vec<String> {
+    draw(app, width, height).0
+}
+
+fn draw(app: &mut App, width: u16, height: u16) -> (Vec<String>, (u16, u16)) {
+    let mut terminal = Terminal::new(TestBackend::new(width, height)).unwrap();
+    terminal.draw(|f| app.ui(f)).unwrap();
+    let cursor = terminal.get_cursor().unwrap();
+    (rows(terminal.backend().buffer()), cursor)
+}
+
+fn rows(buffer: &Buffer) -> Vec<String> {
+    let width = buffer.area.width as usize;
+    buffer
+        .content
+        .chunks(width)
+        .map(|row| {
+            let mut line = String::new();
+            // cells behind a wide character are blank placeholders
+            let mut hidden = 0;
+            for cell in row {
+                if hidden > 0 {
+                    hidden -= 1;
+                    continue;
+                }
+                line.push_str(&cell.symbol);
+                hidden = cell.symbol.width().saturating_sub(1);
+            }
+            line
+        })
+        .collect()
+}
+
+fn assert_screen(actual: &[String], expected: &[&str]) {
+    assert_eq!(
+        actual,
+        expected,
+        "\nactual screen:\n{}\n",
+        actual.join("\n")
+    );
+}
+
+async fn press(app: &mut App, code: KeyCode) -> bool {
+    let key = KeyEvent::new(code, KeyModifiers::NONE);
+    app.handle_input(Event::Key(key)).await
+}
+
+async fn type_text(app: &mut App, text: &str) {
+    for c in text.chars() {
+        press(app, KeyCode::Char(c)).await;
+    }
+}
+
+fn member(id: usize, name: &str) -> proto::Member {
+    proto::Member {
+        id,
+        name: name.to_owned(),
+        status: proto::Status::Online,
+        idle_secs: 0,
+    }
+}
+
+/// Join `room` the way the server confirms it, with us as the only member
+fn join(app: &mut App, room: &str) {
+    app.nickname = Some("alice".to_owned());
+    app.handle_event(ChatEvent::Roster(proto::Roster {
+        room: room.to_owned(),
+        members: vec![member(1, "alice")],
+        seq: 0,
+    }));
+}
+
+fn chat(room: &str, from: &str, body: &str) -> ChatEvent {
+    ChatEvent::Chat(proto::ChatMessage {
+        room: room.to_owned(),
+        from: from.to_owned(),
+        body: body.to_owned(),
+        time: TIME.to_owned(),
+        id: 0,
+        nonce: None,
+        seq: 0,
+        edited: false,
+        deleted: false,
+        reply_to: None,
+        reactions: Vec::new(),
+        mentions: proto::parse_mentions(body),
+    })
+}
+
+/// Chat the server stored as message `id`
+fn posted(room: &str, id: u64, from: &str, body: &str) -> proto::ChatMessage {
+    proto::ChatMessage {
+        room: room.to_owned(),
+        from: from.to_owned(),
+        body: body.to_owned(),
+        time: TIME.to_owned(),
+        id,
+        nonce: None,
+        seq: id,
+        edited: false,
+        deleted: false,
+        reply_to: None,
+        reactions: Vec::new(),
+        mentions: proto::parse_mentions(body),
+    }
+}
+
+#[tokio::test]
+async fn normal_mode() {
+    let mut app = app();
+    assert_screen(
+        &screen(&mut app, 60, 8),
+        &[
+            " 1 status                                                   ",
+            "┌Messages #status──────────────────────────────────────────┐",
+            "│                                                          │",
+            "└──────────────────────────────────────────────────────────┘",
+            "Press q to exit, e to start editing, PgUp/PgDn to ○ offline ",
+            "┌Input─────────────────────────────────────────────────────┐",
+            "│                                                          │",
+            "└──────────────────────────────────────────────────────────┘",
+        ],
+    );
+
+    assert!(press(&mut app, KeyCode::Char('q')).await);
+}
+
+#[tokio::test]
+async fn editing_mode() {
+    let mut app = app();
+    assert!(!press(&mut app, KeyCode::Char('e')).await);
+    // `q` is text while editing
+    type_text(&mut app, "hi q").await;
+
+    let (rows, cursor) = draw(&mut app, 60, 8);
+    assert_screen(
+        &rows[4..],
+        &[
+            "Press Esc to stop editing, Enter to record the me ○ offline ",
+            "┌Input─────────────────────────────────────────────────────┐",
+            "│hi q                                                      │",
+            "└──────────────────────────────────────────────────────────┘",
+        ],
+    );
+    assert_eq!(cursor, (5, 6));
+
+    // sending without a connection leaves a notice and clears the input
+    press(&mut app, KeyCode::Enter).await;
+    let rows = screen(&mut app, 60, 8);
+    assert_eq!(
+        rows[2],
+        "│Join a room to chat: /join <room>                         │"
+    );
+    assert_eq!(
+        rows[6],
+        "│                                                          │"
+    );
+
+    press(&mut app, KeyCode::Esc).await;
+    assert!(press(&mut app, KeyCode::Char('q')).await);
+}
+
+#[tokio::test]
+async fn scrolling() {
+    let mut app = app();
+    join(&mut app, "rust");
+    for i in 0..10 {
+        app.handle_event(chat("rust", "bob", &format!("line {}", i)));
+    }
+    // Down at the bottom stays at the bottom
+    for _ in 0..3 {
+        press(&mut app, KeyCode::Down).await;
+    }
+    assert_screen(
+        &screen(&mut app, 60, 10)[..6],
+        &[
+            " 1 status │ 2 rust                                          ",
+            "┌Messages #rust────────────────────┐┌Members (1)───────────┐",
+            "│[2024-01-01 12:00:00] bob: line 7 ││● alice               │",
+            "│[2024-01-01 12:00:00] bob: line 8 ││                      │",
+            "│[2024-01-01 12:00:00] bob: line 9 ││                      │",
+            "└──────────────────────────────────┘└──────────────────────┘",
+        ],
+    );
+
+    // a page keeps one row of context, new messages don't move the view
+    press(&mut app, KeyCode::PageUp).await;
+    app.handle_event(chat("rust", "bob", "late"));
+    assert_screen(
+        &screen(&mut app, 60, 10)[1..6],
+        &[
+            "┌Messages #rust────────────────────┐┌Members (1)───────────┐",
+            "│[2024-01-01 12:00:00] bob: line 5 ││● alice               │",
+            "│[2024-01-01 12:00:00] bob: line 6 ││                      │",
+            "│[2024-01-01 12:00:00] bob: line 7 ││                      │",
+            "└───── 1 new message (End to jump) ┘└──────────────────────┘",
+        ],
+    );
+
+    press(&mut app, KeyCode::Char('g')).await;
+    assert_eq!(
+        screen(&mut app, 60, 10)[2],
+        "│[2024-01-01 12:00:00] bob: line 0 ││● alice               │"
+    );
+
+    press(&mut app, KeyCode::End).await;
+    assert_screen(
+        &screen(&mut app, 60, 10)[2..6],
+        &[
+            "│[2024-01-01 12:00:00] bob: line 8 ││● alice               │",
+            "│[2024-01-01 12:00:00] bob: line 9 ││                      │",
+            "│[2024-01-01 12:00:00] bob: late   ││                      │",
+            "└──────────────────────────────────┘└──────────────────────┘",
+        ],
+    );
+}
+
+#[tokio::test]
+async fn scrollback_keeps_the_newest_messages() {
+    let mut app = app();
+    join(&mut app, "rust");
+    let count = MAX_MESSAGES as u64 + 2;
+    for id in 1..=count {
+        let body = format!("n {}", id);
+        app.handle_event(ChatEvent::Chat(posted("rust", id, "bob", &body)));
+    }
+    // edits of dropped chats go nowhere
+    for (id, new_body) in [(1, "gone"), (3, "kept")] {
+        app.handle_event(ChatEvent::Edited(proto::Edited {
+            room: "rust".into(),
+            message_id: id,
+            new_body: new_body.into(),
+            by: "bob".into(),
+            seq: count + id,
+        }));
+    }
+
+    press(&mut app, KeyCode::Char('g')).await;
+    assert_screen(
+        &screen(&mut app, 60, 10)[2..5],
+        &[
+            "│[2024-01-01 12:00:00] bob: kept   ││● alice               │",
+            "│(edited)                          ││                      │",
+            "│[2024-01-01 12:00:00] bob: n 4    ││                      │",
+        ],
+    );
+    press(&mut app, KeyCode::End).await;
+    assert_eq!(
+        screen(&mut app, 60, 10)[4],
+        format!(
+            "│[2024-01-01 12:00:00] bob: n {} ││                      │",
+            count
+        )
+    );
+}
+
+#[tokio::test]
+async fn long_lines_wrap_at_words() {
+    let mut app = app();
+    join(&mut app, "rust");
+    app.handle_event(chat(
+        "rust",
+        "bob",
+        "a rather long line that has to wrap in the narrow message pane",
+    ));
+    assert_screen(
+        &screen(&mut app, 60, 10)[1..6],
+        &[
+            "┌Messages #rust────────────────────┐┌Members (1)───────────┐",
+            "│[2024-01-01 12:00:00] bob: a      ││● alice               │",
+            "│rather long line that has to wrap ││                      │",
+            "│in the narrow message pane        ││                      │",
+            "└──────────────────────────────────┘└──────────────────────┘",
+        ],
+    );
+}
+
+#[tokio::test]
+async fn wide_characters() {
+    let mut app = app();
+    join(&mut app, "rust");
+    app.handle_event(chat("rust", "bob", "世界你好世界你好世界你好"));
+    assert_screen(
+        &screen(&mut app, 60, 10)[2..4],
+        &[
+            "│[2024-01-01 12:00:00] bob: 世界你 ││● alice               │",
+            "│好世界你好世界你好                ││                      │",
+        ],
+    );
+
+    // multi-byte input used to panic, wide chars take two columns
+    press(&mut app, KeyCode::Char('e')).await;
+    type_text(&mut app, "é世x").await;
+    let (rows, cursor) = draw(&mut app, 60, 10);
+    assert_eq!(
+        rows[8],
+        "│é世x                                                      │"
+    );
+    assert_eq!(cursor, (5, 8));
+
+    press(&mut app, KeyCode::Left).await;
+    press(&mut app, KeyCode::Backspace).await;
+    let (rows, cursor) = draw(&mut app, 60, 10);
+    assert_eq!(
+        rows[8],
+        "│éx                                                        │"
+    );
+    assert_eq!(cursor, (2, 8));
+}
+
+#[tokio::test]
+async fn connection_status() {
+    let mut app = app();
+    app.server_url = Some("ws://localhost:3000".to_owned());
+    assert!(screen(&mut app, 60, 8)[4].ends_with(" ○ offline "));
+
+    app.handle_event(ChatEvent::Connected);
+    let rows = screen(&mut app, 60, 8);
+    assert!(rows[4].ends_with(" ● ws://localhost:3000 "), "{}", rows[4]);
+    assert_eq!(
+        rows[2],
+        "│Connection established                                    │"
+    );
+
+    app.handle_event(ChatEvent::Disconnected {
+        reason: "heartbeat timed out".to_owned(),
+        reconnecting: true,
+    });
+    let rows = screen(&mut app, 60, 8);
+    assert!(
+        rows[4].ends_with(" ◌ reconnecting to ws://localhost:3000 "),
+        "{}",
+        rows[4]
+    );
+
+    app.handle_event(ChatEvent::Disconnected {
+        reason: "closed".to_owned(),
+        reconnecting: false,
+    });
+    let rows = screen(&mut app, 60, 8);
+    assert!(rows[4].ends_with(" ○ offline "));
+    assert_eq!(
+        rows[2],
+        "│Connection closed                                         │"
+    );
+}
+
+#[tokio::test]
+async fn own_chats_show_their_delivery_state() {
+    let mut app = app();
+    join(&mut app, "rust");
+    app.track_chat("rust".into(), "hi".into(), None, Ok("n1".into()));
+    app.track_chat("rust".into(), "oops".into(), None, Ok("n2".into()));
+    app.track_chat("rust".into(), "later".into(), None, Ok("n3".into()));
+    assert_screen(
+        &screen(&mut app, 60, 10)[2..5],
+        &[
+            "│alice: hi (sending…)              ││● alice               │",
+            "│alice: oops (sending…)            ││                      │",
+            "│alice: later (sending…)           ││                      │",
+        ],
+    );
+
+    app.handle_event(ChatEvent::Ack(proto::Ack {
+        nonce: "n1".into(),
+        message_id: 7,
+        time: TIME.into(),
+        wait_secs: 0,
+    }));
+    // the echo of an own chat doesn't show up twice
+    app.handle_event(ChatEvent::Chat(proto::ChatMessage {
+        room: "rust".into(),
+        from: "alice".into(),
+        body: "hi".into(),
+        time: TIME.into(),
+        id: 7,
+        nonce: Some("n1".into()),
+        seq: 1,
+        edited: false,
+        deleted: false,
+        reply_to: None,
+        reactions: Vec::new(),
+        mentions: Vec::new(),
+    }));
+    app.handle_event(ChatEvent::Error(proto::ErrorInfo {
+        message: "not in room: rust".into(),
+        nonce: Some("n2".into()),
+        detail: None,
+    }));
+    // pending chats are retried while reconnecting, not given up
+    app.handle_event(ChatEvent::Disconnected {
+        reason: "heartbeat timed out".into(),
+        reconnecting: true,
+    });
+    assert_screen(
+        &screen(&mut app, 80, 12)[2..6],
+        &[
+            "│[2024-01-01 12:00:00] alice: hi                       ││                      │",
+            "│alice: oops (not sent: not in room: rust)             ││                      │",
+            "│alice: later (sending…)                               ││                      │",
+            "│Connection lost (heartbeat timed out), reconnecting   ││                      │",
+        ],
+    );
+
+    app.handle_event(ChatEvent::Disconnected {
+        reason: "closed".into(),
+        reconnecting: false,
+    });
+    assert_eq!(
+        screen(&mut app, 80, 12)[4],
+        "│alice: later (not sent: connection closed)            ││                      │"
+    );
+}
+
+#[tokio::test]
+async fn edited_and_deleted_chats_are_marked() {
+    let mut app = app();
+    join(&mut app, "rust");
+    for (id, from, body) in [
+        (1, "bob", "helo"),
+        (2, "bob", "secret"),
+        (3, "carol", "hey"),
+    ] {
+        app.handle_event(ChatEvent::Chat(posted("rust", id, from, body)));
+    }
+    app.handle_event(ChatEvent::Edited(proto::Edited {
+        room: "rust".into(),
+        message_id: 1,
+        new_body: "hello".into(),
+        by: "bob".into(),
+        seq: 4,
+    }));
+    app.handle_event(ChatEvent::Deleted(proto::Deleted {
+        room: "rust".into(),
+        message_id: 2,
+        by: "bob".into(),
+        seq: 5,
+    }));
+    assert_screen(
+        &screen(&mut app, 80, 12)[2..5],
+        &[
+            "│[2024-01-01 12:00:00] bob: hello (edited)             ││● alice               │",
+            "│[2024-01-01 12:00:00] bob: (message deleted)          ││                      │",
+            "│[2024-01-01 12:00:00] carol: hey                      ││                      │",
+        ],
+    );
+}
+
+#[tokio::test]
+async fn replies_quote_their_parent_and_open_as_a_thread() {
+    let mut app = app();
+    join(&mut app, "rust");
+    app.handle_event(ChatEvent::Chat(posted("rust", 1, "bob", "parse json?")));
+    app.handle_event(ChatEvent::Chat(posted("rust", 2, "carol", "hi all")));
+    let mut reply = posted("rust", 3, "dave", "serde_json");
+    reply.reply_to = Some(1);
+    app.handle_event(ChatEvent::Chat(reply));
+    assert_screen(
+        &screen(&mut app, 90, 12)[2..5],
+        &[
+            "│[2024-01-01 12:00:00] bob: parse json?                          ││● alice               │",
+            "│[2024-01-01 12:00:00] carol: hi all                             ││                      │",
+            "│[2024-01-01 12:00:00] dave: ↳ bob \"parse json?\" serde_json      ││                      │",
+        ],
+    );
+
+    // pick the reply, move up past carol to the question and open its thread
+    press(&mut app, KeyCode::Char('s')).await;
+    assert_eq!(app.rooms[1].selected, Some(3));
+    press(&mut app, KeyCode::Up).await;
+    press(&mut app, KeyCode::Up).await;
+    assert_eq!(app.rooms[1].selected, Some(1));
+    press(&mut app, KeyCode::Char('t')).await;
+    let rows = screen(&mut app, 90, 12);
+    assert_screen(
+        &rows[1..4],
+        &[
+            "┌Thread #rust────────────────────────────────────────────────────┐┌Members (1)───────────┐",
+            "│[2024-01-01 12:00:00] bob: parse json?                          ││● alice               │",
+            "│  [2024-01-01 12:00:00] dave: serde_json                        ││                      │",
+        ],
+    );
+    assert!(
+        rows[10].starts_with("│") && rows[9].starts_with("┌Reply to bob"),
+        "{:?}",
+        rows
+    );
+
+    press(&mut app, KeyCode::Esc).await;
+    assert!(screen(&mut app, 90, 12)[1].starts_with("┌Messages #rust"));
+
+    // picking a chat and pressing Enter starts the answer
+    press(&mut app, KeyCode::Char('s')).await;
+    press(&mut app, KeyCode::Up).await;
+    press(&mut app, KeyCode::Enter).await;
+    assert_eq!(app.rooms[1].reply_to, Some(2));
+    assert!(screen(&mut app, 90, 12)[9].starts_with("┌Reply to carol"));
+    press(&mut app, KeyCode::Esc).await;
+    assert_eq!(app.rooms[1].reply_to, None);
+}
+
+#[tokio::test]
+async fn reactions_are_counted_under_the_message() {
+    let mut app = app();
+    join(&mut app, "rust");
+    app.handle_event(ChatEvent::Chat(posted("rust", 1, "bob", "deployed")));
+    for (by, emoji) in [("carol", "👍"), ("dave", "👍"), ("dave", "🎉")] {
+        app.handle_event(ChatEvent::Reacted(proto::Reacted {
+            room: "rust".into(),
+            message_id: 1,
+            emoji: emoji.into(),
+            by: by.into(),
+            seq: 0,
+        }));
+    }
+    app.handle_event(ChatEvent::Unreacted(proto::Reacted {
+        room: "rust".into(),
+        message_id: 1,
+        emoji: "👍".into(),
+        by: "carol".into(),
+        seq: 0,
+    }));
+    assert_screen(
+        &screen(&mut app, 80, 12)[2..4],
+        &[
+            "│[2024-01-01 12:00:00] bob: deployed                   ││● alice               │",
+            "│  👍 1  🎉 1                                          ││                      │",
+        ],
+    );
+
+    // the picker completes shortcodes
+    press(&mut app, KeyCode::Char('s')).await;
+    press(&mut app, KeyCode::Char(':')).await;
+    type_text(&mut app, "t").await;
+    let rows = screen(&mut app, 80, 12);
+    assert_eq!(
+        rows[8],
+        "Tab to complete:  🎉 :tada:  🤔 :thinking:                            ○ offline "
+    );
+    assert!(rows[9].starts_with("┌React to bob"), "{:?}", rows);
+    press(&mut app, KeyCode::Tab).await;
+    assert_eq!(app.input, ":tada:");
+
+    // without a connection the reaction goes nowhere
+    press(&mut app, KeyCode::Enter).await;
+    assert_eq!(app.rooms[1].react_to, None);
+    assert_eq!(
+        screen(&mut app, 80, 12)[4].trim_end_matches([' ', '│']),
+        "│Not connected"
+    );
+}
+
+#[tokio::test]
+async fn typing_members_show_in_the_tip_row() {
+    let mut app = app();
+    join(&mut app, "rust");
+    let typing = |name: &str, state| {
+        ChatEvent::Typing(proto::TypingStatus {
+            room: "rust".into(),
+            name: name.into(),
+            state,
+            expires_secs: 6,
+        })
+    };
+    app.handle_event(typing("bob", proto::TypingState::Active));
+    assert_eq!(
+        screen(&mut app, 90, 12)[8],
+        "Press q to exit, e to start editing, PgUp/PgDn to scroll, Ctrl+ bob is typing…  ○ offline "
+    );
+
+    app.handle_event(typing("carol", proto::TypingState::Active));
+    assert!(screen(&mut app, 90, 12)[8].contains(" bob and carol are typing… "));
+
+    // a chat ends the typing of its author
+    app.handle_event(chat("rust", "bob", "hi"));
+    app.handle_event(typing("carol", proto::TypingState::Stopped));
+    assert!(!screen(&mut app, 90, 12)[8].contains("typing"));
+
+    // nobody refreshed it
+    app.handle_event(ChatEvent::Typing(proto::TypingStatus {
+        room: "rust".into(),
+        name: "dave".into(),
+        state: proto::TypingState::Active,
+        expires_secs: 0,
+    }));
+    assert!(!screen(&mut app, 90, 12)[8].contains("typing"));
+}
+
+#[tokio::test]
+async fn unread_chats_are_counted_and_divided_off() {
+    let mut app = app();
+    join(&mut app, "rust");
+    join(&mut app, "go");
+    for (id, body) in [(1, "one"), (2, "two"), (3, "three")] {
+        app.handle_event(ChatEvent::Chat(posted("rust", id, "bob", body)));
+    }
+    // another session of ours read up to the second one
+    app.handle_event(ChatEvent::Read(proto::MarkRead {
+        room: "rust".into(),
+        message_id: 2,
+    }));
+    assert!(screen(&mut app, 70, 10)[0].contains("2 rust (1)"));
+
+    app.switch_room(1);
+    assert_screen(
+        &screen(&mut app, 70, 10)[1..6],
+        &[
+            "┌Messages #rust──────────────────────────────┐┌Members (1)───────────┐",
+            "│[2024-01-01 12:00:00] bob: two              ││● alice               │",
+            "│──── new messages ────                      ││                      │",
+            "│[2024-01-01 12:00:00] bob: three            ││                      │",
+            "└────────────────────────────────────────────┘└──────────────────────┘",
+        ],
+    );
+
+    // after a reconnect the view stays at the first chat we missed
+    app.connection = ConnectionStatus::Reconnecting;
+    app.handle_event(ChatEvent::Connected);
+    app.handle_event(ChatEvent::Read(proto::MarkRead {
+        room: "rust".into(),
+        message_id: 3,
+    }));
+    for id in 4..=9 {
+        let body = format!("missed {}", id);
+        app.handle_event(ChatEvent::Chat(posted("rust", id, "bob", &body)));
+    }
+    assert_screen(
+        &screen(&mut app, 70, 10)[1..6],
+        &[
+            "┌Messages #rust──────────────────────────────┐┌Members (1)───────────┐",
+            "│──── new messages ────                      ││● alice               │",
+            "│[2024-01-01 12:00:00] bob: missed 4         ││                      │",
+            "│[2024-01-01 12:00:00] bob: missed 5         ││                      │",
+            "└────────────── 4 new messages (End to jump) ┘└──────────────────────┘",
+        ],
+    );
+
+    // once the user takes over new chats are followed again
+    press(&mut app, KeyCode::End).await;
+    app.handle_event(ChatEvent::Chat(posted("rust", 10, "bob", "live")));
+    assert_eq!(
+        screen(&mut app, 70, 10)[4],
+        "│[2024-01-01 12:00:00] bob: live             ││                      │"
+    );
+}
+
+#[tokio::test]
+async fn mentions_are_highlighted_notified_and_collected() {
+    let config: ConfigFile = toml::from_str("notify = \"osc777\"").unwrap();
+    let mut app = App::new(config, Profile::default(), false);
+    join(&mut app, "rust");
+    join(&mut app, "go");
+    app.handle_event(chat("rust", "bob", "hey @alice, look"));
+    app.handle_event(chat("rust", "bob", "and alice without the at"));
+    assert!(screen(&mut app, 70, 10)[0].contains("2 rust (2) @1"));
+    assert_eq!(
+        app.alerts,
+        ["\x1b]777;notify;bob in #rust;hey @alice, look\x07"]
+    );
+
+    app.switch_room(1);
+    let mut terminal = Terminal::new(TestBackend::new(70, 10)).unwrap();
+    terminal.draw(|f| app.ui(f)).unwrap();
+    let buffer = terminal.backend().buffer();
+    assert_eq!(buffer.get(1, 2).fg, Color::Red);
+    assert_eq!(buffer.get(1, 3).fg, Color::Reset);
+
+    press(&mut app, KeyCode::Char('e')).await;
+    type_text(&mut app, "/mentions").await;
+    press(&mut app, KeyCode::Enter).await;
+    let rows = screen(&mut app, 70, 10);
+    assert_eq!(
+        rows[1..3],
+        [
+            "┌Mentions────────────────────────────────────┐┌Members (1)───────────┐",
+            "│[2024-01-01 12:00:00] #rust bob: hey        ││● alice               │",
+        ]
+    );
+    press(&mut app, KeyCode::Esc).await;
+    assert!(screen(&mut app, 70, 10)[1].starts_with("┌Messages #rust"));
+}
+
+#[tokio::test]
+async fn member_statuses_and_whois_are_shown() {
+    let mut app = app();
+    join(&mut app, "rust");
+    let update = |name: &str, id, status| {
+        ChatEvent::Presence(proto::Presence {
+            room: "rust".into(),
+            kind: proto::PresenceKind::Updated,
+            member: proto::Member {
+                status,
+                ..member(id, name)
+            },
+            seq: 0,
+        })
+    };
+    app.handle_event(update("bob", 2, proto::Status::Busy));
+    let away = proto::Status::Away {
+        message: "lunch".into(),
+    };
+    app.handle_event(update("carol", 3, away));
+    app.handle_event(update("dave", 4, proto::Status::Invisible));
+    assert_eq!(
+        screen(&mut app, 70, 10)[1..5],
+        [
+            "┌Messages #rust──────────────────────────────┐┌Members (3)───────────┐",
+            "│                                            ││● alice               │",
+            "│                                            ││● bob busy            │",
+            "│                                            ││○ carol away: lunch   │",
+        ]
+    );
+
+    // going invisible drops the member from the list
+    app.handle_event(update("bob", 2, proto::Status::Invisible));
+    assert!(screen(&mut app, 70, 10)[1].contains("Members (2)"));
+
+    app.handle_event(ChatEvent::WhoIs(proto::UserInfo {
+        name: "carol".into(),
+        status: proto::Status::Online,
+        profile: proto::Profile {
+            display_name: Some("Carol".into()),
+            pronouns: Some("she/her".into()),
+            timezone: Some("UTC".into()),
+            bio: None,
+        },
+        idle_secs: 120,
+    }));
+    assert_eq!(
+        screen(&mut app, 70, 10)[2..4],
+        [
+            "│carol (Carol, she/her) is online, idle 2m   ││● alice               │",
+            "│  timezone: UTC                             ││○ carol away: lunch   │",
+        ]
+    );
+}
+
+#[tokio::test]
+async fn room_topic_is_shown_and_described() {
+    let mut app = app();
+    join(&mut app, "rust");
+    app.handle_event(ChatEvent::RoomInfo(proto::RoomInfo {
+        room: "rust".into(),
+        topic: "borrow checking".into(),
+        created_by: Some("alice".into()),
+        created_at: TIME.into(),
+        settings: proto::RoomSettings {
+            slow_mode_secs: 30,
+            ..proto::RoomSettings::default()
+        },
+        ..proto::RoomInfo::default()
+    }));
+    press(&mut app, KeyCode::Char('e')).await;
+    type_text(&mut app, "/info").await;
+    press(&mut app, KeyCode::Enter).await;
+    assert_screen(
+        &screen(&mut app, 70, 11)[1..7],
+        &[
+            "┌Messages #rust · borrow checking────────────┐┌Members (1)───────────┐",
+            "│Topic of #rust: borrow checking             ││● alice               │",
+            "│#rust: borrow checking                      ││                      │",
+            "│  created by alice at 2024-01-01 12:00:00   ││                      │",
+            "│  slow mode 30s                             ││                      │",
+            "└────────────────────────────────────────────┘└──────────────────────┘",
+        ],
+    );
+}
+
+#[tokio::test]
+async fn room_lists_invites_and_refusals_are_shown() {
+    let mut app = app();
+    join(&mut app, "rust");
+    app.handle_event(ChatEvent::Rooms(proto::RoomList {
+        rooms: vec![
+            proto::RoomSummary {
+                room: "main".into(),
+                topic: String::new(),
+                visibility: proto::Visibility::Public,
+                members: 5,
+            },
+            proto::RoomSummary {
+                room: "vault".into(),
+                topic: "secrets".into(),
+                visibility: proto::Visibility::Password,
+                members: 2,
+            },
+        ],
+    }));
+    app.handle_event(ChatEvent::Invite(proto::InviteCode {
+        room: "rust".into(),
+        code: "a1b2c3d4e5".into(),
+        max_uses: 3,
+        expires_secs: 3600,
+    }));
+    app.handle_event(ChatEvent::Error(proto::ErrorInfo {
+        message: "cannot join #vault: a password is required".into(),
+        nonce: None,
+        detail: Some(proto::ErrorDetail::JoinRefused {
+            room: "vault".into(),
+            reason: proto::JoinRefusal::PasswordRequired,
+        }),
+    }));
+    assert_screen(
+        &screen(&mut app, 80, 12)[2..7],
+        &[
+            "│  #main (5)                                           ││● alice               │",
+            "│  #vault (2) password protected: secrets              ││                      │",
+            "│Invite to #rust (3 uses, expires in 1h): /accept      ││                      │",
+            "│a1b2c3d4e5                                            ││                      │",
+            "│#vault needs a password: /join vault <password>       ││                      │",
+        ],
+    );
+}
+
+#[tokio::test]
+async fn slow_mode_holds_chats_back() {
+    let mut app = app();
+    join(&mut app, "rust");
+    app.track_chat("rust".into(), "hi".into(), None, Ok("n1".into()));
+    app.handle_event(ChatEvent::Ack(proto::Ack {
+        nonce: "n1".into(),
+        message_id: 7,
+        time: TIME.into(),
+        wait_secs: 30,
+    }));
+    press(&mut app, KeyCode::Char('e')).await;
+    type_text(&mut app, "again!").await;
+    press(&mut app, KeyCode::Backspace).await;
+    press(&mut app, KeyCode::Enter).await;
+    assert_screen(
+        &screen(&mut app, 60, 10)[7..],
+        &[
+            "┌Slow mode, wait 30s───────────────────────────────────────┐",
+            "│again                                                     │",
+            "└──────────────────────────────────────────────────────────┘",
+        ],
+    );
+    for _ in 0..5 {
+        press(&mut app, KeyCode::Backspace).await;
+    }
+
+    // an error names the wait for chats the server held back
+    app.handle_event(ChatEvent::Error(proto::ErrorInfo {
+        message: "slow mode in #rust, wait 5s".into(),
+        nonce: None,
+        detail: Some(proto::ErrorDetail::SlowMode {
+            room: "rust".into(),
+            wait_secs: 5,
+        }),
+    }));
+    assert_eq!(app.rooms[app.active].slow_mode_wait(), Some(5));
+
+    app.rooms[app.active].slow_until = Some(Instant::now());
+    type_text(&mut app, "again").await;
+    assert_screen(
+        &screen(&mut app, 60, 10)[7..],
+        &[
+            "┌Input─────────────────────────────────────────────────────┐",
+            "│again                                                     │",
+            "└──────────────────────────────────────────────────────────┘",
+        ],
+    );
+
+    // commands are not held back
+    app.rooms[app.active].slow_down(30);
+    for _ in 0..5 {
+        press(&mut app, KeyCode::Backspace).await;
+    }
+    type_text(&mut app, "/leave").await;
+    press(&mut app, KeyCode::Enter).await;
+    assert!(app.rooms[app.active].is_status());
+}
+
+#[tokio::test]
+async fn expired_chats_and_closed_rooms_go_away() {
+    let mut app = app();
+    join(&mut app, "rust");
+    app.handle_event(ChatEvent::Chat(posted("rust", 1, "bob", "parse json?")));
+    app.handle_event(ChatEvent::Chat(posted("rust", 2, "carol", "hi all")));
+    let mut reply = posted("rust", 3, "dave", "serde_json");
+    reply.reply_to = Some(1);
+    app.handle_event(ChatEvent::Chat(reply));
+    app.handle_event(ChatEvent::Expired(proto::Expired {
+        room: "rust".into(),
+        message_ids: vec![1, 2],
+        seq: 4,
+    }));
+    assert_screen(
+        &screen(&mut app, 70, 10)[2..4],
+        &[
+            "│[2024-01-01 12:00:00] dave: serde_json      ││● alice               │",
+            "│                                            ││                      │",
+        ],
+    );
+
+    app.handle_event(ChatEvent::RoomClosed(proto::RoomClosed {
+        room: "rust".into(),
+    }));
+    assert_screen(
+        &screen(&mut app, 70, 10)[..3],
+        &[
+            " 1 status                                                             ",
+            "┌Messages #status────────────────────────────────────────────────────┐",
+            "│#rust closed, its time ran out                                      │",
+        ],
+    );
+}
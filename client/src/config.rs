@@ -0,0 +1,287 @@
+use std::{collections::HashMap, path::PathBuf, str::FromStr};
+
+use anyhow::{anyhow, Context, Result};
+use crossterm::event::{KeyCode, KeyEvent, KeyModifiers};
+use ratatui::style::Color;
+use serde::{Deserialize, Deserializer};
+
+pub use chat_client::TlsOptions;
+
+/// Contents of `~/.config/rust-chat/client.toml`
+///
+/// Top level connection keys are the defaults of every profile:
+///
+/// ```toml
+/// url = "ws://localhost:3000"
+/// nickname = "alice"
+/// rooms = ["rust"]
+/// default_profile = "work"
+/// notify = "osc777"
+///
+/// [profiles.work]
+/// url = "wss://chat.example.com:14514"
+/// token = "secret"
+/// tls = { ca_file = "/etc/ssl/work-ca.pem" }
+///
+/// [theme]
+/// mention = "light red"
+///
+/// [keys]
+/// next_room = "ctrl-n"
+/// ```
+#[derive(Deserialize, Default, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct ConfigFile {
+    /// Profile used when none is given on the command line
+    pub default_profile: Option<String>,
+    #[serde(flatten)]
+    pub defaults: Profile,
+    pub profiles: HashMap<String, Profile>,
+    /// How mentions and private messages get our attention
+    pub notify: Notify,
+    pub theme: Theme,
+    pub keys: KeyBindings,
+}
+
+/// Terminal notification for mentions and private messages
+#[derive(Deserialize, Default, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "lowercase")]
+pub enum Notify {
+    /// Ring the terminal bell
+    #[default]
+    Bell,
+    /// Desktop notification through OSC 9, understood by iTerm2, WezTerm and kitty
+    Osc9,
+    /// Desktop notification through OSC 777, understood by foot, Ghostty and urxvt
+    Osc777,
+    Off,
+}
+
+impl Notify {
+    /// Escape sequence that notifies about `body`, titled `title` where the
+    /// terminal shows titles
+    pub fn sequence(&self, title: &str, body: &str) -> Option<String> {
+        // control characters would end the sequence early
+        let clean = |text: &str| -> String { text.chars().filter(|c| !c.is_control()).collect() };
+        match self {
+            Notify::Bell => Some("\x07".to_owned()),
+            Notify::Osc9 => Some(format!("\x1b]9;{}: {}\x07", clean(title), clean(body))),
+            Notify::Osc777 => Some(format!(
+                "\x1b]777;notify;{};{}\x07",
+                clean(title).replace(';', ","),
+                clean(body)
+            )),
+            Notify::Off => None,
+        }
+    }
+}
+
+/// Connection settings of one server
+#[derive(Deserialize, Default, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Profile {
+    pub url: Option<String>,
+    pub nickname: Option<String>,
+    /// Rooms joined right after login, `main` is always joined
+    pub rooms: Vec<String>,
+    /// Sent as `Authorization: Bearer <token>` with the handshake
+    pub token: Option<String>,
+    pub tls: Option<TlsOptions>,
+}
+
+impl Profile {
+    /// Fill the unset fields from `defaults`
+    fn or(self, defaults: &Profile) -> Profile {
+        Profile {
+            url: self.url.or_else(|| defaults.url.clone()),
+            nickname: self.nickname.or_else(|| defaults.nickname.clone()),
+            rooms: if self.rooms.is_empty() {
+                defaults.rooms.clone()
+            } else {
+                self.rooms
+            },
+            token: self.token.or_else(|| defaults.token.clone()),
+            tls: self.tls.or_else(|| defaults.tls.clone()),
+        }
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct Theme {
+    /// Selected tab
+    #[serde(deserialize_with = "de_color")]
+    pub highlight: Color,
+    /// Input box while editing
+    #[serde(deserialize_with = "de_color")]
+    pub input: Color,
+    /// Mention counters and highlights
+    #[serde(deserialize_with = "de_color")]
+    pub mention: Color,
+    /// Online marker in the member list
+    #[serde(deserialize_with = "de_color")]
+    pub online: Color,
+}
+
+impl Default for Theme {
+    fn default() -> Theme {
+        Theme {
+            highlight: Color::Reset,
+            input: Color::Yellow,
+            mention: Color::Red,
+            online: Color::Green,
+        }
+    }
+}
+
+fn de_color<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Color, D::Error> {
+    let name = String::deserialize(deserializer)?;
+    Color::from_str(&name).map_err(|_| serde::de::Error::custom(format!("unknown color: {name}")))
+}
+
+/// A key with modifiers, written like `q`, `ctrl-n`, `alt-enter` or `pageup`
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyBinding {
+    pub code: KeyCode,
+    pub modifiers: KeyModifiers,
+}
+
+impl KeyBinding {
+    pub const fn new(code: KeyCode, modifiers: KeyModifiers) -> KeyBinding {
+        KeyBinding { code, modifiers }
+    }
+
+    pub fn matches(&self, key: &KeyEvent) -> bool {
+        // shift is part of the character for letters, e.g. `G`
+        let modifiers = match key.code {
+            KeyCode::Char(_) => key.modifiers - KeyModifiers::SHIFT,
+            _ => key.modifiers,
+        };
+        key.code == self.code && modifiers == self.modifiers
+    }
+}
+
+impl FromStr for KeyBinding {
+    type Err = anyhow::Error;
+
+    fn from_str(s: &str) -> Result<KeyBinding> {
+        let mut modifiers = KeyModifiers::NONE;
+        let mut rest = s;
+        while let Some((modifier, key)) = rest.split_once('-').filter(|(_, key)| !key.is_empty()) {
+            modifiers |= match modifier.to_lowercase().as_str() {
+                "ctrl" | "control" => KeyModifiers::CONTROL,
+                "alt" => KeyModifiers::ALT,
+                "shift" => KeyModifiers::SHIFT,
+                _ => return Err(anyhow!("unknown modifier in key binding: {s}")),
+            };
+            rest = key;
+        }
+
+        let code = match rest.to_lowercase().as_str() {
+            "enter" => KeyCode::Enter,
+            "esc" => KeyCode::Esc,
+            "tab" => KeyCode::Tab,
+            "backspace" => KeyCode::Backspace,
+            "up" => KeyCode::Up,
+            "down" => KeyCode::Down,
+            "left" => KeyCode::Left,
+            "right" => KeyCode::Right,
+            "home" => KeyCode::Home,
+            "end" => KeyCode::End,
+            "pageup" => KeyCode::PageUp,
+            "pagedown" => KeyCode::PageDown,
+            "space" => KeyCode::Char(' '),
+            key if key.len() > 1 && key.starts_with('f') => key[1..]
+                .parse()
+                .map(KeyCode::F)
+                .map_err(|_| anyhow!("unknown key in key binding: {s}"))?,
+            _ => {
+                let mut chars = rest.chars();
+                match (chars.next(), chars.next()) {
+                    (Some(c), None) => KeyCode::Char(c),
+                    _ => return Err(anyhow!("unknown key in key binding: {s}")),
+                }
+            }
+        };
+        Ok(KeyBinding::new(code, modifiers))
+    }
+}
+
+impl<'de> Deserialize<'de> for KeyBinding {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<KeyBinding, D::Error> {
+        let s = String::deserialize(deserializer)?;
+        s.parse().map_err(serde::de::Error::custom)
+    }
+}
+
+#[derive(Deserialize, Clone, Debug)]
+#[serde(default, deny_unknown_fields)]
+pub struct KeyBindings {
+    pub quit: KeyBinding,
+    pub edit: KeyBinding,
+    /// Pick a message in the scrollback to reply to
+    pub select: KeyBinding,
+    pub next_room: KeyBinding,
+    pub prev_room: KeyBinding,
+    pub page_up: KeyBinding,
+    pub page_down: KeyBinding,
+    pub jump_to_bottom: KeyBinding,
+}
+
+impl Default for KeyBindings {
+    fn default() -> KeyBindings {
+        KeyBindings {
+            quit: KeyBinding::new(KeyCode::Char('q'), KeyModifiers::NONE),
+            edit: KeyBinding::new(KeyCode::Char('e'), KeyModifiers::NONE),
+            select: KeyBinding::new(KeyCode::Char('s'), KeyModifiers::NONE),
+            next_room: KeyBinding::new(KeyCode::Char('n'), KeyModifiers::CONTROL),
+            prev_room: KeyBinding::new(KeyCode::Char('p'), KeyModifiers::CONTROL),
+            page_up: KeyBinding::new(KeyCode::PageUp, KeyModifiers::NONE),
+            page_down: KeyBinding::new(KeyCode::PageDown, KeyModifiers::NONE),
+            jump_to_bottom: KeyBinding::new(KeyCode::End, KeyModifiers::NONE),
+        }
+    }
+}
+
+/// `$XDG_CONFIG_HOME/rust-chat/client.toml`, falling back to `~/.config`
+pub fn default_config_path() -> Option<PathBuf> {
+    let config_home = std::env::var_os("XDG_CONFIG_HOME")
+        .filter(|dir| !dir.is_empty())
+        .map(PathBuf::from)
+        .or_else(|| dirs::home_dir().map(|home| home.join(".config")))?;
+    Some(config_home.join("rust-chat").join("client.toml"))
+}
+
+impl ConfigFile {
+    /// Read the config file, a missing default file is an empty config
+    pub fn load(path: Option<&PathBuf>) -> Result<ConfigFile> {
+        let (path, required) = match path {
+            Some(path) => (path.clone(), true),
+            None => match default_config_path() {
+                Some(path) => (path, false),
+                None => return Ok(ConfigFile::default()),
+            },
+        };
+
+        let text = match std::fs::read_to_string(&path) {
+            Ok(text) => text,
+            Err(err) if !required && err.kind() == std::io::ErrorKind::NotFound => {
+                return Ok(ConfigFile::default());
+            }
+            Err(err) => return Err(err).context(format!("reading {}", path.display())),
+        };
+        toml::from_str(&text).context(format!("parsing {}", path.display()))
+    }
+
+    /// Settings of the named profile, or of `default_profile`, on top of the defaults
+    pub fn profile(&self, name: Option<&str>) -> Result<Profile> {
+        match name.or(self.default_profile.as_deref()) {
+            Some(name) => self
+                .profiles
+                .get(name)
+                .map(|profile| profile.clone().or(&self.defaults))
+                .ok_or_else(|| anyhow!("no profile named {name}")),
+            None => Ok(self.defaults.clone()),
+        }
+    }
+}
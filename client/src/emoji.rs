@@ -0,0 +1,49 @@
+//! Emoji shortcodes for reactions, like `:+1:`
+
+/// Shortcodes and their emoji, offered in this order
+const SHORTCODES: &[(&str, &str)] = &[
+    ("+1", "👍"),
+    ("-1", "👎"),
+    ("tada", "🎉"),
+    ("eyes", "👀"),
+    ("joy", "😂"),
+    ("smile", "😄"),
+    ("thinking", "🤔"),
+    ("rocket", "🚀"),
+    ("fire", "🔥"),
+    ("clap", "👏"),
+    ("pray", "🙏"),
+    ("100", "💯"),
+    ("ok_hand", "👌"),
+    ("wave", "👋"),
+    ("cry", "😢"),
+    ("white_check_mark", "✅"),
+    ("x", "❌"),
+];
+
+/// Emoji of a reaction typed as `:shortcode:` or as the emoji itself
+pub fn reaction(input: &str) -> Option<String> {
+    let input = input.trim();
+    match input
+        .strip_prefix(':')
+        .and_then(|rest| rest.strip_suffix(':'))
+    {
+        Some(code) => SHORTCODES
+            .iter()
+            .find(|(known, _)| *known == code)
+            .map(|(_, emoji)| (*emoji).to_owned()),
+        None if input.is_empty() || input.starts_with(':') => None,
+        None if input.contains(char::is_whitespace) => None,
+        None => Some(input.to_owned()),
+    }
+}
+
+/// Shortcodes that start with what was typed so far, e.g. `:t`
+pub fn completions(typed: &str) -> Vec<(&'static str, &'static str)> {
+    let prefix = typed.trim().trim_start_matches(':').trim_end_matches(':');
+    SHORTCODES
+        .iter()
+        .filter(|(code, _)| code.starts_with(prefix))
+        .copied()
+        .collect()
+}
@@ -0,0 +1,370 @@
+use chat_client::{ChatClient, ClientOptions, Event, Events};
+use clap::ValueEnum;
+use tokio::io::{AsyncBufReadExt, BufReader};
+
+use super::config::Profile;
+use super::room::{describe_invite, describe_room, describe_rooms, describe_status, describe_user};
+
+/// Exit codes of the headless client
+pub const EXIT_OK: i32 = 0;
+pub const EXIT_USAGE: i32 = 2;
+pub const EXIT_CONNECT: i32 = 3;
+pub const EXIT_AUTH: i32 = 4;
+pub const EXIT_SEND: i32 = 5;
+
+#[derive(ValueEnum, Clone, Copy, Debug, PartialEq)]
+pub enum OutputFormat {
+    /// One human readable line per event
+    Text,
+    /// One JSON object per line
+    Json,
+}
+
+struct Headless {
+    client: ChatClient,
+    format: OutputFormat,
+    /// Room plain input lines are sent to
+    room: String,
+    /// Exit code once the session ends
+    status: i32,
+    /// A send failed, the connection is unusable
+    broken: bool,
+}
+
+/// Line mode client: stdin lines are chat messages or `/` commands,
+/// incoming events are written to stdout. Returns the process exit code.
+pub async fn run(profile: Profile, format: OutputFormat, keep_open: bool) -> i32 {
+    let Some(url) = profile.url.clone() else {
+        eprintln!("no server url, pass --url or select a profile");
+        return EXIT_USAGE;
+    };
+
+    // a script should see a lost connection instead of a silent retry
+    let mut options = ClientOptions::new(&url);
+    options.token = profile.token.clone();
+    options.tls = profile.tls.clone();
+    options.reconnect = None;
+    let (client, mut events) = match ChatClient::connect(options).await {
+        Ok(connection) => connection,
+        Err(err) => {
+            eprintln!("connection to {} failed: {}", url, err);
+            return match err {
+                chat_client::Error::Unauthorized => EXIT_AUTH,
+                _ => EXIT_CONNECT,
+            };
+        }
+    };
+
+    let mut session = Headless {
+        client,
+        format,
+        room: profile
+            .rooms
+            .first()
+            .cloned()
+            .unwrap_or_else(|| "main".to_owned()),
+        status: EXIT_OK,
+        broken: false,
+    };
+
+    if let Some(name) = profile.nickname {
+        let result = session.client.login(&name).await;
+        session.check_sent(result);
+    }
+    for room in profile.rooms {
+        let result = session.client.join(&room).await;
+        session.check_sent(result);
+    }
+
+    let mut lines = BufReader::new(tokio::io::stdin()).lines();
+    let mut stdin_open = true;
+    loop {
+        if session.broken {
+            return EXIT_SEND;
+        }
+
+        tokio::select! {
+            line = lines.next_line(), if stdin_open => match line {
+                Ok(Some(line)) => {
+                    if !session.submit(line).await {
+                        break;
+                    }
+                }
+                _ => {
+                    stdin_open = false;
+                    if !keep_open {
+                        break;
+                    }
+                }
+            },
+            event = events.next_event() => match event {
+                Some(Event::Disconnected { reason, .. }) => {
+                    if stdin_open {
+                        eprintln!("connection lost: {}", reason);
+                        return EXIT_CONNECT;
+                    }
+                    return session.status;
+                }
+                Some(event) => session.handle_event(event),
+                None => return session.status,
+            },
+        }
+    }
+
+    // the close is queued behind everything we sent, drain the replies
+    session.client.close().await;
+    session.drain(&mut events).await;
+
+    session.status
+}
+
+impl Headless {
+    fn check_sent<T>(&mut self, result: Result<T, chat_client::Error>) {
+        if let Err(err) = result {
+            eprintln!("send failed: {}", err);
+            self.status = EXIT_SEND;
+            self.broken = true;
+        }
+    }
+
+    /// Send one input line, returns false when the session should end
+    async fn submit(&mut self, line: String) -> bool {
+        let line = line.trim_end_matches(['\r', '\n']);
+        if line.is_empty() {
+            return true;
+        }
+
+        let Some(command) = line.strip_prefix('/') else {
+            let result = self.client.send_chat(&self.room, line).await;
+            self.check_sent(result);
+            return true;
+        };
+
+        let (name, arg) = command.split_once(' ').unwrap_or((command, ""));
+        let arg = arg.trim();
+        match name {
+            "quit" | "exit" => return false,
+            "login" | "nick" if !arg.is_empty() => {
+                let result = self.client.login(arg).await;
+                self.check_sent(result);
+            }
+            "join" if !arg.is_empty() => {
+                let (room, result) = match arg.split_once(' ') {
+                    Some((room, password)) => (
+                        room,
+                        self.client.join_with_password(room, password.trim()).await,
+                    ),
+                    None => (arg, self.client.join(arg).await),
+                };
+                self.check_sent(result);
+                self.room = room.trim_start_matches('#').to_owned();
+            }
+            "create" => match arg
+                .split_once(' ')
+                .map(|(room, minutes)| (room, minutes.trim().parse::<u64>()))
+            {
+                Some((room, Ok(minutes))) if minutes > 0 => {
+                    let result = self.client.create_room(room, minutes * 60).await;
+                    self.check_sent(result);
+                    self.room = room.trim_start_matches('#').to_owned();
+                }
+                _ => {
+                    eprintln!("usage: /create <room> <minutes>");
+                    self.status = EXIT_USAGE;
+                }
+            },
+            "invite" => {
+                let limits: Result<Vec<u64>, _> = arg.split_whitespace().map(str::parse).collect();
+                match limits {
+                    Ok(limits) if limits.len() <= 2 => {
+                        let max_uses = limits.first().copied().unwrap_or(0) as u32;
+                        let expires_secs = limits.get(1).copied().unwrap_or(0) * 60;
+                        let result = self.client.invite(&self.room, max_uses, expires_secs).await;
+                        self.check_sent(result);
+                    }
+                    _ => {
+                        eprintln!("usage: /invite [max uses] [minutes]");
+                        self.status = EXIT_USAGE;
+                    }
+                }
+            }
+            "accept" if !arg.is_empty() => {
+                let result = self.client.accept_invite(arg).await;
+                self.check_sent(result);
+            }
+            "rooms" => {
+                let result = self.client.list_rooms().await;
+                self.check_sent(result);
+            }
+            "leave" => {
+                let room = if arg.is_empty() {
+                    self.room.as_str()
+                } else {
+                    arg
+                };
+                let result = self.client.leave(room).await;
+                self.check_sent(result);
+            }
+            "msg" => match arg.split_once(' ') {
+                Some((to, body)) if !body.trim().is_empty() => {
+                    let result = self.client.send_dm(to, body).await;
+                    self.check_sent(result);
+                }
+                _ => {
+                    eprintln!("usage: /msg <name> <message>");
+                    self.status = EXIT_USAGE;
+                }
+            },
+            "reply" => match arg.split_once(' ').map(|(id, body)| (id.parse(), body)) {
+                Some((Ok(id), body)) if !body.trim().is_empty() => {
+                    let result = self.client.reply(&self.room, id, body).await;
+                    self.check_sent(result);
+                }
+                _ => {
+                    eprintln!("usage: /reply <message id> <message>");
+                    self.status = EXIT_USAGE;
+                }
+            },
+            "react" | "unreact" => match arg.split_once(' ').map(|(id, emoji)| (id.parse(), emoji))
+            {
+                Some((Ok(id), emoji)) if !emoji.trim().is_empty() => {
+                    let result = if name == "react" {
+                        self.client.react(id, emoji.trim()).await
+                    } else {
+                        self.client.unreact(id, emoji.trim()).await
+                    };
+                    self.check_sent(result);
+                }
+                _ => {
+                    eprintln!("usage: /{} <message id> <emoji>", name);
+                    self.status = EXIT_USAGE;
+                }
+            },
+            "whois" if !arg.is_empty() => {
+                let result = self.client.whois(arg).await;
+                self.check_sent(result);
+            }
+            "topic" if !arg.is_empty() => {
+                let result = self.client.set_topic(&self.room, Some(arg), None).await;
+                self.check_sent(result);
+            }
+            "info" => {
+                let result = self.client.room_info(&self.room).await;
+                self.check_sent(result);
+            }
+            "room" if !arg.is_empty() => {
+                self.room = arg.trim_start_matches('#').to_owned();
+            }
+            // `//text` sends a message starting with a slash
+            _ if command.starts_with('/') => {
+                let result = self.client.send_chat(&self.room, command).await;
+                self.check_sent(result);
+            }
+            _ => {
+                eprintln!("unknown command: /{}", command);
+                self.status = EXIT_USAGE;
+            }
+        }
+        true
+    }
+
+    fn handle_event(&mut self, event: Event) {
+        if let Event::Error(error) = &event {
+            eprintln!("server error: {}", error.message);
+            self.status = EXIT_SEND;
+        }
+        self.print(&event);
+    }
+
+    /// Print what is still queued until the connection is closed
+    async fn drain(&mut self, events: &mut Events) {
+        while let Some(event) = events.next_event().await {
+            if matches!(event, Event::Disconnected { .. }) {
+                break;
+            }
+            self.handle_event(event);
+        }
+    }
+
+    fn print(&self, event: &Event) {
+        match self.format {
+            OutputFormat::Json => {
+                if let Ok(line) = serde_json::to_string(event) {
+                    println!("{}", line);
+                }
+            }
+            OutputFormat::Text => match event {
+                Event::Notice { message } | Event::Motd { message } => println!("{}", message),
+                Event::Chat(chat) => match chat.reply_to {
+                    Some(parent) => println!(
+                        "[{}] #{} {} (re {}): {}",
+                        chat.time, chat.room, chat.from, parent, chat.body
+                    ),
+                    None => println!(
+                        "[{}] #{} {}: {}",
+                        chat.time, chat.room, chat.from, chat.body
+                    ),
+                },
+                Event::Roster(roster) => {
+                    let names: Vec<&str> = roster.members.iter().map(|m| m.name.as_str()).collect();
+                    println!("* #{} members: {}", roster.room, names.join(", "));
+                }
+                Event::WhoIs(info) => println!("* {}", describe_user(info)),
+                Event::RoomInfo(info) => println!("* {}", describe_room(info)),
+                Event::Invite(invite) => println!("* {}", describe_invite(invite)),
+                Event::InviteAccepted(accepted) => {
+                    println!("* accepted invite to #{}", accepted.room)
+                }
+                Event::Rooms(list) => println!("* {}", describe_rooms(list)),
+                Event::RoomClosed(closed) => println!("* #{} closed", closed.room),
+                Event::Expired(expired) => {
+                    for message_id in &expired.message_ids {
+                        println!("* #{} message {} expired", expired.room, message_id);
+                    }
+                }
+                Event::Direct(direct) => {
+                    println!(
+                        "[{}] @{} -> {}: {}",
+                        direct.time, direct.from, direct.to, direct.body
+                    )
+                }
+                Event::Presence(presence) => {
+                    let action = match &presence.kind {
+                        proto::PresenceKind::Joined => "joined".to_owned(),
+                        proto::PresenceKind::Left => "left".to_owned(),
+                        proto::PresenceKind::Renamed { from } => format!("was {}, now in", from),
+                        proto::PresenceKind::Updated => {
+                            format!("is {} in", describe_status(&presence.member.status))
+                        }
+                    };
+                    println!("* {} {} #{}", presence.member.name, action, presence.room);
+                }
+                Event::Edited(edited) => println!(
+                    "* #{} {} edited message {}: {}",
+                    edited.room, edited.by, edited.message_id, edited.new_body
+                ),
+                Event::Deleted(deleted) => println!(
+                    "* #{} {} deleted message {}",
+                    deleted.room, deleted.by, deleted.message_id
+                ),
+                Event::Reacted(reacted) => println!(
+                    "* #{} {} reacted {} to message {}",
+                    reacted.room, reacted.by, reacted.emoji, reacted.message_id
+                ),
+                Event::Unreacted(reacted) => println!(
+                    "* #{} {} took back {} from message {}",
+                    reacted.room, reacted.by, reacted.emoji, reacted.message_id
+                ),
+                Event::Missed(missed) => println!("* missed {} messages", missed.count),
+                // errors also go to stderr, typing and read positions are only
+                // noise in a transcript
+                Event::Error(_)
+                | Event::Ack(_)
+                | Event::Typing(_)
+                | Event::Read(_)
+                | Event::Connected
+                | Event::Disconnected { .. } => {}
+            },
+        }
+    }
+}
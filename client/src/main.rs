@@ -1,5 +1,6 @@
-use std::io;
+use std::{io, path::PathBuf};
 
+use clap::Parser;
 use crossterm::{
     event::{DisableMouseCapture, EnableMouseCapture},
     execute,
@@ -8,10 +9,103 @@ use crossterm::{
 use ratatui::prelude::*;
 
 mod app;
-mod client;
+mod config;
+mod emoji;
+mod headless;
+mod room;
+mod scrollback;
+
+/// Terminal client for rust-chat
+#[derive(Parser, Debug)]
+#[command(version, about)]
+struct Args {
+    /// Config file [default: ~/.config/rust-chat/client.toml]
+    #[arg(short, long, value_name = "PATH")]
+    config: Option<PathBuf>,
+
+    /// Server profile from the config file
+    #[arg(short, long, value_name = "NAME")]
+    profile: Option<String>,
+
+    /// Server url, e.g. ws://localhost:3000
+    #[arg(short, long)]
+    url: Option<String>,
+
+    /// Nickname to log in with
+    #[arg(short, long)]
+    nick: Option<String>,
+
+    /// Room to join after login, may be repeated
+    #[arg(short = 'j', long = "join", value_name = "ROOM")]
+    rooms: Vec<String>,
+
+    /// Auth token sent with the handshake
+    #[arg(long)]
+    token: Option<String>,
+
+    /// Extra PEM root certificates for wss:// servers
+    #[arg(long, value_name = "PATH")]
+    ca_file: Option<PathBuf>,
+
+    /// Accept any server certificate
+    #[arg(long)]
+    insecure: bool,
+
+    /// Start without connecting to the server
+    #[arg(long)]
+    no_connect: bool,
+
+    /// Line mode without the terminal UI: stdin lines are sent, events go to stdout
+    #[arg(long)]
+    headless: bool,
+
+    /// Output format in headless mode
+    #[arg(long, value_enum, default_value = "text")]
+    format: headless::OutputFormat,
+
+    /// In headless mode, keep printing events after stdin is closed
+    #[arg(long)]
+    keep_open: bool,
+}
+
+impl Args {
+    /// Command line flags on top of the selected profile
+    fn profile(&self, config: &config::ConfigFile) -> anyhow::Result<config::Profile> {
+        let mut profile = config.profile(self.profile.as_deref())?;
+        if let Some(url) = &self.url {
+            profile.url = Some(url.clone());
+        }
+        if let Some(nick) = &self.nick {
+            profile.nickname = Some(nick.clone());
+        }
+        if !self.rooms.is_empty() {
+            profile.rooms = self.rooms.clone();
+        }
+        if let Some(token) = &self.token {
+            profile.token = Some(token.clone());
+        }
+        if self.ca_file.is_some() || self.insecure {
+            let tls = profile.tls.get_or_insert_with(Default::default);
+            if let Some(ca_file) = &self.ca_file {
+                tls.ca_file = Some(ca_file.clone());
+            }
+            tls.insecure |= self.insecure;
+        }
+        Ok(profile)
+    }
+}
 
 #[tokio::main]
-async fn main() -> std::io::Result<()> {
+async fn main() -> anyhow::Result<()> {
+    let args = Args::parse();
+    let config = config::ConfigFile::load(args.config.as_ref())?;
+    let profile = args.profile(&config)?;
+
+    if args.headless {
+        let code = headless::run(profile, args.format, args.keep_open).await;
+        std::process::exit(code);
+    }
+
     // setup terminal
     enable_raw_mode()?;
     let mut stdout = io::stdout();
@@ -20,7 +114,7 @@ async fn main() -> std::io::Result<()> {
     let mut terminal = Terminal::new(backend)?;
 
     // create app and run it
-    let app = app::App::default();
+    let app = app::App::new(config, profile, !args.no_connect);
     let res = app.run_app(&mut terminal).await;
 
     // restore terminal
@@ -0,0 +1,593 @@
+use std::collections::{HashMap, HashSet};
+use std::time::{Duration, Instant};
+
+use ratatui::{prelude::*, widgets::*};
+
+use super::config::Theme;
+use super::scrollback::Scrollback;
+
+/// Name of the tab that collects connection notices
+pub const STATUS_ROOM: &str = "status";
+
+/// One tab of the client: a joined room or the status buffer
+pub struct Room {
+    pub name: String,
+    /// Received messages and the viewport over them
+    pub scrollback: Scrollback,
+    /// Messages received while another tab was active
+    pub unread: usize,
+    /// Unread messages that mention our nickname
+    pub mentions: usize,
+    /// Last member list sent by the server
+    pub members: Vec<proto::Member>,
+    /// When `members` was received, idle times keep counting from here
+    pub roster_at: Instant,
+    /// Chats by message id with their line in `scrollback`, for edits and deletions
+    pub chats: HashMap<u64, (usize, proto::ChatMessage)>,
+    /// Chat picked in the scrollback
+    pub selected: Option<u64>,
+    /// Chat the next message answers
+    pub reply_to: Option<u64>,
+    /// Chat the input picks a reaction for
+    pub react_to: Option<u64>,
+    /// First chat of the thread shown instead of the scrollback
+    pub thread: Option<u64>,
+    /// Members composing a chat and when that runs out
+    pub typing: HashMap<String, Instant>,
+    /// Last chat read here or in another session of our name
+    pub read_up_to: Option<u64>,
+    /// Reconnected and nothing was pressed since: arriving chats are unread
+    /// and the view goes to the first of them
+    pub catching_up: bool,
+    /// Details last sent by the server
+    pub info: Option<proto::RoomInfo>,
+    /// Slow mode holds our next chat back until then
+    pub slow_until: Option<Instant>,
+}
+
+impl Room {
+    pub fn new(name: &str) -> Room {
+        Room {
+            name: name.to_owned(),
+            scrollback: Scrollback::default(),
+            unread: 0,
+            mentions: 0,
+            members: Vec::new(),
+            roster_at: Instant::now(),
+            chats: HashMap::new(),
+            selected: None,
+            reply_to: None,
+            react_to: None,
+            thread: None,
+            typing: HashMap::new(),
+            read_up_to: None,
+            catching_up: false,
+            info: None,
+            slow_until: None,
+        }
+    }
+
+    pub fn is_status(&self) -> bool {
+        self.name == STATUS_ROOM
+    }
+
+    /// Private conversation tab, named `@peer`
+    pub fn is_direct(&self) -> bool {
+        self.name.starts_with('@')
+    }
+
+    pub fn mark_read(&mut self) {
+        self.unread = 0;
+        self.mentions = 0;
+    }
+
+    /// Take the chats up to `message_id` as read: the ones after it are
+    /// counted and the divider goes above the first
+    pub fn read_until(&mut self, message_id: u64) {
+        self.read_up_to = Some(message_id);
+        let Some((read, _)) = self.chats.get(&message_id) else {
+            return;
+        };
+        let unread: Vec<usize> = self
+            .chats
+            .values()
+            .map(|(line, _)| *line)
+            .filter(|line| line > read)
+            .collect();
+        self.unread = unread.len();
+        self.mentions = self.mentions.min(self.unread);
+        self.scrollback.set_divider(unread.into_iter().min());
+    }
+
+    /// Take over new details, a topic that is new to us is shown
+    pub fn set_info(&mut self, info: proto::RoomInfo) {
+        let previous = self.info.as_ref().map(|i| i.topic.as_str());
+        if previous != Some(info.topic.as_str()) {
+            if !info.topic.is_empty() {
+                self.scrollback
+                    .push(format!("Topic of #{}: {}", self.name, info.topic));
+            } else if previous.is_some() {
+                self.scrollback
+                    .push(format!("#{} has no topic anymore", self.name));
+            }
+        }
+        self.info = Some(info);
+    }
+
+    /// Title of the message pane, with the topic when there is one
+    pub fn title(&self) -> String {
+        match self.info.as_ref().filter(|i| !i.topic.is_empty()) {
+            Some(info) => format!("Messages #{} · {}", self.name, info.topic),
+            None => format!("Messages #{}", self.name),
+        }
+    }
+
+    pub fn set_members(&mut self, members: Vec<proto::Member>) {
+        self.members = members;
+        self.roster_at = Instant::now();
+    }
+
+    /// Insert or replace a member, keeping the list sorted by name
+    pub fn upsert_member(&mut self, member: proto::Member) {
+        // store the idle time relative to `roster_at` like the rest of the list
+        let mut member = member;
+        member.idle_secs = member
+            .idle_secs
+            .saturating_sub(self.roster_at.elapsed().as_secs());
+
+        self.members.retain(|m| m.id != member.id);
+        let at = self
+            .members
+            .partition_point(|m| m.name.to_lowercase() < member.name.to_lowercase());
+        self.members.insert(at, member);
+    }
+
+    /// Apply a status change, members going invisible leave the list
+    pub fn update_member(&mut self, member: proto::Member) {
+        if member.status == proto::Status::Invisible {
+            self.remove_member(member.id);
+        } else {
+            self.upsert_member(member);
+        }
+    }
+
+    pub fn remove_member(&mut self, id: usize) {
+        self.members.retain(|m| m.id != id);
+    }
+
+    pub fn set_typing(&mut self, status: proto::TypingStatus) {
+        match status.state {
+            proto::TypingState::Active => {
+                let until = Instant::now() + Duration::from_secs(status.expires_secs);
+                self.typing.insert(status.name, until);
+            }
+            proto::TypingState::Stopped => {
+                self.typing.remove(&status.name);
+            }
+        }
+    }
+
+    /// Start the slow mode countdown
+    pub fn slow_down(&mut self, wait_secs: u64) {
+        if wait_secs > 0 {
+            self.slow_until = Some(Instant::now() + Duration::from_secs(wait_secs));
+        }
+    }
+
+    /// Seconds left before slow mode lets us chat here again
+    pub fn slow_mode_wait(&self) -> Option<u64> {
+        let left = self.slow_until?.checked_duration_since(Instant::now())?;
+        (!left.is_zero()).then(|| left.as_secs_f64().ceil() as u64)
+    }
+
+    /// Tip row notice of the members typing right now, e.g. `alice is typing…`
+    pub fn typing_notice(&self) -> Option<String> {
+        let now = Instant::now();
+        let mut names: Vec<&str> = self
+            .typing
+            .iter()
+            .filter(|(_, until)| **until > now)
+            .map(|(name, _)| name.as_str())
+            .collect();
+        names.sort_unstable();
+        match names.as_slice() {
+            [] => None,
+            [name] => Some(format!("{} is typing…", name)),
+            [first, second] => Some(format!("{} and {} are typing…", first, second)),
+            _ => Some("several people are typing…".to_owned()),
+        }
+    }
+
+    /// Remember the chat shown on `line`
+    pub fn record_chat(&mut self, line: usize, chat: proto::ChatMessage) {
+        if chat.id != 0 {
+            self.chats.insert(chat.id, (line, chat));
+        }
+    }
+
+    /// Scrollback line of a chat, replies quote their parent
+    pub fn line(&self, chat: &proto::ChatMessage) -> String {
+        chat_line(chat, chat.reply_to.and_then(|id| self.chat(id)))
+    }
+
+    pub fn chat(&self, message_id: u64) -> Option<&proto::ChatMessage> {
+        self.chats.get(&message_id).map(|(_, chat)| chat)
+    }
+
+    /// Change a shown chat and render it and the replies quoting it again
+    pub fn amend_chat(&mut self, message_id: u64, change: impl FnOnce(&mut proto::ChatMessage)) {
+        let Some((_, chat)) = self.chats.get_mut(&message_id) else {
+            return;
+        };
+        change(chat);
+        let lines: Vec<(usize, String)> = self
+            .chats
+            .values()
+            .filter(|(_, chat)| chat.id == message_id || chat.reply_to == Some(message_id))
+            .map(|(line, chat)| (*line, self.line(chat)))
+            .collect();
+        for (line, text) in lines {
+            self.scrollback.replace(line, text);
+        }
+    }
+
+    /// Take a chat the server purged out of the scrollback, replies stop
+    /// quoting it
+    pub fn expire_chat(&mut self, message_id: u64) {
+        let Some((line, _)) = self.chats.remove(&message_id) else {
+            return;
+        };
+        self.scrollback.remove(line);
+        if self.selected == Some(message_id) {
+            self.select(None);
+        }
+        for picked in [&mut self.reply_to, &mut self.react_to, &mut self.thread] {
+            if *picked == Some(message_id) {
+                *picked = None;
+            }
+        }
+        let replies: Vec<(usize, String)> = self
+            .chats
+            .values()
+            .filter(|(_, chat)| chat.reply_to == Some(message_id))
+            .map(|(line, chat)| (*line, self.line(chat)))
+            .collect();
+        for (line, text) in replies {
+            self.scrollback.replace(line, text);
+        }
+    }
+
+    /// Pick a chat in the scrollback, or none
+    pub fn select(&mut self, message_id: Option<u64>) {
+        self.selected = message_id.filter(|id| self.chats.contains_key(id));
+        let line = self
+            .selected
+            .and_then(|id| self.chats.get(&id))
+            .map(|(line, _)| *line);
+        self.scrollback.highlight(line);
+        if let Some(line) = line {
+            self.scrollback.reveal(line);
+        }
+    }
+
+    pub fn select_last(&mut self) {
+        let last = self
+            .chats
+            .values()
+            .max_by_key(|(line, _)| *line)
+            .map(|(_, chat)| chat.id);
+        self.select(last);
+    }
+
+    /// Pick the chat above (`back`) or below the selected one
+    pub fn select_step(&mut self, back: bool) {
+        let Some((current, _)) = self.selected.and_then(|id| self.chats.get(&id)) else {
+            return self.select_last();
+        };
+        let lines = self.chats.values().map(|(line, chat)| (*line, chat.id));
+        let next = if back {
+            lines.filter(|(line, _)| line < current).max()
+        } else {
+            lines.filter(|(line, _)| line > current).min()
+        };
+        if let Some((_, id)) = next {
+            self.select(Some(id));
+        }
+    }
+
+    /// First chat of the thread `message_id` belongs to, as far as it is known
+    pub fn thread_root(&self, message_id: u64) -> u64 {
+        let mut root = message_id;
+        while let Some(parent) = self.chat(root).and_then(|chat| chat.reply_to) {
+            if self.chat(parent).is_none() {
+                break;
+            }
+            root = parent;
+        }
+        root
+    }
+
+    /// Lines of the thread started by `root`: the chat and the replies to it
+    /// or to other replies, in the order they arrived
+    pub fn thread_lines(&self, root: u64) -> Vec<String> {
+        let mut chats: Vec<&proto::ChatMessage> = self.chats.values().map(|(_, c)| c).collect();
+        chats.sort_by_key(|chat| self.chats[&chat.id].0);
+
+        let mut thread = HashSet::from([root]);
+        let mut lines = Vec::new();
+        for chat in chats {
+            if chat.id == root {
+                lines.push(chat_line(chat, None));
+            } else if let Some(parent) = chat.reply_to.filter(|id| thread.contains(id)) {
+                thread.insert(chat.id);
+                // answers to the first chat need no quote
+                let quoted = self.chat(parent).filter(|_| parent != root);
+                lines.push(format!("  {}", chat_line(chat, quoted)));
+            }
+        }
+        lines
+    }
+
+    /// Latest chat of `name` that is not deleted
+    pub fn last_chat_of(&self, name: &str) -> Option<&proto::ChatMessage> {
+        self.last_chat_where(|chat| chat.from == name)
+    }
+
+    /// Latest chat that is not deleted
+    pub fn last_chat(&self) -> Option<&proto::ChatMessage> {
+        self.last_chat_where(|_| true)
+    }
+
+    fn last_chat_where(
+        &self,
+        pick: impl Fn(&proto::ChatMessage) -> bool,
+    ) -> Option<&proto::ChatMessage> {
+        self.chats
+            .values()
+            .filter(|(_, chat)| !chat.deleted && pick(chat))
+            .max_by_key(|(line, _)| *line)
+            .map(|(_, chat)| chat)
+    }
+
+    /// Title shown in the tab bar, e.g. `2 rust (3) @1`
+    pub fn tab_title(&self, index: usize, theme: &Theme) -> Line<'static> {
+        let mut spans = vec![Span::raw(format!("{} {}", index + 1, self.name))];
+        if self.unread > 0 {
+            spans.push(Span::raw(format!(" ({})", self.unread)).bold());
+        }
+        if self.mentions > 0 {
+            spans.push(
+                Span::raw(format!(" @{}", self.mentions))
+                    .fg(theme.mention)
+                    .bold(),
+            );
+        }
+        Line::from(spans)
+    }
+
+    pub fn render_members(&self, f: &mut Frame, area: Rect, theme: &Theme) {
+        let elapsed = self.roster_at.elapsed();
+        let items: Vec<ListItem> = self
+            .members
+            .iter()
+            .map(|m| {
+                let idle = format_idle(Duration::from_secs(m.idle_secs) + elapsed);
+                let line = match &m.status {
+                    proto::Status::Online => Line::from(vec![
+                        "● ".fg(theme.online),
+                        Span::raw(m.name.clone()),
+                        format!(" {}", idle).dim(),
+                    ]),
+                    proto::Status::Away { .. } => Line::from(vec![
+                        "○ ".dim(),
+                        Span::raw(m.name.clone()).dim(),
+                        format!(" {} {}", describe_status(&m.status), idle).dim(),
+                    ]),
+                    proto::Status::Busy => Line::from(vec![
+                        "● ".fg(theme.mention),
+                        Span::raw(m.name.clone()),
+                        " busy".dim(),
+                    ]),
+                    proto::Status::Invisible => Line::from(vec![
+                        "◌ ".dim(),
+                        Span::raw(m.name.clone()).dim(),
+                        " invisible".dim(),
+                    ]),
+                };
+                ListItem::new(line)
+            })
+            .collect();
+
+        let title = format!("Members ({})", self.members.len());
+        let members = List::new(items).block(Block::default().borders(Borders::ALL).title(title));
+        f.render_widget(members, area);
+    }
+}
+
+/// Scrollback line of a chat, e.g. `[2024-01-01 12:00:00] bob: hi (edited)`,
+/// a reply starts with the quoted `parent` like `↳ alice "how are…" fine`
+/// and reaction counts go on a row below, like `  👍 2  🎉 1`
+pub fn chat_line(chat: &proto::ChatMessage, parent: Option<&proto::ChatMessage>) -> String {
+    if chat.deleted {
+        return format!("[{}] {}: (message deleted)", chat.time, chat.from);
+    }
+    let quote = match parent {
+        Some(parent) if parent.deleted => format!("↳ {} (message deleted) ", parent.from),
+        Some(parent) => format!("↳ {} \"{}\" ", parent.from, snippet(&parent.body)),
+        None => String::new(),
+    };
+    let edited = if chat.edited { " (edited)" } else { "" };
+    let reactions: String = chat
+        .reactions
+        .iter()
+        .map(|r| format!("  {} {}", r.emoji, r.by.len()))
+        .collect();
+    let reactions = if reactions.is_empty() {
+        reactions
+    } else {
+        format!("\n{}", reactions)
+    };
+    format!(
+        "[{}] {}: {}{}{}{}",
+        chat.time, chat.from, quote, chat.body, edited, reactions
+    )
+}
+
+/// Start of a quoted body
+fn snippet(body: &str) -> String {
+    const QUOTED_CHARS: usize = 20;
+    match body.char_indices().nth(QUOTED_CHARS) {
+        Some((end, _)) => format!("{}…", body[..end].trim_end()),
+        None => body.to_owned(),
+    }
+}
+
+/// Status as words, e.g. `away: lunch`
+pub fn describe_status(status: &proto::Status) -> String {
+    match status {
+        proto::Status::Online => "online".to_owned(),
+        proto::Status::Away { message } if message.is_empty() => "away".to_owned(),
+        proto::Status::Away { message } => format!("away: {}", message),
+        proto::Status::Busy => "busy".to_owned(),
+        proto::Status::Invisible => "invisible".to_owned(),
+    }
+}
+
+/// Answer to `/whois`, e.g. `bob (Bob, he/him) is away: lunch, idle 5m` with
+/// the timezone and bio on rows below
+pub fn describe_user(info: &proto::UserInfo) -> String {
+    let profile = &info.profile;
+    let about: Vec<&str> = [&profile.display_name, &profile.pronouns]
+        .into_iter()
+        .flatten()
+        .map(String::as_str)
+        .collect();
+    let mut text = info.name.clone();
+    if !about.is_empty() {
+        text.push_str(&format!(" ({})", about.join(", ")));
+    }
+    text.push_str(&format!(" is {}", describe_status(&info.status)));
+    let idle = format_idle(Duration::from_secs(info.idle_secs));
+    if !idle.is_empty() {
+        text.push_str(&format!(", idle {}", idle));
+    }
+    if let Some(timezone) = &profile.timezone {
+        text.push_str(&format!("\n  timezone: {}", timezone));
+    }
+    if let Some(bio) = &profile.bio {
+        text.push_str(&format!("\n  {}", bio));
+    }
+    text
+}
+
+/// Answer to `/info`, e.g. `#rust: borrow checking` with the description,
+/// creator and settings on rows below
+pub fn describe_room(info: &proto::RoomInfo) -> String {
+    let mut text = format!("#{}", info.room);
+    if !info.topic.is_empty() {
+        text.push_str(&format!(": {}", info.topic));
+    }
+    if !info.description.is_empty() {
+        text.push_str(&format!("\n  {}", info.description));
+    }
+    match &info.created_by {
+        Some(by) => text.push_str(&format!("\n  created by {} at {}", by, info.created_at)),
+        None => text.push_str(&format!("\n  created at {}", info.created_at)),
+    }
+    let settings = &info.settings;
+    let mut limits = Vec::new();
+    if settings.max_members > 0 {
+        limits.push(format!("at most {} members", settings.max_members));
+    }
+    if settings.slow_mode_secs > 0 {
+        limits.push(format!("slow mode {}s", settings.slow_mode_secs));
+    }
+    if settings.read_only {
+        limits.push("read-only".to_owned());
+    }
+    if settings.retention_secs > 0 {
+        let retention = format_span(settings.retention_secs);
+        limits.push(format!("chats kept for {}", retention));
+    }
+    if settings.announcement {
+        limits.push("announcements only".to_owned());
+    }
+    if settings.visibility != proto::Visibility::Public {
+        limits.push(describe_visibility(settings.visibility).to_owned());
+    }
+    if !limits.is_empty() {
+        text.push_str(&format!("\n  {}", limits.join(", ")));
+    }
+    if let Some(secs) = info.expires_secs {
+        let left = format_span(secs);
+        text.push_str(&format!("\n  closes in {}", left));
+    }
+    text
+}
+
+pub fn describe_visibility(visibility: proto::Visibility) -> &'static str {
+    match visibility {
+        proto::Visibility::Public => "public",
+        proto::Visibility::Unlisted => "unlisted",
+        proto::Visibility::Password => "password protected",
+        proto::Visibility::InviteOnly => "invite-only",
+    }
+}
+
+/// Answer to `/rooms`, one room per row
+pub fn describe_rooms(list: &proto::RoomList) -> String {
+    let mut text = "Rooms:".to_owned();
+    for room in &list.rooms {
+        text.push_str(&format!("\n  #{} ({})", room.room, room.members));
+        if room.visibility != proto::Visibility::Public {
+            text.push_str(&format!(" {}", describe_visibility(room.visibility)));
+        }
+        if !room.topic.is_empty() {
+            text.push_str(&format!(": {}", room.topic));
+        }
+    }
+    text
+}
+
+/// Answer to `/invite`, with how to use the code
+pub fn describe_invite(invite: &proto::InviteCode) -> String {
+    let mut limits = Vec::new();
+    if invite.max_uses > 0 {
+        limits.push(format!(
+            "{} use{}",
+            invite.max_uses,
+            if invite.max_uses == 1 { "" } else { "s" }
+        ));
+    }
+    if invite.expires_secs > 0 {
+        let expires = format_idle(Duration::from_secs(invite.expires_secs));
+        limits.push(format!("expires in {}", expires));
+    }
+    let limits = match limits.is_empty() {
+        true => String::new(),
+        false => format!(" ({})", limits.join(", ")),
+    };
+    format!(
+        "Invite to #{}{}: /accept {}",
+        invite.room, limits, invite.code
+    )
+}
+
+/// Short time span like `format_idle`, seconds when under a minute
+fn format_span(secs: u64) -> String {
+    match secs {
+        0..=59 => format!("{}s", secs),
+        _ => format_idle(Duration::from_secs(secs)),
+    }
+}
+
+/// Short idle time: empty while active, then minutes, hours and days
+pub fn format_idle(idle: Duration) -> String {
+    let secs = idle.as_secs();
+    match secs {
+        0..=59 => String::new(),
+        60..=3599 => format!("{}m", secs / 60),
+        3600..=86399 => format!("{}h", secs / 3600),
+        _ => format!("{}d", secs / 86400),
+    }
+}
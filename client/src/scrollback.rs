@@ -0,0 +1,413 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+
+use ratatui::{prelude::*, widgets::*};
+use unicode_width::UnicodeWidthChar;
+
+/// How many rows a single mouse wheel notch scrolls
+pub const WHEEL_STEP: usize = 3;
+
+/// Messages a scrollback keeps, older ones are dropped
+pub const MAX_MESSAGES: usize = 1024;
+
+/// Row drawn above the first unread message
+const DIVIDER: &str = "──── new messages ────";
+
+/// Scrollable view over the received messages.
+///
+/// The view is anchored at the bottom: `offset` counts wrapped rows scrolled up
+/// from the newest message, so `0` means new messages are followed automatically.
+/// Only the last `MAX_MESSAGES` are kept, indexes keep counting past them.
+#[derive(Default)]
+pub struct Scrollback {
+    /// History of recorded messages
+    messages: VecDeque<String>,
+    /// Index of the oldest message kept
+    first: usize,
+    /// Wrapped rows of each message at `wrapped_width`
+    wrapped: VecDeque<usize>,
+    wrapped_width: usize,
+    /// Rows scrolled up from the bottom
+    offset: usize,
+    /// Messages received while scrolled up
+    unseen: usize,
+    /// Index of the message shown highlighted
+    highlight: Option<usize>,
+    /// Index of the first unread message, a divider row goes above it
+    divider: Option<usize>,
+    /// Messages drawn in their own style, like mentions
+    styles: HashMap<usize, Style>,
+    /// Messages taken out of view, the indexes of the others stay valid
+    removed: HashSet<usize>,
+    /// Inner size of the last rendered viewport
+    width: u16,
+    height: u16,
+}
+
+impl Scrollback {
+    /// Record a message, returns its index for `replace`
+    pub fn push(&mut self, message: String) -> usize {
+        self.rewrap(self.width as usize);
+        let rows = wrapped_rows(&message, self.wrapped_width);
+        if !self.is_following() {
+            // keep the rows on screen still while new ones arrive below them
+            self.offset += rows;
+            self.unseen += 1;
+        }
+        self.messages.push_back(message);
+        self.wrapped.push_back(rows);
+        if self.messages.len() > MAX_MESSAGES {
+            self.messages.pop_front();
+            self.wrapped.pop_front();
+            let gone = self.first;
+            self.first += 1;
+            self.styles.remove(&gone);
+            self.removed.remove(&gone);
+            if self.divider == Some(gone) {
+                self.divider = None;
+            }
+            if self.highlight == Some(gone) {
+                self.highlight = None;
+            }
+        }
+        self.end() - 1
+    }
+
+    /// Record a message, following new ones only as long as the divider
+    /// stays on screen
+    pub fn push_below_divider(&mut self, message: String) -> usize {
+        let index = self.push(message);
+        if let (true, Some(divider)) = (self.is_following(), self.divider) {
+            let rows: usize = (divider..self.end()).map(|i| self.row_count(i)).sum();
+            if rows > self.height as usize {
+                self.offset = rows - self.height as usize;
+                self.unseen += 1;
+            }
+        }
+        index
+    }
+
+    /// Rewrite a recorded message in place
+    pub fn replace(&mut self, index: usize, message: String) {
+        let Some(at) = self.position(index) else {
+            return;
+        };
+        if self.removed.contains(&index) {
+            return;
+        }
+        self.rewrap(self.width as usize);
+        let before = self.wrapped[at];
+        let after = wrapped_rows(&message, self.wrapped_width);
+        if !self.is_following() {
+            self.offset = (self.offset + after).saturating_sub(before);
+        }
+        self.messages[at] = message;
+        self.wrapped[at] = after;
+    }
+
+    /// Stop showing the message at `index`
+    pub fn remove(&mut self, index: usize) {
+        let Some(at) = self.position(index) else {
+            return;
+        };
+        if self.removed.contains(&index) {
+            return;
+        }
+        if !self.is_following() {
+            self.rewrap(self.width as usize);
+            self.offset = self.offset.saturating_sub(self.wrapped[at]);
+        }
+        self.removed.insert(index);
+    }
+
+    /// Draw the message at `index` in `style`
+    pub fn set_style(&mut self, index: usize, style: Style) {
+        self.styles.insert(index, style);
+    }
+
+    /// Highlight a message, or none
+    pub fn highlight(&mut self, index: Option<usize>) {
+        self.highlight = index;
+    }
+
+    /// Draw the new messages divider above the message at `index`, or nowhere
+    pub fn set_divider(&mut self, index: Option<usize>) {
+        if !self.is_following() {
+            // it usually lands on a message that arrived below the viewport
+            let rows = |divider: Option<usize>| usize::from(divider.is_some());
+            self.offset = (self.offset + rows(index)).saturating_sub(rows(self.divider));
+        }
+        self.divider = index;
+    }
+
+    pub fn divider(&self) -> Option<usize> {
+        self.divider
+    }
+
+    /// Scroll just enough to show the whole message at `index`, with the
+    /// divider above it
+    pub fn reveal(&mut self, index: usize) {
+        if self.position(index).is_none() {
+            return;
+        }
+        self.rewrap(self.width as usize);
+        let below: usize = (index + 1..self.end()).map(|i| self.row_count(i)).sum();
+        let rows = self.row_count(index);
+        if self.offset > below {
+            self.offset = below;
+        } else if below + rows > self.offset + self.height as usize {
+            self.offset = (below + rows).saturating_sub(self.height as usize);
+        }
+        if self.is_following() {
+            self.unseen = 0;
+        }
+    }
+
+    pub fn is_following(&self) -> bool {
+        self.offset == 0
+    }
+
+    pub fn scroll_up(&mut self, rows: usize) {
+        self.offset = self.offset.saturating_add(rows).min(self.max_offset());
+    }
+
+    pub fn scroll_down(&mut self, rows: usize) {
+        self.offset = self.offset.saturating_sub(rows);
+        if self.is_following() {
+            self.unseen = 0;
+        }
+    }
+
+    pub fn page_up(&mut self) {
+        self.scroll_up(self.page_size());
+    }
+
+    pub fn page_down(&mut self) {
+        self.scroll_down(self.page_size());
+    }
+
+    pub fn scroll_to_top(&mut self) {
+        self.offset = self.max_offset();
+    }
+
+    pub fn scroll_to_bottom(&mut self) {
+        self.offset = 0;
+        self.unseen = 0;
+    }
+
+    /// Keep one row of context when paging
+    fn page_size(&self) -> usize {
+        (self.height as usize).saturating_sub(1).max(1)
+    }
+
+    /// Index after the newest message
+    fn end(&self) -> usize {
+        self.first + self.messages.len()
+    }
+
+    /// Place of the message at `index` in `messages`, unless it was dropped
+    fn position(&self, index: usize) -> Option<usize> {
+        index
+            .checked_sub(self.first)
+            .filter(|at| *at < self.messages.len())
+    }
+
+    /// Wrap the messages again when the width changed
+    fn rewrap(&mut self, width: usize) {
+        if width == self.wrapped_width {
+            return;
+        }
+        self.wrapped = self
+            .messages
+            .iter()
+            .map(|message| wrapped_rows(message, width))
+            .collect();
+        self.wrapped_width = width;
+    }
+
+    /// Rows of the message at `index` with the divider above it, as wrapped
+    /// by the last `rewrap`
+    fn row_count(&self, index: usize) -> usize {
+        let Some(at) = self.position(index) else {
+            return 0;
+        };
+        let divider = usize::from(self.divider == Some(index));
+        if self.removed.contains(&index) {
+            divider
+        } else {
+            divider + self.wrapped[at]
+        }
+    }
+
+    /// Wrapped rows of one message, preceded by the divider above it
+    fn message_rows(&self, index: usize, width: usize) -> Vec<Line<'static>> {
+        let mut style = self.styles.get(&index).copied().unwrap_or_default();
+        if self.highlight == Some(index) {
+            style = style.add_modifier(Modifier::REVERSED);
+        }
+        let message = self
+            .position(index)
+            .map_or("", |at| self.messages[at].as_str());
+        let line = Line::from(Span::styled(message, style));
+        let mut rows = Vec::new();
+        if self.divider == Some(index) {
+            rows.push(Line::from(DIVIDER.dim()));
+        }
+        if !self.removed.contains(&index) {
+            rows.extend(wrap_line(&line, width));
+        }
+        rows
+    }
+
+    fn total_rows(&self) -> usize {
+        (self.first..self.end()).map(|i| self.row_count(i)).sum()
+    }
+
+    fn max_offset(&mut self) -> usize {
+        self.rewrap(self.width as usize);
+        self.total_rows().saturating_sub(self.height as usize)
+    }
+
+    pub fn render(&mut self, f: &mut Frame, area: Rect, block: Block) {
+        let mut block = block;
+        if self.unseen > 0 {
+            let tip = format!(
+                " {} new message{} (End to jump) ",
+                self.unseen,
+                if self.unseen == 1 { "" } else { "s" }
+            );
+            block = block.title(
+                block::Title::from(tip.reversed())
+                    .position(block::Position::Bottom)
+                    .alignment(Alignment::Right),
+            );
+        } else if !self.is_following() {
+            block = block.title(
+                block::Title::from(" scrolled ".dim())
+                    .position(block::Position::Bottom)
+                    .alignment(Alignment::Right),
+            );
+        }
+
+        let inner = block.inner(area);
+        f.render_widget(block, area);
+
+        self.width = inner.width;
+        self.height = inner.height;
+
+        let width = inner.width as usize;
+        let height = inner.height as usize;
+        self.rewrap(width);
+        self.offset = self.offset.min(self.total_rows().saturating_sub(height));
+        if self.is_following() {
+            self.unseen = 0;
+        }
+
+        // only the messages in view are wrapped, from the bottom up
+        let mut skip = self.offset;
+        let mut left = height;
+        let mut chunks = Vec::new();
+        for index in (self.first..self.end()).rev() {
+            if left == 0 {
+                break;
+            }
+            let count = self.row_count(index);
+            if skip >= count {
+                skip -= count;
+                continue;
+            }
+            let mut rows = self.message_rows(index, width);
+            rows.truncate(count - skip);
+            skip = 0;
+            let start = rows.len().saturating_sub(left);
+            left -= rows.len() - start;
+            chunks.push(rows.split_off(start));
+        }
+        let visible: Vec<Line> = chunks.into_iter().rev().flatten().collect();
+        f.render_widget(Paragraph::new(visible), inner);
+    }
+}
+
+/// Wrap a styled line into rows no wider than `width` terminal cells.
+///
+/// Breaks at the last whitespace or wide character when possible and falls back to breaking
+/// inside a word. Wide characters are never split across rows.
+pub fn wrap_line(line: &Line<'_>, width: usize) -> Vec<Line<'static>> {
+    let width = width.max(1);
+    let mut rows: Vec<Vec<(char, Style)>> = Vec::new();
+    let mut current: Vec<(char, Style)> = Vec::new();
+    let mut current_width = 0;
+    // index in `current` just after the last whitespace or wide character,
+    // CJK text has no spaces and may break between any two characters
+    let mut last_break: Option<usize> = None;
+
+    let chars = line
+        .spans
+        .iter()
+        .flat_map(|span| span.content.chars().map(move |c| (c, span.style)));
+
+    for (ch, style) in chars {
+        if ch == '\n' {
+            rows.push(std::mem::take(&mut current));
+            current_width = 0;
+            last_break = None;
+            continue;
+        }
+
+        let ch_width = ch.width().unwrap_or(0);
+        if current_width + ch_width > width && !current.is_empty() {
+            match last_break {
+                Some(at) if at < current.len() => {
+                    let rest = current.split_off(at);
+                    rows.push(std::mem::replace(&mut current, rest));
+                    current_width = current.iter().map(|(c, _)| c.width().unwrap_or(0)).sum();
+                }
+                _ => {
+                    rows.push(std::mem::take(&mut current));
+                    current_width = 0;
+                }
+            }
+            last_break = None;
+
+            if ch.is_whitespace() && current.is_empty() {
+                continue;
+            }
+        }
+
+        current.push((ch, style));
+        current_width += ch_width;
+        if ch.is_whitespace() || ch_width > 1 {
+            last_break = Some(current.len());
+        }
+    }
+    rows.push(current);
+
+    rows.into_iter().map(into_line).collect()
+}
+
+fn wrapped_rows(message: &str, width: usize) -> usize {
+    wrap_line(&as_line(message), width).len()
+}
+
+/// A message as one span, `Line::raw` would drop its line breaks
+fn as_line(message: &str) -> Line<'_> {
+    Line::from(Span::raw(message))
+}
+
+fn into_line(row: Vec<(char, Style)>) -> Line<'static> {
+    let mut spans: Vec<Span<'static>> = Vec::new();
+    let mut text = String::new();
+    let mut style = Style::default();
+
+    for (ch, ch_style) in row {
+        if ch_style != style && !text.is_empty() {
+            spans.push(Span::styled(std::mem::take(&mut text), style));
+        }
+        style = ch_style;
+        text.push(ch);
+    }
+    if !text.is_empty() {
+        spans.push(Span::styled(text, style));
+    }
+
+    Line::from(spans)
+}
@@ -0,0 +1,14 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use proto::{ChatPacket, ChatPacketType};
+
+// Raw frames as they arrive from the network
+fuzz_target!(|data: &[u8]| {
+    let Ok(packet) = ChatPacket::deserialize(data) else {
+        return;
+    };
+    if packet.packet_type != ChatPacketType::Unknown {
+        assert_eq!(packet.serialize(), data);
+    }
+});
@@ -0,0 +1,31 @@
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+use proto::*;
+
+// Frames decoded all the way to the payloads the server and clients read
+fuzz_target!(|data: &[u8]| {
+    let Ok(packet) = ChatPacket::deserialize(data) else {
+        return;
+    };
+    match packet.packet_type {
+        ChatPacketType::Chat => {
+            let _ = packet.payload::<ChatSend>();
+            let _ = packet.payload::<ChatMessage>();
+        }
+        ChatPacketType::Direct => {
+            let _ = packet.payload::<DirectSend>();
+            let _ = packet.payload::<DirectMessage>();
+        }
+        ChatPacketType::Roster => {
+            let _ = packet.payload::<Roster>();
+        }
+        ChatPacketType::Presence => {
+            let _ = packet.payload::<Presence>();
+        }
+        ChatPacketType::Error => {
+            let _ = packet.payload::<ErrorInfo>();
+        }
+        _ => {}
+    }
+});
@@ -1,15 +1,96 @@
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+
+/// Handshake header with the id a client keeps across reconnects. A
+/// connection presenting the id of an open session takes its place, the
+/// old connection is closed
+pub const CLIENT_ID_HEADER: &str = "x-client-id";
+
 #[derive(Clone, Debug, PartialEq)]
 pub enum ChatPacketType {
     Unknown,
     // client send login with name
     // server pass login with to all
     Login,
-    // client send chat message
-    // server pass chat message to all
+    // client send `ChatSend` to a joined room
+    // server pass `ChatMessage` to room members
     Chat,
     // client send close
     // server pass close to all
     Close,
+    // client send room name to join, or `JoinRoom` for a password protected room
+    // server pass `Roster` to the joiner and `Presence` to room members
+    Join,
+    // client send room name to leave
+    // server pass `Presence` to room members
+    Leave,
+    // client send room name to request the member list
+    // server pass `Roster`
+    Roster,
+    // server pass `Presence` when a member joins, leaves or renames
+    Presence,
+    // server pass `ErrorInfo` when a request is rejected
+    Error,
+    // client send `DirectSend` to a user by name
+    // server pass `DirectMessage` to the recipient and back to the sender
+    Direct,
+    // server pass `Missed` when frames were dropped because the client reads too slowly
+    Missed,
+    // server pass `Ack` to the sender once a chat with a nonce is accepted
+    Ack,
+    // client send `Resync` to ask for the broadcasts of a room from a sequence number on
+    // server pass `Resync` with the first one it still has, followed by the packets
+    Resync,
+    // client send `Edit` for its own message, or any message of a room it operates
+    // server pass `Edited` to room members
+    Edit,
+    // client send `Delete` under the same rules as `Edit`
+    // server pass `Deleted` to room members
+    Delete,
+    // client send `React` to add its reaction to a message of a joined room
+    // server pass `Reacted` to room members
+    React,
+    // client send `React` to take its reaction back
+    // server pass `Reacted` to room members
+    Unreact,
+    // client send `Typing` while composing a chat and when it stops
+    // server pass `TypingStatus` to room members, neither numbered nor kept
+    Typing,
+    // client send `MarkRead` with the last message it showed in a room
+    // server pass `MarkRead` to the other sessions using the same name, and
+    // the stored one after a login or join
+    MarkRead,
+    // client send `Status` to change its availability
+    // server pass `Presence` of kind `Updated` to the members of its rooms
+    Status,
+    // client send `Profile` with the fields to change
+    Profile,
+    // client send `WhoIs` with a name
+    // server pass `UserInfo` with the status and profile behind it
+    WhoIs,
+    // client send `SetTopic` to change the topic or description of a room it operates
+    // server pass `RoomInfo` to room members
+    SetTopic,
+    // client send `RoomQuery` for the details of a joined room, operators may
+    // change its settings with it
+    // server pass `RoomInfo` to the requester, to every joiner and to room
+    // members after a change
+    RoomInfo,
+    // server pass the message of the day as text once the session is connected
+    Motd,
+    // client send `Invite` for a room it may invite to
+    // server pass `InviteCode` to the requester
+    Invite,
+    // client send `AcceptInvite` with a code to join the room it is for
+    // server pass `AcceptInvite` naming the room, then what a `Join` gets
+    AcceptInvite,
+    // client send empty message
+    // server pass `RoomList` with the rooms the requester may see
+    ListRooms,
+    // server pass `RoomClosed` to the members of a room whose TTL ran out
+    RoomClosed,
+    // server pass `Expired` to room members once chats outlived the retention
+    // of the room
+    Expired,
 }
 
 impl From<u8> for ChatPacketType {
@@ -18,6 +99,32 @@ impl From<u8> for ChatPacketType {
             1 => Self::Login,
             2 => Self::Chat,
             3 => Self::Close,
+            4 => Self::Join,
+            5 => Self::Leave,
+            6 => Self::Roster,
+            7 => Self::Presence,
+            8 => Self::Error,
+            9 => Self::Direct,
+            10 => Self::Missed,
+            11 => Self::Ack,
+            12 => Self::Resync,
+            13 => Self::Edit,
+            14 => Self::Delete,
+            15 => Self::React,
+            16 => Self::Unreact,
+            17 => Self::Typing,
+            18 => Self::MarkRead,
+            19 => Self::Status,
+            20 => Self::Profile,
+            21 => Self::WhoIs,
+            22 => Self::SetTopic,
+            23 => Self::RoomInfo,
+            24 => Self::Motd,
+            25 => Self::Invite,
+            26 => Self::AcceptInvite,
+            27 => Self::ListRooms,
+            28 => Self::RoomClosed,
+            29 => Self::Expired,
             _ => Self::Unknown,
         }
     }
@@ -29,6 +136,32 @@ impl From<ChatPacketType> for u8 {
             ChatPacketType::Login => 1,
             ChatPacketType::Chat => 2,
             ChatPacketType::Close => 3,
+            ChatPacketType::Join => 4,
+            ChatPacketType::Leave => 5,
+            ChatPacketType::Roster => 6,
+            ChatPacketType::Presence => 7,
+            ChatPacketType::Error => 8,
+            ChatPacketType::Direct => 9,
+            ChatPacketType::Missed => 10,
+            ChatPacketType::Ack => 11,
+            ChatPacketType::Resync => 12,
+            ChatPacketType::Edit => 13,
+            ChatPacketType::Delete => 14,
+            ChatPacketType::React => 15,
+            ChatPacketType::Unreact => 16,
+            ChatPacketType::Typing => 17,
+            ChatPacketType::MarkRead => 18,
+            ChatPacketType::Status => 19,
+            ChatPacketType::Profile => 20,
+            ChatPacketType::WhoIs => 21,
+            ChatPacketType::SetTopic => 22,
+            ChatPacketType::RoomInfo => 23,
+            ChatPacketType::Motd => 24,
+            ChatPacketType::Invite => 25,
+            ChatPacketType::AcceptInvite => 26,
+            ChatPacketType::ListRooms => 27,
+            ChatPacketType::RoomClosed => 28,
+            ChatPacketType::Expired => 29,
             _ => 0,
         }
     }
@@ -49,6 +182,35 @@ impl ChatPacket {
         }
     }
 
+    /// Build a packet whose message is the JSON encoding of `payload`
+    ///
+    /// Panics if `payload` can't be encoded as JSON, which doesn't happen for
+    /// the payloads of this crate: they derive `Serialize` and hold no maps
+    /// with non-string keys
+    pub fn with_payload<T: Serialize>(packet_type: ChatPacketType, payload: &T) -> Self {
+        let packet_message = serde_json::to_string(payload)
+            .expect("derived payloads without non-string map keys always encode");
+        Self::new(packet_type, packet_message)
+    }
+
+    /// Decode the JSON payload carried in the message
+    pub fn payload<T: DeserializeOwned>(&self) -> Result<T, serde_json::Error> {
+        serde_json::from_str(&self.packet_message)
+    }
+
+    /// Copy of the packet with `seq` set in its JSON payload, other packets are
+    /// returned as they are
+    pub fn sequenced(&self, seq: u64) -> Self {
+        let mut packet = self.clone();
+        if let Ok(serde_json::Value::Object(mut fields)) =
+            serde_json::from_str::<serde_json::Value>(&self.packet_message)
+        {
+            fields.insert("seq".to_owned(), seq.into());
+            packet.packet_message = serde_json::Value::Object(fields).to_string();
+        }
+        packet
+    }
+
     pub fn serialize(&self) -> Vec<u8> {
         let mut serialized_packet = Vec::new();
         let packet_type: u8 = self.packet_type.to_owned().into();
@@ -57,12 +219,614 @@ impl ChatPacket {
         serialized_packet
     }
 
-    pub fn deserialize(packet: Vec<u8>) -> Self {
-        let packet_type = ChatPacketType::from(packet[0]);
-        let packet_message = String::from_utf8(packet[1..].to_vec()).unwrap();
-        Self {
-            packet_type,
+    /// Decode a frame from the network, never panics.
+    /// Unknown type bytes decode as `ChatPacketType::Unknown`.
+    pub fn deserialize(packet: &[u8]) -> Result<Self, DecodeError> {
+        let (&type_byte, message) = packet.split_first().ok_or(DecodeError::Empty)?;
+        let packet_message = std::str::from_utf8(message)
+            .map_err(|err| DecodeError::InvalidUtf8 {
+                // offset in the whole frame, after the type byte
+                at: err.valid_up_to() + 1,
+            })?
+            .to_owned();
+        Ok(Self {
+            packet_type: ChatPacketType::from(type_byte),
             packet_message,
+        })
+    }
+}
+
+/// Why a frame is not a packet
+#[derive(Clone, Debug, PartialEq)]
+pub enum DecodeError {
+    /// No type byte
+    Empty,
+    /// The message is not valid UTF-8 from byte `at` on
+    InvalidUtf8 { at: usize },
+}
+
+impl std::fmt::Display for DecodeError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            DecodeError::Empty => write!(f, "empty packet"),
+            DecodeError::InvalidUtf8 { at } => write!(f, "invalid utf-8 at byte {}", at),
         }
     }
 }
+
+impl std::error::Error for DecodeError {}
+
+/// Chat line sent by a client to one of its rooms
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChatSend {
+    pub room: String,
+    pub body: String,
+    /// chosen by the client, acknowledged with `Ack` and never delivered twice
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    /// id of the message in the same room this answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<u64>,
+}
+
+/// Chat line delivered by the server to room members
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ChatMessage {
+    pub room: String,
+    pub from: String,
+    pub body: String,
+    pub time: String,
+    /// assigned by the server
+    #[serde(default)]
+    pub id: u64,
+    /// nonce of the `ChatSend` it came from
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    /// position among the broadcasts of the room, 0 when not sequenced
+    #[serde(default)]
+    pub seq: u64,
+    /// the body was changed after sending
+    #[serde(default)]
+    pub edited: bool,
+    /// the message was deleted, the body is empty
+    #[serde(default)]
+    pub deleted: bool,
+    /// id of the message in the same room this answers
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<u64>,
+    /// reactions in the order they were first given
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub reactions: Vec<Reaction>,
+    /// names mentioned as `@name` in the body, see `parse_mentions`
+    #[serde(default, skip_serializing_if = "Vec::is_empty")]
+    pub mentions: Vec<String>,
+}
+
+impl ChatMessage {
+    /// Count the reaction of `by`, false when it was already counted
+    pub fn add_reaction(&mut self, emoji: &str, by: &str) -> bool {
+        match self.reactions.iter_mut().find(|r| r.emoji == emoji) {
+            Some(reaction) if reaction.by.iter().any(|name| name == by) => false,
+            Some(reaction) => {
+                reaction.by.push(by.to_owned());
+                true
+            }
+            None => {
+                self.reactions.push(Reaction {
+                    emoji: emoji.to_owned(),
+                    by: vec![by.to_owned()],
+                });
+                true
+            }
+        }
+    }
+
+    /// Take back the reaction of `by`, false when it wasn't counted
+    pub fn remove_reaction(&mut self, emoji: &str, by: &str) -> bool {
+        let Some(at) = self.reactions.iter().position(|r| r.emoji == emoji) else {
+            return false;
+        };
+        let reaction = &mut self.reactions[at];
+        let count = reaction.by.len();
+        reaction.by.retain(|name| name != by);
+        let removed = reaction.by.len() < count;
+        if reaction.by.is_empty() {
+            self.reactions.remove(at);
+        }
+        removed
+    }
+
+    pub fn has_reacted(&self, emoji: &str, by: &str) -> bool {
+        self.reactions
+            .iter()
+            .any(|r| r.emoji == emoji && r.by.iter().any(|name| name == by))
+    }
+}
+
+/// Names mentioned in a body as `@name`, each once in the order they appear.
+///
+/// A name runs over letters, digits, `_`, `-` and `.` but doesn't end with a
+/// dot, and the `@` must not follow one of those, so `a@b.com` is no mention
+pub fn parse_mentions(body: &str) -> Vec<String> {
+    let is_name_char = |c: char| c.is_alphanumeric() || matches!(c, '_' | '-' | '.');
+    let mut mentions: Vec<String> = Vec::new();
+    let mut previous = None;
+    for (at, c) in body.char_indices() {
+        let starts = c == '@' && !previous.is_some_and(is_name_char);
+        previous = Some(c);
+        if !starts {
+            continue;
+        }
+        let rest = &body[at + 1..];
+        let end = rest.find(|c: char| !is_name_char(c)).unwrap_or(rest.len());
+        let name = rest[..end].trim_end_matches('.');
+        if !name.is_empty() && !mentions.iter().any(|m| m == name) {
+            mentions.push(name.to_owned());
+        }
+    }
+    mentions
+}
+
+/// Everyone who reacted to a message with the same emoji
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Reaction {
+    pub emoji: String,
+    pub by: Vec<String>,
+}
+
+/// New body for a message, sent by its author or a room operator
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Edit {
+    pub message_id: u64,
+    pub new_body: String,
+}
+
+/// Removal of a message, sent by its author or a room operator
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Delete {
+    pub message_id: u64,
+}
+
+/// A message of the room was edited
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Edited {
+    pub room: String,
+    pub message_id: u64,
+    pub new_body: String,
+    /// who edited it
+    pub by: String,
+    /// position among the broadcasts of the room, 0 when not sequenced
+    #[serde(default)]
+    pub seq: u64,
+}
+
+/// A message of the room was deleted
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Deleted {
+    pub room: String,
+    pub message_id: u64,
+    /// who deleted it
+    pub by: String,
+    /// position among the broadcasts of the room, 0 when not sequenced
+    #[serde(default)]
+    pub seq: u64,
+}
+
+/// Reaction to a message, sent to add it with `React` and to take it back
+/// with `Unreact`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct React {
+    pub message_id: u64,
+    pub emoji: String,
+}
+
+/// A reaction to a message of the room was added or, with `Unreact`, taken back
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Reacted {
+    pub room: String,
+    pub message_id: u64,
+    pub emoji: String,
+    pub by: String,
+    /// position among the broadcasts of the room, 0 when not sequenced
+    #[serde(default)]
+    pub seq: u64,
+}
+
+/// Confirmation that a chat was accepted and broadcast
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Ack {
+    pub nonce: String,
+    pub message_id: u64,
+    pub time: String,
+    /// seconds until slow mode lets the sender chat in the room again
+    #[serde(default)]
+    pub wait_secs: u64,
+}
+
+/// Private message sent by a client to a user
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DirectSend {
+    pub to: String,
+    pub body: String,
+}
+
+/// Private message delivered to both ends of the conversation
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct DirectMessage {
+    pub from: String,
+    pub to: String,
+    pub body: String,
+    pub time: String,
+}
+
+/// Availability a member shows to the others
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+#[serde(tag = "state", rename_all = "snake_case")]
+pub enum Status {
+    #[default]
+    Online,
+    Away {
+        #[serde(default, skip_serializing_if = "String::is_empty")]
+        message: String,
+    },
+    Busy,
+    /// seen by the others as offline, left out of member lists
+    Invisible,
+}
+
+/// What a member tells about itself. Fields left out of a `Profile` packet
+/// keep their value, empty ones are cleared
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct Profile {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub display_name: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub pronouns: Option<String>,
+    /// e.g. `Europe/Berlin`
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub timezone: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub bio: Option<String>,
+}
+
+/// Request for what is known about the sessions using a name
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct WhoIs {
+    pub name: String,
+}
+
+/// Answer to a `WhoIs`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct UserInfo {
+    pub name: String,
+    pub status: Status,
+    /// empty for members of other nodes
+    pub profile: Profile,
+    pub idle_secs: u64,
+}
+
+/// Room member as seen by the server
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Member {
+    pub id: usize,
+    pub name: String,
+    /// as the member set it, `Away` once idle for longer than the server's
+    /// away threshold
+    #[serde(default)]
+    pub status: Status,
+    /// seconds since the member last joined, renamed or chatted
+    pub idle_secs: u64,
+}
+
+/// Full member list of a room
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Roster {
+    pub room: String,
+    pub members: Vec<Member>,
+    /// sequence number of the last broadcast of the room
+    #[serde(default)]
+    pub seq: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum PresenceKind {
+    Joined,
+    Left,
+    Renamed {
+        from: String,
+    },
+    /// the status changed
+    Updated,
+}
+
+/// Change of a single member in a room
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Presence {
+    pub room: String,
+    pub kind: PresenceKind,
+    pub member: Member,
+    /// position among the broadcasts of the room, 0 when not sequenced
+    #[serde(default)]
+    pub seq: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+pub enum TypingState {
+    Active,
+    Stopped,
+}
+
+/// Composing a chat in a room, sent again every few seconds while it lasts
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Typing {
+    pub room: String,
+    pub state: TypingState,
+}
+
+/// A member started, continued or stopped composing a chat
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct TypingStatus {
+    pub room: String,
+    pub name: String,
+    pub state: TypingState,
+    /// how long an `Active` state lasts unless it is sent again
+    #[serde(default)]
+    pub expires_secs: u64,
+}
+
+/// Who finds and joins a room
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, Default, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum Visibility {
+    /// listed, anyone joins
+    #[default]
+    Public,
+    /// not listed, anyone knowing the name joins
+    Unlisted,
+    /// listed, joining takes the password or an invite
+    Password,
+    /// not listed, joining takes an invite
+    InviteOnly,
+}
+
+impl Visibility {
+    /// Whether `ListRooms` shows the room to non-members
+    pub fn is_listed(&self) -> bool {
+        matches!(self, Visibility::Public | Visibility::Password)
+    }
+}
+
+/// Limits of a room, changed by its operators
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct RoomSettings {
+    /// 0 for no limit
+    #[serde(default)]
+    pub max_members: u32,
+    /// seconds a member waits between two chats, 0 for none
+    #[serde(default)]
+    pub slow_mode_secs: u64,
+    /// nobody may chat
+    #[serde(default)]
+    pub read_only: bool,
+    /// seconds chats stay in the history, 0 to keep them
+    #[serde(default)]
+    pub retention_secs: u64,
+    /// only operators may chat
+    #[serde(default)]
+    pub announcement: bool,
+    #[serde(default)]
+    pub visibility: Visibility,
+}
+
+/// Change of the texts describing a room, fields left out keep their value
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct SetTopic {
+    pub room: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub topic: Option<String>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub description: Option<String>,
+}
+
+/// Request for the details of a room, replacing its settings when given
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RoomQuery {
+    pub room: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub settings: Option<RoomSettings>,
+    /// new password of a password protected room, never sent back
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+}
+
+/// Join request, sent as JSON when it carries a password or TTL and as the
+/// bare room name otherwise
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct JoinRoom {
+    pub room: String,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub password: Option<String>,
+    /// seconds until the room closes, when the join creates it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub ttl_secs: Option<u64>,
+}
+
+impl JoinRoom {
+    /// Read the message of a `Join` packet in either form
+    pub fn parse(message: &str) -> JoinRoom {
+        match serde_json::from_str(message) {
+            Ok(join) => join,
+            Err(_) => JoinRoom {
+                room: message.to_owned(),
+                password: None,
+                ttl_secs: None,
+            },
+        }
+    }
+
+    /// Message of the `Join` packet
+    pub fn to_message(&self) -> String {
+        match (&self.password, self.ttl_secs) {
+            (None, None) => self.room.clone(),
+            _ => serde_json::to_string(self).unwrap_or_default(),
+        }
+    }
+}
+
+/// Request for an invite code, 0 for no limit
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Invite {
+    pub room: String,
+    #[serde(default)]
+    pub max_uses: u32,
+    #[serde(default)]
+    pub expires_secs: u64,
+}
+
+/// Invite code issued by the server, 0 for no limit
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct InviteCode {
+    pub room: String,
+    pub code: String,
+    #[serde(default)]
+    pub max_uses: u32,
+    #[serde(default)]
+    pub expires_secs: u64,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct AcceptInvite {
+    pub code: String,
+    /// set in the answer
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub room: String,
+}
+
+/// Room as listed by `ListRooms`
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RoomSummary {
+    pub room: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub topic: String,
+    pub visibility: Visibility,
+    pub members: usize,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RoomList {
+    pub rooms: Vec<RoomSummary>,
+}
+
+/// Details of a room
+#[derive(Serialize, Deserialize, Clone, Debug, Default, PartialEq)]
+pub struct RoomInfo {
+    pub room: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub topic: String,
+    #[serde(default, skip_serializing_if = "String::is_empty")]
+    pub description: String,
+    /// none for the rooms of the server itself
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub created_by: Option<String>,
+    pub created_at: String,
+    #[serde(default)]
+    pub settings: RoomSettings,
+    /// seconds left until the room closes, for rooms created with a TTL
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub expires_secs: Option<u64>,
+    /// position among the broadcasts of the room, 0 when not sequenced
+    #[serde(default)]
+    pub seq: u64,
+}
+
+/// Room that closed because its TTL ran out, its members are out of it
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct RoomClosed {
+    pub room: String,
+}
+
+/// Chats purged from the history of a room once they outlived its retention
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Expired {
+    pub room: String,
+    pub message_ids: Vec<u64>,
+    /// position among the broadcasts of the room
+    #[serde(default)]
+    pub seq: u64,
+}
+
+/// Read position of an account in a room: the messages up to `message_id`
+/// were shown
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct MarkRead {
+    pub room: String,
+    pub message_id: u64,
+}
+
+/// Reason a request was rejected
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct ErrorInfo {
+    pub message: String,
+    /// nonce of the rejected chat
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub nonce: Option<String>,
+    /// what the request ran into, for clients acting on it
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub detail: Option<ErrorDetail>,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+#[serde(tag = "kind", rename_all = "snake_case")]
+pub enum ErrorDetail {
+    /// joining directly or through an invite was refused, the room is empty
+    /// for unknown invites
+    JoinRefused { room: String, reason: JoinRefusal },
+    /// a chat came sooner than the slow mode of the room allows
+    SlowMode { room: String, wait_secs: u64 },
+}
+
+#[derive(Serialize, Deserialize, Clone, Copy, Debug, PartialEq)]
+#[serde(rename_all = "snake_case")]
+pub enum JoinRefusal {
+    InvalidName,
+    Full,
+    PasswordRequired,
+    WrongPassword,
+    InviteOnly,
+    /// unknown or used up
+    InvalidInvite,
+    InviteExpired,
+}
+
+impl std::fmt::Display for JoinRefusal {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let reason = match self {
+            JoinRefusal::InvalidName => "invalid room name",
+            JoinRefusal::Full => "the room is full",
+            JoinRefusal::PasswordRequired => "a password is required",
+            JoinRefusal::WrongPassword => "wrong password",
+            JoinRefusal::InviteOnly => "the room is invite-only",
+            JoinRefusal::InvalidInvite => "unknown or used up invite",
+            JoinRefusal::InviteExpired => "the invite expired",
+        };
+        f.write_str(reason)
+    }
+}
+
+/// Request for the broadcasts of a room from `from_seq` on, and the answer
+/// naming the first one the server still had
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Resync {
+    pub room: String,
+    pub from_seq: u64,
+    /// In the answer, the last broadcast replayed. Ask again from the one
+    /// after it once it arrived when the gap isn't filled yet
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub until_seq: Option<u64>,
+}
+
+/// Frames the server dropped for a client that didn't keep up
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Missed {
+    pub count: u64,
+}
@@ -0,0 +1,444 @@
+use proptest::prelude::*;
+
+use proto::*;
+
+/// Every packet type, found through the byte mapping so new variants are
+/// covered without touching this list
+fn packet_types() -> Vec<ChatPacketType> {
+    let mut types: Vec<ChatPacketType> = Vec::new();
+    for byte in 0..=u8::MAX {
+        let packet_type = ChatPacketType::from(byte);
+        if !types.contains(&packet_type) {
+            types.push(packet_type);
+        }
+    }
+    types
+}
+
+fn any_packet_type() -> impl Strategy<Value = ChatPacketType> {
+    proptest::sample::select(packet_types())
+}
+
+fn any_status() -> impl Strategy<Value = Status> {
+    prop_oneof![
+        Just(Status::Online),
+        any::<String>().prop_map(|message| Status::Away { message }),
+        Just(Status::Busy),
+        Just(Status::Invisible),
+    ]
+}
+
+fn any_member() -> impl Strategy<Value = Member> {
+    (any::<usize>(), any::<String>(), any_status(), any::<u64>()).prop_map(
+        |(id, name, status, idle_secs)| Member {
+            id,
+            name,
+            status,
+            idle_secs,
+        },
+    )
+}
+
+fn any_visibility() -> impl Strategy<Value = Visibility> {
+    proptest::sample::select(vec![
+        Visibility::Public,
+        Visibility::Unlisted,
+        Visibility::Password,
+        Visibility::InviteOnly,
+    ])
+}
+
+fn any_presence_kind() -> impl Strategy<Value = PresenceKind> {
+    prop_oneof![
+        Just(PresenceKind::Joined),
+        Just(PresenceKind::Left),
+        any::<String>().prop_map(|from| PresenceKind::Renamed { from }),
+        Just(PresenceKind::Updated),
+    ]
+}
+
+/// Encode `payload`, send it through the frame codec and decode it again
+fn through_the_wire<T>(packet_type: ChatPacketType, payload: &T) -> T
+where
+    T: serde::Serialize + serde::de::DeserializeOwned,
+{
+    let bytes = ChatPacket::with_payload(packet_type.clone(), payload).serialize();
+    let packet = ChatPacket::deserialize(&bytes).expect("frame decodes");
+    assert_eq!(packet.packet_type, packet_type);
+    packet.payload().expect("payload decodes")
+}
+
+proptest! {
+    #[test]
+    fn packets_round_trip(packet_type in any_packet_type(), message in any::<String>()) {
+        let packet = ChatPacket::new(packet_type, message);
+        prop_assert_eq!(ChatPacket::deserialize(&packet.serialize()), Ok(packet));
+    }
+
+    #[test]
+    fn decoding_never_panics(bytes in proptest::collection::vec(any::<u8>(), 0..64)) {
+        if let Ok(packet) = ChatPacket::deserialize(&bytes) {
+            // known type bytes encode back to the same frame
+            if packet.packet_type != ChatPacketType::Unknown {
+                prop_assert_eq!(packet.serialize(), bytes);
+            }
+        }
+    }
+
+    #[test]
+    fn chat_payloads_round_trip(
+        room in any::<String>(),
+        from in any::<String>(),
+        body in any::<String>(),
+        time in any::<String>(),
+        id in any::<u64>(),
+        nonce in any::<Option<String>>(),
+        seq in any::<u64>(),
+        reply_to in any::<Option<u64>>(),
+    ) {
+        let send = ChatSend { room: room.clone(), body: body.clone(), nonce: nonce.clone(), reply_to };
+        prop_assert_eq!(through_the_wire(ChatPacketType::Chat, &send), send);
+
+        let message = ChatMessage {
+            room: room.clone(),
+            from: from.clone(),
+            body: body.clone(),
+            time: time.clone(),
+            id,
+            nonce: nonce.clone(),
+            seq,
+            edited: seq % 2 == 0,
+            deleted: seq % 3 == 0,
+            reply_to,
+            reactions: vec![Reaction { emoji: body.clone(), by: vec![from.clone()] }],
+            mentions: vec![from.clone()],
+        };
+        prop_assert_eq!(through_the_wire(ChatPacketType::Chat, &message), message);
+
+        let edit = Edit { message_id: id, new_body: body.clone() };
+        prop_assert_eq!(through_the_wire(ChatPacketType::Edit, &edit), edit);
+        let edited = Edited { room: room.clone(), message_id: id, new_body: body, by: from.clone(), seq };
+        prop_assert_eq!(through_the_wire(ChatPacketType::Edit, &edited), edited);
+
+        let delete = Delete { message_id: id };
+        prop_assert_eq!(through_the_wire(ChatPacketType::Delete, &delete), delete);
+        let deleted = Deleted { room: room.clone(), message_id: id, by: from.clone(), seq };
+        prop_assert_eq!(through_the_wire(ChatPacketType::Delete, &deleted), deleted);
+
+        let react = React { message_id: id, emoji: time.clone() };
+        prop_assert_eq!(through_the_wire(ChatPacketType::Unreact, &react), react);
+        let reacted = Reacted { room, message_id: id, emoji: time.clone(), by: from, seq };
+        prop_assert_eq!(through_the_wire(ChatPacketType::React, &reacted), reacted);
+
+        if let Some(nonce) = nonce {
+            let ack = Ack { nonce, message_id: id, time, wait_secs: seq };
+            prop_assert_eq!(through_the_wire(ChatPacketType::Ack, &ack), ack);
+        }
+    }
+
+    #[test]
+    fn direct_payloads_round_trip(
+        from in any::<String>(),
+        to in any::<String>(),
+        body in any::<String>(),
+        time in any::<String>(),
+    ) {
+        let send = DirectSend { to: to.clone(), body: body.clone() };
+        prop_assert_eq!(through_the_wire(ChatPacketType::Direct, &send), send);
+
+        let message = DirectMessage { from, to, body, time };
+        prop_assert_eq!(through_the_wire(ChatPacketType::Direct, &message), message);
+    }
+
+    #[test]
+    fn member_payloads_round_trip(
+        room in any::<String>(),
+        members in proptest::collection::vec(any_member(), 0..8),
+        kind in any_presence_kind(),
+        member in any_member(),
+        seq in any::<u64>(),
+    ) {
+        let roster = Roster { room: room.clone(), members, seq };
+        prop_assert_eq!(through_the_wire(ChatPacketType::Roster, &roster), roster);
+
+        let presence = Presence { room, kind, member, seq };
+        prop_assert_eq!(through_the_wire(ChatPacketType::Presence, &presence), presence);
+    }
+
+    #[test]
+    fn error_payloads_round_trip(
+        message in any::<String>(),
+        nonce in any::<Option<String>>(),
+        room in any::<String>(),
+        wait_secs in any::<u64>(),
+        reason in proptest::sample::select(vec![
+            JoinRefusal::InvalidName,
+            JoinRefusal::Full,
+            JoinRefusal::PasswordRequired,
+            JoinRefusal::WrongPassword,
+            JoinRefusal::InviteOnly,
+            JoinRefusal::InvalidInvite,
+            JoinRefusal::InviteExpired,
+        ]),
+    ) {
+        let error = ErrorInfo { message: message.clone(), nonce: nonce.clone(), detail: None };
+        prop_assert_eq!(through_the_wire(ChatPacketType::Error, &error), error);
+        let detail = Some(ErrorDetail::JoinRefused { room: room.clone(), reason });
+        let error = ErrorInfo { message: message.clone(), nonce: nonce.clone(), detail };
+        prop_assert_eq!(through_the_wire(ChatPacketType::Error, &error), error);
+        let detail = Some(ErrorDetail::SlowMode { room, wait_secs });
+        let error = ErrorInfo { message, nonce, detail };
+        prop_assert_eq!(through_the_wire(ChatPacketType::Error, &error), error);
+    }
+
+    #[test]
+    fn resync_payloads_round_trip(
+        room in any::<String>(),
+        from_seq in any::<u64>(),
+        until_seq in any::<Option<u64>>(),
+    ) {
+        let resync = Resync { room, from_seq, until_seq };
+        prop_assert_eq!(through_the_wire(ChatPacketType::Resync, &resync), resync);
+    }
+
+    #[test]
+    fn sequencing_keeps_the_payload(body in any::<String>(), seq in any::<u64>()) {
+        let send = ChatSend { room: "rust".into(), body, nonce: None, reply_to: None };
+        let packet = ChatPacket::with_payload(ChatPacketType::Chat, &send).sequenced(seq);
+        let message: serde_json::Value = serde_json::from_str(&packet.packet_message).unwrap();
+        prop_assert_eq!(message["seq"].as_u64(), Some(seq));
+        prop_assert_eq!(packet.payload::<ChatSend>().unwrap(), send);
+    }
+
+    #[test]
+    fn typing_payloads_round_trip(room in any::<String>(), name in any::<String>(), active in any::<bool>(), expires_secs in any::<u64>()) {
+        let state = if active { TypingState::Active } else { TypingState::Stopped };
+        let typing = Typing { room: room.clone(), state };
+        prop_assert_eq!(through_the_wire(ChatPacketType::Typing, &typing), typing);
+        let status = TypingStatus { room, name, state, expires_secs };
+        prop_assert_eq!(through_the_wire(ChatPacketType::Typing, &status), status);
+    }
+
+    #[test]
+    fn read_positions_round_trip(room in any::<String>(), message_id in any::<u64>()) {
+        let read = MarkRead { room, message_id };
+        prop_assert_eq!(through_the_wire(ChatPacketType::MarkRead, &read), read);
+    }
+
+    #[test]
+    fn profiles_round_trip(
+        name in any::<String>(),
+        status in any_status(),
+        fields in any::<[Option<String>; 4]>(),
+        idle_secs in any::<u64>(),
+    ) {
+        prop_assert_eq!(through_the_wire(ChatPacketType::Status, &status), status.clone());
+        let [display_name, pronouns, timezone, bio] = fields;
+        let profile = Profile { display_name, pronouns, timezone, bio };
+        prop_assert_eq!(through_the_wire(ChatPacketType::Profile, &profile), profile.clone());
+        let whois = WhoIs { name: name.clone() };
+        prop_assert_eq!(through_the_wire(ChatPacketType::WhoIs, &whois), whois);
+        let info = UserInfo { name, status, profile, idle_secs };
+        prop_assert_eq!(through_the_wire(ChatPacketType::WhoIs, &info), info);
+    }
+
+    #[test]
+    fn room_payloads_round_trip(
+        room in any::<String>(),
+        texts in any::<[Option<String>; 2]>(),
+        created_by in any::<Option<String>>(),
+        created_at in any::<String>(),
+        limits in any::<(u32, u64, bool, bool, u64)>(),
+        visibility in any_visibility(),
+        password in any::<Option<String>>(),
+        expires_secs in any::<Option<u64>>(),
+        message_ids in any::<Vec<u64>>(),
+        seq in any::<u64>(),
+    ) {
+        let [topic, description] = texts;
+        let set = SetTopic { room: room.clone(), topic, description };
+        prop_assert_eq!(through_the_wire(ChatPacketType::SetTopic, &set), set.clone());
+        let (max_members, slow_mode_secs, read_only, announcement, retention_secs) = limits;
+        let settings = RoomSettings {
+            max_members,
+            slow_mode_secs,
+            read_only,
+            announcement,
+            retention_secs,
+            visibility,
+        };
+        let query = RoomQuery { room: room.clone(), settings: Some(settings.clone()), password };
+        prop_assert_eq!(through_the_wire(ChatPacketType::RoomInfo, &query), query);
+        let info = RoomInfo {
+            room: room.clone(),
+            topic: set.topic.unwrap_or_default(),
+            description: set.description.unwrap_or_default(),
+            created_by,
+            created_at,
+            settings,
+            expires_secs,
+            seq,
+        };
+        prop_assert_eq!(through_the_wire(ChatPacketType::RoomInfo, &info), info);
+        let closed = RoomClosed { room: room.clone() };
+        prop_assert_eq!(through_the_wire(ChatPacketType::RoomClosed, &closed), closed);
+        let expired = Expired { room, message_ids, seq };
+        prop_assert_eq!(through_the_wire(ChatPacketType::Expired, &expired), expired);
+    }
+
+    #[test]
+    fn invite_payloads_round_trip(
+        room in any::<String>(),
+        code in any::<String>(),
+        max_uses in any::<u32>(),
+        expires_secs in any::<u64>(),
+        topic in any::<String>(),
+        visibility in any_visibility(),
+        members in any::<usize>(),
+    ) {
+        let invite = Invite { room: room.clone(), max_uses, expires_secs };
+        prop_assert_eq!(through_the_wire(ChatPacketType::Invite, &invite), invite);
+        let issued = InviteCode { room: room.clone(), code: code.clone(), max_uses, expires_secs };
+        prop_assert_eq!(through_the_wire(ChatPacketType::Invite, &issued), issued);
+        let accept = AcceptInvite { code, room: room.clone() };
+        prop_assert_eq!(through_the_wire(ChatPacketType::AcceptInvite, &accept), accept);
+        let list = RoomList { rooms: vec![RoomSummary { room, topic, visibility, members }] };
+        prop_assert_eq!(through_the_wire(ChatPacketType::ListRooms, &list), list);
+    }
+
+    #[test]
+    fn joins_read_back_in_either_form(
+        room in any::<String>(),
+        password in any::<Option<String>>(),
+        ttl_secs in any::<Option<u64>>(),
+    ) {
+        let join = JoinRoom { room, password, ttl_secs };
+        // a bare name that is itself a JSON join can't be told apart
+        prop_assume!(
+            join.to_message() != join.room || JoinRoom::parse(&join.room).room == join.room
+        );
+        prop_assert_eq!(JoinRoom::parse(&join.to_message()), join);
+    }
+
+    #[test]
+    fn missed_payloads_round_trip(count in any::<u64>()) {
+        let missed = Missed { count };
+        prop_assert_eq!(through_the_wire(ChatPacketType::Missed, &missed), missed);
+    }
+}
+
+#[test]
+fn type_bytes_are_stable() {
+    for (byte, packet_type) in packet_types().into_iter().enumerate() {
+        assert_eq!(
+            u8::from(packet_type.clone()),
+            byte as u8,
+            "{:?}",
+            packet_type
+        );
+    }
+}
+
+#[test]
+fn empty_frame_is_rejected() {
+    assert_eq!(ChatPacket::deserialize(&[]), Err(DecodeError::Empty));
+}
+
+#[test]
+fn type_byte_alone_is_an_empty_message() {
+    let packet = ChatPacket::deserialize(&[3]).unwrap();
+    assert_eq!(
+        packet,
+        ChatPacket::new(ChatPacketType::Close, String::new())
+    );
+}
+
+#[test]
+fn invalid_utf8_is_rejected() {
+    assert_eq!(
+        ChatPacket::deserialize(&[2, b'h', b'i', 0xff]),
+        Err(DecodeError::InvalidUtf8 { at: 3 })
+    );
+    // a lone continuation byte
+    assert!(ChatPacket::deserialize(&[4, 0x80]).is_err());
+}
+
+#[test]
+fn truncated_frames_do_not_panic() {
+    let chat = ChatSend {
+        room: "rust".to_owned(),
+        body: "héllo 世界".to_owned(),
+        nonce: None,
+        reply_to: None,
+    };
+    let bytes = ChatPacket::with_payload(ChatPacketType::Chat, &chat).serialize();
+
+    for len in 0..bytes.len() {
+        match ChatPacket::deserialize(&bytes[..len]) {
+            Ok(packet) => assert!(packet.payload::<ChatSend>().is_err(), "{} bytes", len),
+            // cut inside a multi-byte character
+            Err(DecodeError::InvalidUtf8 { .. }) => {}
+            Err(DecodeError::Empty) => assert_eq!(len, 0),
+        }
+    }
+}
+
+#[test]
+fn unknown_type_bytes_decode_as_unknown() {
+    for byte in [0, 42, 200, u8::MAX] {
+        let packet = ChatPacket::deserialize(&[byte, b'x']).unwrap();
+        assert_eq!(packet.packet_type, ChatPacketType::Unknown);
+        assert_eq!(packet.packet_message, "x");
+    }
+}
+
+#[test]
+fn chats_without_the_new_fields_still_decode() {
+    let send: ChatSend = serde_json::from_str(r#"{"room":"rust","body":"hi"}"#).unwrap();
+    assert_eq!(send.nonce, None);
+
+    let message: ChatMessage =
+        serde_json::from_str(r#"{"room":"rust","from":"bob","body":"hi","time":"now"}"#).unwrap();
+    assert_eq!((message.id, message.nonce, message.seq), (0, None, 0));
+    assert!(!message.edited && !message.deleted);
+}
+
+#[test]
+fn reactions_are_counted_once_per_name() {
+    let mut message: ChatMessage =
+        serde_json::from_str(r#"{"room":"rust","from":"bob","body":"hi","time":"now"}"#).unwrap();
+    assert!(message.reactions.is_empty());
+
+    assert!(message.add_reaction("👍", "alice"));
+    assert!(message.add_reaction("🎉", "carol"));
+    assert!(message.add_reaction("👍", "carol"));
+    assert!(!message.add_reaction("👍", "alice"));
+    let counts: Vec<(&str, usize)> = message
+        .reactions
+        .iter()
+        .map(|r| (r.emoji.as_str(), r.by.len()))
+        .collect();
+    assert_eq!(counts, [("👍", 2), ("🎉", 1)]);
+    assert!(message.has_reacted("🎉", "carol"));
+
+    assert!(message.remove_reaction("🎉", "carol"));
+    assert!(!message.remove_reaction("🎉", "carol"));
+    assert!(!message.remove_reaction("👀", "alice"));
+    assert_eq!(message.reactions.len(), 1);
+}
+
+#[test]
+fn sequencing_leaves_plain_packets_alone() {
+    let packet = ChatPacket::new(ChatPacketType::Login, "[now] ID_1 set name to bob".into());
+    assert_eq!(packet.sequenced(3), packet);
+}
+
+#[test]
+fn mentions_are_parsed_from_the_body() {
+    assert_eq!(
+        parse_mentions("@bob, ask @carol.smith or @bob."),
+        ["bob", "carol.smith"]
+    );
+    assert!(parse_mentions("mail alice@example.com, or @ me").is_empty());
+    assert_eq!(parse_mentions("(@dave) @dave@erin"), ["dave"]);
+}
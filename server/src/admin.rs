@@ -0,0 +1,104 @@
+use std::fmt::Write;
+
+use actix::Addr;
+use actix_web::{error, web, Error, HttpRequest, HttpResponse};
+
+use crate::server::{ListSessions, SessionStats, WsServer};
+use crate::{bearer_is, Config};
+
+/// `/metrics` and `/admin/*`, only answered when `ADMIN_TOKEN` is set
+pub fn configure(cfg: &mut web::ServiceConfig) {
+    cfg.route("/metrics", web::get().to(metrics))
+        .route("/admin/sessions", web::get().to(sessions));
+}
+
+/// `None` when the request may go on, the response to send otherwise
+fn refuse(config: &Config, req: &HttpRequest) -> Option<HttpResponse> {
+    match &config.admin_token {
+        None => Some(HttpResponse::NotFound().finish()),
+        Some(token) if !bearer_is(req, token) => Some(HttpResponse::Unauthorized().finish()),
+        Some(_) => None,
+    }
+}
+
+async fn session_stats(srv: &Addr<WsServer>) -> Result<Vec<SessionStats>, Error> {
+    srv.send(ListSessions)
+        .await
+        .map_err(error::ErrorServiceUnavailable)
+}
+
+/// Every connected session as JSON
+async fn sessions(
+    req: HttpRequest,
+    srv: web::Data<Addr<WsServer>>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    if let Some(refused) = refuse(&config, &req) {
+        return Ok(refused);
+    }
+    Ok(HttpResponse::Ok().json(session_stats(&srv).await?))
+}
+
+/// Session gauges in the Prometheus text format
+async fn metrics(
+    req: HttpRequest,
+    srv: web::Data<Addr<WsServer>>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    if let Some(refused) = refuse(&config, &req) {
+        return Ok(refused);
+    }
+    let stats = session_stats(&srv).await?;
+    Ok(HttpResponse::Ok()
+        .content_type("text/plain; version=0.0.4")
+        .body(render_metrics(&stats)))
+}
+
+fn render_metrics(stats: &[SessionStats]) -> String {
+    let mut out = String::new();
+    let _ = writeln!(out, "# HELP chat_sessions Connected websocket sessions");
+    let _ = writeln!(out, "# TYPE chat_sessions gauge");
+    let _ = writeln!(out, "chat_sessions {}", stats.len());
+
+    let _ = writeln!(
+        out,
+        "# HELP chat_session_queue_depth Frames waiting to be written to a session"
+    );
+    let _ = writeln!(out, "# TYPE chat_session_queue_depth gauge");
+    for s in stats {
+        let _ = writeln!(
+            out,
+            "chat_session_queue_depth{{{}}} {}",
+            labels(s),
+            s.queue_depth
+        );
+    }
+
+    let _ = writeln!(
+        out,
+        "# HELP chat_session_dropped_total Frames dropped because a session's outbox was full"
+    );
+    let _ = writeln!(out, "# TYPE chat_session_dropped_total counter");
+    for s in stats {
+        let _ = writeln!(
+            out,
+            "chat_session_dropped_total{{{}}} {}",
+            labels(s),
+            s.dropped
+        );
+    }
+    out
+}
+
+fn labels(stats: &SessionStats) -> String {
+    let name = stats.name.as_deref().unwrap_or_default();
+    format!("session=\"{}\",name=\"{}\"", stats.id, escape_label(name))
+}
+
+/// Label values escape backslashes, quotes and newlines
+fn escape_label(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
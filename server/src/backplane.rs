@@ -0,0 +1,168 @@
+//! Pub/sub between several `ws-server` instances, so sessions connected to
+//! different nodes share rooms, presence and direct messages
+
+use std::collections::{HashSet, VecDeque};
+use std::hash::Hash;
+use std::sync::{Arc, Mutex, PoisonError};
+
+use serde::{Deserialize, Serialize};
+use tokio::sync::mpsc;
+
+use proto::{ChatPacket, ChatPacketType};
+
+use crate::room::Password;
+
+mod redis;
+
+pub use self::redis::RedisBackplane;
+
+/// Envelope and chat ids a node remembers for dropping duplicates
+pub const SEEN_CAPACITY: usize = 4096;
+
+/// Envelopes queued between a backplane and the server, or the Redis
+/// connection, before new ones are dropped
+pub const BACKPLANE_QUEUE: usize = 4096;
+
+/// Transport between the nodes of a cluster.
+///
+/// Every node publishes to and hears from every other node. A node may also
+/// hear its own envelopes and the same envelope more than once, the server
+/// drops those by node and envelope id, and chats by room and message id.
+pub trait Backplane: Send + Sync {
+    /// Hand an envelope to the other nodes, never blocks
+    fn publish(&self, envelope: Envelope);
+
+    /// Start delivering the envelopes of the other nodes to `tx`
+    fn subscribe(&self, tx: mpsc::Sender<Envelope>);
+}
+
+/// Message between nodes
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Envelope {
+    /// Unique per envelope
+    pub id: u64,
+    /// Server id of the publishing node
+    pub node: u64,
+    pub event: NodeEvent,
+}
+
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub enum NodeEvent {
+    /// Packet for the members of a room
+    Room { room: String, packet: Packet },
+    /// Packet for every session
+    All { packet: Packet },
+    /// Packet for the sessions using a name
+    Direct { to: String, packet: Packet },
+    /// A name read a room up to a message on the publishing node
+    Read { name: String, read: proto::MarkRead },
+    /// A member of the publishing node joined, left or renamed
+    Presence(proto::Presence),
+    /// A node started and wants to know the members of the others
+    Hello,
+    /// Every member of the publishing node with its rooms, sent regularly
+    Members(Vec<(String, proto::Member)>),
+    /// Who the rooms run by operators of the publishing node let in, sent
+    /// with the members and on every change
+    Admission(Vec<(String, Admission)>),
+    /// The publishing node is shutting down
+    Bye,
+}
+
+/// Settings and password hash of a room, so the copies other nodes keep of
+/// it let in the same sessions
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Admission {
+    pub settings: proto::RoomSettings,
+    pub password: Option<Password>,
+}
+
+/// `ChatPacket` as carried between nodes
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Packet {
+    pub kind: u8,
+    pub message: String,
+}
+
+impl From<&ChatPacket> for Packet {
+    fn from(pkg: &ChatPacket) -> Packet {
+        Packet {
+            kind: pkg.packet_type.clone().into(),
+            message: pkg.packet_message.clone(),
+        }
+    }
+}
+
+impl From<Packet> for ChatPacket {
+    fn from(packet: Packet) -> ChatPacket {
+        ChatPacket::new(ChatPacketType::from(packet.kind), packet.message)
+    }
+}
+
+/// Recently seen envelope or message ids
+#[derive(Debug)]
+pub struct Seen<K> {
+    order: VecDeque<K>,
+    ids: HashSet<K>,
+}
+
+impl<K: Clone + Eq + Hash> Seen<K> {
+    /// Remember `id`, false if it was seen before
+    pub fn insert(&mut self, id: K) -> bool {
+        if !self.ids.insert(id.clone()) {
+            return false;
+        }
+        self.order.push_back(id);
+        if self.order.len() > SEEN_CAPACITY {
+            if let Some(oldest) = self.order.pop_front() {
+                self.ids.remove(&oldest);
+            }
+        }
+        true
+    }
+}
+
+impl<K> Default for Seen<K> {
+    fn default() -> Seen<K> {
+        Seen {
+            order: VecDeque::new(),
+            ids: HashSet::new(),
+        }
+    }
+}
+
+/// Backplane for nodes running in one process, clones share the bus
+#[derive(Clone, Default)]
+pub struct InProcess {
+    subscribers: Arc<Mutex<Vec<mpsc::Sender<Envelope>>>>,
+}
+
+impl InProcess {
+    pub fn new() -> InProcess {
+        InProcess::default()
+    }
+}
+
+impl Backplane for InProcess {
+    fn publish(&self, envelope: Envelope) {
+        let mut subscribers = self
+            .subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner);
+        subscribers.retain(|tx| match tx.try_send(envelope.clone()) {
+            Ok(()) => true,
+            Err(mpsc::error::TrySendError::Full(_)) => {
+                log::warn!("backplane: subscriber is behind, dropping an envelope");
+                true
+            }
+            Err(mpsc::error::TrySendError::Closed(_)) => false,
+        });
+    }
+
+    fn subscribe(&self, tx: mpsc::Sender<Envelope>) {
+        self.subscribers
+            .lock()
+            .unwrap_or_else(PoisonError::into_inner)
+            .push(tx);
+    }
+}
@@ -0,0 +1,226 @@
+use std::io;
+use std::time::Duration;
+
+use tokio::io::{AsyncBufReadExt, AsyncRead, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::TcpStream;
+use tokio::sync::mpsc;
+
+use super::{Backplane, Envelope, BACKPLANE_QUEUE};
+
+/// Wait before connecting again after the Redis connection failed
+pub const RECONNECT_DELAY: Duration = Duration::from_secs(1);
+
+/// Largest value of a push, well above the largest envelope
+pub const MAX_BULK_LEN: usize = 1024 * 1024;
+
+/// Most values in a push, pub/sub pushes have three
+const MAX_PUSH_ITEMS: usize = 16;
+
+/// Longest header line of a push, a type marker and a number
+const MAX_LINE_LEN: u64 = 64;
+
+/// Backplane over the PUBLISH and SUBSCRIBE commands of a Redis server.
+///
+/// Envelopes are JSON encoded and published to a single channel. Delivery is
+/// at most once, envelopes published while a connection is down or while its
+/// queue is full are lost and the regular member announcements bring the
+/// nodes back in sync.
+pub struct RedisBackplane {
+    addr: String,
+    channel: String,
+    outgoing: mpsc::Sender<Vec<u8>>,
+}
+
+impl RedisBackplane {
+    /// `url` is `redis://host[:port]`, connections are made in the background.
+    /// Must be called from within an actix system.
+    pub fn new(url: &str, channel: &str) -> io::Result<RedisBackplane> {
+        let addr = parse_url(url)?;
+        let (outgoing, rx) = mpsc::channel(BACKPLANE_QUEUE);
+        actix_web::rt::spawn(publisher(addr.clone(), channel.to_owned(), rx));
+        Ok(RedisBackplane {
+            addr,
+            channel: channel.to_owned(),
+            outgoing,
+        })
+    }
+}
+
+impl Backplane for RedisBackplane {
+    fn publish(&self, envelope: Envelope) {
+        match serde_json::to_vec(&envelope) {
+            Ok(payload) => {
+                // closed only once the publisher is gone with the system
+                if let Err(mpsc::error::TrySendError::Full(_)) = self.outgoing.try_send(payload) {
+                    log::warn!("backplane: {} is behind, dropping an envelope", self.addr);
+                }
+            }
+            Err(err) => log::error!("backplane: can't encode envelope: {}", err),
+        }
+    }
+
+    fn subscribe(&self, tx: mpsc::Sender<Envelope>) {
+        actix_web::rt::spawn(subscriber(self.addr.clone(), self.channel.clone(), tx));
+    }
+}
+
+/// `host:port` of a `redis://` url
+fn parse_url(url: &str) -> io::Result<String> {
+    let invalid = || io::Error::new(io::ErrorKind::InvalidInput, format!("invalid url: {}", url));
+    let rest = url.strip_prefix("redis://").ok_or_else(invalid)?;
+    // a database number is meaningless for pub/sub
+    let host = rest.split('/').next().unwrap_or_default();
+    if host.is_empty() || host.contains('@') {
+        return Err(invalid());
+    }
+    if host
+        .rsplit_once(':')
+        .is_some_and(|(_, port)| port.parse::<u16>().is_ok())
+    {
+        Ok(host.to_owned())
+    } else {
+        Ok(format!("{}:6379", host))
+    }
+}
+
+/// RESP encoding of a command
+fn command(args: &[&[u8]]) -> Vec<u8> {
+    let mut out = format!("*{}\r\n", args.len()).into_bytes();
+    for arg in args {
+        out.extend(format!("${}\r\n", arg.len()).as_bytes());
+        out.extend(*arg);
+        out.extend(b"\r\n");
+    }
+    out
+}
+
+async fn publisher(addr: String, channel: String, mut rx: mpsc::Receiver<Vec<u8>>) {
+    loop {
+        let stream = match TcpStream::connect(&addr).await {
+            Ok(stream) => stream,
+            Err(err) => {
+                log::warn!("backplane: can't connect to {}: {}", addr, err);
+                tokio::time::sleep(RECONNECT_DELAY).await;
+                continue;
+            }
+        };
+        let (mut reader, mut writer) = stream.into_split();
+        // replies are subscriber counts, read them so the server never blocks on us
+        let mut replies = actix_web::rt::spawn(async move {
+            let mut buf = [0; 1024];
+            while let Ok(1..) = reader.read(&mut buf).await {}
+        });
+
+        loop {
+            tokio::select! {
+                payload = rx.recv() => {
+                    let Some(payload) = payload else {
+                        replies.abort();
+                        return;
+                    };
+                    let publish = command(&[b"PUBLISH", channel.as_bytes(), &payload]);
+                    if let Err(err) = writer.write_all(&publish).await {
+                        log::warn!("backplane: publishing to {} failed: {}", addr, err);
+                        break;
+                    }
+                }
+                _ = &mut replies => {
+                    log::warn!("backplane: {} closed the connection", addr);
+                    break;
+                }
+            }
+        }
+        replies.abort();
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn subscriber(addr: String, channel: String, tx: mpsc::Sender<Envelope>) {
+    loop {
+        match subscribe(&addr, &channel, &tx).await {
+            // the server is gone
+            Ok(()) => return,
+            Err(err) => log::warn!("backplane: subscription to {} failed: {}", addr, err),
+        }
+        tokio::time::sleep(RECONNECT_DELAY).await;
+    }
+}
+
+async fn subscribe(addr: &str, channel: &str, tx: &mpsc::Sender<Envelope>) -> io::Result<()> {
+    let stream = TcpStream::connect(addr).await?;
+    let (reader, mut writer) = stream.into_split();
+    writer
+        .write_all(&command(&[b"SUBSCRIBE", channel.as_bytes()]))
+        .await?;
+    log::info!("backplane: subscribed to {} on {}", channel, addr);
+
+    let mut reader = BufReader::new(reader);
+    loop {
+        let push = read_push(&mut reader).await?;
+        // subscribe confirmations are `["subscribe", channel, count]`
+        let [kind, _, payload] = push.as_slice() else {
+            continue;
+        };
+        if kind != b"message" {
+            continue;
+        }
+        match serde_json::from_slice::<Envelope>(payload) {
+            Ok(envelope) => {
+                // a server that is behind holds up reading from Redis
+                if tx.send(envelope).await.is_err() {
+                    return Ok(());
+                }
+            }
+            Err(err) => log::warn!("backplane: ignoring invalid envelope: {}", err),
+        }
+    }
+}
+
+/// One pub/sub push: an array of bulk strings and integers, refused when
+/// it's larger than a push of this backplane can be
+async fn read_push<R: AsyncRead + Unpin>(reader: &mut BufReader<R>) -> io::Result<Vec<Vec<u8>>> {
+    let line = read_line(reader).await?;
+    let count = match line.strip_prefix('*') {
+        Some(count) => count.parse::<usize>().map_err(invalid_data)?,
+        None => return Err(invalid_data(line)),
+    };
+    if count > MAX_PUSH_ITEMS {
+        return Err(invalid_data(format!("push of {} values", count)));
+    }
+
+    let mut items = Vec::with_capacity(count);
+    for _ in 0..count {
+        let line = read_line(reader).await?;
+        if let Some(len) = line.strip_prefix('$') {
+            let len = len.parse::<usize>().map_err(invalid_data)?;
+            if len > MAX_BULK_LEN {
+                return Err(invalid_data(format!("value of {} bytes", len)));
+            }
+            let mut item = vec![0; len + 2];
+            reader.read_exact(&mut item).await?;
+            item.truncate(len);
+            items.push(item);
+        } else if let Some(value) = line.strip_prefix(':') {
+            items.push(value.as_bytes().to_vec());
+        } else {
+            return Err(invalid_data(line));
+        }
+    }
+    Ok(items)
+}
+
+async fn read_line<R: AsyncRead + Unpin>(reader: &mut BufReader<R>) -> io::Result<String> {
+    let mut line = String::new();
+    let read = reader.take(MAX_LINE_LEN).read_line(&mut line).await?;
+    if read as u64 == MAX_LINE_LEN && !line.ends_with('\n') {
+        return Err(invalid_data("line too long"));
+    }
+    if !line.ends_with('\n') {
+        return Err(io::ErrorKind::UnexpectedEof.into());
+    }
+    Ok(line.trim_end().to_owned())
+}
+
+fn invalid_data(err: impl ToString) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, err.to_string())
+}
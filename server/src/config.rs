@@ -0,0 +1,103 @@
+use std::time::Duration;
+
+use crate::outbox::{OverflowPolicy, OUTBOX_CAPACITY};
+use crate::server::{AWAY_AFTER, EDIT_WINDOW, TYPING_EXPIRY};
+use crate::session::{CLIENT_TIMEOUT, HEARTBEAT_INTERVAL};
+
+/// Server settings, read from the environment and `.env`
+#[derive(Clone, Debug)]
+pub struct Config {
+    /// `PORT`
+    pub port: u16,
+    /// `AUTH_TOKEN`, clients must send `Authorization: Bearer <token>` when set
+    pub auth_token: Option<String>,
+    /// `HEARTBEAT_INTERVAL_SECS`, how often heartbeat pings are sent
+    pub heartbeat_interval: Duration,
+    /// `CLIENT_TIMEOUT_SECS`, how long a silent client stays connected
+    pub client_timeout: Duration,
+    /// `OUTBOX_CAPACITY`, frames queued for a slow client
+    pub outbox_capacity: usize,
+    /// `OUTBOX_POLICY`, `drop-oldest`, `marker` or `disconnect` once the outbox is full
+    pub outbox_policy: OverflowPolicy,
+    /// `ADMIN_TOKEN`, enables `/metrics` and `/admin/*` for requests bearing it
+    pub admin_token: Option<String>,
+    /// `BACKPLANE_URL`, `redis://host:port` shared by the nodes of a cluster
+    pub backplane_url: Option<String>,
+    /// `BACKPLANE_CHANNEL`, pub/sub channel of the cluster
+    pub backplane_channel: String,
+    /// `EDIT_WINDOW_SECS`, how long after sending a message may be edited or deleted,
+    /// 0 disables both
+    pub edit_window: Duration,
+    /// `TYPING_EXPIRY_SECS`, how long a member shows as typing without saying so again
+    pub typing_expiry: Duration,
+    /// `AWAY_AFTER_SECS`, how long after its last login, join or chat a member
+    /// shows as away, 0 never
+    pub away_after: Duration,
+    /// `MOTD`, message of the day sent to every new session
+    pub motd: Option<String>,
+}
+
+impl Default for Config {
+    fn default() -> Config {
+        Config {
+            port: 3000,
+            auth_token: None,
+            heartbeat_interval: HEARTBEAT_INTERVAL,
+            client_timeout: CLIENT_TIMEOUT,
+            outbox_capacity: OUTBOX_CAPACITY,
+            outbox_policy: OverflowPolicy::Marker,
+            admin_token: None,
+            backplane_url: None,
+            backplane_channel: "ws-chat".to_owned(),
+            edit_window: EDIT_WINDOW,
+            typing_expiry: TYPING_EXPIRY,
+            away_after: AWAY_AFTER,
+            motd: None,
+        }
+    }
+}
+
+impl Config {
+    pub fn from_env() -> Config {
+        let defaults = Config::default();
+        Config {
+            port: env_parse("PORT").unwrap_or(defaults.port),
+            auth_token: std::env::var("AUTH_TOKEN").ok().filter(|t| !t.is_empty()),
+            heartbeat_interval: env_parse("HEARTBEAT_INTERVAL_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.heartbeat_interval),
+            client_timeout: env_parse("CLIENT_TIMEOUT_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.client_timeout),
+            outbox_capacity: env_parse("OUTBOX_CAPACITY").unwrap_or(defaults.outbox_capacity),
+            outbox_policy: env_parse("OUTBOX_POLICY").unwrap_or(defaults.outbox_policy),
+            admin_token: std::env::var("ADMIN_TOKEN").ok().filter(|t| !t.is_empty()),
+            backplane_url: std::env::var("BACKPLANE_URL")
+                .ok()
+                .filter(|u| !u.is_empty()),
+            backplane_channel: env_parse("BACKPLANE_CHANNEL").unwrap_or(defaults.backplane_channel),
+            edit_window: env_parse("EDIT_WINDOW_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.edit_window),
+            typing_expiry: env_parse("TYPING_EXPIRY_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.typing_expiry),
+            away_after: env_parse("AWAY_AFTER_SECS")
+                .map(Duration::from_secs)
+                .unwrap_or(defaults.away_after),
+            motd: std::env::var("MOTD").ok().filter(|m| !m.is_empty()),
+        }
+    }
+}
+
+/// Parse an environment variable, warning about values that don't parse
+pub(crate) fn env_parse<T: std::str::FromStr>(key: &str) -> Option<T> {
+    let value = std::env::var(key).ok().filter(|v| !v.is_empty())?;
+    match value.parse() {
+        Ok(parsed) => Some(parsed),
+        Err(_) => {
+            log::warn!("ignoring invalid {}: {}", key, value);
+            None
+        }
+    }
+}
@@ -0,0 +1,120 @@
+use std::net::TcpListener;
+use std::sync::Arc;
+
+use actix::*;
+use actix_web::{
+    dev::Server, http::header, middleware::Logger, web, App, Error, HttpRequest, HttpResponse,
+    HttpServer,
+};
+use actix_web_actors::ws;
+
+pub mod admin;
+pub mod backplane;
+pub mod config;
+pub mod outbox;
+pub mod room;
+pub mod server;
+pub mod session;
+
+pub use config::Config;
+
+/// Whether the request carries the configured bearer token
+fn authorized(config: &Config, req: &HttpRequest) -> bool {
+    match &config.auth_token {
+        Some(token) => bearer_is(req, token),
+        None => true,
+    }
+}
+
+/// Whether the request carries `Authorization: Bearer <token>`
+fn bearer_is(req: &HttpRequest, token: &str) -> bool {
+    req.headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .is_some_and(|value| value == token)
+}
+
+/// Id the client keeps across reconnects, if it sent a usable one
+fn client_id(req: &HttpRequest) -> Option<String> {
+    req.headers()
+        .get(proto::CLIENT_ID_HEADER)
+        .and_then(|value| value.to_str().ok())
+        .filter(|id| (1..=64).contains(&id.len()))
+        .filter(|id| id.chars().all(|c| c.is_ascii_alphanumeric() || c == '-'))
+        .map(str::to_owned)
+}
+
+/// Entry point for our websocket route
+async fn route(
+    req: HttpRequest,
+    stream: web::Payload,
+    srv: web::Data<Addr<server::WsServer>>,
+    config: web::Data<Config>,
+) -> Result<HttpResponse, Error> {
+    log::debug!("web route request: {:?}", req);
+
+    if !authorized(&config, &req) {
+        return Ok(HttpResponse::Unauthorized().finish());
+    }
+
+    // frames from the chat server bypass the session's own unbounded queue,
+    // the outbox is written next to it
+    let outbox = Arc::new(outbox::Outbox::new(
+        config.outbox_capacity,
+        config.outbox_policy,
+    ));
+    let session = session::WsSession::new(
+        srv.get_ref().clone(),
+        &config,
+        outbox.clone(),
+        client_id(&req),
+    );
+    let own_frames = ws::WebsocketContext::create(session, stream);
+    Ok(ws::handshake(&req)?.streaming(futures_util::stream::select(own_frames, outbox.stream())))
+}
+
+/// Start the chat server actor and serve the websocket route on `listener`,
+/// joining the cluster at `BACKPLANE_URL` when set.
+/// Must be called from within an actix system.
+pub fn serve(listener: TcpListener, config: Config) -> std::io::Result<Server> {
+    let backplane: Option<Arc<dyn backplane::Backplane>> = match &config.backplane_url {
+        Some(url) => Some(Arc::new(backplane::RedisBackplane::new(
+            url,
+            &config.backplane_channel,
+        )?)),
+        None => None,
+    };
+    serve_with(listener, config, backplane)
+}
+
+/// Like `serve`, with the backplane given instead of configured
+pub fn serve_with(
+    listener: TcpListener,
+    config: Config,
+    backplane: Option<Arc<dyn backplane::Backplane>>,
+) -> std::io::Result<Server> {
+    let mut server = server::WsServer::new()
+        .with_edit_window(config.edit_window)
+        .with_typing_expiry(config.typing_expiry)
+        .with_away_after(config.away_after)
+        .with_motd(config.motd.clone());
+    if let Some(backplane) = backplane {
+        server = server.with_backplane(backplane);
+    }
+    let server = server.start();
+    let config = web::Data::new(config);
+
+    let http = HttpServer::new(move || {
+        App::new()
+            .app_data(web::Data::new(server.clone()))
+            .app_data(config.clone())
+            .route("/", web::get().to(route))
+            .configure(admin::configure)
+            .wrap(Logger::default())
+    })
+    .listen(listener)?
+    .run();
+
+    Ok(http)
+}
@@ -0,0 +1,300 @@
+use std::collections::VecDeque;
+use std::pin::Pin;
+use std::str::FromStr;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::task::{Context, Poll, Waker};
+
+use actix::prelude::*;
+use actix_codec::Encoder;
+use actix_http::ws::{Codec, Message as WsMessage};
+use actix_web::web::{Bytes, BytesMut};
+use futures_util::Stream;
+use tokio::sync::broadcast::{self, error::RecvError};
+
+use proto::{ChatPacket, ChatPacketType};
+
+use crate::room::RoomFrame;
+
+/// Frames queued for a session before the overflow policy kicks in
+pub const OUTBOX_CAPACITY: usize = 256;
+
+/// Times the capacity critical frames may fill before the session is closed
+/// whatever the overflow policy
+const CRITICAL_SLACK: usize = 2;
+
+/// What happens when a session's outbox is full
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum OverflowPolicy {
+    /// Silently drop the oldest room broadcast
+    DropOldest,
+    /// Drop the oldest room broadcast and tell the client how many it missed
+    Marker,
+    /// Close the connection with 1008 (policy violation)
+    Disconnect,
+}
+
+impl FromStr for OverflowPolicy {
+    type Err = String;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "drop-oldest" => Ok(OverflowPolicy::DropOldest),
+            "marker" => Ok(OverflowPolicy::Marker),
+            "disconnect" => Ok(OverflowPolicy::Disconnect),
+            _ => Err(format!("unknown overflow policy: {}", s)),
+        }
+    }
+}
+
+/// Sent to the owning session when the outbox overflows under
+/// `OverflowPolicy::Disconnect`, or critical frames fill it past `CRITICAL_SLACK`
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Overflowed {
+    pub queued: usize,
+}
+
+/// Frames replayed for one `Resync`, queued while any of them is
+#[derive(Clone, Debug, Default)]
+pub struct Replay(Arc<()>);
+
+impl Replay {
+    pub fn queued(&self) -> bool {
+        Arc::strong_count(&self.0) > 1
+    }
+}
+
+#[derive(Debug)]
+struct Queued {
+    bytes: Bytes,
+    /// Addressed to this session alone, never dropped
+    critical: bool,
+    /// Keeps its replay queued until the frame is written or dropped
+    _replay: Option<Replay>,
+}
+
+#[derive(Default)]
+struct State {
+    frames: VecDeque<Queued>,
+    /// Dropped since the last marker was sent
+    missed: u64,
+    /// Dropped over the whole session
+    dropped: u64,
+    overflowed: bool,
+    closed: bool,
+    owner: Option<Recipient<Overflowed>>,
+    waker: Option<Waker>,
+}
+
+impl State {
+    fn drop_frames(&mut self, count: u64, policy: OverflowPolicy) {
+        self.dropped += count;
+        if policy == OverflowPolicy::Marker {
+            self.missed += count;
+        }
+    }
+
+    /// Give up on the session, its owner closes the connection
+    fn overflow(&mut self) {
+        let queued = self.frames.len();
+        self.overflowed = true;
+        self.frames.clear();
+        self.dropped += queued as u64 + 1;
+        if let Some(owner) = &self.owner {
+            owner.do_send(Overflowed { queued });
+        }
+        self.wake();
+    }
+
+    fn wake(&mut self) {
+        if let Some(waker) = self.waker.take() {
+            waker.wake();
+        }
+    }
+}
+
+/// Bounded queue of frames on their way to one client.
+///
+/// The session actor only runs while the connection accepts writes, so frames
+/// for a slow client pile up here instead of in its mailbox, where the
+/// overflow policy can bound them.
+pub struct Outbox {
+    capacity: usize,
+    policy: OverflowPolicy,
+    state: Mutex<State>,
+}
+
+impl std::fmt::Debug for Outbox {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("Outbox")
+            .field("capacity", &self.capacity)
+            .field("policy", &self.policy)
+            .field("depth", &self.depth())
+            .finish()
+    }
+}
+
+impl Outbox {
+    pub fn new(capacity: usize, policy: OverflowPolicy) -> Outbox {
+        Outbox {
+            capacity: capacity.max(1),
+            policy,
+            state: Mutex::default(),
+        }
+    }
+
+    fn state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(PoisonError::into_inner)
+    }
+
+    /// Session told about an overflow
+    pub fn set_owner(&self, owner: Recipient<Overflowed>) {
+        self.state().owner = Some(owner);
+    }
+
+    /// Queue a frame, applying the overflow policy when the outbox is full.
+    /// Critical frames are only ever dropped by a disconnect.
+    pub fn push(&self, bytes: Bytes, critical: bool) {
+        self.queue(Queued {
+            bytes,
+            critical,
+            _replay: None,
+        });
+    }
+
+    /// Queue a frame of a replay, dropped like a room broadcast
+    pub fn push_replay(&self, bytes: Bytes, replay: &Replay) {
+        self.queue(Queued {
+            bytes,
+            critical: false,
+            _replay: Some(replay.clone()),
+        });
+    }
+
+    fn queue(&self, frame: Queued) {
+        let mut state = self.state();
+        if state.closed || state.overflowed {
+            return;
+        }
+
+        if state.frames.len() >= self.capacity {
+            if self.policy == OverflowPolicy::Disconnect {
+                state.overflow();
+                return;
+            }
+
+            match state.frames.iter().position(|queued| !queued.critical) {
+                Some(oldest) => {
+                    state.frames.remove(oldest);
+                    state.drop_frames(1, self.policy);
+                }
+                None if !frame.critical => {
+                    state.drop_frames(1, self.policy);
+                    return;
+                }
+                None if state.frames.len() >= self.capacity * CRITICAL_SLACK => {
+                    // the client asks for more than it reads
+                    state.overflow();
+                    return;
+                }
+                None => {}
+            }
+        }
+
+        state.frames.push_back(frame);
+        state.wake();
+    }
+
+    /// Count frames lost before they reached the outbox
+    pub fn record_missed(&self, count: u64) {
+        let mut state = self.state();
+        state.drop_frames(count, self.policy);
+        state.wake();
+    }
+
+    /// Frames queued before the overflow policy kicks in
+    pub fn capacity(&self) -> usize {
+        self.capacity
+    }
+
+    /// Frames waiting to be written
+    pub fn depth(&self) -> usize {
+        self.state().frames.len()
+    }
+
+    /// Frames dropped so far
+    pub fn dropped(&self) -> u64 {
+        self.state().dropped
+    }
+
+    /// Stop accepting frames and end the stream, the session is gone
+    pub fn close(&self) {
+        let mut state = self.state();
+        state.closed = true;
+        state.frames.clear();
+        state.owner = None;
+        state.wake();
+    }
+
+    /// Websocket frames for the response body
+    pub fn stream(self: Arc<Self>) -> OutboxStream {
+        OutboxStream {
+            outbox: self,
+            codec: Codec::new(),
+        }
+    }
+}
+
+/// The outbox encoded as websocket frames, written next to the session's own frames
+pub struct OutboxStream {
+    outbox: Arc<Outbox>,
+    codec: Codec,
+}
+
+impl Stream for OutboxStream {
+    type Item = Result<Bytes, actix_web::Error>;
+
+    fn poll_next(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        let this = self.get_mut();
+        let mut state = this.outbox.state();
+        if state.closed || state.overflowed {
+            return Poll::Ready(None);
+        }
+
+        let mut buf = BytesMut::new();
+        if state.missed > 0 {
+            let missed = proto::Missed {
+                count: std::mem::take(&mut state.missed),
+            };
+            let pkg = ChatPacket::with_payload(ChatPacketType::Missed, &missed);
+            let frame = WsMessage::Binary(Bytes::from(pkg.serialize()));
+            this.codec.encode(frame, &mut buf)?;
+        }
+        while let Some(queued) = state.frames.pop_front() {
+            this.codec
+                .encode(WsMessage::Binary(queued.bytes), &mut buf)?;
+        }
+
+        if buf.is_empty() {
+            state.waker = Some(cx.waker().clone());
+            Poll::Pending
+        } else {
+            Poll::Ready(Some(Ok(buf.freeze())))
+        }
+    }
+}
+
+/// Move the broadcasts of a room into the outbox until the room is dropped
+pub async fn forward(mut rx: broadcast::Receiver<RoomFrame>, outbox: Arc<Outbox>, id: usize) {
+    loop {
+        match rx.recv().await {
+            Ok(frame) if frame.skip != id => outbox.push(frame.bytes, false),
+            Ok(_) => {}
+            Err(RecvError::Lagged(missed)) => {
+                log::warn!("session {} missed {} room frames", id, missed);
+                outbox.record_missed(missed);
+            }
+            Err(RecvError::Closed) => break,
+        }
+    }
+}
@@ -0,0 +1,411 @@
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::time::{Duration, Instant};
+
+use actix_web::web::Bytes;
+use rand::{distributions::Alphanumeric, Rng};
+use serde::{Deserialize, Serialize};
+use tokio::sync::broadcast;
+
+use proto::{ChatPacket, ChatPacketType};
+
+use crate::backplane::Admission;
+
+/// Frames a room buffers for each subscriber before a slow one starts missing them
+pub const ROOM_BACKLOG: usize = 1024;
+
+/// Broadcasts a room keeps for members catching up with `Resync`
+pub const ROOM_HISTORY: usize = 1024;
+
+/// Packet published to a room, encoded once and shared by every subscriber
+#[derive(Clone, Debug)]
+pub struct RoomFrame {
+    pub bytes: Bytes,
+    /// Session that doesn't get the frame, 0 for none
+    pub skip: usize,
+}
+
+/// Characters of the random salt of a room password
+const SALT_LEN: usize = 16;
+
+/// Salted SHA-256 of a room password, the password itself isn't kept
+#[derive(Serialize, Deserialize, Clone, Debug, PartialEq)]
+pub struct Password {
+    salt: String,
+    hash: String,
+}
+
+impl Password {
+    pub fn new(password: &str, rng: &mut impl Rng) -> Password {
+        let salt: String = rng
+            .sample_iter(&Alphanumeric)
+            .take(SALT_LEN)
+            .map(char::from)
+            .collect();
+        let hash = salted(&salt, password);
+        Password { salt, hash }
+    }
+
+    /// Whether `password` is the one hashed, in time independent of where
+    /// the hashes differ
+    pub fn matches(&self, password: &str) -> bool {
+        let hash = salted(&self.salt, password);
+        hash.len() == self.hash.len()
+            && hash
+                .bytes()
+                .zip(self.hash.bytes())
+                .fold(0, |diff, (a, b)| diff | (a ^ b))
+                == 0
+    }
+}
+
+fn salted(salt: &str, password: &str) -> String {
+    sha256::digest(format!("{}{}", salt, password))
+}
+
+/// Payload published to a room, kept decoded in the history so chats are
+/// looked up and changed without parsing them again
+#[derive(Clone, Debug)]
+pub enum Broadcast {
+    Chat {
+        chat: proto::ChatMessage,
+        /// Client id of the sender, none for chats of other nodes
+        author: Option<String>,
+    },
+    Edited(proto::Edited),
+    Deleted(proto::Deleted),
+    Reacted {
+        reacted: proto::Reacted,
+        added: bool,
+    },
+    Presence(proto::Presence),
+    RoomInfo(proto::RoomInfo),
+    Expired(proto::Expired),
+    /// Passed on without a number and forgotten
+    Typing(proto::TypingStatus),
+}
+
+impl Broadcast {
+    /// Payload of a packet published on another node, none for packets
+    /// that aren't room broadcasts
+    pub fn decode(pkg: &ChatPacket) -> Option<Broadcast> {
+        let broadcast = match pkg.packet_type {
+            ChatPacketType::Chat => Broadcast::Chat {
+                chat: pkg.payload().ok()?,
+                author: None,
+            },
+            ChatPacketType::Edit => Broadcast::Edited(pkg.payload().ok()?),
+            ChatPacketType::Delete => Broadcast::Deleted(pkg.payload().ok()?),
+            ChatPacketType::React | ChatPacketType::Unreact => Broadcast::Reacted {
+                reacted: pkg.payload().ok()?,
+                added: pkg.packet_type == ChatPacketType::React,
+            },
+            ChatPacketType::Presence => Broadcast::Presence(pkg.payload().ok()?),
+            ChatPacketType::RoomInfo => Broadcast::RoomInfo(pkg.payload().ok()?),
+            ChatPacketType::Expired => Broadcast::Expired(pkg.payload().ok()?),
+            ChatPacketType::Typing => Broadcast::Typing(pkg.payload().ok()?),
+            _ => return None,
+        };
+        Some(broadcast)
+    }
+
+    pub fn packet(&self) -> ChatPacket {
+        match self {
+            Broadcast::Chat { chat, .. } => ChatPacket::with_payload(ChatPacketType::Chat, chat),
+            Broadcast::Edited(edited) => ChatPacket::with_payload(ChatPacketType::Edit, edited),
+            Broadcast::Deleted(deleted) => {
+                ChatPacket::with_payload(ChatPacketType::Delete, deleted)
+            }
+            Broadcast::Reacted { reacted, added } => {
+                let packet_type = if *added {
+                    ChatPacketType::React
+                } else {
+                    ChatPacketType::Unreact
+                };
+                ChatPacket::with_payload(packet_type, reacted)
+            }
+            Broadcast::Presence(presence) => {
+                ChatPacket::with_payload(ChatPacketType::Presence, presence)
+            }
+            Broadcast::RoomInfo(info) => ChatPacket::with_payload(ChatPacketType::RoomInfo, info),
+            Broadcast::Expired(expired) => {
+                ChatPacket::with_payload(ChatPacketType::Expired, expired)
+            }
+            Broadcast::Typing(status) => ChatPacket::with_payload(ChatPacketType::Typing, status),
+        }
+    }
+
+    fn set_seq(&mut self, seq: u64) {
+        match self {
+            Broadcast::Chat { chat, .. } => chat.seq = seq,
+            Broadcast::Edited(edited) => edited.seq = seq,
+            Broadcast::Deleted(deleted) => deleted.seq = seq,
+            Broadcast::Reacted { reacted, .. } => reacted.seq = seq,
+            Broadcast::Presence(presence) => presence.seq = seq,
+            Broadcast::RoomInfo(info) => info.seq = seq,
+            Broadcast::Expired(expired) => expired.seq = seq,
+            Broadcast::Typing(_) => {}
+        }
+    }
+}
+
+/// Members of a room and the channel their sessions read broadcasts from,
+/// so fan-out happens in the sessions instead of the server actor.
+///
+/// Every broadcast gets the next sequence number of the room. Numbers are
+/// kept per node and start over when the room is recreated.
+#[derive(Debug)]
+pub struct Room {
+    pub members: HashSet<usize>,
+    /// Client ids allowed to edit and delete any message and to change the
+    /// room, the client that created it. Keyed on client ids because names
+    /// are chosen freely and sessions end with their connection.
+    pub operators: HashSet<String>,
+    pub topic: String,
+    pub description: String,
+    /// Name of the creator, none for the main room
+    pub created_by: Option<String>,
+    pub created_at: String,
+    pub settings: proto::RoomSettings,
+    /// Of a password protected room
+    pub password: Option<Password>,
+    /// Client ids that joined before, let back in without password or invite
+    pub admitted: HashSet<String>,
+    /// When each name last chatted within the slow mode interval, kept when
+    /// its session leaves or reconnects
+    pub last_chat: HashMap<String, Instant>,
+    /// When a room created with a TTL closes
+    pub expires: Option<Instant>,
+    tx: broadcast::Sender<RoomFrame>,
+    /// Sequence number of the last broadcast
+    seq: u64,
+    /// Last broadcasts with their sequence numbers and when they were
+    /// published, oldest first without gaps in the numbers
+    history: VecDeque<(u64, Instant, Broadcast)>,
+    /// Sequence numbers of the chats in the history by message id
+    chats: HashMap<u64, u64>,
+}
+
+impl Room {
+    pub fn new() -> Room {
+        let (tx, _) = broadcast::channel(ROOM_BACKLOG);
+        Room {
+            members: HashSet::new(),
+            operators: HashSet::new(),
+            topic: String::new(),
+            description: String::new(),
+            created_by: None,
+            created_at: chrono::Local::now().format("%Y-%m-%d %H:%M:%S").to_string(),
+            settings: proto::RoomSettings::default(),
+            password: None,
+            admitted: HashSet::new(),
+            last_chat: HashMap::new(),
+            expires: None,
+            tx,
+            seq: 0,
+            history: VecDeque::new(),
+            chats: HashMap::new(),
+        }
+    }
+
+    pub fn seq(&self) -> u64 {
+        self.seq
+    }
+
+    /// Details of the room named `room`
+    pub fn info(&self, room: &str) -> proto::RoomInfo {
+        proto::RoomInfo {
+            room: room.to_owned(),
+            topic: self.topic.clone(),
+            description: self.description.clone(),
+            created_by: self.created_by.clone(),
+            created_at: self.created_at.clone(),
+            settings: self.settings.clone(),
+            expires_secs: self
+                .expires
+                .map(|at| at.saturating_duration_since(Instant::now()).as_secs()),
+            seq: 0,
+        }
+    }
+
+    /// Whether only invited sessions, or those with the password, get in
+    pub fn restricted(&self) -> bool {
+        matches!(
+            self.settings.visibility,
+            proto::Visibility::Password | proto::Visibility::InviteOnly
+        )
+    }
+
+    /// Whether the operators set anything a new room wouldn't have
+    pub fn configured(&self) -> bool {
+        !self.topic.is_empty()
+            || !self.description.is_empty()
+            || self.settings != proto::RoomSettings::default()
+            || self.password.is_some()
+    }
+
+    /// Who the room lets in, for the other nodes
+    pub fn admission(&self) -> Admission {
+        Admission {
+            settings: self.settings.clone(),
+            password: self.password.clone(),
+        }
+    }
+
+    /// Receiver for the frames published from now on
+    pub fn subscribe(&self) -> broadcast::Receiver<RoomFrame> {
+        self.tx.subscribe()
+    }
+
+    /// Number the broadcast, encode it once and hand it to every subscriber,
+    /// never blocks. Typing notices are passed on without a number.
+    pub fn publish(&mut self, mut broadcast: Broadcast, skip: usize) {
+        if let Broadcast::Typing(_) = broadcast {
+            let frame = RoomFrame {
+                bytes: Bytes::from(broadcast.packet().serialize()),
+                skip,
+            };
+            let _ = self.tx.send(frame);
+            return;
+        }
+        self.seq += 1;
+        broadcast.set_seq(self.seq);
+        let frame = RoomFrame {
+            bytes: Bytes::from(broadcast.packet().serialize()),
+            skip,
+        };
+        if let Broadcast::Chat { chat, .. } = &broadcast {
+            self.chats.insert(chat.id, self.seq);
+        }
+        self.history
+            .push_back((self.seq, Instant::now(), broadcast));
+        if self.history.len() > ROOM_HISTORY {
+            self.pop_oldest();
+        }
+        // no subscribers is fine, nobody is listening yet
+        let _ = self.tx.send(frame);
+    }
+
+    fn pop_oldest(&mut self) -> Option<(u64, Instant, Broadcast)> {
+        let oldest = self.history.pop_front()?;
+        if let (_, _, Broadcast::Chat { chat, .. }) = &oldest {
+            self.chats.remove(&chat.id);
+        }
+        Some(oldest)
+    }
+
+    /// Index in the history of the broadcast numbered `seq`
+    fn index(&self, seq: u64) -> Option<usize> {
+        let (first, _, _) = self.history.front()?;
+        usize::try_from(seq.checked_sub(*first)?).ok()
+    }
+
+    fn chat_mut(&mut self, message_id: u64) -> Option<&mut proto::ChatMessage> {
+        let index = self.index(*self.chats.get(&message_id)?)?;
+        match self.history.get_mut(index) {
+            Some((_, _, Broadcast::Chat { chat, .. })) => Some(chat),
+            _ => None,
+        }
+    }
+
+    /// Chat in the history
+    pub fn message(&self, message_id: u64) -> Option<proto::ChatMessage> {
+        let index = self.index(*self.chats.get(&message_id)?)?;
+        match self.history.get(index) {
+            Some((_, _, Broadcast::Chat { chat, .. })) => Some(chat.clone()),
+            _ => None,
+        }
+    }
+
+    /// When a chat in the history was published here
+    pub fn sent(&self, message_id: u64) -> Option<Instant> {
+        let index = self.index(*self.chats.get(&message_id)?)?;
+        self.history.get(index).map(|(_, at, _)| *at)
+    }
+
+    /// Client id of the sender of a chat in the history, none for chats of
+    /// other nodes
+    pub fn author(&self, message_id: u64) -> Option<&str> {
+        let index = self.index(*self.chats.get(&message_id)?)?;
+        match self.history.get(index) {
+            Some((_, _, Broadcast::Chat { author, .. })) => author.as_deref(),
+            _ => None,
+        }
+    }
+
+    /// Sequence number of a chat in the history
+    pub fn position(&self, message_id: u64) -> Option<u64> {
+        self.chats.get(&message_id).copied()
+    }
+
+    /// Replace the body of a chat in the history
+    pub fn edit_message(&mut self, message_id: u64, new_body: &str) {
+        if let Some(chat) = self.chat_mut(message_id) {
+            chat.mentions = proto::parse_mentions(new_body);
+            chat.body = new_body.to_owned();
+            chat.edited = true;
+        }
+    }
+
+    /// Leave only a tombstone of a chat in the history
+    pub fn delete_message(&mut self, message_id: u64) {
+        if let Some(chat) = self.chat_mut(message_id) {
+            chat.body.clear();
+            chat.reactions.clear();
+            chat.mentions.clear();
+            chat.deleted = true;
+        }
+    }
+
+    /// Add or remove a reaction to a chat in the history
+    pub fn react(&mut self, reacted: &proto::Reacted, added: bool) {
+        if let Some(chat) = self.chat_mut(reacted.message_id) {
+            if added {
+                chat.add_reaction(&reacted.emoji, &reacted.by);
+            } else {
+                chat.remove_reaction(&reacted.emoji, &reacted.by);
+            }
+        }
+    }
+
+    /// At most `limit` broadcasts from `from_seq` on that are still in the
+    /// history, with the sequence number of the first one, or the next one
+    /// when there are none
+    pub fn since(&self, from_seq: u64, limit: usize) -> (u64, Vec<ChatPacket>) {
+        let skip = self.index(from_seq).unwrap_or(0);
+        let packets: Vec<&(u64, Instant, Broadcast)> =
+            self.history.iter().skip(skip).take(limit).collect();
+        let first = packets.first().map_or(self.seq + 1, |(seq, _, _)| *seq);
+        (
+            first,
+            packets
+                .into_iter()
+                .map(|(_, _, broadcast)| broadcast.packet())
+                .collect(),
+        )
+    }
+
+    /// Drop the broadcasts published longer than the retention of the room
+    /// before `now`, returns the ids of the chats among them
+    pub fn purge(&mut self, now: Instant) -> Vec<u64> {
+        let retention = Duration::from_secs(self.settings.retention_secs);
+        let mut message_ids = Vec::new();
+        if retention.is_zero() {
+            return message_ids;
+        }
+        while let Some((_, at, _)) = self.history.front() {
+            if now.saturating_duration_since(*at) < retention {
+                break;
+            }
+            if let Some((_, _, Broadcast::Chat { chat, .. })) = self.pop_oldest() {
+                message_ids.push(chat.id);
+            }
+        }
+        message_ids
+    }
+}
+
+impl Default for Room {
+    fn default() -> Room {
+        Room::new()
+    }
+}
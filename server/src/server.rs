@@ -1,17 +1,103 @@
-use std::collections::{HashMap, HashSet};
+use std::collections::{HashMap, HashSet, VecDeque};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
 use actix::prelude::*;
-use rand::{self, rngs::ThreadRng, Rng};
+use actix_web::web::Bytes;
+use rand::{self, distributions::Alphanumeric, rngs::ThreadRng, Rng};
+use serde::Serialize;
+use tokio::sync::{broadcast, mpsc};
+use tokio_stream::wrappers::ReceiverStream;
 
-use proto::ChatPacket;
+use proto::{ChatPacket, ChatPacketType};
+
+use crate::backplane::{Admission, Backplane, Envelope, NodeEvent, Seen, BACKPLANE_QUEUE};
+use crate::outbox::{Outbox, Replay};
+use crate::room::{Broadcast, Password, Room, RoomFrame};
+
+/// How long a member may stay idle before being reported as away
+pub const AWAY_AFTER: Duration = Duration::from_secs(5 * 60);
+
+/// Longest away message, in characters
+const MAX_STATUS_LEN: usize = 100;
+
+/// Longest display name, pronouns or timezone, in characters
+const MAX_PROFILE_FIELD_LEN: usize = 64;
+
+/// Longest bio, in characters
+const MAX_BIO_LEN: usize = 300;
+
+/// Room every session joins on connect
+pub const MAIN_ROOM: &str = "main";
+
+/// How often a node announces its members to the others
+pub const ANNOUNCE_INTERVAL: Duration = Duration::from_secs(15);
+
+/// How long a silent node keeps its members
+const NODE_TIMEOUT: Duration = Duration::from_secs(3 * 15);
+
+/// How long after sending a message may still be edited or deleted
+pub const EDIT_WINDOW: Duration = Duration::from_secs(15 * 60);
+
+/// How long a member shows as typing unless it says so again
+pub const TYPING_EXPIRY: Duration = Duration::from_secs(6);
+
+/// How often typing members that went quiet are looked for
+const TYPING_SWEEP: Duration = Duration::from_secs(1);
+
+/// How often rooms are checked for a TTL or retention that ran out
+const ROOM_SWEEP: Duration = Duration::from_secs(1);
+
+/// Longest reaction accepted, in bytes, enough for emoji sequences and short words
+const MAX_REACTION_LEN: usize = 32;
+
+/// Acknowledged chats remembered for answering retries
+const RECENT_ACKS: usize = 4096;
+
+/// Longest chat body accepted, in characters, edits included
+const MAX_CHAT_LEN: usize = 48 * 1024;
+
+/// Longest accepted room name
+const MAX_ROOM_NAME_LEN: usize = 32;
+
+/// Longest room topic accepted
+const MAX_TOPIC_LEN: usize = 200;
+
+/// Longest room description accepted
+const MAX_DESCRIPTION_LEN: usize = 1000;
+
+/// Characters of an invite code
+const INVITE_CODE_LEN: usize = 10;
+
+/// Tells a session to start or stop reading the broadcasts of a room
+#[derive(Message)]
+#[rtype(result = "()")]
+pub enum Subscription {
+    Subscribe {
+        room: String,
+        rx: broadcast::Receiver<RoomFrame>,
+    },
+    Unsubscribe {
+        room: String,
+    },
+}
 
 /// New chat session is created
 #[derive(Message)]
 #[rtype(usize)]
 pub struct Connect {
-    pub addr: Recipient<ChatPacket>,
+    pub outbox: Arc<Outbox>,
+    pub subscriptions: Recipient<Subscription>,
+    pub replaced: Recipient<Replaced>,
+    /// Sessions of the same client are replaced
+    pub client_id: Option<String>,
 }
 
+/// Tells a session another connection of its client took its place
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Replaced;
+
 /// Session is disconnected
 #[derive(Message)]
 #[rtype(result = "()")]
@@ -19,55 +105,1163 @@ pub struct Disconnect {
     pub id: usize,
 }
 
+/// Session sets or changes its name
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Login {
+    pub id: usize,
+    pub name: String,
+}
+
+/// Session joins a room, creating it if needed
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Join {
+    pub id: usize,
+    pub room: String,
+    pub password: Option<String>,
+    /// lifetime of the room when the join creates it
+    pub ttl_secs: Option<u64>,
+}
+
+/// Session leaves a room
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Leave {
+    pub id: usize,
+    pub room: String,
+}
+
+/// Session requests the member list of a room
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ListMembers {
+    pub id: usize,
+    pub room: String,
+}
+
+/// Session sends a chat line to a room
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Chat {
+    pub id: usize,
+    pub room: String,
+    pub body: String,
+    pub nonce: Option<String>,
+    pub reply_to: Option<u64>,
+}
+
+/// Session changes the body of a message
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Edit {
+    pub id: usize,
+    pub message_id: u64,
+    pub new_body: String,
+}
+
+/// Session deletes a message
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Delete {
+    pub id: usize,
+    pub message_id: u64,
+}
+
+/// Session adds or takes back a reaction to a message
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct React {
+    pub id: usize,
+    pub message_id: u64,
+    pub emoji: String,
+    pub added: bool,
+}
+
+/// Session starts, keeps on or stops composing a chat
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Typing {
+    pub id: usize,
+    pub room: String,
+    pub state: proto::TypingState,
+}
+
+/// Session showed the messages of a room up to one
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct MarkRead {
+    pub id: usize,
+    pub room: String,
+    pub message_id: u64,
+}
+
+/// Session changes its availability
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetStatus {
+    pub id: usize,
+    pub status: proto::Status,
+}
+
+/// Session changes some fields of its profile
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetProfile {
+    pub id: usize,
+    pub profile: proto::Profile,
+}
+
+/// Session asks about the sessions using a name
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct WhoIs {
+    pub id: usize,
+    pub name: String,
+}
+
+/// Session changes the topic or description of a room
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct SetTopic {
+    pub id: usize,
+    pub room: String,
+    pub topic: Option<String>,
+    pub description: Option<String>,
+}
+
+/// Session asks for the details of a room, replacing its settings when given
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct RoomInfo {
+    pub id: usize,
+    pub room: String,
+    pub settings: Option<proto::RoomSettings>,
+    pub password: Option<String>,
+}
+
+/// Session asks for an invite code to a room, 0 for no limit
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Invite {
+    pub id: usize,
+    pub room: String,
+    pub max_uses: u32,
+    pub expires_secs: u64,
+}
+
+/// Session joins a room with an invite code
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct AcceptInvite {
+    pub id: usize,
+    pub code: String,
+}
+
+/// Session asks for the rooms it may see
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct ListRooms {
+    pub id: usize,
+}
+
+/// Session asks for the broadcasts of a room it missed
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Resync {
+    pub id: usize,
+    pub room: String,
+    pub from_seq: u64,
+}
+
+/// Session sends a private message to every session using a name
+#[derive(Message)]
+#[rtype(result = "()")]
+pub struct Direct {
+    pub id: usize,
+    pub to: String,
+    pub body: String,
+}
+
+/// Admin request for the state of every session
+#[derive(Message)]
+#[rtype(result = "Vec<SessionStats>")]
+pub struct ListSessions;
+
+/// State of a session as reported by the admin API
+#[derive(Serialize, Clone, Debug)]
+pub struct SessionStats {
+    pub id: usize,
+    pub name: Option<String>,
+    pub rooms: Vec<String>,
+    /// frames waiting in the outbox
+    pub queue_depth: usize,
+    /// frames dropped because the outbox was full
+    pub dropped: u64,
+    pub idle_secs: u64,
+}
+
+/// Server side view of a connected session
 #[derive(Debug)]
+struct SessionInfo {
+    outbox: Arc<Outbox>,
+    subscriptions: Recipient<Subscription>,
+    replaced: Recipient<Replaced>,
+    /// Id the client keeps across reconnects, `ID_<session>` without one
+    client_id: String,
+    name: Option<String>,
+    /// last login, join or chat
+    last_active: Instant,
+    status: proto::Status,
+    profile: proto::Profile,
+    /// last replay by room
+    replays: HashMap<String, Replay>,
+}
+
+/// Acks of recent chats by client id and nonce, so a chat retried after
+/// a reconnect is acknowledged again instead of delivered twice
+#[derive(Debug, Default)]
+struct RecentAcks {
+    order: VecDeque<(String, String)>,
+    acks: HashMap<(String, String), proto::Ack>,
+}
+
+impl RecentAcks {
+    fn get(&self, client_id: &str, nonce: &str) -> Option<&proto::Ack> {
+        self.acks.get(&(client_id.to_owned(), nonce.to_owned()))
+    }
+
+    fn insert(&mut self, client_id: String, ack: proto::Ack) {
+        let key = (client_id, ack.nonce.clone());
+        if self.acks.insert(key.clone(), ack).is_none() {
+            self.order.push_back(key);
+        }
+        if self.order.len() > RECENT_ACKS {
+            if let Some(oldest) = self.order.pop_front() {
+                self.acks.remove(&oldest);
+            }
+        }
+    }
+}
+
+/// Invite code waiting to be accepted
+#[derive(Debug)]
+struct PendingInvite {
+    room: String,
+    /// none for no limit
+    uses_left: Option<u32>,
+    expires: Option<Instant>,
+}
+
+impl PendingInvite {
+    fn expired(&self) -> bool {
+        self.expires.is_some_and(|at| at <= Instant::now())
+    }
+}
+
+/// Member connected to another node
+#[derive(Debug)]
+struct RemoteMember {
+    node: u64,
+    member: proto::Member,
+}
+
 pub struct WsServer {
-    #[allow(dead_code)]
-    server_id: i32,
+    /// Random per instance, tells the nodes of a cluster apart
+    server_id: u64,
     // 存储所有的 session
-    sessions: HashMap<usize, Recipient<ChatPacket>>,
-    // 存储 channel 列表
-    channels: HashMap<String, HashSet<usize>>,
+    sessions: HashMap<usize, SessionInfo>,
+    // 存储 room 列表
+    rooms: HashMap<String, Room>,
     rng: ThreadRng,
+    backplane: Option<Arc<dyn Backplane>>,
+    /// Members of other nodes by room
+    remote: HashMap<String, HashMap<usize, RemoteMember>>,
+    /// Other nodes and when they were last heard from
+    nodes: HashMap<u64, Instant>,
+    /// Who the rooms of other nodes let in, by room
+    admissions: HashMap<String, Admission>,
+    seen: Seen<u64>,
+    /// Chats of other nodes by room and message id, the same chat may come
+    /// in several envelopes
+    seen_chats: Seen<(String, u64)>,
+    acks: RecentAcks,
+    edit_window: Duration,
+    /// Sessions typing in a room and when that runs out
+    typing: HashMap<(String, usize), Instant>,
+    typing_expiry: Duration,
+    /// Last message read by name, then room
+    reads: HashMap<String, HashMap<String, u64>>,
+    away_after: Duration,
+    /// Message of the day sent to every new session
+    motd: Option<String>,
+    /// Invite codes of the rooms of this node
+    invites: HashMap<String, PendingInvite>,
+}
+
+impl WsServer {
+    pub fn new() -> WsServer {
+        let server_id = rand::random::<u64>();
+        log::info!("WsServer new {}", server_id);
+        WsServer {
+            server_id,
+            sessions: HashMap::new(),
+            rooms: HashMap::new(),
+            rng: rand::thread_rng(),
+            backplane: None,
+            remote: HashMap::new(),
+            nodes: HashMap::new(),
+            admissions: HashMap::new(),
+            seen: Seen::default(),
+            seen_chats: Seen::default(),
+            acks: RecentAcks::default(),
+            edit_window: EDIT_WINDOW,
+            typing: HashMap::new(),
+            typing_expiry: TYPING_EXPIRY,
+            reads: HashMap::new(),
+            away_after: AWAY_AFTER,
+            motd: None,
+            invites: HashMap::new(),
+        }
+    }
+
+    /// Share rooms, presence and direct messages with the other nodes on `backplane`
+    pub fn with_backplane(mut self, backplane: Arc<dyn Backplane>) -> WsServer {
+        self.backplane = Some(backplane);
+        self
+    }
+
+    /// How long after sending a message may still be edited or deleted
+    pub fn with_edit_window(mut self, edit_window: Duration) -> WsServer {
+        self.edit_window = edit_window;
+        self
+    }
+
+    /// How long a member may stay idle before being reported as away, zero
+    /// for never
+    pub fn with_away_after(mut self, away_after: Duration) -> WsServer {
+        self.away_after = away_after;
+        self
+    }
+
+    /// Greet every new session with `motd`
+    pub fn with_motd(mut self, motd: Option<String>) -> WsServer {
+        self.motd = motd;
+        self
+    }
+
+    /// How long a member shows as typing unless it says so again
+    pub fn with_typing_expiry(mut self, typing_expiry: Duration) -> WsServer {
+        self.typing_expiry = typing_expiry;
+        self
+    }
+}
+
+impl Default for WsServer {
+    fn default() -> WsServer {
+        WsServer::new()
+    }
+}
+
+impl WsServer {
+    /// Hand an event to the other nodes
+    fn broadcast(&self, event: NodeEvent) {
+        if let Some(backplane) = &self.backplane {
+            backplane.publish(Envelope {
+                id: rand::random(),
+                node: self.server_id,
+                event,
+            });
+        }
+    }
+
+    /// Send message to all users in the channel on this node
+    fn publish_local(&mut self, channel_id: &str, broadcast: Broadcast, skip_id: usize) {
+        if let Some(room) = self.rooms.get_mut(channel_id) {
+            room.publish(broadcast, skip_id);
+        }
+    }
+
+    /// Send message to all users in the channel
+    fn send_message_by_channel(&mut self, channel_id: &str, broadcast: Broadcast, skip_id: usize) {
+        let packet = (&broadcast.packet()).into();
+        self.publish_local(channel_id, broadcast, skip_id);
+        self.broadcast(NodeEvent::Room {
+            room: channel_id.to_owned(),
+            packet,
+        });
+    }
+
+    /// Send message to every session on this node, encoded once
+    fn send_message_to_local(&self, pkg: &ChatPacket) {
+        let bytes = Bytes::from(pkg.serialize());
+        for session in self.sessions.values() {
+            session.outbox.push(bytes.clone(), false);
+        }
+    }
+
+    /// Send message to every session
+    fn send_message_to_all(&self, pkg: &ChatPacket) {
+        self.send_message_to_local(pkg);
+        self.broadcast(NodeEvent::All { packet: pkg.into() });
+    }
+}
+
+impl WsServer {
+    /// Send message to user by id
+    fn send_message_by_id(&self, session_id: usize, pkg: &ChatPacket) {
+        if let Some(session) = self.sessions.get(&session_id) {
+            session.outbox.push(Bytes::from(pkg.serialize()), true);
+        }
+    }
+
+    /// Reject a request of the session with a reason
+    fn send_error(&self, session_id: usize, message: String) {
+        self.reject(session_id, message, None);
+    }
+
+    /// Reject a request, naming the chat it was about
+    fn reject(&self, session_id: usize, message: String, nonce: Option<String>) {
+        let info = proto::ErrorInfo {
+            message,
+            nonce,
+            detail: None,
+        };
+        self.send_error_info(session_id, &info);
+    }
+
+    fn send_error_info(&self, session_id: usize, info: &proto::ErrorInfo) {
+        let pkg = ChatPacket::with_payload(ChatPacketType::Error, info);
+        self.send_message_by_id(session_id, &pkg);
+    }
+
+    /// Reject a join or invite, `room` is empty for unknown invites
+    fn refuse_join(&self, session_id: usize, room: &str, reason: proto::JoinRefusal) {
+        let message = match room {
+            "" => format!("cannot join: {}", reason),
+            room => format!("cannot join #{}: {}", room, reason),
+        };
+        let info = proto::ErrorInfo {
+            message,
+            nonce: None,
+            detail: Some(proto::ErrorDetail::JoinRefused {
+                room: room.to_owned(),
+                reason,
+            }),
+        };
+        self.send_error_info(session_id, &info);
+    }
+}
+
+impl WsServer {
+    fn display_name(&self, session_id: usize) -> String {
+        match self.sessions.get(&session_id).and_then(|s| s.name.clone()) {
+            Some(name) => name,
+            None => format!("ID_{}", session_id),
+        }
+    }
+
+    /// Id the client of the session keeps across reconnects
+    fn client_id(&self, session_id: usize) -> Option<&str> {
+        self.sessions
+            .get(&session_id)
+            .map(|session| session.client_id.as_str())
+    }
+
+    /// Whether the client of the session operates the room
+    fn operates(&self, room: &Room, session_id: usize) -> bool {
+        self.client_id(session_id)
+            .is_some_and(|id| room.operators.contains(id))
+    }
+
+    fn member(&self, session_id: usize) -> proto::Member {
+        let session = self.sessions.get(&session_id);
+        let idle = session.map(|s| s.last_active.elapsed()).unwrap_or_default();
+        let status = match session.map(|s| &s.status) {
+            Some(proto::Status::Online) | None
+                if !self.away_after.is_zero() && idle >= self.away_after =>
+            {
+                proto::Status::Away {
+                    message: String::new(),
+                }
+            }
+            Some(status) => status.clone(),
+            None => proto::Status::Online,
+        };
+        proto::Member {
+            id: session_id,
+            name: self.display_name(session_id),
+            status,
+            idle_secs: idle.as_secs(),
+        }
+    }
+
+    fn touch(&mut self, session_id: usize) {
+        if let Some(session) = self.sessions.get_mut(&session_id) {
+            session.last_active = Instant::now();
+        }
+    }
+
+    fn is_member(&self, room: &str, session_id: usize) -> bool {
+        self.rooms
+            .get(room)
+            .map(|room| room.members.contains(&session_id))
+            .unwrap_or(false)
+    }
+
+    /// Rooms the session is a member of
+    fn rooms_of(&self, session_id: usize) -> Vec<String> {
+        self.rooms
+            .iter()
+            .filter(|(_, room)| room.members.contains(&session_id))
+            .map(|(name, _)| name.to_owned())
+            .collect()
+    }
+
+    /// Take the session out of its rooms, deliver `Leave` to the other
+    /// members and forget it
+    fn remove_session(&mut self, session_id: usize) -> Option<SessionInfo> {
+        if !self.sessions.contains_key(&session_id) {
+            return None;
+        }
+        for room in self.rooms_of(session_id) {
+            self.leave_room(&room, session_id);
+        }
+        let session = self.sessions.remove(&session_id);
+        log::info!("current session count: {}", self.sessions.len());
+        session
+    }
+
+    /// Tell room members about a change of one member
+    fn send_presence(&mut self, room: &str, kind: proto::PresenceKind, session_id: usize) {
+        // a member doesn't hear its own join or leave but its renames are
+        // part of the room sequence it follows
+        let skip = match kind {
+            proto::PresenceKind::Renamed { .. } => 0,
+            _ => session_id,
+        };
+        let member = self.member(session_id);
+        // invisible members come and go unnoticed
+        if member.status == proto::Status::Invisible && kind != proto::PresenceKind::Updated {
+            return;
+        }
+        let presence = proto::Presence {
+            room: room.to_owned(),
+            kind,
+            member,
+            seq: 0,
+        };
+        self.publish_local(room, Broadcast::Presence(presence.clone()), skip);
+        self.broadcast(NodeEvent::Presence(presence));
+    }
+
+    fn send_roster(&self, room: &str, session_id: usize) {
+        let mut members: Vec<proto::Member> = self
+            .rooms
+            .get(room)
+            .map(|room| room.members.iter().map(|id| self.member(*id)).collect())
+            .unwrap_or_default();
+        let seq = self.rooms.get(room).map_or(0, Room::seq);
+        if let Some(remote) = self.remote.get(room) {
+            members.extend(remote.values().map(|r| r.member.clone()));
+        }
+        members.retain(|m| m.id == session_id || m.status != proto::Status::Invisible);
+        members.sort_by_key(|m| m.name.to_lowercase());
+
+        let roster = proto::Roster {
+            room: room.to_owned(),
+            members,
+            seq,
+        };
+        let pkg = ChatPacket::with_payload(ChatPacketType::Roster, &roster);
+        self.send_message_by_id(session_id, &pkg);
+    }
+
+    /// Add the session to the room, creating it if needed. Members are told
+    /// about the joiner and the joiner gets the member list, then the broadcasts.
+    fn join_room(&mut self, room: &str, session_id: usize) {
+        let joined = self.open_room(room, session_id).members.insert(session_id);
+        if joined {
+            self.send_presence(room, proto::PresenceKind::Joined, session_id);
+        }
+        self.send_roster(room, session_id);
+        self.send_read(room, session_id);
+        self.send_room_info(room, session_id);
+
+        // nothing can be published between the roster and the subscription
+        if let (true, Some(session), Some(room_info)) =
+            (joined, self.sessions.get(&session_id), self.rooms.get(room))
+        {
+            session.subscriptions.do_send(Subscription::Subscribe {
+                room: room.to_owned(),
+                rx: room_info.subscribe(),
+            });
+        }
+    }
+
+    /// The room, created by the session with its client as operator if needed.
+    /// A room live on another node is run by the operators there, its copy
+    /// here lets in whoever the original lets in.
+    fn open_room(&mut self, room: &str, session_id: usize) -> &mut Room {
+        let name = self.display_name(session_id);
+        let client_id = self.client_id(session_id).map(str::to_owned);
+        let remote = self.remote.contains_key(room);
+        let admission = self.admissions.get(room).filter(|_| remote).cloned();
+        self.rooms.entry(room.to_owned()).or_insert_with(|| {
+            let mut created = Room::new();
+            if room != MAIN_ROOM && !remote {
+                created.operators.extend(client_id);
+                created.created_by = Some(name);
+            }
+            if let Some(admission) = admission {
+                created.settings = admission.settings;
+                created.password = admission.password;
+            }
+            created
+        })
+    }
+
+    /// Remove the session from the room and drop the room once it is empty,
+    /// unless it is a room run here that its operators configured
+    fn leave_room(&mut self, room: &str, session_id: usize) -> bool {
+        let removed = match self.rooms.get_mut(room) {
+            Some(room) => room.members.remove(&session_id),
+            None => false,
+        };
+        if removed {
+            self.typing.remove(&(room.to_owned(), session_id));
+            if let Some(session) = self.sessions.get(&session_id) {
+                session.subscriptions.do_send(Subscription::Unsubscribe {
+                    room: room.to_owned(),
+                });
+            }
+            self.send_presence(room, proto::PresenceKind::Left, session_id);
+            // the settings, password and invites of a configured room outlive
+            // its members, so do its operators
+            let emptied = self.rooms.get(room).is_some_and(|r| {
+                r.members.is_empty() && !(r.configured() && r.created_by.is_some())
+            });
+            if room != MAIN_ROOM && emptied {
+                self.rooms.remove(room);
+                self.invites.retain(|_, invite| invite.room != room);
+            }
+        }
+        removed
+    }
+}
+
+/// Room details and limits
+impl WsServer {
+    fn send_room_info(&self, room: &str, session_id: usize) {
+        if let Some(room_info) = self.rooms.get(room) {
+            let pkg = ChatPacket::with_payload(ChatPacketType::RoomInfo, &room_info.info(room));
+            self.send_message_by_id(session_id, &pkg);
+        }
+    }
+
+    /// Whether the session may change the room, otherwise why it may not
+    fn changeable(&self, session_id: usize, room: &str) -> Result<(), String> {
+        let Some(room_info) = self
+            .rooms
+            .get(room)
+            .filter(|r| r.members.contains(&session_id))
+        else {
+            return Err(format!("not in room: {}", room));
+        };
+        if !self.operates(room_info, session_id) {
+            return Err(format!("only operators may change #{}", room));
+        }
+        Ok(())
+    }
+
+    /// Whether the session may join the room, creating it is always fine.
+    /// Invited sessions skip the password and the invite requirement,
+    /// clients that joined before and operators too.
+    fn admission(
+        &self,
+        room: &str,
+        session_id: usize,
+        password: Option<&str>,
+        invited: bool,
+    ) -> Result<(), proto::JoinRefusal> {
+        let (settings, room_password, known) = match self.rooms.get(room) {
+            Some(room_info) if room_info.members.contains(&session_id) => return Ok(()),
+            Some(room_info) => (
+                &room_info.settings,
+                room_info.password.as_ref(),
+                self.operates(room_info, session_id)
+                    || self
+                        .client_id(session_id)
+                        .is_some_and(|id| room_info.admitted.contains(id)),
+            ),
+            // the first member here of a room of another node
+            None => match self.admissions.get(room) {
+                Some(admission) if self.remote.contains_key(room) => {
+                    (&admission.settings, admission.password.as_ref(), false)
+                }
+                _ => return Ok(()),
+            },
+        };
+        if !invited && !known {
+            match settings.visibility {
+                proto::Visibility::InviteOnly => return Err(proto::JoinRefusal::InviteOnly),
+                proto::Visibility::Password => match password {
+                    None => return Err(proto::JoinRefusal::PasswordRequired),
+                    Some(password) if !room_password.is_some_and(|p| p.matches(password)) => {
+                        return Err(proto::JoinRefusal::WrongPassword)
+                    }
+                    Some(_) => {}
+                },
+                proto::Visibility::Public | proto::Visibility::Unlisted => {}
+            }
+        }
+        let max_members = settings.max_members as usize;
+        if max_members > 0 && self.member_count(room) >= max_members {
+            return Err(proto::JoinRefusal::Full);
+        }
+        Ok(())
+    }
+
+    /// Join an admitted session, remembering its client for later joins
+    fn admit(&mut self, room: &str, session_id: usize) {
+        let client_id = self.client_id(session_id).map(str::to_owned);
+        if let Some(room_info) = self.rooms.get_mut(room) {
+            room_info.admitted.extend(client_id);
+        }
+        self.touch(session_id);
+        self.join_room(room, session_id);
+    }
+
+    /// Members of a room on every node
+    fn member_count(&self, room: &str) -> usize {
+        let local = self.rooms.get(room).map_or(0, |r| r.members.len());
+        local + self.remote.get(room).map_or(0, HashMap::len)
+    }
+
+    /// Why the session may not chat in the room right now, if it may not
+    fn chat_limit(&self, room: &str, session_id: usize) -> Option<proto::ErrorInfo> {
+        let room_info = self.rooms.get(room)?;
+        let refusal = |message| proto::ErrorInfo {
+            message,
+            nonce: None,
+            detail: None,
+        };
+        if room_info.settings.read_only {
+            return Some(refusal(format!("#{} is read-only", room)));
+        }
+        let operator = self.operates(room_info, session_id);
+        if room_info.settings.announcement && !operator {
+            return Some(refusal(format!("only operators may post in #{}", room)));
+        }
+        let interval = self.slow_mode(room, session_id);
+        let since = room_info
+            .last_chat
+            .get(&self.display_name(session_id))?
+            .elapsed();
+        (since < interval).then(|| {
+            let wait_secs = (interval - since).as_secs_f64().ceil() as u64;
+            proto::ErrorInfo {
+                detail: Some(proto::ErrorDetail::SlowMode {
+                    room: room.to_owned(),
+                    wait_secs,
+                }),
+                ..refusal(format!("slow mode in #{}, wait {}s", room, wait_secs))
+            }
+        })
+    }
+
+    /// Interval slow mode puts between two chats of the session, operators
+    /// aren't slowed down
+    fn slow_mode(&self, room: &str, session_id: usize) -> Duration {
+        match self.rooms.get(room) {
+            Some(room) if !self.operates(room, session_id) => {
+                Duration::from_secs(room.settings.slow_mode_secs)
+            }
+            _ => Duration::ZERO,
+        }
+    }
+}
+
+/// Typing notices
+impl WsServer {
+    fn send_typing(&mut self, room: &str, session_id: usize, state: proto::TypingState) {
+        let status = proto::TypingStatus {
+            room: room.to_owned(),
+            name: self.display_name(session_id),
+            state,
+            expires_secs: self.typing_expiry.as_secs(),
+        };
+        self.send_message_by_channel(room, Broadcast::Typing(status), session_id);
+    }
+
+    /// Tell the rooms about members that stopped saying they are typing
+    fn expire_typing(&mut self) {
+        let now = Instant::now();
+        let expired: Vec<(String, usize)> = self
+            .typing
+            .iter()
+            .filter(|(_, until)| **until <= now)
+            .map(|(key, _)| key.clone())
+            .collect();
+        for (room, session_id) in expired {
+            self.typing.remove(&(room.clone(), session_id));
+            self.send_typing(&room, session_id, proto::TypingState::Stopped);
+        }
+    }
+}
+
+/// Room lifetimes and message retention
+impl WsServer {
+    /// Close the rooms whose TTL ran out and purge the chats that outlived
+    /// the retention of their room
+    fn expire_rooms(&mut self) {
+        let now = Instant::now();
+        let closed: Vec<String> = self
+            .rooms
+            .iter()
+            .filter(|(_, room)| room.expires.is_some_and(|at| at <= now))
+            .map(|(name, _)| name.clone())
+            .collect();
+        for room in closed {
+            self.close_room(&room);
+        }
+
+        let mut purged = Vec::new();
+        for (name, room) in self.rooms.iter_mut() {
+            let message_ids = room.purge(now);
+            if !message_ids.is_empty() {
+                purged.push(proto::Expired {
+                    room: name.clone(),
+                    message_ids,
+                    seq: 0,
+                });
+            }
+        }
+        // every node purges its own history, the tombstones stay local
+        for expired in purged {
+            let room = expired.room.clone();
+            self.publish_local(&room, Broadcast::Expired(expired), 0);
+        }
+    }
+
+    /// Drop the room and tell its members they are out of it
+    fn close_room(&mut self, room: &str) {
+        let Some(closed) = self.rooms.remove(room) else {
+            return;
+        };
+        log::info!("room {} closed after its TTL", room);
+        self.invites.retain(|_, invite| invite.room != room);
+        self.typing.retain(|(typed_in, _), _| typed_in != room);
+        let pkg = ChatPacket::with_payload(
+            ChatPacketType::RoomClosed,
+            &proto::RoomClosed {
+                room: room.to_owned(),
+            },
+        );
+        for session_id in closed.members {
+            if let Some(session) = self.sessions.get(&session_id) {
+                session.subscriptions.do_send(Subscription::Unsubscribe {
+                    room: room.to_owned(),
+                });
+            }
+            self.send_message_by_id(session_id, &pkg);
+            // only the other nodes hear of it, the room is gone here
+            self.send_presence(room, proto::PresenceKind::Left, session_id);
+        }
+    }
+}
+
+/// Read positions, kept per name so every session of an account shares them
+impl WsServer {
+    /// Local sessions using a name
+    fn sessions_named(&self, name: &str) -> Vec<usize> {
+        self.sessions
+            .iter()
+            .filter(|(_, session)| session.name.as_deref() == Some(name))
+            .map(|(id, _)| *id)
+            .collect()
+    }
+
+    /// Remember how far a name read in a room and tell its local sessions
+    /// but `skip`, returns false when the position did not move.
+    ///
+    /// Positions only move forward while both messages are in the history,
+    /// older ones can't be compared and are taken as they come
+    fn store_read(&mut self, name: &str, read: &proto::MarkRead, skip: usize) -> bool {
+        let last = self.reads.get(name).and_then(|rooms| rooms.get(&read.room));
+        if last == Some(&read.message_id) {
+            return false;
+        }
+        if let (Some(room), Some(last)) = (self.rooms.get(&read.room), last) {
+            if let (Some(before), Some(after)) =
+                (room.position(*last), room.position(read.message_id))
+            {
+                if after < before {
+                    return false;
+                }
+            }
+        }
+        self.reads
+            .entry(name.to_owned())
+            .or_default()
+            .insert(read.room.clone(), read.message_id);
+
+        let pkg = ChatPacket::with_payload(ChatPacketType::MarkRead, read);
+        for session_id in self.sessions_named(name) {
+            if session_id != skip {
+                self.send_message_by_id(session_id, &pkg);
+            }
+        }
+        true
+    }
+
+    /// Stored read position of the session's name in a room, if any
+    fn send_read(&self, room: &str, session_id: usize) {
+        let Some(name) = self.sessions.get(&session_id).and_then(|s| s.name.as_ref()) else {
+            return;
+        };
+        if let Some(message_id) = self.reads.get(name).and_then(|rooms| rooms.get(room)) {
+            let read = proto::MarkRead {
+                room: room.to_owned(),
+                message_id: *message_id,
+            };
+            let pkg = ChatPacket::with_payload(ChatPacketType::MarkRead, &read);
+            self.send_message_by_id(session_id, &pkg);
+        }
+    }
 }
 
-static SERVER_ID_SEQ: i32 = 0;
-
+/// Editing, deleting and reacting
 impl WsServer {
-    pub fn new() -> WsServer {
-        let server_id = SERVER_ID_SEQ + 1;
-        log::info!("WsServer new {}", server_id);
-        WsServer {
-            server_id: server_id,
-            sessions: HashMap::new(),
-            channels: HashMap::new(),
-            rng: rand::thread_rng(),
+    /// Room and current state of a message the session can see, otherwise why not
+    fn visible_message(
+        &self,
+        session_id: usize,
+        message_id: u64,
+    ) -> Result<(String, proto::ChatMessage), String> {
+        let found = self
+            .rooms
+            .iter()
+            .find_map(|(name, room)| room.message(message_id).map(|chat| (name, room, chat)));
+        let Some((name, room, chat)) = found else {
+            return Err(format!("no such message: {}", message_id));
+        };
+        if !room.members.contains(&session_id) {
+            return Err(format!("not in room: {}", name));
+        }
+        if chat.deleted {
+            return Err("message was deleted".to_owned());
         }
+        Ok((name.to_owned(), chat))
+    }
+
+    /// Room of a message the session may change, otherwise why it may not
+    fn amendable(&self, session_id: usize, message_id: u64) -> Result<String, String> {
+        let (name, _) = self.visible_message(session_id, message_id)?;
+        // names change hands and sessions reconnect, client ids stay
+        let allowed = self.rooms.get(&name).is_some_and(|room| {
+            room.author(message_id)
+                .is_some_and(|author| self.client_id(session_id) == Some(author))
+                || self.operates(room, session_id)
+        });
+        if !allowed {
+            return Err("not your message".to_owned());
+        }
+
+        let sent = self.rooms.get(&name).and_then(|room| room.sent(message_id));
+        if sent.is_none_or(|at| at.elapsed() >= self.edit_window) {
+            return Err("too late to change the message".to_owned());
+        }
+        Ok(name)
     }
 }
 
+/// State shared with the other nodes
 impl WsServer {
-    /// Send message to all users in the channel
-    #[allow(dead_code)]
-    fn send_message_by_channel(&self, channel_id: &str, pkg: &ChatPacket, skip_id: usize) {
-        if let Some(chanel_list) = self.channels.get(channel_id) {
-            for session_id in chanel_list {
-                if *session_id != skip_id {
-                    if let Some(addr) = self.sessions.get(session_id) {
-                        addr.do_send(pkg.to_owned());
-                    }
+    /// Every local member with its rooms
+    fn local_members(&self) -> Vec<(String, proto::Member)> {
+        let mut members = Vec::new();
+        for (name, room) in &self.rooms {
+            for id in &room.members {
+                members.push((name.to_owned(), self.member(*id)));
+            }
+        }
+        members
+    }
+
+    /// Settings and password of the rooms run by local operators
+    fn local_admissions(&self) -> Vec<(String, Admission)> {
+        self.rooms
+            .iter()
+            .filter(|(_, room)| !room.operators.is_empty())
+            .map(|(name, room)| (name.to_owned(), room.admission()))
+            .collect()
+    }
+
+    fn announce(&self) {
+        self.broadcast(NodeEvent::Members(self.local_members()));
+        self.broadcast(NodeEvent::Admission(self.local_admissions()));
+    }
+
+    /// Let the local copies of rooms of other nodes in the same sessions as
+    /// the originals, rooms run by local operators keep their own
+    fn set_remote_admissions(&mut self, admissions: Vec<(String, Admission)>) {
+        for (name, admission) in admissions {
+            if let Some(room) = self.rooms.get_mut(&name) {
+                if room.operators.is_empty() {
+                    room.settings = admission.settings.clone();
+                    room.password = admission.password.clone();
                 }
             }
+            self.admissions.insert(name, admission);
         }
     }
-}
 
-impl WsServer {
-    /// Send message to user by id
-    #[allow(dead_code)]
-    fn send_message_by_id(&self, session_id: usize, pkg: &ChatPacket) {
-        if let Some(addr) = self.sessions.get(&session_id) {
-            addr.do_send(pkg.to_owned());
+    /// Whether a session of another node uses the name
+    fn is_remote_name(&self, name: &str) -> bool {
+        self.remote
+            .values()
+            .flat_map(|room| room.values())
+            .any(|r| r.member.name == name)
+    }
+
+    /// Apply a presence of another node and pass it on to the local members
+    fn remote_presence(&mut self, node: u64, presence: proto::Presence) {
+        let members = self.remote.entry(presence.room.clone()).or_default();
+        match presence.kind {
+            proto::PresenceKind::Left => {
+                members.remove(&presence.member.id);
+                if members.is_empty() {
+                    self.remote.remove(&presence.room);
+                    self.admissions.remove(&presence.room);
+                }
+            }
+            _ => {
+                let member = presence.member.clone();
+                members.insert(member.id, RemoteMember { node, member });
+            }
         }
+        if presence.member.status == proto::Status::Invisible
+            && presence.kind != proto::PresenceKind::Updated
+        {
+            return;
+        }
+        let room = presence.room.clone();
+        self.publish_local(&room, Broadcast::Presence(presence), 0);
+    }
+
+    /// Replace the members of a node, local members see the difference as presences
+    fn set_remote_members(&mut self, node: u64, members: Vec<(String, proto::Member)>) {
+        let current: HashSet<(&str, usize)> = members
+            .iter()
+            .map(|(room, member)| (room.as_str(), member.id))
+            .collect();
+        let mut gone = Vec::new();
+        for (room, remote) in &self.remote {
+            for r in remote.values() {
+                if r.node == node && !current.contains(&(room.as_str(), r.member.id)) {
+                    gone.push((room.to_owned(), r.member.clone()));
+                }
+            }
+        }
+
+        for (room, member) in gone {
+            let kind = proto::PresenceKind::Left;
+            self.remote_presence(
+                node,
+                proto::Presence {
+                    room,
+                    kind,
+                    member,
+                    seq: 0,
+                },
+            );
+        }
+        for (room, member) in members {
+            let known = self
+                .remote
+                .get(&room)
+                .is_some_and(|remote| remote.contains_key(&member.id));
+            if known {
+                // refresh idle times quietly
+                if let Some(r) = self
+                    .remote
+                    .get_mut(&room)
+                    .and_then(|m| m.get_mut(&member.id))
+                {
+                    r.member = member;
+                }
+            } else {
+                let kind = proto::PresenceKind::Joined;
+                self.remote_presence(
+                    node,
+                    proto::Presence {
+                        room,
+                        kind,
+                        member,
+                        seq: 0,
+                    },
+                );
+            }
+        }
+    }
+
+    /// Take over the changes another node made to a room
+    fn apply_remote(&mut self, room: &str, broadcast: &Broadcast) {
+        let Some(room) = self.rooms.get_mut(room) else {
+            return;
+        };
+        match broadcast {
+            Broadcast::RoomInfo(info) => {
+                room.topic = info.topic.clone();
+                room.description = info.description.clone();
+                room.settings = info.settings.clone();
+            }
+            Broadcast::Edited(edited) => room.edit_message(edited.message_id, &edited.new_body),
+            Broadcast::Deleted(deleted) => room.delete_message(deleted.message_id),
+            Broadcast::Reacted { reacted, added } => room.react(reacted, *added),
+            _ => {}
+        }
+    }
+
+    /// Forget nodes that stopped announcing themselves
+    fn expire_nodes(&mut self) {
+        let silent: Vec<u64> = self
+            .nodes
+            .iter()
+            .filter(|(_, seen)| seen.elapsed() > NODE_TIMEOUT)
+            .map(|(node, _)| *node)
+            .collect();
+        for node in silent {
+            log::warn!("node {} went silent, dropping its members", node);
+            self.nodes.remove(&node);
+            self.set_remote_members(node, Vec::new());
+        }
+    }
+}
+
+/// Room names are short single words, an optional leading `#` is ignored
+pub fn normalize_room_name(room: &str) -> Option<String> {
+    let room = room.trim().trim_start_matches('#');
+    // `@name` is how clients show private conversations
+    if room.is_empty()
+        || room.starts_with('@')
+        || room.chars().count() > MAX_ROOM_NAME_LEN
+        || room.chars().any(|c| c.is_whitespace() || c.is_control())
+    {
+        return None;
+    }
+    Some(room.to_owned())
+}
+
+/// Why a chat or edit can't carry `body`, if it can't
+fn body_error(body: &str) -> Option<String> {
+    if body.trim().is_empty() {
+        Some("empty message".to_owned())
+    } else if body.chars().count() > MAX_CHAT_LEN {
+        Some("message too long".to_owned())
+    } else {
+        None
     }
 }
 
@@ -76,6 +1270,78 @@ impl Actor for WsServer {
     /// We are going to use simple Context, we just need ability to communicate
     /// with other actors.
     type Context = Context<Self>;
+
+    fn started(&mut self, ctx: &mut Context<Self>) {
+        ctx.run_interval(TYPING_SWEEP, |act, _| act.expire_typing());
+        ctx.run_interval(ROOM_SWEEP, |act, _| act.expire_rooms());
+
+        let Some(backplane) = &self.backplane else {
+            return;
+        };
+        let (tx, rx) = mpsc::channel(BACKPLANE_QUEUE);
+        backplane.subscribe(tx);
+        ctx.add_stream(ReceiverStream::new(rx));
+
+        self.broadcast(NodeEvent::Hello);
+        ctx.run_interval(ANNOUNCE_INTERVAL, |act, _| {
+            act.announce();
+            act.expire_nodes();
+        });
+    }
+
+    fn stopping(&mut self, _: &mut Context<Self>) -> Running {
+        self.broadcast(NodeEvent::Bye);
+        Running::Stop
+    }
+}
+
+/// Envelopes of the other nodes
+impl StreamHandler<Envelope> for WsServer {
+    fn handle(&mut self, envelope: Envelope, _: &mut Context<Self>) {
+        if envelope.node == self.server_id || !self.seen.insert(envelope.id) {
+            return;
+        }
+        let node = envelope.node;
+        if self.nodes.insert(node, Instant::now()).is_none() {
+            log::info!("node {} joined the cluster", node);
+        }
+
+        match envelope.event {
+            NodeEvent::Room { room, packet } => {
+                if let Some(broadcast) = Broadcast::decode(&packet.into()) {
+                    if let Broadcast::Chat { chat, .. } = &broadcast {
+                        if !self.seen_chats.insert((room.clone(), chat.id)) {
+                            return;
+                        }
+                    }
+                    self.apply_remote(&room, &broadcast);
+                    self.publish_local(&room, broadcast, 0);
+                }
+            }
+            NodeEvent::All { packet } => self.send_message_to_local(&packet.into()),
+            NodeEvent::Direct { to, packet } => {
+                let pkg: ChatPacket = packet.into();
+                for session_id in self.sessions_named(&to) {
+                    self.send_message_by_id(session_id, &pkg);
+                }
+            }
+            NodeEvent::Read { name, read } => {
+                self.store_read(&name, &read, 0);
+            }
+            NodeEvent::Presence(presence) => self.remote_presence(node, presence),
+            NodeEvent::Hello => self.announce(),
+            NodeEvent::Members(members) => self.set_remote_members(node, members),
+            NodeEvent::Admission(admissions) => self.set_remote_admissions(admissions),
+            NodeEvent::Bye => {
+                log::info!("node {} left the cluster", node);
+                self.nodes.remove(&node);
+                self.set_remote_members(node, Vec::new());
+            }
+        }
+    }
+
+    /// The backplane is gone, keep serving the local sessions
+    fn finished(&mut self, _: &mut Context<Self>) {}
 }
 
 /// Handler for Connect message.
@@ -85,21 +1351,49 @@ impl Handler<Connect> for WsServer {
     type Result = usize;
 
     fn handle(&mut self, msg: Connect, _: &mut Context<Self>) -> Self::Result {
+        // the client reconnected before the lost connection timed out
+        if let Some(client_id) = &msg.client_id {
+            let stale: Vec<usize> = self
+                .sessions
+                .iter()
+                .filter(|(_, session)| session.client_id == *client_id)
+                .map(|(id, _)| *id)
+                .collect();
+            for id in stale {
+                if let Some(session) = self.remove_session(id) {
+                    session.replaced.do_send(Replaced);
+                }
+            }
+        }
+
         // register session with random id
         let mut session_id: usize = self.rng.gen::<usize>();
         while self.sessions.contains_key(&session_id) {
             session_id = self.rng.gen::<usize>();
         }
-        self.sessions.insert(session_id, msg.addr);
+        self.sessions.insert(
+            session_id,
+            SessionInfo {
+                outbox: msg.outbox,
+                subscriptions: msg.subscriptions,
+                replaced: msg.replaced,
+                client_id: msg
+                    .client_id
+                    .unwrap_or_else(|| format!("ID_{}", session_id)),
+                name: None,
+                last_active: Instant::now(),
+                status: proto::Status::Online,
+                profile: proto::Profile::default(),
+                replays: HashMap::new(),
+            },
+        );
 
         // auto join session to main room
-        if !self.channels.contains_key("main") {
-            self.channels.insert("main".to_owned(), HashSet::new());
+        self.join_room(MAIN_ROOM, session_id);
+        if let Some(motd) = &self.motd {
+            let pkg = ChatPacket::new(ChatPacketType::Motd, motd.clone());
+            self.send_message_by_id(session_id, &pkg);
         }
-        self.channels
-            .entry("main".to_owned())
-            .or_default()
-            .insert(session_id);
 
         log::info!("current session count: {}", self.sessions.len());
 
@@ -113,36 +1407,759 @@ impl Handler<Disconnect> for WsServer {
     type Result = ();
 
     fn handle(&mut self, msg: Disconnect, _: &mut Context<Self>) {
-        // let mut channels_leave_msg: Vec<String> = Vec::new();
-
-        // remove address
-        if self.sessions.remove(&msg.id).is_some() {
-            // remove session from all channels and deliver `Leave` to other users
-            for (_, sessions) in &mut self.channels {
-                if sessions.remove(&msg.id) {
-                    // channels_leave_msg.push(name.to_owned());
+        self.remove_session(msg.id);
+    }
+}
+
+/// Handler for Login message.
+///
+/// A name is used by one session at a time across the cluster
+impl Handler<Login> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Login, _: &mut Context<Self>) {
+        // `ID_` names are what sessions without a name go by
+        if msg.name.is_empty() || msg.name.starts_with("ID_") {
+            self.send_error(msg.id, format!("invalid name: {}", msg.name));
+            return;
+        }
+        let taken = self
+            .sessions_named(&msg.name)
+            .iter()
+            .any(|id| *id != msg.id);
+        if taken || self.is_remote_name(&msg.name) {
+            self.send_error(msg.id, format!("name taken: {}", msg.name));
+            return;
+        }
+        let Some(session) = self.sessions.get_mut(&msg.id) else {
+            return;
+        };
+        let old_name = session.name.replace(msg.name.clone());
+        session.last_active = Instant::now();
+
+        let current_local = chrono::Local::now();
+        let time = current_local.format("%Y-%m-%d %H:%M:%S");
+
+        let time_and_tip = if let Some(name) = &old_name {
+            format!(
+                "[{}] ID_{} changed name from {} to {}",
+                time, msg.id, name, msg.name
+            )
+        } else {
+            format!("[{}] ID_{} set name to {}", time, msg.id, msg.name)
+        };
+        let pkg = ChatPacket::new(ChatPacketType::Login, time_and_tip);
+        self.send_message_to_all(&pkg);
+
+        let from = old_name.unwrap_or_else(|| format!("ID_{}", msg.id));
+        for room in self.rooms_of(msg.id) {
+            let kind = proto::PresenceKind::Renamed { from: from.clone() };
+            self.send_presence(&room, kind, msg.id);
+            self.send_read(&room, msg.id);
+        }
+    }
+}
+
+/// Handler for Join message.
+impl Handler<Join> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Join, _: &mut Context<Self>) {
+        let Some(room) = normalize_room_name(&msg.room) else {
+            self.refuse_join(msg.id, &msg.room, proto::JoinRefusal::InvalidName);
+            return;
+        };
+        if let Err(reason) = self.admission(&room, msg.id, msg.password.as_deref(), false) {
+            self.refuse_join(msg.id, &room, reason);
+            return;
+        }
+        if let Some(ttl) = msg.ttl_secs.filter(|_| room != MAIN_ROOM) {
+            if !self.rooms.contains_key(&room) {
+                self.open_room(&room, msg.id).expires =
+                    Some(Instant::now() + Duration::from_secs(ttl));
+            }
+        }
+        self.admit(&room, msg.id);
+    }
+}
+
+/// Handler for Leave message.
+impl Handler<Leave> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Leave, _: &mut Context<Self>) {
+        let room = normalize_room_name(&msg.room).unwrap_or(msg.room);
+        if !self.leave_room(&room, msg.id) {
+            self.send_error(msg.id, format!("not in room: {}", room));
+        }
+    }
+}
+
+/// Handler for ListMembers message.
+impl Handler<ListMembers> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: ListMembers, _: &mut Context<Self>) {
+        let room = normalize_room_name(&msg.room).unwrap_or(msg.room);
+        if !self.is_member(&room, msg.id) {
+            self.send_error(msg.id, format!("not in room: {}", room));
+            return;
+        }
+        self.send_roster(&room, msg.id);
+    }
+}
+
+/// Handler for Chat message.
+impl Handler<Chat> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Chat, _: &mut Context<Self>) {
+        let room = normalize_room_name(&msg.room).unwrap_or(msg.room);
+        let Some(client_id) = self.client_id(msg.id).map(str::to_owned) else {
+            return;
+        };
+        // a retry of a chat that already went out
+        if let Some(ack) = msg
+            .nonce
+            .as_ref()
+            .and_then(|n| self.acks.get(&client_id, n))
+        {
+            let pkg = ChatPacket::with_payload(ChatPacketType::Ack, ack);
+            self.send_message_by_id(msg.id, &pkg);
+            return;
+        }
+        if !self.is_member(&room, msg.id) {
+            self.reject(msg.id, format!("not in room: {}", room), msg.nonce);
+            return;
+        }
+        if let Some(reason) = body_error(&msg.body) {
+            self.reject(msg.id, reason, msg.nonce);
+            return;
+        }
+        if let Some(error) = self.chat_limit(&room, msg.id) {
+            let error = proto::ErrorInfo {
+                nonce: msg.nonce,
+                ..error
+            };
+            self.send_error_info(msg.id, &error);
+            return;
+        }
+        if let Some(parent) = msg.reply_to {
+            let found = self.rooms.get(&room).and_then(|room| room.message(parent));
+            if found.is_none() {
+                let reason = format!("no message {} in {} to reply to", parent, room);
+                self.reject(msg.id, reason, msg.nonce);
+                return;
+            }
+        }
+        self.touch(msg.id);
+        // the chat itself tells the room the member stopped typing
+        self.typing.remove(&(room.clone(), msg.id));
+        let from = self.display_name(msg.id);
+        if let Some(room) = self.rooms.get_mut(&room) {
+            let slow_mode = Duration::from_secs(room.settings.slow_mode_secs);
+            room.last_chat.retain(|_, at| at.elapsed() < slow_mode);
+            room.last_chat.insert(from.clone(), Instant::now());
+        }
+
+        let current_local = chrono::Local::now();
+        let message = proto::ChatMessage {
+            room: room.clone(),
+            from,
+            mentions: proto::parse_mentions(&msg.body),
+            body: msg.body,
+            time: current_local.format("%Y-%m-%d %H:%M:%S").to_string(),
+            id: self.rng.gen(),
+            nonce: msg.nonce,
+            seq: 0,
+            edited: false,
+            deleted: false,
+            reply_to: msg.reply_to,
+            reactions: Vec::new(),
+        };
+        let (message_id, time, nonce) = (message.id, message.time.clone(), message.nonce.clone());
+        let broadcast = Broadcast::Chat {
+            chat: message,
+            author: Some(client_id.clone()),
+        };
+        self.send_message_by_channel(&room, broadcast, 0);
+
+        if let Some(nonce) = nonce {
+            let ack = proto::Ack {
+                nonce,
+                message_id,
+                time,
+                wait_secs: self.slow_mode(&room, msg.id).as_secs(),
+            };
+            let pkg = ChatPacket::with_payload(ChatPacketType::Ack, &ack);
+            self.send_message_by_id(msg.id, &pkg);
+            self.acks.insert(client_id, ack);
+        }
+    }
+}
+
+/// Handler for Edit message.
+impl Handler<Edit> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Edit, _: &mut Context<Self>) {
+        if let Some(reason) = body_error(&msg.new_body) {
+            self.send_error(msg.id, reason);
+            return;
+        }
+        let room = match self.amendable(msg.id, msg.message_id) {
+            Ok(room) => room,
+            Err(reason) => {
+                self.send_error(msg.id, reason);
+                return;
+            }
+        };
+        self.touch(msg.id);
+        if let Some(room_info) = self.rooms.get_mut(&room) {
+            room_info.edit_message(msg.message_id, &msg.new_body);
+        }
+
+        let edited = proto::Edited {
+            room: room.clone(),
+            message_id: msg.message_id,
+            new_body: msg.new_body,
+            by: self.display_name(msg.id),
+            seq: 0,
+        };
+        self.send_message_by_channel(&room, Broadcast::Edited(edited), 0);
+    }
+}
+
+/// Handler for Delete message.
+impl Handler<Delete> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Delete, _: &mut Context<Self>) {
+        let room = match self.amendable(msg.id, msg.message_id) {
+            Ok(room) => room,
+            Err(reason) => {
+                self.send_error(msg.id, reason);
+                return;
+            }
+        };
+        self.touch(msg.id);
+        if let Some(room_info) = self.rooms.get_mut(&room) {
+            room_info.delete_message(msg.message_id);
+        }
+
+        let deleted = proto::Deleted {
+            room: room.clone(),
+            message_id: msg.message_id,
+            by: self.display_name(msg.id),
+            seq: 0,
+        };
+        self.send_message_by_channel(&room, Broadcast::Deleted(deleted), 0);
+    }
+}
+
+/// Handler for React message.
+///
+/// Reactions that change nothing, like a second identical one, are dropped
+impl Handler<React> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: React, _: &mut Context<Self>) {
+        let emoji = msg.emoji.trim();
+        if emoji.is_empty() || emoji.len() > MAX_REACTION_LEN || emoji.contains(char::is_whitespace)
+        {
+            self.send_error(msg.id, format!("invalid reaction: {}", msg.emoji));
+            return;
+        }
+        let (room, chat) = match self.visible_message(msg.id, msg.message_id) {
+            Ok(found) => found,
+            Err(reason) => {
+                self.send_error(msg.id, reason);
+                return;
+            }
+        };
+        let by = self.display_name(msg.id);
+        if chat.has_reacted(emoji, &by) == msg.added {
+            return;
+        }
+        self.touch(msg.id);
+
+        let reacted = proto::Reacted {
+            room: room.clone(),
+            message_id: msg.message_id,
+            emoji: emoji.to_owned(),
+            by,
+            seq: 0,
+        };
+        if let Some(room_info) = self.rooms.get_mut(&room) {
+            room_info.react(&reacted, msg.added);
+        }
+        let broadcast = Broadcast::Reacted {
+            reacted,
+            added: msg.added,
+        };
+        self.send_message_by_channel(&room, broadcast, 0);
+    }
+}
+
+/// Handler for Typing message.
+///
+/// Neither touches the member nor goes into the history, a stop that
+/// follows no start is dropped
+impl Handler<Typing> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Typing, _: &mut Context<Self>) {
+        let room = normalize_room_name(&msg.room).unwrap_or(msg.room);
+        if !self.is_member(&room, msg.id) {
+            self.send_error(msg.id, format!("not in room: {}", room));
+            return;
+        }
+        let key = (room.clone(), msg.id);
+        match msg.state {
+            proto::TypingState::Active => {
+                self.typing.insert(key, Instant::now() + self.typing_expiry);
+            }
+            proto::TypingState::Stopped => {
+                if self.typing.remove(&key).is_none() {
+                    return;
+                }
+            }
+        }
+        self.send_typing(&room, msg.id, msg.state);
+    }
+}
+
+/// Handler for MarkRead message.
+///
+/// Only sessions with a name have a read position
+impl Handler<MarkRead> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: MarkRead, _: &mut Context<Self>) {
+        let room = normalize_room_name(&msg.room).unwrap_or(msg.room);
+        if !self.is_member(&room, msg.id) {
+            self.send_error(msg.id, format!("not in room: {}", room));
+            return;
+        }
+        let Some(name) = self.sessions.get(&msg.id).and_then(|s| s.name.clone()) else {
+            self.send_error(msg.id, "log in to keep read positions".to_owned());
+            return;
+        };
+        let read = proto::MarkRead {
+            room,
+            message_id: msg.message_id,
+        };
+        if self.store_read(&name, &read, msg.id) {
+            self.broadcast(NodeEvent::Read { name, read });
+        }
+    }
+}
+
+/// Handler for SetStatus message.
+///
+/// The rooms of the session hear about it, an unchanged status is dropped
+impl Handler<SetStatus> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetStatus, _: &mut Context<Self>) {
+        if let proto::Status::Away { message } = &msg.status {
+            if message.chars().count() > MAX_STATUS_LEN {
+                self.send_error(msg.id, "away message too long".to_owned());
+                return;
+            }
+        }
+        let Some(session) = self.sessions.get_mut(&msg.id) else {
+            return;
+        };
+        session.last_active = Instant::now();
+        if session.status == msg.status {
+            return;
+        }
+        session.status = msg.status;
+        for room in self.rooms_of(msg.id) {
+            self.send_presence(&room, proto::PresenceKind::Updated, msg.id);
+        }
+    }
+}
+
+/// Handler for SetProfile message.
+impl Handler<SetProfile> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetProfile, _: &mut Context<Self>) {
+        let fields = [
+            (
+                "display name",
+                &msg.profile.display_name,
+                MAX_PROFILE_FIELD_LEN,
+            ),
+            ("pronouns", &msg.profile.pronouns, MAX_PROFILE_FIELD_LEN),
+            ("timezone", &msg.profile.timezone, MAX_PROFILE_FIELD_LEN),
+            ("bio", &msg.profile.bio, MAX_BIO_LEN),
+        ];
+        for (field, value, max) in fields {
+            if value.as_ref().is_some_and(|v| v.chars().count() > max) {
+                self.send_error(msg.id, format!("{} too long", field));
+                return;
+            }
+        }
+        let Some(session) = self.sessions.get_mut(&msg.id) else {
+            return;
+        };
+        let profile = &mut session.profile;
+        // empty values clear a field, missing ones keep it
+        let update = |field: &mut Option<String>, value: Option<String>| {
+            if let Some(value) = value {
+                *field = Some(value.trim().to_owned()).filter(|v| !v.is_empty());
+            }
+        };
+        update(&mut profile.display_name, msg.profile.display_name);
+        update(&mut profile.pronouns, msg.profile.pronouns);
+        update(&mut profile.timezone, msg.profile.timezone);
+        update(&mut profile.bio, msg.profile.bio);
+    }
+}
+
+/// Handler for WhoIs message.
+///
+/// Invisible sessions are only found by sessions using the same name
+impl Handler<WhoIs> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: WhoIs, _: &mut Context<Self>) {
+        let own = self.sessions.get(&msg.id).and_then(|s| s.name.as_deref()) == Some(&msg.name);
+        let local = self
+            .sessions_named(&msg.name)
+            .into_iter()
+            .map(|id| (self.member(id), self.sessions[&id].profile.clone()));
+        let remote = self
+            .remote
+            .values()
+            .flat_map(|room| room.values())
+            .filter(|r| r.member.name == msg.name)
+            .map(|r| (r.member.clone(), proto::Profile::default()));
+        // the most recently active session speaks for the name
+        let found = local
+            .chain(remote)
+            .filter(|(member, _)| own || member.status != proto::Status::Invisible)
+            .min_by_key(|(member, _)| member.idle_secs);
+        let Some((member, profile)) = found else {
+            self.send_error(msg.id, format!("no such user: {}", msg.name));
+            return;
+        };
+        let info = proto::UserInfo {
+            name: member.name,
+            status: member.status,
+            profile,
+            idle_secs: member.idle_secs,
+        };
+        let pkg = ChatPacket::with_payload(ChatPacketType::WhoIs, &info);
+        self.send_message_by_id(msg.id, &pkg);
+    }
+}
+
+/// Handler for SetTopic message.
+impl Handler<SetTopic> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: SetTopic, _: &mut Context<Self>) {
+        let room = normalize_room_name(&msg.room).unwrap_or(msg.room);
+        if let Err(reason) = self.changeable(msg.id, &room) {
+            self.send_error(msg.id, reason);
+            return;
+        }
+        let fields = [
+            ("topic", &msg.topic, MAX_TOPIC_LEN),
+            ("description", &msg.description, MAX_DESCRIPTION_LEN),
+        ];
+        for (field, value, max) in fields {
+            if value.as_ref().is_some_and(|v| v.chars().count() > max) {
+                self.send_error(msg.id, format!("{} too long", field));
+                return;
+            }
+        }
+        let Some(room_info) = self.rooms.get_mut(&room) else {
+            return;
+        };
+        if let Some(topic) = msg.topic {
+            room_info.topic = topic.trim().to_owned();
+        }
+        if let Some(description) = msg.description {
+            room_info.description = description.trim().to_owned();
+        }
+        let info = room_info.info(&room);
+        self.send_message_by_channel(&room, Broadcast::RoomInfo(info), 0);
+    }
+}
+
+/// Handler for RoomInfo message.
+impl Handler<RoomInfo> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: RoomInfo, _: &mut Context<Self>) {
+        let room = normalize_room_name(&msg.room).unwrap_or(msg.room);
+        let Some(settings) = msg.settings else {
+            if self.is_member(&room, msg.id) {
+                self.send_room_info(&room, msg.id);
+            } else {
+                self.send_error(msg.id, format!("not in room: {}", room));
+            }
+            return;
+        };
+        if let Err(reason) = self.changeable(msg.id, &room) {
+            self.send_error(msg.id, reason);
+            return;
+        }
+        let password = msg
+            .password
+            .filter(|p| !p.is_empty())
+            .map(|p| Password::new(&p, &mut self.rng));
+        let Some(room_info) = self.rooms.get_mut(&room) else {
+            return;
+        };
+        if settings.visibility != proto::Visibility::Password {
+            room_info.password = None;
+        } else if password.is_some() {
+            room_info.password = password;
+        } else if room_info.password.is_none() {
+            self.send_error(
+                msg.id,
+                "a password protected room needs a password".to_owned(),
+            );
+            return;
+        }
+        room_info.settings = settings;
+        let info = room_info.info(&room);
+        let admission = room_info.admission();
+        self.send_message_by_channel(&room, Broadcast::RoomInfo(info), 0);
+        self.broadcast(NodeEvent::Admission(vec![(room, admission)]));
+    }
+}
+
+/// Handler for Invite message.
+///
+/// Members invite to public and unlisted rooms, operators to the others
+impl Handler<Invite> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Invite, _: &mut Context<Self>) {
+        let room = normalize_room_name(&msg.room).unwrap_or(msg.room);
+        let Some(room_info) = self
+            .rooms
+            .get(&room)
+            .filter(|r| r.members.contains(&msg.id))
+        else {
+            self.send_error(msg.id, format!("not in room: {}", room));
+            return;
+        };
+        if room_info.restricted() && !self.operates(room_info, msg.id) {
+            self.send_error(msg.id, format!("only operators may invite to #{}", room));
+            return;
+        }
+
+        self.invites.retain(|_, invite| !invite.expired());
+        let code: String = (&mut self.rng)
+            .sample_iter(&Alphanumeric)
+            .take(INVITE_CODE_LEN)
+            .map(char::from)
+            .collect();
+        let invite = PendingInvite {
+            room: room.clone(),
+            uses_left: Some(msg.max_uses).filter(|n| *n > 0),
+            expires: Some(msg.expires_secs)
+                .filter(|secs| *secs > 0)
+                .map(|secs| Instant::now() + Duration::from_secs(secs)),
+        };
+        self.invites.insert(code.clone(), invite);
+
+        let issued = proto::InviteCode {
+            room,
+            code,
+            max_uses: msg.max_uses,
+            expires_secs: msg.expires_secs,
+        };
+        let pkg = ChatPacket::with_payload(ChatPacketType::Invite, &issued);
+        self.send_message_by_id(msg.id, &pkg);
+    }
+}
+
+/// Handler for AcceptInvite message.
+impl Handler<AcceptInvite> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: AcceptInvite, _: &mut Context<Self>) {
+        let code = msg.code.trim();
+        let Some(invite) = self.invites.get(code) else {
+            self.refuse_join(msg.id, "", proto::JoinRefusal::InvalidInvite);
+            return;
+        };
+        let room = invite.room.clone();
+        if invite.expired() {
+            self.invites.remove(code);
+            self.refuse_join(msg.id, &room, proto::JoinRefusal::InviteExpired);
+            return;
+        }
+        if let Err(reason) = self.admission(&room, msg.id, None, true) {
+            self.refuse_join(msg.id, &room, reason);
+            return;
+        }
+        if let Some(invite) = self.invites.get_mut(code) {
+            if let Some(uses_left) = &mut invite.uses_left {
+                *uses_left -= 1;
+                if *uses_left == 0 {
+                    self.invites.remove(code);
                 }
             }
+        }
+        // clients rejoin the room after reconnecting
+        let accepted = proto::AcceptInvite {
+            code: code.to_owned(),
+            room: room.clone(),
+        };
+        let pkg = ChatPacket::with_payload(ChatPacketType::AcceptInvite, &accepted);
+        self.send_message_by_id(msg.id, &pkg);
+        self.admit(&room, msg.id);
+    }
+}
+
+/// Handler for ListRooms message.
+impl Handler<ListRooms> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: ListRooms, _: &mut Context<Self>) {
+        let mut rooms: Vec<proto::RoomSummary> = self
+            .rooms
+            .iter()
+            .filter(|(_, room)| {
+                room.settings.visibility.is_listed() || room.members.contains(&msg.id)
+            })
+            .map(|(name, room)| proto::RoomSummary {
+                room: name.to_owned(),
+                topic: room.topic.clone(),
+                visibility: room.settings.visibility,
+                members: self.member_count(name),
+            })
+            .collect();
+        rooms.sort_by(|a, b| a.room.cmp(&b.room));
 
-            log::info!("current session count: {}", self.sessions.len());
+        let list = proto::RoomList { rooms };
+        let pkg = ChatPacket::with_payload(ChatPacketType::ListRooms, &list);
+        self.send_message_by_id(msg.id, &pkg);
+    }
+}
+
+/// Handler for Resync message.
+///
+/// Answers with the first sequence number still in the history, then as many
+/// broadcasts from there on as the outbox has room for. A request for a room whose
+/// last replay is still queued is ignored
+impl Handler<Resync> for WsServer {
+    type Result = ();
+
+    fn handle(&mut self, msg: Resync, _: &mut Context<Self>) {
+        let room = normalize_room_name(&msg.room).unwrap_or(msg.room);
+        let Some(room_info) = self
+            .rooms
+            .get(&room)
+            .filter(|r| r.members.contains(&msg.id))
+        else {
+            self.send_error(msg.id, format!("not in room: {}", room));
+            return;
+        };
+        let Some(session) = self.sessions.get_mut(&msg.id) else {
+            return;
+        };
+        if session.replays.get(&room).is_some_and(Replay::queued) {
+            return;
+        }
+        // what fits next to the answer, replaying more would drop the first ones
+        let room_left = session
+            .outbox
+            .capacity()
+            .saturating_sub(session.outbox.depth() + 1)
+            .max(1);
+        let (from_seq, packets) = room_info.since(msg.from_seq, room_left);
+
+        let replay = Replay::default();
+        let resync = proto::Resync {
+            room: room.clone(),
+            from_seq,
+            until_seq: (!packets.is_empty()).then(|| from_seq + packets.len() as u64 - 1),
+        };
+        let pkg = ChatPacket::with_payload(ChatPacketType::Resync, &resync);
+        session.outbox.push(Bytes::from(pkg.serialize()), true);
+        for pkg in &packets {
+            session
+                .outbox
+                .push_replay(Bytes::from(pkg.serialize()), &replay);
         }
-        // send message to other users
-        // for c in channels_leave_msg {
-        //     self.send_message(&c, "Someone disconnected", 0);
-        // }
+        session.replays.insert(room, replay);
     }
 }
 
-/// Handler for Package message.
-/// for notify bytes to client
-impl Handler<ChatPacket> for WsServer {
+/// Handler for Direct message.
+impl Handler<Direct> for WsServer {
     type Result = ();
 
-    fn handle(&mut self, pkg: ChatPacket, _ctx: &mut Self::Context) {
-        println!("session handle send package");
+    fn handle(&mut self, msg: Direct, _: &mut Context<Self>) {
+        if let Some(reason) = body_error(&msg.body) {
+            self.send_error(msg.id, reason);
+            return;
+        }
+        let recipients: Vec<usize> = self
+            .sessions
+            .iter()
+            .filter(|(_, session)| session.name.as_deref() == Some(msg.to.as_str()))
+            .map(|(id, _)| *id)
+            .collect();
+        let remote = self.is_remote_name(&msg.to);
+        if recipients.is_empty() && !remote {
+            self.send_error(msg.id, format!("no such user: {}", msg.to));
+            return;
+        }
+        self.touch(msg.id);
 
-        for (_session_id, addr) in &self.sessions {
-            addr.do_send(pkg.to_owned());
+        let current_local = chrono::Local::now();
+        let message = proto::DirectMessage {
+            from: self.display_name(msg.id),
+            to: msg.to,
+            body: msg.body,
+            time: current_local.format("%Y-%m-%d %H:%M:%S").to_string(),
+        };
+        let pkg = ChatPacket::with_payload(ChatPacketType::Direct, &message);
+        for session_id in &recipients {
+            self.send_message_by_id(*session_id, &pkg);
+        }
+        if remote {
+            self.broadcast(NodeEvent::Direct {
+                to: message.to.clone(),
+                packet: (&pkg).into(),
+            });
+        }
+        if !recipients.contains(&msg.id) {
+            self.send_message_by_id(msg.id, &pkg);
         }
     }
 }
+
+/// Handler for ListSessions message.
+impl Handler<ListSessions> for WsServer {
+    type Result = MessageResult<ListSessions>;
+
+    fn handle(&mut self, _: ListSessions, _: &mut Context<Self>) -> Self::Result {
+        let mut stats: Vec<SessionStats> = self
+            .sessions
+            .iter()
+            .map(|(id, session)| SessionStats {
+                id: *id,
+                name: session.name.clone(),
+                rooms: self.rooms_of(*id),
+                queue_depth: session.outbox.depth(),
+                dropped: session.outbox.dropped(),
+                idle_secs: session.last_active.elapsed().as_secs(),
+            })
+            .collect();
+        stats.sort_by_key(|s| s.id);
+        MessageResult(stats)
+    }
+}
@@ -1,10 +1,14 @@
 use actix::prelude::*;
 use actix_web_actors::ws;
 use proto::ChatPacket;
+use std::collections::HashMap;
+use std::sync::Arc;
 use std::time::Duration;
 use std::time::Instant;
+use tokio::task::JoinHandle;
 
-use crate::server;
+use crate::outbox::{self, Outbox, Overflowed};
+use crate::{server, Config};
 
 /// How often heartbeat pings are sent
 pub const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(5);
@@ -21,24 +25,55 @@ pub struct WsSession {
     /// otherwise we drop connection.
     pub heartbeat: Instant,
 
-    /// joined room
-    pub room: String,
-
     /// peer name
     pub name: Option<String>,
 
     /// Chat server
     pub addr: Addr<server::WsServer>,
+
+    /// How often heartbeats are checked
+    pub heartbeat_interval: Duration,
+
+    /// How long the client may stay silent
+    pub client_timeout: Duration,
+
+    /// Frames from the chat server on their way to the client
+    pub outbox: Arc<Outbox>,
+
+    /// Tasks forwarding the broadcasts of the joined rooms into the outbox
+    pub subscriptions: HashMap<String, JoinHandle<()>>,
+
+    /// Id the client keeps across reconnects
+    pub client_id: Option<String>,
 }
 
 impl WsSession {
+    pub fn new(
+        addr: Addr<server::WsServer>,
+        config: &Config,
+        outbox: Arc<Outbox>,
+        client_id: Option<String>,
+    ) -> WsSession {
+        WsSession {
+            id: 0,
+            heartbeat: Instant::now(),
+            name: None,
+            addr,
+            heartbeat_interval: config.heartbeat_interval,
+            client_timeout: config.client_timeout,
+            outbox,
+            subscriptions: HashMap::new(),
+            client_id,
+        }
+    }
+
     /// helper method that sends ping to client every 5 seconds (HEARTBEAT_INTERVAL).
     ///
     /// also this method checks heartbeats from client
     fn heartbeat(&self, ctx: &mut ws::WebsocketContext<Self>) {
-        ctx.run_interval(HEARTBEAT_INTERVAL, |act, ctx| {
+        ctx.run_interval(self.heartbeat_interval, |act, ctx| {
             // check client heartbeats
-            if Instant::now().duration_since(act.heartbeat) > CLIENT_TIMEOUT {
+            if Instant::now().duration_since(act.heartbeat) > act.client_timeout {
                 // heartbeat timed out
                 println!("Websocket Client heartbeat failed, disconnecting!");
 
@@ -47,9 +82,6 @@ impl WsSession {
 
                 // stop actor
                 ctx.stop();
-
-                // don't try to send a ping
-                return;
             }
         });
     }
@@ -70,9 +102,13 @@ impl Actor for WsSession {
         // HttpContext::state() is instance of WsChatSessionState, state is shared
         // across all routes within application
         let addr: Addr<WsSession> = ctx.address();
+        self.outbox.set_owner(addr.clone().recipient());
         self.addr
             .send(server::Connect {
-                addr: addr.recipient(),
+                outbox: self.outbox.clone(),
+                subscriptions: addr.clone().recipient(),
+                replaced: addr.recipient(),
+                client_id: self.client_id.clone(),
             })
             .into_actor(self)
             .then(|res, act, ctx| {
@@ -94,6 +130,10 @@ impl Actor for WsSession {
 
         // notify chat server
         self.addr.do_send(server::Disconnect { id: self.id });
+        for (_, task) in self.subscriptions.drain() {
+            task.abort();
+        }
+        self.outbox.close();
         Running::Stop
     }
 }
@@ -119,8 +159,15 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsSession {
             ws::Message::Pong(_) => (),
             ws::Message::Text(_) => (),
             ws::Message::Binary(bytes) => {
-                let bytes = bytes.to_vec();
-                let packet = ChatPacket::deserialize(bytes);
+                let packet = match ChatPacket::deserialize(&bytes) {
+                    Ok(packet) => packet,
+                    Err(err) => {
+                        log::error!("malformed packet from session {}: {}", self.id, err);
+                        ctx.close(Some(ws::CloseCode::Invalid.into()));
+                        ctx.stop();
+                        return;
+                    }
+                };
 
                 match packet.packet_type {
                     proto::ChatPacketType::Close => {
@@ -129,27 +176,13 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsSession {
                     }
                     proto::ChatPacketType::Login => {
                         self.heartbeat = Instant::now();
-
-                        let current_local = chrono::Local::now();
-                        let time = current_local.format("%Y-%m-%d %H:%M:%S");
-
-                        let time_and_tip = if let Some(name) = &self.name {
-                            format!(
-                                "[{}] ID_{} changed name from {} to {}",
-                                time, self.id, name, packet.packet_message
-                            )
-                        } else {
-                            format!(
-                                "[{}] ID_{} set name to {}",
-                                time, self.id, packet.packet_message
-                            )
-                        };
-
-                        let pack = ChatPacket::new(proto::ChatPacketType::Login, time_and_tip);
                         self.name = Some(packet.packet_message.clone());
 
                         self.addr
-                            .send(pack)
+                            .send(server::Login {
+                                id: self.id,
+                                name: packet.packet_message,
+                            })
                             .into_actor(self)
                             .then(|res, act, ctx| {
                                 match res {
@@ -166,25 +199,273 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsSession {
                     proto::ChatPacketType::Chat => {
                         self.heartbeat = Instant::now();
 
-                        let current_local = chrono::Local::now();
-                        let time = current_local.format("%Y-%m-%d %H:%M:%S");
-
-                        let time_and_name = if let Some(name) = &self.name {
-                            format!("[{}] {}: ", time, name)
-                        } else {
-                            format!("[{}] ID_{}: ", time, self.id)
+                        let chat = match packet.payload::<proto::ChatSend>() {
+                            Ok(chat) => chat,
+                            Err(err) => {
+                                log::error!("invalid chat packet: {}", err);
+                                return;
+                            }
                         };
-                        let pack = ChatPacket::new(
-                            proto::ChatPacketType::Chat,
-                            time_and_name + packet.packet_message.as_str(),
-                        );
 
                         self.addr
-                            .send(pack)
+                            .send(server::Chat {
+                                id: self.id,
+                                room: chat.room,
+                                body: chat.body,
+                                nonce: chat.nonce,
+                                reply_to: chat.reply_to,
+                            })
                             .into_actor(self)
                             .then(|_res, _act, _ctx| fut::ready(()))
                             .wait(ctx);
                     }
+                    proto::ChatPacketType::Direct => {
+                        self.heartbeat = Instant::now();
+
+                        let direct = match packet.payload::<proto::DirectSend>() {
+                            Ok(direct) => direct,
+                            Err(err) => {
+                                log::error!("invalid direct packet: {}", err);
+                                return;
+                            }
+                        };
+                        self.addr.do_send(server::Direct {
+                            id: self.id,
+                            to: direct.to,
+                            body: direct.body,
+                        });
+                    }
+                    proto::ChatPacketType::Join => {
+                        self.heartbeat = Instant::now();
+                        let join = proto::JoinRoom::parse(&packet.packet_message);
+                        self.addr.do_send(server::Join {
+                            id: self.id,
+                            room: join.room,
+                            password: join.password,
+                            ttl_secs: join.ttl_secs,
+                        });
+                    }
+                    proto::ChatPacketType::Leave => {
+                        self.heartbeat = Instant::now();
+                        self.addr.do_send(server::Leave {
+                            id: self.id,
+                            room: packet.packet_message,
+                        });
+                    }
+                    proto::ChatPacketType::Roster => {
+                        self.addr.do_send(server::ListMembers {
+                            id: self.id,
+                            room: packet.packet_message,
+                        });
+                    }
+                    proto::ChatPacketType::Edit => {
+                        self.heartbeat = Instant::now();
+
+                        let edit = match packet.payload::<proto::Edit>() {
+                            Ok(edit) => edit,
+                            Err(err) => {
+                                log::error!("invalid edit packet: {}", err);
+                                return;
+                            }
+                        };
+                        self.addr.do_send(server::Edit {
+                            id: self.id,
+                            message_id: edit.message_id,
+                            new_body: edit.new_body,
+                        });
+                    }
+                    proto::ChatPacketType::Delete => {
+                        self.heartbeat = Instant::now();
+
+                        let delete = match packet.payload::<proto::Delete>() {
+                            Ok(delete) => delete,
+                            Err(err) => {
+                                log::error!("invalid delete packet: {}", err);
+                                return;
+                            }
+                        };
+                        self.addr.do_send(server::Delete {
+                            id: self.id,
+                            message_id: delete.message_id,
+                        });
+                    }
+                    proto::ChatPacketType::React | proto::ChatPacketType::Unreact => {
+                        self.heartbeat = Instant::now();
+
+                        let react = match packet.payload::<proto::React>() {
+                            Ok(react) => react,
+                            Err(err) => {
+                                log::error!("invalid reaction packet: {}", err);
+                                return;
+                            }
+                        };
+                        self.addr.do_send(server::React {
+                            id: self.id,
+                            message_id: react.message_id,
+                            emoji: react.emoji,
+                            added: packet.packet_type == proto::ChatPacketType::React,
+                        });
+                    }
+                    proto::ChatPacketType::Typing => {
+                        self.heartbeat = Instant::now();
+
+                        let typing = match packet.payload::<proto::Typing>() {
+                            Ok(typing) => typing,
+                            Err(err) => {
+                                log::error!("invalid typing packet: {}", err);
+                                return;
+                            }
+                        };
+                        self.addr.do_send(server::Typing {
+                            id: self.id,
+                            room: typing.room,
+                            state: typing.state,
+                        });
+                    }
+                    proto::ChatPacketType::MarkRead => {
+                        self.heartbeat = Instant::now();
+
+                        let read = match packet.payload::<proto::MarkRead>() {
+                            Ok(read) => read,
+                            Err(err) => {
+                                log::error!("invalid read packet: {}", err);
+                                return;
+                            }
+                        };
+                        self.addr.do_send(server::MarkRead {
+                            id: self.id,
+                            room: read.room,
+                            message_id: read.message_id,
+                        });
+                    }
+                    proto::ChatPacketType::Status => {
+                        self.heartbeat = Instant::now();
+
+                        let status = match packet.payload::<proto::Status>() {
+                            Ok(status) => status,
+                            Err(err) => {
+                                log::error!("invalid status packet: {}", err);
+                                return;
+                            }
+                        };
+                        self.addr.do_send(server::SetStatus {
+                            id: self.id,
+                            status,
+                        });
+                    }
+                    proto::ChatPacketType::Profile => {
+                        self.heartbeat = Instant::now();
+
+                        let profile = match packet.payload::<proto::Profile>() {
+                            Ok(profile) => profile,
+                            Err(err) => {
+                                log::error!("invalid profile packet: {}", err);
+                                return;
+                            }
+                        };
+                        self.addr.do_send(server::SetProfile {
+                            id: self.id,
+                            profile,
+                        });
+                    }
+                    proto::ChatPacketType::WhoIs => {
+                        self.heartbeat = Instant::now();
+
+                        let whois = match packet.payload::<proto::WhoIs>() {
+                            Ok(whois) => whois,
+                            Err(err) => {
+                                log::error!("invalid whois packet: {}", err);
+                                return;
+                            }
+                        };
+                        self.addr.do_send(server::WhoIs {
+                            id: self.id,
+                            name: whois.name,
+                        });
+                    }
+                    proto::ChatPacketType::SetTopic => {
+                        self.heartbeat = Instant::now();
+
+                        let set = match packet.payload::<proto::SetTopic>() {
+                            Ok(set) => set,
+                            Err(err) => {
+                                log::error!("invalid topic packet: {}", err);
+                                return;
+                            }
+                        };
+                        self.addr.do_send(server::SetTopic {
+                            id: self.id,
+                            room: set.room,
+                            topic: set.topic,
+                            description: set.description,
+                        });
+                    }
+                    proto::ChatPacketType::RoomInfo => {
+                        self.heartbeat = Instant::now();
+
+                        let query = match packet.payload::<proto::RoomQuery>() {
+                            Ok(query) => query,
+                            Err(err) => {
+                                log::error!("invalid room info packet: {}", err);
+                                return;
+                            }
+                        };
+                        self.addr.do_send(server::RoomInfo {
+                            id: self.id,
+                            room: query.room,
+                            settings: query.settings,
+                            password: query.password,
+                        });
+                    }
+                    proto::ChatPacketType::Invite => {
+                        self.heartbeat = Instant::now();
+
+                        let invite = match packet.payload::<proto::Invite>() {
+                            Ok(invite) => invite,
+                            Err(err) => {
+                                log::error!("invalid invite packet: {}", err);
+                                return;
+                            }
+                        };
+                        self.addr.do_send(server::Invite {
+                            id: self.id,
+                            room: invite.room,
+                            max_uses: invite.max_uses,
+                            expires_secs: invite.expires_secs,
+                        });
+                    }
+                    proto::ChatPacketType::AcceptInvite => {
+                        self.heartbeat = Instant::now();
+
+                        let accept = match packet.payload::<proto::AcceptInvite>() {
+                            Ok(accept) => accept,
+                            Err(err) => {
+                                log::error!("invalid accept invite packet: {}", err);
+                                return;
+                            }
+                        };
+                        self.addr.do_send(server::AcceptInvite {
+                            id: self.id,
+                            code: accept.code,
+                        });
+                    }
+                    proto::ChatPacketType::ListRooms => {
+                        self.addr.do_send(server::ListRooms { id: self.id });
+                    }
+                    proto::ChatPacketType::Resync => {
+                        let resync = match packet.payload::<proto::Resync>() {
+                            Ok(resync) => resync,
+                            Err(err) => {
+                                log::error!("invalid resync packet: {}", err);
+                                return;
+                            }
+                        };
+                        self.addr.do_send(server::Resync {
+                            id: self.id,
+                            room: resync.room,
+                            from_seq: resync.from_seq,
+                        });
+                    }
                     _ => {
                         log::error!("unknown packet type: {:?}", packet.packet_type);
                         ctx.close(None);
@@ -205,17 +486,58 @@ impl StreamHandler<Result<ws::Message, ws::ProtocolError>> for WsSession {
     }
 }
 
-/// Handler for Package message.
-/// for notify bytes to client
-impl Handler<ChatPacket> for WsSession {
+/// Handler for Subscription message.
+/// starts or stops forwarding the broadcasts of a room
+impl Handler<server::Subscription> for WsSession {
+    type Result = ();
+
+    fn handle(&mut self, msg: server::Subscription, _: &mut Self::Context) {
+        match msg {
+            server::Subscription::Subscribe { room, rx } => {
+                let task = actix_web::rt::spawn(outbox::forward(rx, self.outbox.clone(), self.id));
+                if let Some(old) = self.subscriptions.insert(room, task) {
+                    old.abort();
+                }
+            }
+            server::Subscription::Unsubscribe { room } => {
+                if let Some(task) = self.subscriptions.remove(&room) {
+                    task.abort();
+                }
+            }
+        }
+    }
+}
+
+/// Handler for Replaced message.
+/// the client reconnected, this connection is stale
+impl Handler<server::Replaced> for WsSession {
     type Result = ();
 
-    fn handle(&mut self, pkg: ChatPacket, ctx: &mut Self::Context) {
-        println!("session handle send package");
-        self.heartbeat = Instant::now();
+    fn handle(&mut self, _: server::Replaced, ctx: &mut Self::Context) {
+        log::info!("session {} replaced by a new connection", self.id);
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Policy,
+            description: Some("replaced by a new connection".to_owned()),
+        }));
+        ctx.stop();
+    }
+}
+
+/// Handler for Overflowed message.
+/// the client reads too slowly to keep up, drop it
+impl Handler<Overflowed> for WsSession {
+    type Result = ();
 
-        // send message
-        let msg_bytes = pkg.serialize();
-        ctx.binary(msg_bytes);
+    fn handle(&mut self, msg: Overflowed, ctx: &mut Self::Context) {
+        log::warn!(
+            "session {} fell {} frames behind, disconnecting",
+            self.id,
+            msg.queued
+        );
+        ctx.close(Some(ws::CloseReason {
+            code: ws::CloseCode::Policy,
+            description: Some("too slow to keep up".to_owned()),
+        }));
+        ctx.stop();
     }
 }
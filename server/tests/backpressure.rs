@@ -0,0 +1,298 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use actix_codec::Decoder;
+use actix_http::ws::{Codec, Frame};
+use actix_web::web::{Bytes, BytesMut};
+use chat_client::{ChatClient, Event};
+use futures_util::StreamExt;
+use proto::{ChatPacket, ChatPacketType, PresenceKind};
+use tokio_tungstenite::tungstenite::protocol::frame::coding::CloseCode;
+use tokio_tungstenite::tungstenite::Message;
+
+use ws_server::outbox::{Outbox, OverflowPolicy};
+use ws_server::room::ROOM_HISTORY;
+use ws_server::Config;
+
+mod support;
+
+use support::{RawSocket, TestServer, TIMEOUT};
+
+const ADMIN_TOKEN: &str = "admin-secret";
+
+fn packet(body: &str) -> Bytes {
+    Bytes::from(ChatPacket::new(ChatPacketType::Chat, body.to_owned()).serialize())
+}
+
+/// Everything the outbox has ready, decoded back into packets
+async fn drain(outbox: &Arc<Outbox>) -> Vec<ChatPacket> {
+    let mut stream = outbox.clone().stream();
+    let mut buf = match tokio::time::timeout(Duration::from_millis(100), stream.next()).await {
+        Ok(Some(Ok(bytes))) => BytesMut::from(&bytes[..]),
+        _ => return Vec::new(),
+    };
+
+    let mut codec = Codec::new().client_mode();
+    let mut packets = Vec::new();
+    while let Some(frame) = codec.decode(&mut buf).expect("valid websocket frame") {
+        match frame {
+            Frame::Binary(bytes) => packets.push(ChatPacket::deserialize(&bytes).unwrap()),
+            other => panic!("unexpected frame: {:?}", other),
+        }
+    }
+    packets
+}
+
+fn bodies(packets: &[ChatPacket]) -> Vec<&str> {
+    packets.iter().map(|p| p.packet_message.as_str()).collect()
+}
+
+#[actix_web::test]
+async fn full_outbox_drops_the_oldest_broadcast_and_marks_the_gap() {
+    let outbox = Arc::new(Outbox::new(3, OverflowPolicy::Marker));
+    outbox.push(packet("direct"), true);
+    for body in ["b", "c", "d", "e"] {
+        outbox.push(packet(body), false);
+    }
+    assert_eq!(outbox.depth(), 3);
+    assert_eq!(outbox.dropped(), 2);
+
+    let packets = drain(&outbox).await;
+    assert_eq!(packets[0].packet_type, ChatPacketType::Missed);
+    let missed: proto::Missed = packets[0].payload().unwrap();
+    assert_eq!(missed.count, 2);
+    assert_eq!(bodies(&packets[1..]), ["direct", "d", "e"]);
+    assert_eq!(outbox.depth(), 0);
+
+    // the marker is sent once per gap
+    outbox.push(packet("f"), false);
+    assert_eq!(bodies(&drain(&outbox).await), ["f"]);
+}
+
+#[actix_web::test]
+async fn drop_oldest_keeps_frames_for_the_session_itself() {
+    let outbox = Arc::new(Outbox::new(2, OverflowPolicy::DropOldest));
+    outbox.push(packet("a"), true);
+    outbox.push(packet("b"), true);
+    // a broadcast has nothing to replace
+    outbox.push(packet("c"), false);
+    // a direct frame goes over the limit rather than getting lost
+    outbox.push(packet("d"), true);
+    assert_eq!(outbox.dropped(), 1);
+
+    assert_eq!(bodies(&drain(&outbox).await), ["a", "b", "d"]);
+}
+
+#[actix_web::test]
+async fn critical_frames_past_the_slack_end_the_stream() {
+    let outbox = Arc::new(Outbox::new(2, OverflowPolicy::DropOldest));
+    for body in ["a", "b", "c", "d"] {
+        outbox.push(packet(body), true);
+    }
+    assert_eq!(outbox.depth(), 4);
+
+    outbox.push(packet("e"), true);
+    assert_eq!(outbox.depth(), 0);
+    assert_eq!(outbox.dropped(), 5);
+    assert!(outbox.clone().stream().next().await.is_none());
+}
+
+#[actix_web::test]
+async fn closed_outbox_ends_its_stream() {
+    let outbox = Arc::new(Outbox::new(2, OverflowPolicy::Marker));
+    outbox.push(packet("a"), false);
+    outbox.close();
+    outbox.push(packet("b"), false);
+
+    assert_eq!(outbox.depth(), 0);
+    assert!(outbox.clone().stream().next().await.is_none());
+}
+
+fn admin_config(policy: OverflowPolicy) -> Config {
+    Config {
+        outbox_capacity: 16,
+        outbox_policy: policy,
+        admin_token: Some(ADMIN_TOKEN.to_owned()),
+        ..Config::default()
+    }
+}
+
+/// Stats of one session from the admin API, `None` once it is gone
+async fn session_stats(server: &TestServer, id: usize) -> Option<serde_json::Value> {
+    let (status, body) = server.get("/admin/sessions", Some(ADMIN_TOKEN)).await;
+    assert_eq!(status, 200, "{}", body);
+    let sessions: Vec<serde_json::Value> = serde_json::from_str(&body).unwrap();
+    sessions
+        .into_iter()
+        .find(|s| s["id"].as_u64() == Some(id as u64))
+}
+
+/// A peer that never reads, chatted at until the server starts dropping its frames
+async fn stalled_peer(server: &TestServer) -> (RawSocket, usize) {
+    let mut alice = server.login("alice").await;
+    let stalled = server.raw().await;
+    let stalled_id = alice
+        .expect("stalled peer joining", |event| match event {
+            Event::Presence(p) if p.kind == PresenceKind::Joined => Some(p.member.id),
+            _ => None,
+        })
+        .await;
+
+    // alice reads everything she is sent, only the raw peer falls behind
+    let support::TestClient { client, mut events } = alice;
+    actix_web::rt::spawn(async move { while events.next_event().await.is_some() {} });
+
+    let body = "x".repeat(32 * 1024);
+    let started = Instant::now();
+    loop {
+        for _ in 0..20 {
+            client.send_chat("main", &body).await.unwrap();
+        }
+        // a disconnected peer is gone by the time we look
+        let Some(stats) = session_stats(server, stalled_id).await else {
+            break;
+        };
+        if stats["dropped"].as_u64().unwrap_or_default() > 0 {
+            break;
+        }
+        assert!(
+            started.elapsed() < TIMEOUT * 4,
+            "outbox never overflowed: {}",
+            stats
+        );
+    }
+    (stalled, stalled_id)
+}
+
+#[actix_web::test]
+async fn slow_reader_is_told_what_it_missed() {
+    let server = TestServer::with_config(admin_config(OverflowPolicy::Marker));
+    let (mut stalled, stalled_id) = stalled_peer(&server).await;
+
+    let stats = session_stats(&server, stalled_id)
+        .await
+        .expect("still connected");
+    assert_eq!(stats["queue_depth"].as_u64(), Some(16), "{}", stats);
+    let (_, metrics) = server.get("/metrics", Some(ADMIN_TOKEN)).await;
+    let gauge = format!("chat_session_queue_depth{{session=\"{}\"", stalled_id);
+    assert!(metrics.contains(&gauge), "{}", metrics);
+
+    let missed = tokio::time::timeout(TIMEOUT, async {
+        while let Some(Ok(message)) = stalled.next().await {
+            if let Message::Binary(bytes) = message {
+                let packet = ChatPacket::deserialize(&bytes).unwrap();
+                if packet.packet_type == ChatPacketType::Missed {
+                    return packet.payload::<proto::Missed>().unwrap();
+                }
+            }
+        }
+        panic!("connection ended before the marker");
+    })
+    .await
+    .expect("missed marker");
+    assert!(missed.count > 0);
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn slow_reader_is_disconnected_by_policy() {
+    let server = TestServer::with_config(admin_config(OverflowPolicy::Disconnect));
+    let (mut stalled, _) = stalled_peer(&server).await;
+
+    let code = tokio::time::timeout(TIMEOUT, async {
+        while let Some(Ok(message)) = stalled.next().await {
+            if let Message::Close(frame) = message {
+                return frame.map(|frame| frame.code);
+            }
+        }
+        None
+    })
+    .await
+    .expect("close frame");
+    assert_eq!(code, Some(CloseCode::Policy));
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn slow_client_fills_the_gap_from_history() {
+    let server = TestServer::with_config(admin_config(OverflowPolicy::Marker));
+    let support::TestClient {
+        client: alice,
+        mut events,
+    } = server.login("alice").await;
+    actix_web::rt::spawn(async move { while events.next_event().await.is_some() {} });
+
+    // a tiny event queue stops the connection from reading while nobody listens
+    let mut options = server.options();
+    options.queue_size = 4;
+    let (_bob, mut bob_events) = ChatClient::connect(options).await.unwrap();
+    let bob_id = loop {
+        match bob_events.next_event().await {
+            Some(Event::Roster(roster)) => {
+                let bob = roster.members.iter().find(|m| m.name != "alice");
+                break bob.expect("bob in the roster").id;
+            }
+            Some(_) => {}
+            None => panic!("bob never got the roster"),
+        }
+    };
+
+    let padding = "x".repeat(32 * 1024);
+    let mut sent = 0;
+    loop {
+        for _ in 0..20 {
+            let body = format!("{} {}", sent, padding);
+            alice.send_chat("main", &body).await.unwrap();
+            sent += 1;
+        }
+        let stats = session_stats(&server, bob_id)
+            .await
+            .expect("bob still connected");
+        if stats["dropped"].as_u64().unwrap_or_default() > 0 {
+            break;
+        }
+        assert!(sent < ROOM_HISTORY, "outbox never overflowed: {}", stats);
+    }
+
+    // the dropped broadcasts are fetched again, in order and once
+    let mut next = 0;
+    tokio::time::timeout(TIMEOUT * 2, async {
+        while next < sent {
+            match bob_events.next_event().await {
+                Some(Event::Chat(chat)) => {
+                    let number = chat.body.split(' ').next().unwrap();
+                    assert_eq!(number, next.to_string());
+                    next += 1;
+                }
+                Some(_) => {}
+                None => panic!("bob's connection ended"),
+            }
+        }
+    })
+    .await
+    .unwrap_or_else(|_| panic!("bob got {} of {} chats", next, sent));
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn admin_api_requires_the_admin_token() {
+    let server = TestServer::start();
+    assert_eq!(server.get("/metrics", None).await.0, 404);
+
+    let server = TestServer::with_config(admin_config(OverflowPolicy::Marker));
+    let _alice = server.login("alice").await;
+    for path in ["/metrics", "/admin/sessions"] {
+        assert_eq!(server.get(path, None).await.0, 401);
+        assert_eq!(server.get(path, Some("wrong")).await.0, 401);
+    }
+
+    let (status, metrics) = server.get("/metrics", Some(ADMIN_TOKEN)).await;
+    assert_eq!(status, 200);
+    assert!(metrics.contains("chat_sessions 1\n"), "{}", metrics);
+    assert!(metrics.contains("name=\"alice\"} 0\n"), "{}", metrics);
+
+    server.stop().await;
+}
@@ -0,0 +1,370 @@
+use std::time::Duration;
+
+use chat_client::{ChatClient, Event};
+use futures_util::SinkExt;
+use proto::PresenceKind;
+use tokio_tungstenite::tungstenite::Message;
+
+use ws_server::Config;
+
+mod support;
+
+use support::proxy::Proxy;
+use support::{closed_by_server, TestServer};
+
+#[actix_web::test]
+async fn login_is_announced_to_everyone() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let _bob = server.login("bob").await;
+
+    // the notice goes to every session and the presence through the room,
+    // the two may arrive in either order
+    let (mut notice, mut from) = (None, None);
+    while notice.is_none() || from.is_none() {
+        let event = alice
+            .expect("login notice and rename presence", |event| match &event {
+                Event::Notice { .. } => Some(event),
+                Event::Presence(p) if p.room == "main" && p.member.name == "bob" => Some(event),
+                _ => None,
+            })
+            .await;
+        match event {
+            Event::Notice { message } => notice = Some(message),
+            Event::Presence(p) => match p.kind {
+                PresenceKind::Renamed { from: old } => from = Some(old),
+                kind => panic!("unexpected presence: {:?}", kind),
+            },
+            _ => unreachable!(),
+        }
+    }
+    let (notice, from) = (notice.unwrap(), from.unwrap());
+    assert!(notice.ends_with("set name to bob"), "{}", notice);
+    assert!(from.starts_with("ID_"), "{}", from);
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn rename_is_broadcast_to_shared_rooms() {
+    let server = TestServer::start();
+    let alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+
+    alice.client.join("lobby").await.unwrap();
+    bob.client.join("lobby").await.unwrap();
+    bob.expect("alice in lobby", |event| match event {
+        Event::Presence(p) if p.room == "lobby" && p.kind == PresenceKind::Joined => Some(()),
+        Event::Roster(r) if r.room == "lobby" && r.members.len() == 2 => Some(()),
+        _ => None,
+    })
+    .await;
+
+    alice.client.login("alicia").await.unwrap();
+    let renamed = PresenceKind::Renamed {
+        from: "alice".into(),
+    };
+    let mut notice = None;
+    let mut renamed_in = Vec::new();
+    while notice.is_none() || renamed_in.len() < 2 {
+        let event = bob
+            .expect("rename notice and presences", |event| match &event {
+                Event::Notice { .. } => Some(event),
+                Event::Presence(p) if p.kind == renamed => Some(event),
+                _ => None,
+            })
+            .await;
+        match event {
+            Event::Notice { message } => notice = Some(message),
+            Event::Presence(p) => {
+                assert_eq!(p.member.name, "alicia");
+                renamed_in.push(p.room);
+            }
+            _ => unreachable!(),
+        }
+    }
+    let notice = notice.unwrap();
+    assert!(
+        notice.ends_with("changed name from alice to alicia"),
+        "{}",
+        notice
+    );
+
+    renamed_in.sort();
+    assert_eq!(renamed_in, ["lobby", "main"]);
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn names_are_held_by_one_session() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let mut mallory = server.connect().await;
+
+    mallory.client.login("alice").await.unwrap();
+    assert_eq!(mallory.expect_error().await, "name taken: alice");
+    mallory.client.login("ID_1").await.unwrap();
+    assert_eq!(mallory.expect_error().await, "invalid name: ID_1");
+
+    // a rename lets go of the old name
+    alice.client.login("alicia").await.unwrap();
+    alice
+        .expect("own login notice", |event| match event {
+            Event::Notice { message } if message.ends_with(" to alicia") => Some(()),
+            _ => None,
+        })
+        .await;
+    mallory.client.login("alice").await.unwrap();
+    mallory
+        .expect("own login notice", |event| match event {
+            Event::Notice { message } if message.ends_with(" to alice") => Some(()),
+            _ => None,
+        })
+        .await;
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn chat_fans_out_to_room_members_only() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+    let mut carol = server.login("carol").await;
+
+    for client in [&mut alice, &mut bob] {
+        client.client.join("lobby").await.unwrap();
+        client.expect_roster("lobby").await;
+    }
+
+    alice
+        .client
+        .send_chat("lobby", "hello lobby")
+        .await
+        .unwrap();
+    for client in [&mut alice, &mut bob] {
+        let chat = client
+            .expect("lobby chat", |event| match event {
+                Event::Chat(chat) => Some(chat),
+                _ => None,
+            })
+            .await;
+        assert_eq!(chat.room, "lobby");
+        assert_eq!(chat.from, "alice");
+        assert_eq!(chat.body, "hello lobby");
+    }
+    carol
+        .expect_none(Duration::from_millis(300), |event| {
+            matches!(event, Event::Chat(_))
+        })
+        .await;
+
+    // the main room reaches everyone, under any spelling of its name
+    bob.client.send_chat(" #main", "hello all").await.unwrap();
+    for client in [&mut alice, &mut bob, &mut carol] {
+        client
+            .expect("main chat", |event| match event {
+                Event::Chat(chat) if chat.room == "main" && chat.body == "hello all" => Some(()),
+                _ => None,
+            })
+            .await;
+    }
+
+    // chatting in a room you are not in is refused
+    carol.client.send_chat("lobby", "let me in").await.unwrap();
+    carol
+        .expect("not a member error", |event| match event {
+            Event::Error(_) => Some(()),
+            _ => None,
+        })
+        .await;
+
+    // after leaving, the room's broadcasts stop
+    bob.client.leave("lobby").await.unwrap();
+    alice
+        .expect("bob leaving", |event| match event {
+            Event::Presence(p) if p.room == "lobby" && p.kind == PresenceKind::Left => Some(()),
+            _ => None,
+        })
+        .await;
+    alice
+        .client
+        .send_chat("lobby", "bob is gone")
+        .await
+        .unwrap();
+    alice
+        .expect("own chat", |event| match event {
+            Event::Chat(chat) if chat.body == "bob is gone" => Some(()),
+            _ => None,
+        })
+        .await;
+    bob.expect_none(
+        Duration::from_millis(300),
+        |event| matches!(event, Event::Chat(chat) if chat.room == "lobby"),
+    )
+    .await;
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn disconnect_leaves_all_rooms() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+
+    bob.client.join("lobby").await.unwrap();
+    bob.expect_roster("lobby").await;
+    alice.client.join("lobby").await.unwrap();
+    alice.expect_roster("lobby").await;
+
+    bob.client.close().await;
+    let mut left = Vec::new();
+    while left.len() < 2 {
+        let room = alice
+            .expect("bob leaving", |event| match event {
+                Event::Presence(p) if p.kind == PresenceKind::Left => {
+                    assert_eq!(p.member.name, "bob");
+                    Some(p.room)
+                }
+                _ => None,
+            })
+            .await;
+        left.push(room);
+    }
+    left.sort();
+    assert_eq!(left, ["lobby", "main"]);
+
+    // the emptied room is gone, a new joiner finds only themselves
+    alice.client.close().await;
+    let mut carol = server.login("carol").await;
+    carol.client.join("lobby").await.unwrap();
+    let members = carol.expect_roster("lobby").await.members;
+    let names: Vec<&str> = members.iter().map(|m| m.name.as_str()).collect();
+    assert_eq!(names, ["carol"]);
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn silent_client_times_out() {
+    let server = TestServer::with_config(Config {
+        heartbeat_interval: Duration::from_millis(100),
+        client_timeout: Duration::from_millis(300),
+        ..Config::default()
+    });
+    let mut options = server.options();
+    options.heartbeat_interval = Duration::from_millis(50);
+    let (client, events) = ChatClient::connect(options).await.unwrap();
+    let mut alice = support::TestClient { client, events };
+
+    // a peer that never pings
+    let mut silent = server.raw().await;
+    let silent_id = alice
+        .expect("silent peer joining", |event| match event {
+            Event::Presence(p) if p.kind == PresenceKind::Joined => Some(p.member.id),
+            _ => None,
+        })
+        .await;
+
+    let left_id = alice
+        .expect("silent peer dropped", |event| match event {
+            Event::Presence(p) if p.kind == PresenceKind::Left => Some(p.member.id),
+            _ => None,
+        })
+        .await;
+    assert_eq!(left_id, silent_id);
+    assert!(closed_by_server(&mut silent).await);
+
+    // a pinging client survives the same timeout
+    assert!(alice.client.is_connected());
+    alice.client.send_chat("main", "still here").await.unwrap();
+    alice
+        .expect("own chat", |event| match event {
+            Event::Chat(chat) => Some(()).filter(|_| chat.body == "still here"),
+            _ => None,
+        })
+        .await;
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn malformed_frames_close_only_the_sender() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+
+    let frames = [
+        vec![],
+        // chat type byte followed by invalid utf-8
+        vec![2, 0xff, 0xfe],
+        // unknown type byte
+        vec![200, b'x'],
+    ];
+    for frame in frames {
+        let mut socket = server.raw().await;
+        socket.send(Message::Binary(frame.clone())).await.unwrap();
+        assert!(
+            closed_by_server(&mut socket).await,
+            "{:?} should close the session",
+            frame
+        );
+    }
+
+    alice.client.send_chat("main", "still up").await.unwrap();
+    alice
+        .expect("own chat", |event| match event {
+            Event::Chat(chat) => Some(()).filter(|_| chat.body == "still up"),
+            _ => None,
+        })
+        .await;
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn handshake_requires_the_token() {
+    let server = TestServer::with_config(Config {
+        auth_token: Some("secret".to_owned()),
+        ..Config::default()
+    });
+
+    let refused = ChatClient::connect(server.options()).await;
+    assert!(matches!(refused, Err(chat_client::Error::Unauthorized)));
+
+    let mut options = server.options();
+    options.token = Some("secret".to_owned());
+    assert!(ChatClient::connect(options).await.is_ok());
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn reconnect_takes_over_the_name_of_the_lost_connection() {
+    let server = TestServer::start();
+    let proxy = Proxy::start(&server.url).await;
+    let mut alice = server.login_through(&proxy, "alice").await;
+    let mut bob = server.login("bob").await;
+
+    // the server still has the old connection when the client is back
+    proxy.cut().await;
+    alice
+        .expect("reconnect", |event| match event {
+            Event::Connected => Some(()),
+            _ => None,
+        })
+        .await;
+    alice.expect_login("alice").await;
+    let roster = bob.fetch_roster("main").await;
+    let mut names: Vec<&str> = roster.members.iter().map(|m| m.name.as_str()).collect();
+    names.sort();
+    assert_eq!(names, ["alice", "bob"]);
+
+    // other clients still can't take the name
+    let mut mallory = server.connect().await;
+    mallory.client.login("alice").await.unwrap();
+    assert_eq!(mallory.expect_error().await, "name taken: alice");
+
+    server.stop().await;
+}
@@ -0,0 +1,299 @@
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use chat_client::Event;
+use proto::{ChatPacket, ChatPacketType, PresenceKind, Visibility};
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpListener;
+use tokio::sync::mpsc;
+
+use ws_server::backplane::{Backplane, Envelope, InProcess, NodeEvent, BACKPLANE_QUEUE};
+use ws_server::Config;
+
+mod support;
+
+use support::redis::FakeRedis;
+use support::{TestClient, TestServer, TIMEOUT};
+
+/// Ask for the roster until it lists exactly `names`, presences cross nodes asynchronously
+async fn wait_for_roster(client: &mut TestClient, room: &str, names: &[&str]) {
+    let started = Instant::now();
+    loop {
+        client.client.request_roster(room).await.unwrap();
+        let members = client
+            .expect("roster", |event| match event {
+                Event::Roster(r) if r.room == room => Some(r.members),
+                _ => None,
+            })
+            .await;
+        let mut found: Vec<&str> = members.iter().map(|m| m.name.as_str()).collect();
+        found.sort();
+        if found == names {
+            return;
+        }
+        assert!(
+            started.elapsed() < TIMEOUT,
+            "roster of {}: {:?}",
+            room,
+            found
+        );
+        tokio::time::sleep(Duration::from_millis(50)).await;
+    }
+}
+
+#[actix_web::test]
+async fn rooms_presence_and_direct_messages_span_nodes() {
+    let bus = InProcess::new();
+    let a = TestServer::with_backplane(Arc::new(bus.clone()));
+    let b = TestServer::with_backplane(Arc::new(bus.clone()));
+
+    let mut alice = a.login("alice").await;
+    let mut bob = b.login("bob").await;
+    wait_for_roster(&mut bob, "main", &["alice", "bob"]).await;
+    wait_for_roster(&mut alice, "main", &["alice", "bob"]).await;
+
+    alice
+        .client
+        .send_chat("main", "hello from a")
+        .await
+        .unwrap();
+    let chat = bob
+        .expect("chat from the other node", |event| match event {
+            Event::Chat(chat) => Some(chat),
+            _ => None,
+        })
+        .await;
+    assert_eq!(
+        (chat.from.as_str(), chat.body.as_str()),
+        ("alice", "hello from a")
+    );
+
+    // rooms are created on each node as members join
+    bob.client.join("lobby").await.unwrap();
+    alice.client.join("lobby").await.unwrap();
+    bob.expect("alice joining the lobby", |event| match event {
+        Event::Presence(p) if p.room == "lobby" && p.kind == PresenceKind::Joined => {
+            Some(()).filter(|_| p.member.name == "alice")
+        }
+        _ => None,
+    })
+    .await;
+    alice.client.send_chat("lobby", "lobby too").await.unwrap();
+    bob.expect("lobby chat", |event| match event {
+        Event::Chat(chat) if chat.room == "lobby" => Some(()),
+        _ => None,
+    })
+    .await;
+
+    bob.client.send_dm("alice", "psst").await.unwrap();
+    let direct = alice
+        .expect("direct message from the other node", |event| match event {
+            Event::Direct(direct) => Some(direct),
+            _ => None,
+        })
+        .await;
+    assert_eq!(
+        (direct.from.as_str(), direct.body.as_str()),
+        ("bob", "psst")
+    );
+
+    alice.client.close().await;
+    let mut left = Vec::new();
+    while left.len() < 2 {
+        let room = bob
+            .expect("alice leaving", |event| match event {
+                Event::Presence(p) if p.kind == PresenceKind::Left => Some(p.room),
+                _ => None,
+            })
+            .await;
+        left.push(room);
+    }
+    left.sort();
+    assert_eq!(left, ["lobby", "main"]);
+
+    a.stop().await;
+    b.stop().await;
+}
+
+#[actix_web::test]
+async fn rooms_of_other_nodes_let_in_the_same_sessions() {
+    let bus = InProcess::new();
+    let a = TestServer::with_backplane(Arc::new(bus.clone()));
+    let b = TestServer::with_backplane(Arc::new(bus.clone()));
+    let mut alice = a.login("alice").await;
+    let mut bob = b.login("bob").await;
+    wait_for_roster(&mut bob, "main", &["alice", "bob"]).await;
+    let (tx, mut envelopes) = mpsc::channel(BACKPLANE_QUEUE);
+    bus.subscribe(tx);
+
+    alice.client.join("vault").await.unwrap();
+    alice.expect_roster("vault").await;
+    let settings = proto::RoomSettings::default();
+    alice
+        .client
+        .set_room_password("vault", &settings, "s3cret")
+        .await
+        .unwrap();
+    alice
+        .expect("room info", |event| match event {
+            Event::RoomInfo(info) if info.settings.visibility == Visibility::Password => Some(()),
+            _ => None,
+        })
+        .await;
+
+    // only a salted hash of the password goes to the other nodes
+    let mut admitted = false;
+    while let Ok(envelope) = envelopes.try_recv() {
+        assert!(!serde_json::to_string(&envelope).unwrap().contains("s3cret"));
+        if let NodeEvent::Admission(admissions) = envelope.event {
+            admitted |= admissions
+                .iter()
+                .any(|(room, admission)| room == "vault" && admission.password.is_some());
+        }
+    }
+    assert!(admitted);
+
+    bob.client.join("vault").await.unwrap();
+    assert_eq!(
+        bob.expect_error().await,
+        "cannot join #vault: a password is required"
+    );
+    bob.client
+        .join_with_password("vault", "s3cre")
+        .await
+        .unwrap();
+    assert_eq!(
+        bob.expect_error().await,
+        "cannot join #vault: wrong password"
+    );
+    bob.client
+        .join_with_password("vault", "s3cret")
+        .await
+        .unwrap();
+    bob.expect_roster("vault").await;
+    // the copy on bob's node is run by the operators of the original
+    bob.client
+        .set_topic("vault", Some("mine now"), None)
+        .await
+        .unwrap();
+    assert_eq!(bob.expect_error().await, "only operators may change #vault");
+
+    a.stop().await;
+    b.stop().await;
+}
+
+#[actix_web::test]
+async fn duplicate_envelopes_are_delivered_once() {
+    let bus = InProcess::new();
+    let server = TestServer::with_backplane(Arc::new(bus.clone()));
+    let mut bob = server.login("bob").await;
+
+    let message = proto::ChatMessage {
+        room: "main".into(),
+        from: "carol".into(),
+        body: "once".into(),
+        time: String::new(),
+        id: 1,
+        nonce: None,
+        seq: 0,
+        edited: false,
+        deleted: false,
+        reply_to: None,
+        reactions: Vec::new(),
+        mentions: Vec::new(),
+    };
+    let pkg = ChatPacket::with_payload(ChatPacketType::Chat, &message);
+    let envelope = Envelope {
+        id: 42,
+        node: 7,
+        event: NodeEvent::Room {
+            room: "main".into(),
+            packet: (&pkg).into(),
+        },
+    };
+    bus.publish(envelope.clone());
+    // a chat published again in another envelope is the same chat
+    bus.publish(Envelope {
+        id: 43,
+        ..envelope.clone()
+    });
+    bus.publish(envelope);
+
+    bob.expect("relayed chat", |event| match event {
+        Event::Chat(chat) if chat.body == "once" => Some(()),
+        _ => None,
+    })
+    .await;
+    bob.expect_none(Duration::from_millis(300), |event| {
+        matches!(event, Event::Chat(_))
+    })
+    .await;
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn redis_backplane_links_nodes() {
+    let redis = FakeRedis::start().await;
+    let config = Config {
+        backplane_url: Some(redis.url.clone()),
+        ..Config::default()
+    };
+    let a = TestServer::with_config(config.clone());
+    let b = TestServer::with_config(config);
+
+    let started = Instant::now();
+    while redis.subscriber_count() < 2 {
+        assert!(started.elapsed() < TIMEOUT, "nodes never subscribed");
+        tokio::time::sleep(Duration::from_millis(20)).await;
+    }
+
+    let alice = a.login("alice").await;
+    let mut bob = b.login("bob").await;
+    wait_for_roster(&mut bob, "main", &["alice", "bob"]).await;
+
+    alice.client.send_chat("main", "over redis").await.unwrap();
+    bob.expect("chat through redis", |event| match event {
+        Event::Chat(chat) => Some(()).filter(|_| chat.body == "over redis"),
+        _ => None,
+    })
+    .await;
+
+    a.stop().await;
+    b.stop().await;
+}
+
+#[actix_web::test]
+async fn redis_backplane_refuses_oversized_pushes() {
+    let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+    let config = Config {
+        backplane_url: Some(format!("redis://{}", listener.local_addr().unwrap())),
+        ..Config::default()
+    };
+    let server = TestServer::with_config(config);
+
+    // answer each subscription with a push nobody should allocate for, the
+    // subscriber gives up on the connection and makes a new one
+    let oversized: [&[u8]; 2] = [b"*1000000000\r\n", b"*3\r\n$2000000000\r\n"];
+    let mut subscriptions = 0;
+    while subscriptions <= oversized.len() {
+        let (mut stream, _) = tokio::time::timeout(TIMEOUT, listener.accept())
+            .await
+            .expect("no new subscription")
+            .unwrap();
+        let mut buf = [0; 256];
+        let read = stream.read(&mut buf).await.unwrap();
+        if !buf[..read].windows(9).any(|w| w == b"SUBSCRIBE") {
+            // the publisher, kept open
+            tokio::spawn(async move { while let Ok(1..) = stream.read(&mut buf).await {} });
+            continue;
+        }
+        if let Some(push) = oversized.get(subscriptions) {
+            stream.write_all(push).await.unwrap();
+        }
+        subscriptions += 1;
+        tokio::spawn(async move { while let Ok(1..) = stream.read(&mut buf).await {} });
+    }
+
+    server.stop().await;
+}
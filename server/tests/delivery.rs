@@ -0,0 +1,256 @@
+use std::time::Duration;
+
+use chat_client::Event;
+use futures_util::SinkExt;
+use proto::{ChatPacket, ChatPacketType};
+use tokio_tungstenite::tungstenite::Message;
+use ws_server::Config;
+
+mod support;
+
+use support::proxy::Proxy;
+use support::{next_packet, RawSocket, TestClient, TestServer};
+
+async fn expect_ack(client: &mut TestClient, nonce: &str) -> proto::Ack {
+    client
+        .expect("ack", |event| match event {
+            Event::Ack(ack) if ack.nonce == nonce => Some(ack),
+            _ => None,
+        })
+        .await
+}
+
+/// Sequence number in the payload of a room broadcast
+fn seq(packet: &ChatPacket) -> u64 {
+    let payload: serde_json::Value = packet.payload().unwrap();
+    payload["seq"].as_u64().unwrap_or_default()
+}
+
+#[actix_web::test]
+async fn accepted_chat_is_acked_with_its_id() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+
+    let nonce = alice.client.send_chat("main", "hello").await.unwrap();
+    let ack = expect_ack(&mut alice, &nonce).await;
+    let chat = bob
+        .expect("chat", |event| match event {
+            Event::Chat(chat) => Some(chat),
+            _ => None,
+        })
+        .await;
+    assert_eq!(chat.id, ack.message_id);
+    assert_eq!(chat.time, ack.time);
+    assert_eq!(chat.nonce.as_deref(), Some(nonce.as_str()));
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn retried_chat_is_delivered_once() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+
+    let chat = proto::ChatSend {
+        room: "main".to_owned(),
+        body: "only once".to_owned(),
+        nonce: Some("retry-1".to_owned()),
+        reply_to: None,
+    };
+    let packet = ChatPacket::with_payload(ChatPacketType::Chat, &chat);
+    alice.client.send_packet(packet.clone()).await.unwrap();
+    let first = expect_ack(&mut alice, "retry-1").await;
+    alice.client.send_packet(packet).await.unwrap();
+    let second = expect_ack(&mut alice, "retry-1").await;
+    assert_eq!(first, second);
+
+    bob.expect("chat", |event| match event {
+        Event::Chat(chat) if chat.body == "only once" => Some(()),
+        _ => None,
+    })
+    .await;
+    bob.expect_none(Duration::from_millis(300), |event| {
+        matches!(event, Event::Chat(_))
+    })
+    .await;
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn chat_retried_after_a_reconnect_is_delivered_once() {
+    let server = TestServer::start();
+    let proxy = Proxy::start(&server.url).await;
+    // without a name the sender goes by another `ID_` after reconnecting
+    let mut anon = server.connect_through(&proxy).await;
+    let mut bob = server.login("bob").await;
+
+    // the chat goes out but its ack is lost with the connection
+    proxy.mute(true);
+    let nonce = anon.client.send_chat("main", "only once").await.unwrap();
+    let chat = bob
+        .expect("chat", |event| match event {
+            Event::Chat(chat) if chat.body == "only once" => Some(chat),
+            _ => None,
+        })
+        .await;
+    proxy.cut().await;
+
+    // the client sends it again once reconnected and gets the same ack
+    let ack = expect_ack(&mut anon, &nonce).await;
+    assert_eq!(ack.message_id, chat.id);
+    bob.expect_none(Duration::from_millis(300), |event| {
+        matches!(event, Event::Chat(_))
+    })
+    .await;
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn packets_sent_while_reconnecting_go_out_once_connected() {
+    let server = TestServer::start();
+    let proxy = Proxy::start(&server.url).await;
+    let mut alice = server.login_through(&proxy, "alice").await;
+    let mut bob = server.login("bob").await;
+
+    proxy.cut().await;
+    let reconnecting = alice
+        .expect("disconnect", |event| match event {
+            Event::Disconnected { reconnecting, .. } => Some(reconnecting),
+            _ => None,
+        })
+        .await;
+    assert!(reconnecting);
+    let nonce = alice.client.send_chat("main", "held chat").await.unwrap();
+    alice.client.send_dm("bob", "held dm").await.unwrap();
+
+    expect_ack(&mut alice, &nonce).await;
+    bob.expect("chat", |event| match event {
+        Event::Chat(chat) if chat.body == "held chat" => Some(()),
+        _ => None,
+    })
+    .await;
+    bob.expect("direct message", |event| match event {
+        Event::Direct(direct) if direct.body == "held dm" => Some(()),
+        _ => None,
+    })
+    .await;
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn rejected_chat_names_its_nonce() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+
+    let nonce = alice.client.send_chat("lobby", "anyone?").await.unwrap();
+    let error = alice
+        .expect("error", |event| match event {
+            Event::Error(error) => Some(error),
+            _ => None,
+        })
+        .await;
+    assert_eq!(error.nonce, Some(nonce));
+    assert!(error.message.contains("lobby"), "{}", error.message);
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn resync_replays_the_room_history() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let mut raw = server.raw().await;
+    for body in ["one", "two", "three"] {
+        let nonce = alice.client.send_chat("main", body).await.unwrap();
+        expect_ack(&mut alice, &nonce).await;
+    }
+
+    let resync = proto::Resync {
+        room: "main".to_owned(),
+        from_seq: 1,
+        until_seq: None,
+    };
+    let packet = ChatPacket::with_payload(ChatPacketType::Resync, &resync);
+    raw.send(Message::Binary(packet.serialize())).await.unwrap();
+    loop {
+        let packet = next_packet(&mut raw).await;
+        if packet.packet_type == ChatPacketType::Resync {
+            let answer = packet.payload::<proto::Resync>().unwrap();
+            assert_eq!((answer.from_seq, answer.until_seq), (1, Some(6)));
+            break;
+        }
+    }
+
+    // alice joining and renaming, the raw peer joining, then the chats
+    let mut replayed = Vec::new();
+    for expected in 1..=6 {
+        let packet = next_packet(&mut raw).await;
+        assert_eq!(seq(&packet), expected, "{:?}", packet);
+        replayed.push(packet);
+    }
+    let bodies: Vec<String> = replayed
+        .iter()
+        .filter(|p| p.packet_type == ChatPacketType::Chat)
+        .map(|p| p.payload::<proto::ChatMessage>().unwrap().body)
+        .collect();
+    assert_eq!(bodies, ["one", "two", "three"]);
+
+    server.stop().await;
+}
+
+/// Ask for the broadcasts of main from `from_seq` on, returns the answer and
+/// the sequence numbers replayed after it
+async fn resync(raw: &mut RawSocket, from_seq: u64, count: usize) -> (proto::Resync, Vec<u64>) {
+    let resync = proto::Resync {
+        room: "main".to_owned(),
+        from_seq,
+        until_seq: None,
+    };
+    let packet = ChatPacket::with_payload(ChatPacketType::Resync, &resync);
+    raw.send(Message::Binary(packet.serialize())).await.unwrap();
+    let answer = loop {
+        let packet = next_packet(raw).await;
+        if packet.packet_type == ChatPacketType::Resync {
+            break packet.payload::<proto::Resync>().unwrap();
+        }
+    };
+    let mut replayed = Vec::new();
+    for _ in 0..count {
+        replayed.push(seq(&next_packet(raw).await));
+    }
+    (answer, replayed)
+}
+
+#[actix_web::test]
+async fn resync_replays_what_fits_the_outbox() {
+    let server = TestServer::with_config(Config {
+        outbox_capacity: 4,
+        ..Config::default()
+    });
+    let mut alice = server.login("alice").await;
+    let mut raw = server.raw().await;
+    for body in ["one", "two", "three"] {
+        let nonce = alice.client.send_chat("main", body).await.unwrap();
+        expect_ack(&mut alice, &nonce).await;
+    }
+    // nothing left queued for the raw peer
+    while seq(&next_packet(&mut raw).await) != 6 {}
+
+    // the answer takes one of the four frames
+    let (answer, replayed) = resync(&mut raw, 1, 3).await;
+    assert_eq!((answer.from_seq, answer.until_seq), (1, Some(3)));
+    assert_eq!(replayed, [1, 2, 3]);
+    // the next page starts after the last one replayed
+    let (answer, replayed) = resync(&mut raw, 4, 3).await;
+    assert_eq!((answer.from_seq, answer.until_seq), (4, Some(6)));
+    assert_eq!(replayed, [4, 5, 6]);
+    let (answer, _) = resync(&mut raw, 7, 0).await;
+    assert_eq!((answer.from_seq, answer.until_seq), (7, None));
+
+    server.stop().await;
+}
@@ -0,0 +1,202 @@
+use std::time::Duration;
+
+use chat_client::Event;
+use futures_util::SinkExt;
+use proto::{ChatPacket, ChatPacketType, PresenceKind};
+use tokio_tungstenite::tungstenite::Message;
+
+use ws_server::Config;
+
+mod support;
+
+use support::proxy::Proxy;
+use support::{next_packet, TestServer};
+
+#[actix_web::test]
+async fn author_edits_and_deletes_a_message() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+
+    let id = alice.post("main", "helo").await;
+    alice.client.edit(id, "hello").await.unwrap();
+    let edited = bob
+        .expect("edit", |event| match event {
+            Event::Edited(edited) => Some(edited),
+            _ => None,
+        })
+        .await;
+    assert_eq!(
+        (
+            edited.message_id,
+            edited.new_body.as_str(),
+            edited.by.as_str()
+        ),
+        (id, "hello", "alice")
+    );
+    // edits take the same bodies as chats
+    alice.client.edit(id, " ").await.unwrap();
+    assert_eq!(alice.expect_error().await, "empty message");
+    alice
+        .client
+        .edit(id, &"o".repeat(48 * 1024 + 1))
+        .await
+        .unwrap();
+    assert_eq!(alice.expect_error().await, "message too long");
+    alice.client.send_chat("main", "").await.unwrap();
+    assert_eq!(alice.expect_error().await, "empty message");
+    // and so do direct messages
+    alice.client.send_dm("bob", "").await.unwrap();
+    assert_eq!(alice.expect_error().await, "empty message");
+    alice
+        .client
+        .send_dm("bob", &"o".repeat(48 * 1024 + 1))
+        .await
+        .unwrap();
+    assert_eq!(alice.expect_error().await, "message too long");
+
+    // the history has the new body for anyone catching up
+    let mut raw = server.raw().await;
+    let resync = proto::Resync {
+        room: "main".to_owned(),
+        from_seq: 1,
+        until_seq: None,
+    };
+    let packet = ChatPacket::with_payload(ChatPacketType::Resync, &resync);
+    raw.send(Message::Binary(packet.serialize())).await.unwrap();
+    let stored = loop {
+        let packet = next_packet(&mut raw).await;
+        if packet.packet_type == ChatPacketType::Chat {
+            break packet.payload::<proto::ChatMessage>().unwrap();
+        }
+    };
+    assert_eq!((stored.body.as_str(), stored.edited), ("hello", true));
+
+    alice.client.delete(id).await.unwrap();
+    let deleted = bob
+        .expect("deletion", |event| match event {
+            Event::Deleted(deleted) => Some(deleted),
+            _ => None,
+        })
+        .await;
+    assert_eq!(deleted.message_id, id);
+
+    alice.client.edit(id, "too late").await.unwrap();
+    assert_eq!(alice.expect_error().await, "message was deleted");
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn only_the_author_or_an_operator_may_change_a_message() {
+    let server = TestServer::start();
+    let mut carol = server.login("carol").await;
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+
+    // carol creates the room and operates it
+    carol.client.join("lobby").await.unwrap();
+    carol.expect_roster("lobby").await;
+    for client in [&mut alice, &mut bob] {
+        client.client.join("lobby").await.unwrap();
+    }
+    carol
+        .expect("bob in the lobby", |event| match event {
+            Event::Presence(p) if p.kind == PresenceKind::Joined && p.member.name == "bob" => {
+                Some(())
+            }
+            _ => None,
+        })
+        .await;
+
+    let id = alice.post("lobby", "mine").await;
+    bob.client.delete(id).await.unwrap();
+    assert_eq!(bob.expect_error().await, "not your message");
+
+    // the message stays with the client when its name changes hands
+    alice.client.login("alicia").await.unwrap();
+    alice
+        .expect("own login notice", |event| match event {
+            Event::Notice { message } if message.ends_with(" to alicia") => Some(()),
+            _ => None,
+        })
+        .await;
+    let mut mallory = server.login("alice").await;
+    mallory.client.join("lobby").await.unwrap();
+    mallory.expect_roster("lobby").await;
+    mallory.client.delete(id).await.unwrap();
+    assert_eq!(mallory.expect_error().await, "not your message");
+    alice.client.edit(id, "still mine").await.unwrap();
+    alice
+        .expect("own edit", |event| match event {
+            Event::Edited(edited) => Some(()).filter(|_| edited.message_id == id),
+            _ => None,
+        })
+        .await;
+
+    carol.client.delete(id).await.unwrap();
+    let deleted = alice
+        .expect("deletion", |event| match event {
+            Event::Deleted(deleted) => Some(deleted),
+            _ => None,
+        })
+        .await;
+    assert_eq!(
+        (deleted.room.as_str(), deleted.by.as_str()),
+        ("lobby", "carol")
+    );
+
+    bob.client.edit(7, "nothing").await.unwrap();
+    assert_eq!(bob.expect_error().await, "no such message: 7");
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn author_changes_a_message_after_reconnecting() {
+    let server = TestServer::start();
+    let proxy = Proxy::start(&server.url).await;
+    let mut alice = server.login_through(&proxy, "alice").await;
+
+    let id = alice.post("main", "helo").await;
+    proxy.cut().await;
+    alice
+        .expect("reconnect", |event| match event {
+            Event::Connected => Some(()),
+            _ => None,
+        })
+        .await;
+    alice.expect_login("alice").await;
+
+    alice.client.edit(id, "hello").await.unwrap();
+    alice
+        .expect("own edit", |event| match event {
+            Event::Edited(edited) => Some(()).filter(|_| edited.message_id == id),
+            _ => None,
+        })
+        .await;
+    alice.client.delete(id).await.unwrap();
+    alice
+        .expect("own deletion", |event| match event {
+            Event::Deleted(deleted) => Some(()).filter(|_| deleted.message_id == id),
+            _ => None,
+        })
+        .await;
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn changes_are_refused_after_the_window() {
+    let server = TestServer::with_config(Config {
+        edit_window: Duration::ZERO,
+        ..Config::default()
+    });
+    let mut alice = server.login("alice").await;
+
+    let id = alice.post("main", "for good").await;
+    alice.client.edit(id, "changed").await.unwrap();
+    assert_eq!(alice.expect_error().await, "too late to change the message");
+
+    server.stop().await;
+}
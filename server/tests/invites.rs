@@ -0,0 +1,231 @@
+use std::time::Duration;
+
+use chat_client::Event;
+use proto::{JoinRefusal, Visibility};
+
+mod support;
+
+use support::{TestClient, TestServer};
+
+async fn expect_refusal(client: &mut TestClient) -> (String, JoinRefusal) {
+    client
+        .expect("join refusal", |event| match event {
+            Event::Error(proto::ErrorInfo {
+                detail: Some(proto::ErrorDetail::JoinRefused { room, reason }),
+                ..
+            }) => Some((room, reason)),
+            _ => None,
+        })
+        .await
+}
+
+async fn listed_rooms(client: &mut TestClient) -> Vec<String> {
+    client
+        .expect("room list", |event| match event {
+            Event::Rooms(list) => Some(list.rooms.into_iter().map(|r| r.room).collect()),
+            _ => None,
+        })
+        .await
+}
+
+/// Room created by `client` with the given visibility
+async fn create(client: &mut TestClient, room: &str, visibility: Visibility) {
+    client.client.join(room).await.unwrap();
+    client.expect_roster(room).await;
+    let settings = proto::RoomSettings {
+        visibility,
+        ..proto::RoomSettings::default()
+    };
+    client.client.change_room(room, &settings).await.unwrap();
+    client
+        .expect("room info", |event| match event {
+            Event::RoomInfo(info) if info.settings.visibility == visibility => Some(()),
+            _ => None,
+        })
+        .await;
+}
+
+#[actix_web::test]
+async fn password_rooms_take_the_password() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+    alice.client.join("vault").await.unwrap();
+    alice.expect_roster("vault").await;
+    let settings = proto::RoomSettings::default();
+    alice
+        .client
+        .set_room_password("vault", &settings, "s3cret")
+        .await
+        .unwrap();
+    alice
+        .expect("room info", |event| match event {
+            Event::RoomInfo(info) if info.settings.visibility == Visibility::Password => Some(()),
+            _ => None,
+        })
+        .await;
+
+    bob.client.join("vault").await.unwrap();
+    let (room, reason) = expect_refusal(&mut bob).await;
+    assert_eq!(
+        (room.as_str(), reason),
+        ("vault", JoinRefusal::PasswordRequired)
+    );
+    bob.client
+        .join_with_password("vault", "guess")
+        .await
+        .unwrap();
+    assert_eq!(expect_refusal(&mut bob).await.1, JoinRefusal::WrongPassword);
+    bob.client
+        .join_with_password("vault", "s3cret")
+        .await
+        .unwrap();
+    bob.expect_roster("vault").await;
+
+    // once in, the session is let back in, not whoever takes its old name
+    bob.client.leave("vault").await.unwrap();
+    bob.client.join("vault").await.unwrap();
+    bob.expect_roster("vault").await;
+    bob.client.login("robert").await.unwrap();
+    bob.expect("own login notice", |event| match event {
+        Event::Notice { message } if message.ends_with(" to robert") => Some(()),
+        _ => None,
+    })
+    .await;
+    let mut mallory = server.login("bob").await;
+    mallory.client.join("vault").await.unwrap();
+    assert_eq!(
+        expect_refusal(&mut mallory).await.1,
+        JoinRefusal::PasswordRequired
+    );
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn invite_codes_have_use_limits_and_expire() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+    let mut carol = server.login("carol").await;
+    create(&mut alice, "club", Visibility::InviteOnly).await;
+
+    bob.client.join("club").await.unwrap();
+    assert_eq!(expect_refusal(&mut bob).await.1, JoinRefusal::InviteOnly);
+    alice.client.invite("club", 1, 0).await.unwrap();
+    let invite = alice
+        .expect("invite", |event| match event {
+            Event::Invite(invite) => Some(invite),
+            _ => None,
+        })
+        .await;
+    assert_eq!((invite.room.as_str(), invite.max_uses), ("club", 1));
+
+    bob.client.accept_invite(&invite.code).await.unwrap();
+    let accepted = bob
+        .expect("accepted invite", |event| match event {
+            Event::InviteAccepted(accepted) => Some(accepted),
+            _ => None,
+        })
+        .await;
+    assert_eq!(accepted.room, "club");
+    bob.expect_roster("club").await;
+    // only operators invite to rooms that aren't open
+    bob.client.invite("club", 0, 0).await.unwrap();
+    assert_eq!(
+        bob.expect_error().await,
+        "only operators may invite to #club"
+    );
+
+    carol.client.accept_invite(&invite.code).await.unwrap();
+    let (room, reason) = expect_refusal(&mut carol).await;
+    assert_eq!((room.as_str(), reason), ("", JoinRefusal::InvalidInvite));
+
+    alice.client.invite("club", 0, 1).await.unwrap();
+    let invite = alice
+        .expect("invite", |event| match event {
+            Event::Invite(invite) => Some(invite),
+            _ => None,
+        })
+        .await;
+    tokio::time::sleep(Duration::from_millis(1100)).await;
+    carol.client.accept_invite(&invite.code).await.unwrap();
+    assert_eq!(
+        expect_refusal(&mut carol).await.1,
+        JoinRefusal::InviteExpired
+    );
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn restricted_rooms_outlive_their_members() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+    create(&mut alice, "club", Visibility::InviteOnly).await;
+    alice.client.invite("club", 0, 0).await.unwrap();
+    let invite = alice
+        .expect("invite", |event| match event {
+            Event::Invite(invite) => Some(invite),
+            _ => None,
+        })
+        .await;
+    alice.client.join("vault").await.unwrap();
+    alice.expect_roster("vault").await;
+    let settings = proto::RoomSettings::default();
+    alice
+        .client
+        .set_room_password("vault", &settings, "s3cret")
+        .await
+        .unwrap();
+    alice
+        .expect("room info", |event| match event {
+            Event::RoomInfo(info) if info.settings.visibility == Visibility::Password => Some(()),
+            _ => None,
+        })
+        .await;
+    alice.client.close().await;
+
+    // nobody is left, the rooms still let in only whom they did
+    bob.client.join("club").await.unwrap();
+    assert_eq!(expect_refusal(&mut bob).await.1, JoinRefusal::InviteOnly);
+    bob.client.join("vault").await.unwrap();
+    assert_eq!(
+        expect_refusal(&mut bob).await.1,
+        JoinRefusal::PasswordRequired
+    );
+    bob.client
+        .join_with_password("vault", "s3cret")
+        .await
+        .unwrap();
+    bob.expect_roster("vault").await;
+    bob.client.accept_invite(&invite.code).await.unwrap();
+    bob.expect_roster("club").await;
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn hidden_rooms_are_not_listed() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let mut carol = server.login("carol").await;
+    create(&mut alice, "open", Visibility::Public).await;
+    create(&mut alice, "quiet", Visibility::Unlisted).await;
+    create(&mut alice, "club", Visibility::InviteOnly).await;
+
+    carol.client.list_rooms().await.unwrap();
+    assert_eq!(listed_rooms(&mut carol).await, ["main", "open"]);
+    alice.client.list_rooms().await.unwrap();
+    assert_eq!(
+        listed_rooms(&mut alice).await,
+        ["club", "main", "open", "quiet"]
+    );
+
+    // unlisted rooms are joined by name
+    carol.client.join("quiet").await.unwrap();
+    carol.expect_roster("quiet").await;
+
+    server.stop().await;
+}
@@ -0,0 +1,82 @@
+use chat_client::Event;
+
+mod support;
+
+use support::{TestClient, TestServer};
+
+async fn expect_closed(client: &mut TestClient) -> String {
+    client
+        .expect("room closed", |event| match event {
+            Event::RoomClosed(closed) => Some(closed.room),
+            _ => None,
+        })
+        .await
+}
+
+#[actix_web::test]
+async fn rooms_close_when_their_ttl_runs_out() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+    alice.client.create_room("popup", 1).await.unwrap();
+    let info = alice.expect_info("popup").await;
+    assert!(info.expires_secs.is_some_and(|secs| secs <= 1));
+    // only the join creating the room sets its TTL
+    bob.client.create_room("popup", 60).await.unwrap();
+    bob.expect_info("popup").await;
+
+    assert_eq!(expect_closed(&mut alice).await, "popup");
+    assert_eq!(expect_closed(&mut bob).await, "popup");
+    bob.client.send_chat("popup", "still here?").await.unwrap();
+    assert_eq!(bob.expect_error().await, "not in room: popup");
+
+    // the name is free again
+    bob.client.join("popup").await.unwrap();
+    let info = bob.expect_info("popup").await;
+    assert_eq!(
+        (info.created_by.as_deref(), info.expires_secs),
+        (Some("bob"), None)
+    );
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn chats_expire_after_the_retention() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+    alice.client.join("rust").await.unwrap();
+    alice.expect_info("rust").await;
+    bob.client.join("rust").await.unwrap();
+    bob.expect_info("rust").await;
+
+    let settings = proto::RoomSettings {
+        retention_secs: 1,
+        ..proto::RoomSettings::default()
+    };
+    alice.client.change_room("rust", &settings).await.unwrap();
+    bob.expect_info("rust").await;
+    let id = alice.post("rust", "soon gone").await;
+
+    let expired = bob
+        .expect("expired", |event| match event {
+            Event::Expired(expired) => Some(expired),
+            _ => None,
+        })
+        .await;
+    assert_eq!(
+        (expired.room.as_str(), expired.message_ids),
+        ("rust", vec![id])
+    );
+    bob.client
+        .reply("rust", id, "what was that?")
+        .await
+        .unwrap();
+    assert_eq!(
+        bob.expect_error().await,
+        format!("no message {} in rust to reply to", id)
+    );
+
+    server.stop().await;
+}
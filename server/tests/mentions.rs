@@ -0,0 +1,65 @@
+use chat_client::Event;
+use futures_util::SinkExt;
+use proto::{ChatPacket, ChatPacketType};
+use tokio_tungstenite::tungstenite::Message;
+
+mod support;
+
+use support::{next_packet, TestServer};
+
+#[actix_web::test]
+async fn mentions_are_listed_with_the_chat() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+
+    alice
+        .post(
+            "main",
+            "@bob, ask @carol.smith or @bob. (mail me@example.com)",
+        )
+        .await;
+    let chat = bob
+        .expect("chat", |event| match event {
+            Event::Chat(chat) => Some(chat),
+            _ => None,
+        })
+        .await;
+    assert_eq!(chat.mentions, ["bob", "carol.smith"]);
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn edits_change_the_mentions_in_the_history() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let mut raw = server.raw().await;
+    let id = alice.post("main", "ping @bob").await;
+    alice.client.edit(id, "ping @carol").await.unwrap();
+    alice
+        .expect("edit", |event| match event {
+            Event::Edited(edited) => Some(edited),
+            _ => None,
+        })
+        .await;
+
+    let resync = proto::Resync {
+        room: "main".to_owned(),
+        from_seq: 1,
+        until_seq: None,
+    };
+    let packet = ChatPacket::with_payload(ChatPacketType::Resync, &resync);
+    raw.send(Message::Binary(packet.serialize())).await.unwrap();
+    // the live broadcasts come first
+    while next_packet(&mut raw).await.packet_type != ChatPacketType::Resync {}
+    let chat = loop {
+        let packet = next_packet(&mut raw).await;
+        if packet.packet_type == ChatPacketType::Chat {
+            break packet.payload::<proto::ChatMessage>().unwrap();
+        }
+    };
+    assert_eq!(chat.mentions, ["carol"]);
+
+    server.stop().await;
+}
@@ -0,0 +1,111 @@
+use std::time::Duration;
+
+use chat_client::Event;
+use futures_util::SinkExt;
+use proto::{ChatPacket, ChatPacketType};
+use tokio_tungstenite::tungstenite::Message;
+
+mod support;
+
+use support::{next_packet, TestClient, TestServer};
+
+async fn expect_reaction(client: &mut TestClient) -> (bool, proto::Reacted) {
+    client
+        .expect("reaction", |event| match event {
+            Event::Reacted(reacted) => Some((true, reacted)),
+            Event::Unreacted(reacted) => Some((false, reacted)),
+            _ => None,
+        })
+        .await
+}
+
+#[actix_web::test]
+async fn reactions_are_broadcast_as_deltas() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let bob = server.login("bob").await;
+
+    let id = alice.post("main", "deploying now").await;
+    bob.client.react(id, "👍").await.unwrap();
+    let (added, reacted) = expect_reaction(&mut alice).await;
+    assert!(added);
+    assert_eq!(
+        (
+            reacted.message_id,
+            reacted.emoji.as_str(),
+            reacted.by.as_str()
+        ),
+        (id, "👍", "bob")
+    );
+
+    // reacting twice changes nothing and is not broadcast
+    bob.client.react(id, "👍").await.unwrap();
+    alice
+        .expect_none(Duration::from_millis(300), |event| {
+            matches!(event, Event::Reacted(_))
+        })
+        .await;
+
+    bob.client.unreact(id, "👍").await.unwrap();
+    let (added, reacted) = expect_reaction(&mut alice).await;
+    assert!(!added);
+    assert_eq!(reacted.by, "bob");
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn reactions_are_kept_with_the_history() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+
+    let id = alice.post("main", "lunch?").await;
+    for (client, emoji) in [(&bob, "👍"), (&alice, "👍"), (&bob, "🎉")] {
+        client.client.react(id, emoji).await.unwrap();
+    }
+    for _ in 0..3 {
+        expect_reaction(&mut bob).await;
+    }
+
+    let mut raw = server.raw().await;
+    let resync = proto::Resync {
+        room: "main".to_owned(),
+        from_seq: 1,
+        until_seq: None,
+    };
+    let packet = ChatPacket::with_payload(ChatPacketType::Resync, &resync);
+    raw.send(Message::Binary(packet.serialize())).await.unwrap();
+    let stored = loop {
+        let packet = next_packet(&mut raw).await;
+        if packet.packet_type == ChatPacketType::Chat {
+            break packet.payload::<proto::ChatMessage>().unwrap();
+        }
+    };
+    let counts: Vec<(&str, Vec<&str>)> = stored
+        .reactions
+        .iter()
+        .map(|r| (r.emoji.as_str(), r.by.iter().map(String::as_str).collect()))
+        .collect();
+    assert_eq!(counts, [("👍", vec!["bob", "alice"]), ("🎉", vec!["bob"])]);
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn invalid_reactions_are_rejected() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+
+    let id = alice.post("main", "hi").await;
+    alice.client.react(id, "thumbs up").await.unwrap();
+    assert_eq!(alice.expect_error().await, "invalid reaction: thumbs up");
+
+    alice.client.react(id.wrapping_add(1), "👍").await.unwrap();
+    assert_eq!(
+        alice.expect_error().await,
+        format!("no such message: {}", id.wrapping_add(1))
+    );
+
+    server.stop().await;
+}
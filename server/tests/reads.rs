@@ -0,0 +1,69 @@
+use chat_client::Event;
+use proto::PresenceKind;
+
+mod support;
+
+use support::{TestClient, TestServer};
+
+async fn expect_read(client: &mut TestClient) -> proto::MarkRead {
+    client
+        .expect("read position", |event| match event {
+            Event::Read(read) => Some(read),
+            _ => None,
+        })
+        .await
+}
+
+/// New session taking over the name of `client` once the server let go of it
+async fn log_in_again(server: &TestServer, client: TestClient, name: &str) -> TestClient {
+    let mut watcher = server.connect().await;
+    client.client.close().await;
+    watcher
+        .expect("old session leaving", |event| match event {
+            Event::Presence(p) if p.kind == PresenceKind::Left => Some(()),
+            _ => None,
+        })
+        .await;
+    let later = server.connect().await;
+    later.client.login(name).await.unwrap();
+    later
+}
+
+#[actix_web::test]
+async fn stored_position_is_sent_after_login_and_join() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    alice.client.join("rust").await.unwrap();
+    let main = alice.post("main", "one").await;
+    let rust = alice.post("rust", "two").await;
+    alice.client.mark_read("main", main).await.unwrap();
+    alice.client.mark_read("rust", rust).await.unwrap();
+
+    let mut later = log_in_again(&server, alice, "alice").await;
+    let read = expect_read(&mut later).await;
+    assert_eq!((read.room.as_str(), read.message_id), ("main", main));
+    later.client.join("rust").await.unwrap();
+    let read = expect_read(&mut later).await;
+    assert_eq!((read.room.as_str(), read.message_id), ("rust", rust));
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn read_position_does_not_move_back() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let first = alice.post("main", "first").await;
+    let second = alice.post("main", "second").await;
+
+    alice.client.mark_read("main", second).await.unwrap();
+    alice.client.mark_read("main", first).await.unwrap();
+    let mut later = log_in_again(&server, alice, "alice").await;
+    assert_eq!(expect_read(&mut later).await.message_id, second);
+
+    let mut anonymous = server.connect().await;
+    anonymous.client.mark_read("main", first).await.unwrap();
+    assert!(anonymous.expect_error().await.contains("log in"));
+
+    server.stop().await;
+}
@@ -0,0 +1,245 @@
+use chat_client::Event;
+
+use ws_server::Config;
+
+mod support;
+
+use support::proxy::Proxy;
+use support::TestServer;
+
+#[actix_web::test]
+async fn topic_changes_reach_the_room() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+    alice.client.join("rust").await.unwrap();
+    let info = alice.expect_info("rust").await;
+    assert_eq!(info.created_by.as_deref(), Some("alice"));
+    bob.client.join("rust").await.unwrap();
+    bob.expect_info("rust").await;
+
+    alice
+        .client
+        .set_topic("rust", Some(" borrow checking "), Some("all things rust"))
+        .await
+        .unwrap();
+    let info = bob.expect_info("rust").await;
+    assert_eq!(
+        (info.topic.as_str(), info.description.as_str()),
+        ("borrow checking", "all things rust")
+    );
+    bob.client
+        .set_topic("rust", Some("mine now"), None)
+        .await
+        .unwrap();
+    assert_eq!(bob.expect_error().await, "only operators may change #rust");
+
+    let mut carol = server.login("carol").await;
+    carol.client.join("rust").await.unwrap();
+    assert_eq!(carol.expect_info("rust").await.topic, "borrow checking");
+    carol.client.room_info("rust").await.unwrap();
+    let info = carol.expect_info("rust").await;
+    assert_eq!(info.description, "all things rust");
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn operators_keep_their_rooms_across_reconnects() {
+    let server = TestServer::start();
+    let proxy = Proxy::start(&server.url).await;
+    let mut alice = server.login_through(&proxy, "alice").await;
+    alice.client.join("rust").await.unwrap();
+    alice.expect_info("rust").await;
+    alice
+        .client
+        .set_topic("rust", Some("borrow checking"), None)
+        .await
+        .unwrap();
+    alice.expect_info("rust").await;
+
+    // the room empties when the lost connection is replaced
+    proxy.cut().await;
+    let info = alice.expect_info("rust").await;
+    assert_eq!(
+        (info.topic.as_str(), info.created_by.as_deref()),
+        ("borrow checking", Some("alice"))
+    );
+    alice
+        .client
+        .set_topic("rust", Some("lifetimes"), None)
+        .await
+        .unwrap();
+    assert_eq!(alice.expect_info("rust").await.topic, "lifetimes");
+
+    let mut bob = server.login("bob").await;
+    bob.client.join("rust").await.unwrap();
+    assert_eq!(bob.expect_info("rust").await.topic, "lifetimes");
+    bob.client
+        .set_topic("rust", Some("mine now"), None)
+        .await
+        .unwrap();
+    assert_eq!(bob.expect_error().await, "only operators may change #rust");
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn settings_limit_the_room() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+    let mut carol = server.login("carol").await;
+    alice.client.join("rust").await.unwrap();
+    alice.expect_info("rust").await;
+
+    let mut settings = proto::RoomSettings {
+        max_members: 2,
+        slow_mode_secs: 60,
+        read_only: false,
+        ..proto::RoomSettings::default()
+    };
+    alice.client.change_room("rust", &settings).await.unwrap();
+    assert_eq!(alice.expect_info("rust").await.settings, settings);
+    bob.client.join("rust").await.unwrap();
+    bob.expect_info("rust").await;
+    carol.client.join("rust").await.unwrap();
+    assert_eq!(
+        carol.expect_error().await,
+        "cannot join #rust: the room is full"
+    );
+
+    bob.post("rust", "first").await;
+    bob.client.send_chat("rust", "second").await.unwrap();
+    assert_eq!(bob.expect_error().await, "slow mode in #rust, wait 60s");
+    // operators aren't slowed down
+    alice.post("rust", "one").await;
+    alice.post("rust", "two").await;
+
+    settings.read_only = true;
+    alice.client.change_room("rust", &settings).await.unwrap();
+    alice.expect_info("rust").await;
+    alice.client.send_chat("rust", "three").await.unwrap();
+    assert_eq!(alice.expect_error().await, "#rust is read-only");
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn slow_mode_says_how_long_to_wait() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+    alice.client.join("rust").await.unwrap();
+    alice.expect_info("rust").await;
+    bob.client.join("rust").await.unwrap();
+    bob.expect_info("rust").await;
+
+    let settings = proto::RoomSettings {
+        slow_mode_secs: 30,
+        ..proto::RoomSettings::default()
+    };
+    alice.client.change_room("rust", &settings).await.unwrap();
+    bob.expect_info("rust").await;
+
+    let nonce = bob.client.send_chat("rust", "first").await.unwrap();
+    let ack = bob
+        .expect("ack", |event| match event {
+            Event::Ack(ack) if ack.nonce == nonce => Some(ack),
+            _ => None,
+        })
+        .await;
+    assert_eq!(ack.wait_secs, 30);
+    let nonce = bob.client.send_chat("rust", "second").await.unwrap();
+    let error = bob
+        .expect("error", |event| match event {
+            Event::Error(error) => Some(error),
+            _ => None,
+        })
+        .await;
+    assert_eq!(error.nonce, Some(nonce));
+    assert_eq!(
+        error.detail,
+        Some(proto::ErrorDetail::SlowMode {
+            room: "rust".to_owned(),
+            wait_secs: 30
+        })
+    );
+
+    // coming back under the same name doesn't start over
+    bob.client.close().await;
+    let mut bob = server.login("bob").await;
+    bob.client.join("rust").await.unwrap();
+    bob.expect_info("rust").await;
+    bob.client.send_chat("rust", "third").await.unwrap();
+    let error = bob
+        .expect("error", |event| match event {
+            Event::Error(error) => Some(error),
+            _ => None,
+        })
+        .await;
+    assert!(
+        matches!(error.detail, Some(proto::ErrorDetail::SlowMode { .. })),
+        "{:?}",
+        error
+    );
+
+    let nonce = alice.client.send_chat("rust", "one").await.unwrap();
+    let ack = alice
+        .expect("ack", |event| match event {
+            Event::Ack(ack) if ack.nonce == nonce => Some(ack),
+            _ => None,
+        })
+        .await;
+    assert_eq!(ack.wait_secs, 0);
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn announcement_rooms_take_operator_posts() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+    alice.client.join("news").await.unwrap();
+    alice.expect_info("news").await;
+    bob.client.join("news").await.unwrap();
+    bob.expect_info("news").await;
+
+    let settings = proto::RoomSettings {
+        announcement: true,
+        ..proto::RoomSettings::default()
+    };
+    alice.client.change_room("news", &settings).await.unwrap();
+    bob.expect_info("news").await;
+    bob.client.send_chat("news", "hello").await.unwrap();
+    assert_eq!(bob.expect_error().await, "only operators may post in #news");
+    alice.post("news", "release tomorrow").await;
+    let chat = bob
+        .expect("chat", |event| match event {
+            Event::Chat(chat) => Some(chat),
+            _ => None,
+        })
+        .await;
+    assert_eq!(chat.body, "release tomorrow");
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn motd_follows_the_connect() {
+    let server = TestServer::with_config(Config {
+        motd: Some("welcome to the test server".to_owned()),
+        ..Config::default()
+    });
+    let mut client = server.connect().await;
+    let motd = client
+        .expect("motd", |event| match event {
+            Event::Motd { message } => Some(message),
+            _ => None,
+        })
+        .await;
+    assert_eq!(motd, "welcome to the test server");
+
+    server.stop().await;
+}
@@ -0,0 +1,148 @@
+use std::time::Duration;
+
+use chat_client::Event;
+use proto::{PresenceKind, Status};
+
+use ws_server::Config;
+
+mod support;
+
+use support::TestServer;
+
+fn status_of(roster: &proto::Roster, name: &str) -> Option<Status> {
+    roster
+        .members
+        .iter()
+        .find(|m| m.name == name)
+        .map(|m| m.status.clone())
+}
+
+#[actix_web::test]
+async fn status_changes_reach_the_room() {
+    let server = TestServer::start();
+    let alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+
+    let away = Status::Away {
+        message: "lunch".to_owned(),
+    };
+    alice.client.set_status(&away).await.unwrap();
+    let member = bob
+        .expect("update", |event| match event {
+            Event::Presence(p) if p.kind == PresenceKind::Updated => Some(p.member),
+            _ => None,
+        })
+        .await;
+    assert_eq!((member.name.as_str(), &member.status), ("alice", &away));
+    let roster = bob.fetch_roster("main").await;
+    assert_eq!(status_of(&roster, "alice"), Some(away));
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn idle_members_show_as_away() {
+    let server = TestServer::with_config(Config {
+        away_after: Duration::from_secs(1),
+        ..Config::default()
+    });
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+
+    tokio::time::sleep(Duration::from_millis(1200)).await;
+    let roster = bob.fetch_roster("main").await;
+    let idle = Status::Away {
+        message: String::new(),
+    };
+    assert_eq!(status_of(&roster, "alice"), Some(idle));
+
+    alice.post("main", "back").await;
+    let roster = bob.fetch_roster("main").await;
+    assert_eq!(status_of(&roster, "alice"), Some(Status::Online));
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn invisible_members_are_left_out() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+    bob.client.join("lobby").await.unwrap();
+    bob.fetch_roster("lobby").await;
+
+    alice.client.set_status(&Status::Invisible).await.unwrap();
+    bob.expect("update", |event| match event {
+        Event::Presence(p) if p.member.status == Status::Invisible => Some(()),
+        _ => None,
+    })
+    .await;
+    alice.client.join("lobby").await.unwrap();
+    let own = alice.fetch_roster("lobby").await;
+    assert_eq!(status_of(&own, "alice"), Some(Status::Invisible));
+    bob.expect_none(Duration::from_millis(300), |event| {
+        matches!(event, Event::Presence(_))
+    })
+    .await;
+
+    let roster = bob.fetch_roster("lobby").await;
+    assert_eq!(status_of(&roster, "alice"), None);
+    bob.client.whois("alice").await.unwrap();
+    assert_eq!(bob.expect_error().await, "no such user: alice");
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn whois_returns_the_profile() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+
+    let profile = proto::Profile {
+        display_name: Some("Alice Liddell".to_owned()),
+        pronouns: Some("she/her".to_owned()),
+        timezone: Some("Europe/London".to_owned()),
+        bio: None,
+    };
+    alice.client.set_profile(&profile).await.unwrap();
+    // fields left out stay, empty ones are cleared
+    let change = proto::Profile {
+        pronouns: Some(String::new()),
+        bio: Some("x".repeat(301)),
+        ..proto::Profile::default()
+    };
+    alice.client.set_profile(&change).await.unwrap();
+    assert_eq!(alice.expect_error().await, "bio too long");
+    let change = proto::Profile {
+        bio: None,
+        ..change
+    };
+    alice.client.set_profile(&change).await.unwrap();
+    alice.client.set_status(&Status::Busy).await.unwrap();
+    // the update reaching bob shows alice's packets went through
+    bob.expect("update", |event| match event {
+        Event::Presence(p) if p.member.status == Status::Busy => Some(()),
+        _ => None,
+    })
+    .await;
+
+    bob.client.whois("alice").await.unwrap();
+    let info = bob
+        .expect("whois", |event| match event {
+            Event::WhoIs(info) => Some(info),
+            _ => None,
+        })
+        .await;
+    assert_eq!(info.name, "alice");
+    assert_eq!(info.status, Status::Busy);
+    assert_eq!(
+        info.profile,
+        proto::Profile {
+            pronouns: None,
+            ..profile
+        }
+    );
+
+    server.stop().await;
+}
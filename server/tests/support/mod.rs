@@ -0,0 +1,285 @@
+//! Shared setup for the integration tests: a server on an ephemeral localhost
+//! port and scripted clients talking to it
+
+#![allow(dead_code)]
+
+use std::{net::TcpListener, sync::Arc, time::Duration};
+
+use actix_web::dev::ServerHandle;
+use chat_client::{ChatClient, ClientOptions, Event, Events, Reconnect};
+use futures_util::StreamExt;
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::TcpStream;
+use tokio_tungstenite::{tungstenite::Message, MaybeTlsStream, WebSocketStream};
+
+use ws_server::{backplane::Backplane, Config};
+
+pub mod proxy;
+pub mod redis;
+
+use proxy::Proxy;
+
+/// How long a test waits for an expected event
+pub const TIMEOUT: Duration = Duration::from_secs(5);
+
+pub type RawSocket = WebSocketStream<MaybeTlsStream<TcpStream>>;
+
+pub struct TestServer {
+    pub url: String,
+    /// `host:port` for plain HTTP requests
+    pub addr: String,
+    handle: ServerHandle,
+}
+
+impl TestServer {
+    pub fn start() -> TestServer {
+        TestServer::with_config(Config::default())
+    }
+
+    /// Must be called from an actix test, the server runs on its system
+    pub fn with_config(config: Config) -> TestServer {
+        TestServer::spawn(config, None)
+    }
+
+    /// Node of a cluster sharing `backplane`
+    pub fn with_backplane(backplane: Arc<dyn Backplane>) -> TestServer {
+        TestServer::spawn(Config::default(), Some(backplane))
+    }
+
+    fn spawn(config: Config, backplane: Option<Arc<dyn Backplane>>) -> TestServer {
+        let listener = TcpListener::bind("127.0.0.1:0").expect("bind ephemeral port");
+        let port = listener.local_addr().expect("local address").port();
+        let server = match backplane {
+            Some(backplane) => ws_server::serve_with(listener, config, Some(backplane)),
+            None => ws_server::serve(listener, config),
+        }
+        .expect("start server");
+        let handle = server.handle();
+        actix_web::rt::spawn(server);
+
+        TestServer {
+            url: format!("ws://127.0.0.1:{}/", port),
+            addr: format!("127.0.0.1:{}", port),
+            handle,
+        }
+    }
+
+    pub fn options(&self) -> ClientOptions {
+        let mut options = ClientOptions::new(&self.url);
+        // a test should see a lost connection, not a silent retry
+        options.reconnect = None;
+        options
+    }
+
+    /// Anonymous client, returned once it is registered in the main room
+    pub async fn connect(&self) -> TestClient {
+        TestServer::connect_with(self.options()).await
+    }
+
+    async fn connect_with(options: ClientOptions) -> TestClient {
+        let (client, events) = ChatClient::connect(options)
+            .await
+            .expect("connect to test server");
+        let mut client = TestClient { client, events };
+        client
+            .expect("main roster", |event| match event {
+                Event::Roster(roster) if roster.room == "main" => Some(()),
+                _ => None,
+            })
+            .await;
+        client
+    }
+
+    /// Client logged in as `name`, returned once the server applied the name
+    pub async fn login(&self, name: &str) -> TestClient {
+        let mut client = self.connect().await;
+        client.client.login(name).await.expect("send login");
+        client.expect_login(name).await;
+        client
+    }
+
+    /// Anonymous client connected through `proxy`, reconnecting right away
+    /// when the proxy cuts it off
+    pub async fn connect_through(&self, proxy: &Proxy) -> TestClient {
+        let mut options = self.options();
+        options.url = proxy.url.clone();
+        options.reconnect = Some(Reconnect {
+            initial_delay: Duration::from_millis(50),
+            ..Reconnect::default()
+        });
+        TestServer::connect_with(options).await
+    }
+
+    /// Like `connect_through`, logged in as `name`
+    pub async fn login_through(&self, proxy: &Proxy, name: &str) -> TestClient {
+        let mut client = self.connect_through(proxy).await;
+        client.client.login(name).await.expect("send login");
+        client.expect_login(name).await;
+        client
+    }
+
+    /// Plain websocket without the client library, for misbehaving peers
+    pub async fn raw(&self) -> RawSocket {
+        let (socket, _) = tokio_tungstenite::connect_async(self.url.as_str())
+            .await
+            .expect("raw connect to test server");
+        socket
+    }
+
+    /// Status code and body of a GET, with an optional bearer token
+    pub async fn get(&self, path: &str, token: Option<&str>) -> (u16, String) {
+        let mut stream = TcpStream::connect(&self.addr)
+            .await
+            .expect("connect to test server");
+        let auth = token
+            .map(|token| format!("Authorization: Bearer {}\r\n", token))
+            .unwrap_or_default();
+        let request = format!(
+            "GET {} HTTP/1.1\r\nHost: {}\r\n{}Connection: close\r\n\r\n",
+            path, self.addr, auth
+        );
+        stream
+            .write_all(request.as_bytes())
+            .await
+            .expect("send request");
+
+        let mut response = String::new();
+        stream
+            .read_to_string(&mut response)
+            .await
+            .expect("read response");
+        let status = response
+            .split_whitespace()
+            .nth(1)
+            .and_then(|code| code.parse().ok())
+            .expect("status line");
+        let body = response
+            .split_once("\r\n\r\n")
+            .map(|(_, body)| body.to_owned())
+            .unwrap_or_default();
+        (status, body)
+    }
+
+    pub async fn stop(self) {
+        self.handle.stop(false).await;
+    }
+}
+
+pub struct TestClient {
+    pub client: ChatClient,
+    pub events: Events,
+}
+
+impl TestClient {
+    /// Wait for the first event `pick` accepts, skipping everything before it
+    pub async fn expect<T>(&mut self, what: &str, mut pick: impl FnMut(Event) -> Option<T>) -> T {
+        let found = tokio::time::timeout(TIMEOUT, async {
+            while let Some(event) = self.events.next_event().await {
+                if let Some(found) = pick(event) {
+                    return Some(found);
+                }
+            }
+            None
+        })
+        .await;
+
+        match found {
+            Ok(Some(found)) => found,
+            Ok(None) => panic!("event stream ended while waiting for {}", what),
+            Err(_) => panic!("timed out waiting for {}", what),
+        }
+    }
+
+    /// Send a chat and wait for the server to accept it, returns the message id
+    pub async fn post(&mut self, room: &str, body: &str) -> u64 {
+        let nonce = self.client.send_chat(room, body).await.expect("send chat");
+        self.expect("ack", |event| match event {
+            Event::Ack(ack) if ack.nonce == nonce => Some(ack.message_id),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Next details the server sent about `room`
+    pub async fn expect_info(&mut self, room: &str) -> proto::RoomInfo {
+        self.expect("room info", |event| match event {
+            Event::RoomInfo(info) if info.room == room => Some(info),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Next member list the server sent for `room`
+    pub async fn expect_roster(&mut self, room: &str) -> proto::Roster {
+        self.expect("roster", |event| match event {
+            Event::Roster(roster) if roster.room == room => Some(roster),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Wait for the notice of the own login as `name`
+    pub async fn expect_login(&mut self, name: &str) {
+        self.expect("own login notice", |event| match event {
+            Event::Notice { message } if message.ends_with(&format!(" to {}", name)) => Some(()),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Ask for the member list of `room` and wait for it
+    pub async fn fetch_roster(&mut self, room: &str) -> proto::Roster {
+        self.client
+            .request_roster(room)
+            .await
+            .expect("request roster");
+        self.expect_roster(room).await
+    }
+
+    /// Message of the next error the server sent
+    pub async fn expect_error(&mut self) -> String {
+        self.expect("error", |event| match event {
+            Event::Error(error) => Some(error.message),
+            _ => None,
+        })
+        .await
+    }
+
+    /// Assert that nothing `pick` accepts arrives within `wait`
+    pub async fn expect_none(&mut self, wait: Duration, mut pick: impl FnMut(&Event) -> bool) {
+        let _ = tokio::time::timeout(wait, async {
+            while let Some(event) = self.events.next_event().await {
+                assert!(!pick(&event), "unexpected event: {:?}", event);
+            }
+        })
+        .await;
+    }
+}
+
+/// Next packet the server sent to a raw socket
+pub async fn next_packet(socket: &mut RawSocket) -> proto::ChatPacket {
+    loop {
+        let message = tokio::time::timeout(TIMEOUT, socket.next())
+            .await
+            .expect("packet in time")
+            .expect("open socket")
+            .expect("valid message");
+        if let Message::Binary(bytes) = message {
+            return proto::ChatPacket::deserialize(&bytes).expect("valid packet");
+        }
+    }
+}
+
+/// Wait until the server closes the socket, true if it did within `TIMEOUT`
+pub async fn closed_by_server(socket: &mut RawSocket) -> bool {
+    tokio::time::timeout(TIMEOUT, async {
+        loop {
+            match socket.next().await {
+                Some(Ok(Message::Close(_))) | Some(Err(_)) | None => return,
+                Some(Ok(_)) => {}
+            }
+        }
+    })
+    .await
+    .is_ok()
+}
@@ -0,0 +1,116 @@
+//! TCP proxy in front of a test server that drops the client side of its
+//! connections while the server side stays open, the way a connection lost
+//! on the way looks to the server until its heartbeat times out
+
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio::net::tcp::OwnedWriteHalf;
+use tokio::net::{TcpListener, TcpStream};
+use tokio::task::JoinHandle;
+
+type Shared<T> = Arc<tokio::sync::Mutex<T>>;
+
+struct Link {
+    /// Taken to cut the client off
+    client: Shared<Option<OwnedWriteHalf>>,
+    /// Kept so the server never sees the connection end
+    _server: Shared<OwnedWriteHalf>,
+    upstream: JoinHandle<()>,
+}
+
+pub struct Proxy {
+    pub url: String,
+    links: Arc<Mutex<Vec<Link>>>,
+    /// Frames of the server are swallowed
+    muted: Arc<AtomicBool>,
+}
+
+impl Proxy {
+    /// Proxy for the websocket server at `url`
+    pub async fn start(url: &str) -> Proxy {
+        let target = url
+            .trim_start_matches("ws://")
+            .trim_end_matches('/')
+            .to_owned();
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let url = format!("ws://{}/", listener.local_addr().expect("local address"));
+        let links: Arc<Mutex<Vec<Link>>> = Arc::default();
+        let muted: Arc<AtomicBool> = Arc::default();
+
+        let (shared, mute) = (links.clone(), muted.clone());
+        actix_web::rt::spawn(async move {
+            while let Ok((client, _)) = listener.accept().await {
+                let server = TcpStream::connect(&target).await.expect("connect");
+                let link = relay(client, server, mute.clone());
+                shared.lock().unwrap().push(link);
+            }
+        });
+        Proxy { url, links, muted }
+    }
+
+    /// Stop passing on what the server sends, or start again
+    pub fn mute(&self, muted: bool) {
+        self.muted.store(muted, Ordering::Relaxed);
+    }
+
+    /// Close the client side of every connection so far and unmute
+    pub async fn cut(&self) {
+        let clients: Vec<Shared<Option<OwnedWriteHalf>>> = self
+            .links
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|link| {
+                link.upstream.abort();
+                link.client.clone()
+            })
+            .collect();
+        for client in clients {
+            client.lock().await.take();
+        }
+        self.mute(false);
+    }
+}
+
+fn relay(client: TcpStream, server: TcpStream, muted: Arc<AtomicBool>) -> Link {
+    let (mut client_rx, client_tx) = client.into_split();
+    let (mut server_rx, server_tx) = server.into_split();
+    let client_tx: Shared<Option<OwnedWriteHalf>> = Arc::new(Some(client_tx).into());
+    let server_tx: Shared<OwnedWriteHalf> = Arc::new(server_tx.into());
+
+    let to_client = client_tx.clone();
+    actix_web::rt::spawn(async move {
+        let mut buf = [0; 4096];
+        // what can't be passed on is read anyway, so the server never blocks
+        while let Ok(read @ 1..) = server_rx.read(&mut buf).await {
+            if muted.load(Ordering::Relaxed) {
+                continue;
+            }
+            if let Some(client) = to_client.lock().await.as_mut() {
+                let _ = client.write_all(&buf[..read]).await;
+            }
+        }
+    });
+    let to_server = server_tx.clone();
+    let upstream = actix_web::rt::spawn(async move {
+        let mut buf = [0; 4096];
+        while let Ok(read @ 1..) = client_rx.read(&mut buf).await {
+            if to_server
+                .lock()
+                .await
+                .write_all(&buf[..read])
+                .await
+                .is_err()
+            {
+                break;
+            }
+        }
+    });
+    Link {
+        client: client_tx,
+        _server: server_tx,
+        upstream,
+    }
+}
@@ -0,0 +1,114 @@
+//! Just enough of a Redis server for pub/sub: SUBSCRIBE and PUBLISH
+
+use std::collections::HashMap;
+use std::sync::{Arc, Mutex};
+
+use tokio::io::{AsyncBufReadExt, AsyncReadExt, AsyncWriteExt, BufReader};
+use tokio::net::{TcpListener, TcpStream};
+use tokio::sync::mpsc;
+
+type Subscribers = Arc<Mutex<HashMap<Vec<u8>, Vec<mpsc::UnboundedSender<Vec<u8>>>>>>;
+
+pub struct FakeRedis {
+    pub url: String,
+    subscribers: Subscribers,
+}
+
+impl FakeRedis {
+    pub async fn start() -> FakeRedis {
+        let listener = TcpListener::bind("127.0.0.1:0").await.expect("bind");
+        let url = format!("redis://{}", listener.local_addr().expect("local address"));
+        let subscribers = Subscribers::default();
+
+        let shared = subscribers.clone();
+        actix_web::rt::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                actix_web::rt::spawn(serve(stream, shared.clone()));
+            }
+        });
+        FakeRedis { url, subscribers }
+    }
+
+    /// Connections subscribed to any channel
+    pub fn subscriber_count(&self) -> usize {
+        self.subscribers
+            .lock()
+            .unwrap()
+            .values()
+            .map(Vec::len)
+            .sum()
+    }
+}
+
+fn bulk(out: &mut Vec<u8>, item: &[u8]) {
+    out.extend(format!("${}\r\n", item.len()).as_bytes());
+    out.extend(item);
+    out.extend(b"\r\n");
+}
+
+async fn serve(stream: TcpStream, subscribers: Subscribers) {
+    let (reader, mut writer) = stream.into_split();
+    let (tx, mut rx) = mpsc::unbounded_channel::<Vec<u8>>();
+    actix_web::rt::spawn(async move {
+        while let Some(bytes) = rx.recv().await {
+            if writer.write_all(&bytes).await.is_err() {
+                break;
+            }
+        }
+    });
+
+    let mut reader = BufReader::new(reader);
+    while let Some(args) = read_command(&mut reader).await {
+        match args.first().map(|cmd| cmd.to_ascii_uppercase()).as_deref() {
+            Some(b"SUBSCRIBE") if args.len() == 2 => {
+                let channel = args[1].clone();
+                let mut out = b"*3\r\n".to_vec();
+                bulk(&mut out, b"subscribe");
+                bulk(&mut out, &channel);
+                out.extend(b":1\r\n");
+                let _ = tx.send(out);
+                subscribers
+                    .lock()
+                    .unwrap()
+                    .entry(channel)
+                    .or_default()
+                    .push(tx.clone());
+            }
+            Some(b"PUBLISH") if args.len() == 3 => {
+                let mut out = b"*3\r\n".to_vec();
+                bulk(&mut out, b"message");
+                bulk(&mut out, &args[1]);
+                bulk(&mut out, &args[2]);
+
+                let mut subscribers = subscribers.lock().unwrap();
+                let channel = subscribers.entry(args[1].clone()).or_default();
+                channel.retain(|sub| sub.send(out.clone()).is_ok());
+                let _ = tx.send(format!(":{}\r\n", channel.len()).into_bytes());
+            }
+            _ => {
+                let _ = tx.send(b"-ERR unknown command\r\n".to_vec());
+            }
+        }
+    }
+}
+
+/// A command as an array of bulk strings, `None` once the connection is gone
+async fn read_command<R: tokio::io::AsyncRead + Unpin>(
+    reader: &mut BufReader<R>,
+) -> Option<Vec<Vec<u8>>> {
+    let mut line = String::new();
+    reader.read_line(&mut line).await.ok().filter(|n| *n > 0)?;
+    let count: usize = line.trim_end().strip_prefix('*')?.parse().ok()?;
+
+    let mut args = Vec::with_capacity(count);
+    for _ in 0..count {
+        line.clear();
+        reader.read_line(&mut line).await.ok()?;
+        let len: usize = line.trim_end().strip_prefix('$')?.parse().ok()?;
+        let mut arg = vec![0; len + 2];
+        reader.read_exact(&mut arg).await.ok()?;
+        arg.truncate(len);
+        args.push(arg);
+    }
+    Some(args)
+}
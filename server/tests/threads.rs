@@ -0,0 +1,59 @@
+use chat_client::Event;
+
+mod support;
+
+use support::TestServer;
+
+#[actix_web::test]
+async fn replies_name_their_parent() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let bob = server.login("bob").await;
+
+    let parent = alice.post("main", "how do I parse json?").await;
+    let nonce = bob
+        .client
+        .reply("main", parent, "serde_json")
+        .await
+        .unwrap();
+    let reply = alice
+        .expect("reply", |event| match event {
+            Event::Chat(chat) if chat.body == "serde_json" => Some(chat),
+            _ => None,
+        })
+        .await;
+    assert_eq!(reply.reply_to, Some(parent));
+    assert_eq!(reply.nonce, Some(nonce));
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn reply_parent_must_be_in_the_same_room() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    alice.client.join("rust").await.unwrap();
+    alice.expect_roster("rust").await;
+
+    let elsewhere = alice.post("main", "over here").await;
+    let nonce = alice.client.reply("rust", elsewhere, "lost").await.unwrap();
+    let error = alice
+        .expect("rejection", |event| match event {
+            Event::Error(error) => Some(error),
+            _ => None,
+        })
+        .await;
+    assert_eq!(error.nonce, Some(nonce));
+    assert_eq!(
+        error.message,
+        format!("no message {} in rust to reply to", elsewhere)
+    );
+
+    alice.client.reply("main", 7, "anyone?").await.unwrap();
+    assert_eq!(
+        alice.expect_error().await,
+        "no message 7 in main to reply to"
+    );
+
+    server.stop().await;
+}
@@ -0,0 +1,100 @@
+use std::time::Duration;
+
+use chat_client::Event;
+use proto::TypingState;
+
+use ws_server::Config;
+
+mod support;
+
+use support::{TestClient, TestServer};
+
+async fn expect_typing(client: &mut TestClient) -> proto::TypingStatus {
+    client
+        .expect("typing", |event| match event {
+            Event::Typing(status) => Some(status),
+            _ => None,
+        })
+        .await
+}
+
+#[actix_web::test]
+async fn typing_is_relayed_until_the_chat_arrives() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+
+    alice
+        .client
+        .typing("main", TypingState::Active)
+        .await
+        .unwrap();
+    let status = expect_typing(&mut bob).await;
+    assert_eq!(
+        (status.name.as_str(), status.state, status.expires_secs),
+        ("alice", TypingState::Active, 6)
+    );
+
+    // the chat ends it, a stop sent afterwards is not passed on
+    alice.post("main", "done").await;
+    alice
+        .client
+        .typing("main", TypingState::Stopped)
+        .await
+        .unwrap();
+    bob.expect_none(Duration::from_millis(300), |event| {
+        matches!(event, Event::Typing(_))
+    })
+    .await;
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn typing_runs_out_without_a_refresh() {
+    let server = TestServer::with_config(Config {
+        typing_expiry: Duration::from_secs(1),
+        ..Config::default()
+    });
+    let alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+
+    alice
+        .client
+        .typing("main", TypingState::Active)
+        .await
+        .unwrap();
+    assert_eq!(expect_typing(&mut bob).await.state, TypingState::Active);
+    let status = expect_typing(&mut bob).await;
+    assert_eq!(
+        (status.name.as_str(), status.state),
+        ("alice", TypingState::Stopped)
+    );
+
+    server.stop().await;
+}
+
+#[actix_web::test]
+async fn typing_is_not_part_of_the_history() {
+    let server = TestServer::start();
+    let mut alice = server.login("alice").await;
+    let mut bob = server.login("bob").await;
+
+    alice
+        .client
+        .typing("main", TypingState::Active)
+        .await
+        .unwrap();
+    expect_typing(&mut bob).await;
+    alice.post("main", "numbered").await;
+    let chat = bob
+        .expect("chat", |event| match event {
+            Event::Chat(chat) => Some(chat),
+            _ => None,
+        })
+        .await;
+    // both joining and renaming came first
+    assert_eq!(chat.seq, 5);
+
+    server.stop().await;
+}